@@ -0,0 +1,36 @@
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+// A connection parked in a blocking command until one of the keys it waits on is written.
+// Wakeups are only hints: the woken connection re-checks the keys itself.
+#[derive(Debug, Default)]
+pub struct Waiter {
+    woken: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Waiter {
+    pub fn wake(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.cond.notify_one();
+    }
+    // Sleeps until woken or until the deadline (None waits forever). A wakeup that arrived
+    // before the call returns at once, so none are lost between checking and waiting.
+    pub fn wait(&self, deadline: Option<Instant>) {
+        let mut woken = self.woken.lock().unwrap();
+        while !*woken {
+            match deadline {
+                None => woken = self.cond.wait(woken).unwrap(),
+                Some(deadline) => {
+                    let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                        break;
+                    };
+                    woken = self.cond.wait_timeout(woken, left).unwrap().0;
+                }
+            }
+        }
+        *woken = false;
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::resp::Protocol;
+
+// Redis' client-output-buffer-limit for one client class: disconnect once the pending output
+// exceeds `hard` bytes, or has stayed above `soft` bytes for `soft_seconds`.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+pub const PUBSUB_OUTPUT_LIMIT: OutputLimit = OutputLimit {
+    hard: 32 * 1024 * 1024,
+    soft: 8 * 1024 * 1024,
+    soft_seconds: 60,
+};
+
+pub const REPLICA_OUTPUT_LIMIT: OutputLimit = OutputLimit {
+    hard: 256 * 1024 * 1024,
+    soft: 64 * 1024 * 1024,
+    soft_seconds: 60,
+};
+
+#[derive(Debug, Default)]
+struct Pending {
+    frames: VecDeque<Vec<u8>>,
+    bytes: usize,
+    soft_limit_since: Option<Instant>,
+    closed: bool,
+    evicted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    Queued,
+    // This push took the backlog over the limit and the client is being disconnected.
+    Evicted,
+    // The client was already closed or evicted.
+    Closed,
+}
+
+// Bytes waiting to be written to a client socket. The connection's writer thread drains it, so
+// other connections (e.g. PUBLISH) can queue output without blocking on a slow reader.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+impl Outbox {
+    // Queues `bytes` unless the client is gone. With a limit, a client whose backlog grows past
+    // it is evicted instead.
+    pub fn push(&self, bytes: Vec<u8>, limit: Option<OutputLimit>) -> Delivery {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.closed {
+            return Delivery::Closed;
+        }
+        pending.bytes += bytes.len();
+        pending.frames.push_back(bytes);
+        if let Some(limit) = limit {
+            if pending.bytes <= limit.soft {
+                pending.soft_limit_since = None;
+            }
+            let soft_since = *pending.soft_limit_since.get_or_insert_with(Instant::now);
+            let over_soft = pending.bytes > limit.soft
+                && soft_since.elapsed() >= Duration::from_secs(limit.soft_seconds);
+            if pending.bytes > limit.hard || over_soft {
+                pending.closed = true;
+                pending.evicted = true;
+                self.ready.notify_all();
+                return Delivery::Evicted;
+            }
+        }
+        self.ready.notify_all();
+        Delivery::Queued
+    }
+    pub fn backlog(&self) -> usize {
+        self.pending.lock().unwrap().bytes
+    }
+    // Stops accepting output; whatever is already queued is still written.
+    pub fn close(&self) {
+        self.pending.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+    // Blocks until there is output to write. Returns None once the outbox is closed and
+    // drained, or immediately if the client was evicted.
+    pub fn take(&self) -> Option<Vec<u8>> {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            if pending.evicted {
+                return None;
+            }
+            if !pending.frames.is_empty() {
+                let out = pending.frames.drain(..).flatten().collect();
+                pending.bytes = 0;
+                pending.soft_limit_since = None;
+                return Some(out);
+            }
+            if pending.closed {
+                return None;
+            }
+            pending = self.ready.wait(pending).unwrap();
+        }
+    }
+}
+
+// What CLIENT LIST and CLIENT INFO report about a connection. The connection refreshes it after
+// every command, so other connections can read it without touching the session itself.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: String,
+    pub laddr: String,
+    pub created: Instant,
+    pub last_interaction: Instant,
+    pub name: Vec<u8>,
+    pub db: usize,
+    pub protocol: Protocol,
+    pub last_command: String,
+    pub sub: usize,
+    pub psub: usize,
+    pub ssub: usize,
+    // Commands queued by MULTI, or -1 outside a transaction.
+    pub multi: i64,
+    pub watch: usize,
+    // Bytes of unparsed input left when the last command was read.
+    pub qbuf: usize,
+    pub tot_cmds: u64,
+    // Read live, since a subscriber's backlog grows without it running any command.
+    pub outbox: Arc<Outbox>,
+}
+
+impl Default for ClientInfo {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            addr: String::new(),
+            laddr: String::new(),
+            created: Instant::now(),
+            last_interaction: Instant::now(),
+            name: vec![],
+            db: 0,
+            protocol: Protocol::Resp2,
+            last_command: "NULL".into(),
+            sub: 0,
+            psub: 0,
+            ssub: 0,
+            multi: -1,
+            watch: 0,
+            qbuf: 0,
+            tot_cmds: 0,
+            outbox: Arc::default(),
+        }
+    }
+}
+
+impl ClientInfo {
+    pub fn render(&self) -> String {
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} db={} sub={} psub={} ssub={} multi={} \
+             watch={} qbuf={} obl={} tot-cmds={} cmd={} user=default resp={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            String::from_utf8_lossy(&self.name),
+            self.created.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            self.db,
+            self.sub,
+            self.psub,
+            self.ssub,
+            self.multi,
+            self.watch,
+            self.qbuf,
+            self.outbox.backlog(),
+            self.tot_cmds,
+            self.last_command,
+            match self.protocol {
+                Protocol::Resp2 => 2,
+                Protocol::Resp3 => 3,
+            }
+        )
+    }
+}
@@ -0,0 +1,254 @@
+// Several servers in one process, for testing replication without starting processes: a master
+// and its replicas each listen on an ephemeral port, every replica reaches its master through a
+// proxy that can cut the link, and the clock deadlines are read from can be moved forward. There
+// is no cluster mode, so a topology is always one master and its replicas. Nodes are never shut
+// down; they live as long as the process.
+use std::{
+    io,
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    command::{self, Context, Session},
+    db::{self, Databases},
+    network,
+    resp::Reply,
+    server::Server,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct Node {
+    pub port: u16,
+    pub server: Arc<Server>,
+    pub databases: Databases,
+}
+
+impl Node {
+    fn start(master_port: Option<u16>) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let port = listener.local_addr()?.port();
+        let mut server = Server::new(port);
+        server.master = master_port.map(|port| ("127.0.0.1".to_string(), port));
+        let (server, databases) = (Arc::new(server), db::new_databases());
+        let (node_server, node_databases) = (server.clone(), databases.clone());
+        thread::spawn(move || network::run(listener, node_server, node_databases));
+        Ok(Self {
+            port,
+            server,
+            databases,
+        })
+    }
+    // Runs one command as a fresh client would, without a socket in between.
+    pub fn execute(&self, args: &[&str]) -> Reply {
+        let args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        let mut session = Session::default();
+        let mut ctx = Context {
+            db: &self.databases[session.db],
+            databases: &self.databases,
+            server: &self.server,
+            session: &mut session,
+        };
+        command::execute(&mut ctx, &args)
+    }
+    // The replication offset this node has reached: the bytes it has streamed as a master, or
+    // applied as a replica. None for a replica that is not linked to its master.
+    pub fn offset(&self) -> Option<u64> {
+        match &self.server.master {
+            Some(_) => self
+                .server
+                .master_link
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|link| link.offset),
+            None => Some(self.server.replicas.lock().unwrap().offset),
+        }
+    }
+}
+
+// A replica's connection to its master, forwarded through a local listener so it can be cut.
+struct Link {
+    cut: Arc<AtomicBool>,
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Link {
+    // Returns the link and the port the replica should connect to instead of the master's.
+    fn start(master_port: u16) -> io::Result<(Self, u16)> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let port = listener.local_addr()?.port();
+        let link = Self {
+            cut: Arc::default(),
+            connections: Arc::default(),
+        };
+        let (cut, connections) = (link.cut.clone(), link.connections.clone());
+        thread::spawn(move || {
+            for replica in listener.incoming() {
+                let Ok(replica) = replica else {
+                    continue;
+                };
+                // While cut, connections are hung up on as soon as they arrive.
+                if cut.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let Ok(master) = TcpStream::connect(("127.0.0.1", master_port)) else {
+                    continue;
+                };
+                let _ = forward(&replica, &master, &connections);
+            }
+        });
+        Ok((link, port))
+    }
+    fn set_cut(&self, cut: bool) {
+        self.cut.store(cut, Ordering::Relaxed);
+        if cut {
+            for stream in self.connections.lock().unwrap().drain(..) {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+// Copies bytes both ways between two sockets until either side closes, then closes both.
+fn forward(a: &TcpStream, b: &TcpStream, connections: &Mutex<Vec<TcpStream>>) -> io::Result<()> {
+    connections
+        .lock()
+        .unwrap()
+        .extend([a.try_clone()?, b.try_clone()?]);
+    for (mut from, mut to) in [
+        (a.try_clone()?, b.try_clone()?),
+        (b.try_clone()?, a.try_clone()?),
+    ] {
+        thread::spawn(move || {
+            let _ = io::copy(&mut from, &mut to);
+            let _ = from.shutdown(Shutdown::Both);
+            let _ = to.shutdown(Shutdown::Both);
+        });
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Builder {
+    replicas: usize,
+}
+
+impl Builder {
+    pub fn replicas(mut self, count: usize) -> Self {
+        self.replicas = count;
+        self
+    }
+    pub fn start(self) -> io::Result<Topology> {
+        let master = Node::start(None)?;
+        let mut replicas = vec![];
+        let mut links = vec![];
+        for _ in 0..self.replicas {
+            let (link, port) = Link::start(master.port)?;
+            replicas.push(Node::start(Some(port))?);
+            links.push(link);
+        }
+        Ok(Topology {
+            master,
+            replicas,
+            links,
+        })
+    }
+}
+
+pub struct Topology {
+    master: Node,
+    replicas: Vec<Node>,
+    links: Vec<Link>,
+}
+
+impl Topology {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+    pub fn master(&self) -> &Node {
+        &self.master
+    }
+    pub fn replica(&self, index: usize) -> &Node {
+        &self.replicas[index]
+    }
+    // Cuts replica `index` off its master until `heal`; the replica keeps retrying meanwhile.
+    pub fn partition(&self, index: usize) {
+        self.links[index].set_cut(true);
+    }
+    pub fn heal(&self, index: usize) {
+        self.links[index].set_cut(false);
+    }
+    // Moves the clock forward for every node at once, see db::advance_clock.
+    pub fn advance_clock(&self, by: Duration) {
+        db::advance_clock(by);
+    }
+    // Waits until every replica that isn't cut off has applied all the master streamed. False if
+    // that takes longer than `timeout`.
+    pub fn wait_for_sync(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let target = self.master.offset();
+            let synced = self
+                .replicas
+                .iter()
+                .zip(&self.links)
+                .filter(|(_, link)| !link.cut.load(Ordering::Relaxed))
+                .all(|(replica, _)| replica.offset() == target);
+            if synced {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn bulk(s: &str) -> Reply {
+        Reply::BulkString(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn replicas_follow_and_resync_after_a_partition() {
+        let topology = Topology::builder().replicas(2).start().unwrap();
+        assert!(topology.wait_for_sync(TIMEOUT));
+        topology.master().execute(&["SET", "k", "1"]);
+        assert!(topology.wait_for_sync(TIMEOUT));
+        assert_eq!(topology.replica(0).execute(&["GET", "k"]), bulk("1"));
+        assert_eq!(topology.replica(1).execute(&["GET", "k"]), bulk("1"));
+
+        topology.partition(1);
+        topology.master().execute(&["SET", "k", "2"]);
+        assert!(topology.wait_for_sync(TIMEOUT));
+        assert_eq!(topology.replica(0).execute(&["GET", "k"]), bulk("2"));
+        assert_eq!(topology.replica(1).execute(&["GET", "k"]), bulk("1"));
+
+        topology.heal(1);
+        assert!(topology.wait_for_sync(TIMEOUT));
+        assert_eq!(topology.replica(1).execute(&["GET", "k"]), bulk("2"));
+    }
+
+    #[test]
+    fn advancing_the_clock_expires_keys() {
+        let topology = Topology::builder().start().unwrap();
+        let master = topology.master();
+        master.execute(&["SET", "k", "v", "PX", "3600000"]);
+        assert_eq!(master.execute(&["GET", "k"]), bulk("v"));
+        topology.advance_clock(Duration::from_secs(3601));
+        assert_eq!(master.execute(&["GET", "k"]), Reply::Nil);
+    }
+}
@@ -0,0 +1,361 @@
+use super::{notify, parse_int, string::as_string, CommandError, CommandResult, Context};
+use crate::{
+    db::{MapValue, Value},
+    resp::Reply,
+    types::{
+        bitmap::{self, BitOp, FieldType, Overflow, Unit},
+        string::Str,
+    },
+};
+
+// Offsets that would grow the string past proto-max-bulk-len are refused. With a field width,
+// `#N` stands for the N-th field of that width.
+fn parse_bit_offset(ctx: &Context, arg: &[u8], width: Option<u32>) -> Result<usize, CommandError> {
+    let max_bulk_len = ctx.server.config.lock().unwrap().proto_max_bulk_len;
+    let offset = match (arg.strip_prefix(b"#"), width) {
+        (Some(index), Some(width)) => parse_int::<u64>(index)
+            .ok()
+            .and_then(|index| index.checked_mul(width as u64)),
+        _ => parse_int::<u64>(arg).ok(),
+    };
+    offset
+        .filter(|offset| offset >> 3 < max_bulk_len)
+        .map(|offset| offset as usize)
+        .ok_or_else(|| CommandError::Other("bit offset is not an integer or out of range".into()))
+}
+
+fn parse_unit(arg: &[u8]) -> Result<Unit, CommandError> {
+    match arg.to_ascii_uppercase().as_slice() {
+        b"BYTE" => Ok(Unit::Byte),
+        b"BIT" => Ok(Unit::Bit),
+        _ => Err(CommandError::Syntax),
+    }
+}
+
+pub fn bitcount(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let range = match &args[2..] {
+        [] => None,
+        [start, end] => Some((parse_int(start)?, parse_int(end)?, Unit::Byte)),
+        [start, end, unit] => Some((parse_int(start)?, parse_int(end)?, parse_unit(unit)?)),
+        _ => return Err(CommandError::Syntax),
+    };
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let bytes = as_string(value)?;
+    let (start, end, unit) = range.unwrap_or((0, -1, Unit::Byte));
+    let count = bitmap::bit_range(bytes.len(), start, end, unit)
+        .map_or(0, |range| bitmap::count(bytes, range));
+    Ok(Reply::Integer(count as i64))
+}
+
+pub fn bitpos(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let bit = match args[2].as_slice() {
+        b"0" => false,
+        b"1" => true,
+        _ => {
+            return Err(CommandError::Other(
+                "The bit argument must be 1 or 0.".into(),
+            ))
+        }
+    };
+    let (start, end, unit, end_given) = match &args[3..] {
+        [] => (0, -1, Unit::Byte, false),
+        [start] => (parse_int(start)?, -1, Unit::Byte, false),
+        [start, end] => (parse_int(start)?, parse_int(end)?, Unit::Byte, true),
+        [start, end, unit] => (parse_int(start)?, parse_int(end)?, parse_unit(unit)?, true),
+        _ => return Err(CommandError::Syntax),
+    };
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Integer(if bit { -1 } else { 0 }));
+    };
+    let bytes = as_string(value)?;
+    let Some(range) = bitmap::bit_range(bytes.len(), start, end, unit) else {
+        return Ok(Reply::Integer(-1));
+    };
+    let pos = match bitmap::position(bytes, bit, range) {
+        Some(pos) => pos as i64,
+        // Without an explicit end the string is treated as padded with zeros on the right.
+        None if !bit && !end_given => bytes.len() as i64 * 8,
+        None => -1,
+    };
+    Ok(Reply::Integer(pos))
+}
+
+pub fn bitop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let op = match args[1].to_ascii_uppercase().as_slice() {
+        b"AND" => BitOp::And,
+        b"OR" => BitOp::Or,
+        b"XOR" => BitOp::Xor,
+        b"NOT" => BitOp::Not,
+        _ => return Err(CommandError::Syntax),
+    };
+    let (dest, keys) = (&args[2], &args[3..]);
+    if op == BitOp::Not && keys.len() != 1 {
+        return Err(CommandError::Other(
+            "BITOP NOT must be called with a single source key.".into(),
+        ));
+    }
+    let mut guard = ctx.db.write().unwrap();
+    let result = {
+        let sources = keys
+            .iter()
+            .map(|key| match guard.get(key) {
+                Some(value) => as_string(value),
+                None => Ok(&[][..]),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        bitmap::bitop(op, &sources)
+    };
+    let len = result.len() as i64;
+    if result.is_empty() {
+        if guard.remove(dest).is_some() {
+            notify(ctx, notify::GENERIC, "del", dest);
+        }
+    } else {
+        guard.insert(dest, MapValue::new(Value::String(result.into())));
+        notify(ctx, notify::STRING, "set", dest);
+    }
+    Ok(Reply::Integer(len))
+}
+
+pub fn setbit(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let offset = parse_bit_offset(ctx, &args[2], None)?;
+    let bit = match args[3].as_slice() {
+        b"0" => false,
+        b"1" => true,
+        _ => {
+            return Err(CommandError::Other(
+                "bit is not an integer or out of range".into(),
+            ))
+        }
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let value = guard.get_or_insert_with(&args[1], || Value::String(Str::from(&[][..])));
+    let Value::String(data) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let old = data.update(|bytes| bitmap::set_bit(bytes, offset, bit));
+    notify(ctx, notify::STRING, "setbit", &args[1]);
+    Ok(Reply::Integer(old as i64))
+}
+
+pub fn getbit(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let offset = parse_bit_offset(ctx, &args[2], None)?;
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    Ok(Reply::Integer(
+        bitmap::get_bit(as_string(value)?, offset) as i64
+    ))
+}
+
+enum FieldOp {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+struct Field {
+    op: FieldOp,
+    ty: FieldType,
+    offset: usize,
+    overflow: Overflow,
+}
+
+// `i1` to `i64` or `u1` to `u63`.
+fn parse_field_type(arg: &[u8]) -> Result<FieldType, CommandError> {
+    let invalid = || {
+        CommandError::Other(
+            "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported \
+             but i64 is."
+                .into(),
+        )
+    };
+    let signed = match arg.first() {
+        Some(b'i' | b'I') => true,
+        Some(b'u' | b'U') => false,
+        _ => return Err(invalid()),
+    };
+    let max_bits = if signed { 64 } else { 63 };
+    match parse_int::<u32>(&arg[1..]) {
+        Ok(bits) if (1..=max_bits).contains(&bits) => Ok(FieldType { signed, bits }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_fields(ctx: &Context, args: &[Vec<u8>]) -> Result<Vec<Field>, CommandError> {
+    let mut fields = vec![];
+    let mut overflow = Overflow::Wrap;
+    let mut idx = 0;
+    while idx < args.len() {
+        let subcommand = args[idx].to_ascii_uppercase();
+        if subcommand == b"OVERFLOW" {
+            let policy = args.get(idx + 1).ok_or(CommandError::Syntax)?;
+            overflow = match policy.to_ascii_uppercase().as_slice() {
+                b"WRAP" => Overflow::Wrap,
+                b"SAT" => Overflow::Sat,
+                b"FAIL" => Overflow::Fail,
+                _ => {
+                    return Err(CommandError::Other(
+                        "Invalid OVERFLOW type specified".into(),
+                    ))
+                }
+            };
+            idx += 2;
+            continue;
+        }
+        let argc = match subcommand.as_slice() {
+            b"GET" => 3,
+            b"SET" | b"INCRBY" => 4,
+            _ => return Err(CommandError::Syntax),
+        };
+        let op_args = args.get(idx..idx + argc).ok_or(CommandError::Syntax)?;
+        let ty = parse_field_type(&op_args[1])?;
+        let offset = parse_bit_offset(ctx, &op_args[2], Some(ty.bits))?;
+        let op = match subcommand.as_slice() {
+            b"GET" => FieldOp::Get,
+            b"SET" => FieldOp::Set(parse_int(&op_args[3])?),
+            _ => FieldOp::IncrBy(parse_int(&op_args[3])?),
+        };
+        fields.push(Field {
+            op,
+            ty,
+            offset,
+            overflow,
+        });
+        idx += argc;
+    }
+    Ok(fields)
+}
+
+// Runs the operations in order; SET replies with the old value, INCRBY with the new one, and
+// both with nil when FAIL refuses the write.
+fn apply_fields(bytes: &mut Vec<u8>, fields: &[Field]) -> Vec<Reply> {
+    fields
+        .iter()
+        .map(|field| {
+            let old = field.ty.get(bytes, field.offset);
+            let new = match field.op {
+                FieldOp::Get => return Reply::Integer(old),
+                // Unsigned fields take the value's bits as they are, as in Redis.
+                FieldOp::Set(value) if field.ty.signed => value as i128,
+                FieldOp::Set(value) => value as u64 as i128,
+                FieldOp::IncrBy(incr) => old as i128 + incr as i128,
+            };
+            let Some(new) = field.ty.fit(new, field.overflow) else {
+                return Reply::Nil;
+            };
+            field.ty.set(bytes, field.offset, new);
+            match field.op {
+                FieldOp::Set(_) => Reply::Integer(old),
+                _ => Reply::Integer(new),
+            }
+        })
+        .collect()
+}
+
+fn bitfield_generic(ctx: &mut Context, args: &[Vec<u8>], read_only: bool) -> CommandResult {
+    let fields = parse_fields(ctx, &args[2..])?;
+    let writes = fields
+        .iter()
+        .filter(|field| !matches!(field.op, FieldOp::Get));
+    if read_only && writes.clone().next().is_some() {
+        return Err(CommandError::Other(
+            "BITFIELD_RO only supports the GET subcommand".into(),
+        ));
+    }
+    // The string is grown to hold every field written to, even ones FAIL ends up refusing.
+    let Some(end) = writes
+        .map(|field| field.offset + field.ty.bits as usize)
+        .max()
+    else {
+        let guard = ctx.db.read().unwrap();
+        let bytes = match guard.get(&args[1]) {
+            Some(value) => as_string(value)?,
+            None => &[],
+        };
+        return Ok(Reply::Array(
+            fields
+                .iter()
+                .map(|field| Reply::Integer(field.ty.get(bytes, field.offset)))
+                .collect(),
+        ));
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let value = guard.get_or_insert_with(&args[1], || Value::String(Str::from(&[][..])));
+    let Value::String(data) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let replies = data.update(|bytes| {
+        if bytes.len() * 8 < end {
+            bytes.resize(end.div_ceil(8), 0);
+        }
+        apply_fields(bytes, &fields)
+    });
+    notify(ctx, notify::STRING, "setbit", &args[1]);
+    Ok(Reply::Array(replies))
+}
+
+pub fn bitfield(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    bitfield_generic(ctx, args, false)
+}
+
+pub fn bitfield_ro(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    bitfield_generic(ctx, args, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cluster_testing::Topology, resp::Reply};
+
+    fn integers(values: &[i64]) -> Reply {
+        Reply::Array(values.iter().map(|n| Reply::Integer(*n)).collect())
+    }
+
+    #[test]
+    fn overflow_applies_to_the_operations_after_it() {
+        let topology = Topology::builder().start().unwrap();
+        let master = topology.master();
+        // The example from the BITFIELD documentation.
+        let incr = [
+            "BITFIELD", "counters", "INCRBY", "u2", "100", "1", "OVERFLOW", "SAT", "INCRBY", "u2",
+            "102", "1",
+        ];
+        for expected in [[1, 1], [2, 2], [3, 3], [0, 3]] {
+            assert_eq!(master.execute(&incr), integers(&expected));
+        }
+        let fail = [
+            "BITFIELD", "counters", "OVERFLOW", "FAIL", "INCRBY", "u2", "102", "1",
+        ];
+        assert_eq!(master.execute(&fail), Reply::Array(vec![Reply::Nil]));
+        let get = [
+            "BITFIELD_RO",
+            "counters",
+            "GET",
+            "u2",
+            "100",
+            "GET",
+            "u2",
+            "102",
+        ];
+        assert_eq!(master.execute(&get), integers(&[0, 3]));
+
+        // SET replies with the old value; WRAP is the default.
+        let set = [
+            "BITFIELD", "signed", "SET", "i8", "#1", "127", "INCRBY", "i8", "#1", "1",
+        ];
+        assert_eq!(master.execute(&set), integers(&[0, -128]));
+        let sat = [
+            "BITFIELD", "signed", "OVERFLOW", "SAT", "INCRBY", "i8", "#1", "-1000",
+        ];
+        assert_eq!(master.execute(&sat), integers(&[-128]));
+        assert_eq!(
+            master.execute(&["BITFIELD", "signed", "OVERFLOW", "BOUNCE", "GET", "i8", "0"]),
+            Reply::Error("ERR Invalid OVERFLOW type specified".into())
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use super::{parse_int, CommandError, CommandResult, Context};
+use crate::resp::{Protocol, Reply};
+
+pub const SERVER_VERSION: &str = "7.2.0";
+
+pub fn ping(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let message = match args {
+        [_] => None,
+        [_, message] => Some(message.as_slice()),
+        _ => return Err(super::CommandError::WrongArity("ping")),
+    };
+    // Subscribers get a message-shaped reply, the only kind they expect.
+    if ctx.session.in_subscriber_mode() {
+        return Ok(Reply::Array(vec![
+            Reply::from(&b"pong"[..]),
+            Reply::from(message.unwrap_or_default()),
+        ]));
+    }
+    Ok(match message {
+        None => Reply::SimpleString("PONG".into()),
+        Some(message) => Reply::from(message),
+    })
+}
+
+pub fn echo(_ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    Ok(Reply::from(args[1].as_slice()))
+}
+
+pub fn select(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let index: i64 = parse_int(&args[1])?;
+    if index < 0 || index as usize >= ctx.databases.len() {
+        return Err(CommandError::DbIndexOutOfRange);
+    }
+    ctx.session.db = index as usize;
+    Ok(Reply::ok())
+}
+
+pub fn client(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    match (args[1].to_ascii_uppercase().as_slice(), args.len()) {
+        (b"ID", 2) => Ok(Reply::Integer(ctx.session.id as i64)),
+        (b"GETNAME", 2) => Ok(Reply::from(ctx.session.name.clone())),
+        (b"SETNAME", 3) => {
+            let name = &args[2];
+            if name.iter().any(|b| *b <= b' ' || *b > b'~') {
+                return Err(CommandError::Other(
+                    "Client names cannot contain spaces, newlines or special characters.".into(),
+                ));
+            }
+            ctx.session.name = (!name.is_empty()).then(|| name.clone());
+            Ok(Reply::ok())
+        }
+        (b"INFO", 2) => {
+            ctx.session.refresh_info(b"client|info");
+            let line = ctx.session.info.lock().unwrap().render();
+            Ok(Reply::Verbatim("txt", format!("{line}\n").into_bytes()))
+        }
+        (b"LIST", _) => {
+            let mut ids = None;
+            let mut opts = args[2..].iter();
+            while let Some(opt) = opts.next() {
+                match opt.to_ascii_uppercase().as_slice() {
+                    b"ID" => {
+                        let rest: Vec<u64> = opts
+                            .by_ref()
+                            .map(|id| parse_int(id))
+                            .collect::<Result<_, _>>()?;
+                        ids = Some(rest);
+                    }
+                    _ => return Err(CommandError::Syntax),
+                }
+            }
+            ctx.session.refresh_info(b"client|list");
+            let clients: Vec<_> = ctx
+                .server
+                .clients
+                .lock()
+                .unwrap()
+                .values()
+                .cloned()
+                .collect();
+            let mut out = String::new();
+            for client in clients {
+                let info = client.lock().unwrap();
+                if ids.as_ref().is_some_and(|ids| !ids.contains(&info.id)) {
+                    continue;
+                }
+                out.push_str(&info.render());
+                out.push('\n');
+            }
+            Ok(Reply::Verbatim("txt", out.into_bytes()))
+        }
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "CLIENT",
+        )),
+    }
+}
+
+pub fn auth(_ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    match args.len() {
+        2 => Err(CommandError::Other(
+            "AUTH <password> called without any password configured for the default user. \
+             Are you sure your configuration is correct?"
+                .into(),
+        )),
+        3 if args[1].as_slice() == b"default" => Ok(Reply::ok()),
+        3 => Err(CommandError::WrongPass),
+        _ => Err(CommandError::Syntax),
+    }
+}
+
+pub fn hello(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut protocol = ctx.session.protocol;
+    let mut opts = args[1..].iter();
+    if let Some(version) = opts.next() {
+        protocol = match parse_int::<i64>(version) {
+            Ok(2) => Protocol::Resp2,
+            Ok(3) => Protocol::Resp3,
+            Ok(_) => return Err(CommandError::NoProto),
+            Err(_) => {
+                return Err(CommandError::Other(
+                    "Protocol version is not an integer or out of range".into(),
+                ))
+            }
+        };
+    }
+    let mut name = None;
+    while let Some(opt) = opts.next() {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"AUTH" => {
+                let (Some(username), Some(_password)) = (opts.next(), opts.next()) else {
+                    return Err(CommandError::Syntax);
+                };
+                // There are no ACL users besides the passwordless default one.
+                if username.as_slice() != b"default" {
+                    return Err(CommandError::WrongPass);
+                }
+            }
+            b"SETNAME" => name = Some(opts.next().ok_or(CommandError::Syntax)?.clone()),
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    ctx.session.protocol = protocol;
+    if name.is_some() {
+        ctx.session.name = name;
+    }
+    let field = |s: &str| Reply::BulkString(s.as_bytes().to_vec());
+    Ok(Reply::Map(vec![
+        (field("server"), field("redis")),
+        (field("version"), field(SERVER_VERSION)),
+        (
+            field("proto"),
+            Reply::Integer(match protocol {
+                Protocol::Resp2 => 2,
+                Protocol::Resp3 => 3,
+            }),
+        ),
+        (field("id"), Reply::Integer(ctx.session.id as i64)),
+        (field("mode"), field("standalone")),
+        (field("role"), field("master")),
+        (field("modules"), Reply::Array(vec![])),
+    ]))
+}
+
+// Puts the connection back in the state of a fresh one, dropping its subscriptions.
+pub fn quit(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    ctx.session.close_after_reply = true;
+    Ok(Reply::ok())
+}
+
+pub fn reset(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    let session = &mut *ctx.session;
+    {
+        let mut registry = ctx.server.pubsub.lock().unwrap();
+        for channel in session.subscriptions.drain() {
+            registry.unsubscribe(&channel, session.id);
+        }
+    }
+    session.db = 0;
+    session.protocol = Protocol::Resp2;
+    session.name = None;
+    session.transaction = None;
+    session.unwatch_all(ctx.databases);
+    Ok(Reply::SimpleString("RESET".into()))
+}
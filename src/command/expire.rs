@@ -0,0 +1,146 @@
+use super::{notify, parse_int, propagate_deadline, CommandError, CommandResult, Context};
+use crate::{db::now_millis, resp::Reply};
+
+#[derive(Default)]
+pub struct Condition {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl Condition {
+    pub fn parse(args: &[Vec<u8>]) -> Result<Self, CommandError> {
+        let mut condition = Self::default();
+        for arg in args {
+            match arg.to_ascii_uppercase().as_slice() {
+                b"NX" => condition.nx = true,
+                b"XX" => condition.xx = true,
+                b"GT" => condition.gt = true,
+                b"LT" => condition.lt = true,
+                _ => {
+                    return Err(CommandError::Other(format!(
+                        "Unsupported option {}",
+                        String::from_utf8_lossy(arg)
+                    )))
+                }
+            }
+        }
+        if condition.nx && (condition.xx || condition.gt || condition.lt) {
+            return Err(CommandError::Other(
+                "NX and XX, GT or LT options at the same time are not compatible".into(),
+            ));
+        }
+        if condition.gt && condition.lt {
+            return Err(CommandError::Other(
+                "GT and LT options at the same time are not compatible".into(),
+            ));
+        }
+        Ok(condition)
+    }
+    // A key without a deadline counts as expiring infinitely late for GT and LT.
+    pub fn allows(&self, current: Option<i64>, new: i64) -> bool {
+        match current {
+            None => !(self.xx || self.gt),
+            Some(current) => !(self.nx || self.gt && new <= current || self.lt && new >= current),
+        }
+    }
+}
+
+// `unit_millis` scales the argument; `relative` says whether it is an offset from now or an
+// absolute Unix time.
+fn expire_generic(
+    ctx: &mut Context,
+    args: &[Vec<u8>],
+    unit_millis: i64,
+    relative: bool,
+) -> CommandResult {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let invalid = || CommandError::Other(format!("invalid expire time in '{name}' command"));
+    let amount: i64 = parse_int(&args[2])?;
+    let mut deadline = amount.checked_mul(unit_millis).ok_or_else(invalid)?;
+    if relative {
+        deadline = deadline.checked_add(now_millis()).ok_or_else(invalid)?;
+    }
+    let condition = Condition::parse(&args[3..])?;
+    let mut guard = ctx.db.write().unwrap();
+    if guard.get(&args[1]).is_none() {
+        return Ok(Reply::Integer(0));
+    }
+    if !condition.allows(guard.expiry(&args[1]), deadline) {
+        return Ok(Reply::Integer(0));
+    }
+    if deadline <= now_millis() {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    } else {
+        guard.set_expiry(&args[1], Some(deadline));
+        notify(ctx, notify::GENERIC, "expire", &args[1]);
+    }
+    propagate_deadline(ctx, args, deadline);
+    Ok(Reply::Integer(1))
+}
+
+pub fn expire(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    expire_generic(ctx, args, 1000, true)
+}
+
+pub fn pexpire(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    expire_generic(ctx, args, 1, true)
+}
+
+pub fn expireat(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    expire_generic(ctx, args, 1000, false)
+}
+
+pub fn pexpireat(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    expire_generic(ctx, args, 1, false)
+}
+
+pub fn persist(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    if guard.get(&args[1]).is_none() || guard.expiry(&args[1]).is_none() {
+        return Ok(Reply::Integer(0));
+    }
+    guard.set_expiry(&args[1], None);
+    notify(ctx, notify::GENERIC, "persist", &args[1]);
+    Ok(Reply::Integer(1))
+}
+
+// Absolute deadline in milliseconds, -1 without a deadline and -2 for a missing key.
+fn deadline_millis(ctx: &Context, key: &[u8]) -> i64 {
+    let guard = ctx.db.read().unwrap();
+    if guard.peek(key).is_none() {
+        return -2;
+    }
+    guard.expiry(key).unwrap_or(-1)
+}
+
+fn remaining_millis(ctx: &Context, key: &[u8]) -> i64 {
+    match deadline_millis(ctx, key) {
+        missing if missing < 0 => missing,
+        deadline => (deadline - now_millis()).max(0),
+    }
+}
+
+pub fn ttl(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    Ok(Reply::Integer(match remaining_millis(ctx, &args[1]) {
+        millis if millis < 0 => millis,
+        millis => (millis + 500) / 1000,
+    }))
+}
+
+pub fn pttl(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    Ok(Reply::Integer(remaining_millis(ctx, &args[1])))
+}
+
+pub fn expiretime(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    Ok(Reply::Integer(match deadline_millis(ctx, &args[1]) {
+        missing if missing < 0 => missing,
+        deadline => deadline / 1000,
+    }))
+}
+
+pub fn pexpiretime(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    Ok(Reply::Integer(deadline_millis(ctx, &args[1])))
+}
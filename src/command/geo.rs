@@ -0,0 +1,361 @@
+use std::cmp::Ordering;
+
+use super::{
+    notify, parse_float, parse_int,
+    zset::{as_zset, zset_or_create},
+    CommandError, CommandResult, Context,
+};
+use crate::{
+    db::{MapValue, Value},
+    resp::Reply,
+    types::{
+        geo::{self, Shape},
+        zset::{AddFlags, AddOutcome, SortedSet},
+    },
+};
+
+fn parse_unit(arg: &[u8]) -> Result<f64, CommandError> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => Err(CommandError::Other(
+            "unsupported unit provided. please use M, KM, FT, MI".into(),
+        )),
+    }
+}
+
+fn parse_lonlat(lon: &[u8], lat: &[u8]) -> Result<(f64, f64), CommandError> {
+    let (lon, lat) = (parse_float(lon)?, parse_float(lat)?);
+    if !geo::is_valid(lon, lat) {
+        return Err(CommandError::Other(format!(
+            "invalid longitude,latitude pair {lon:.6},{lat:.6}"
+        )));
+    }
+    Ok((lon, lat))
+}
+
+// Same as Redis' LD_STR_HUMAN: 17 decimals with trailing zeros trimmed.
+fn format_coordinate(value: f64) -> Vec<u8> {
+    let formatted = format!("{value:.17}");
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .as_bytes()
+        .to_vec()
+}
+
+pub fn geoadd(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut flags = AddFlags::default();
+    let mut ch = false;
+    let mut idx = 2;
+    while let Some(opt) = args.get(idx) {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"NX" => flags.nx = true,
+            b"XX" => flags.xx = true,
+            b"CH" => ch = true,
+            _ => break,
+        }
+        idx += 1;
+    }
+    let triples = &args[idx..];
+    if triples.is_empty() || !triples.len().is_multiple_of(3) {
+        return Err(CommandError::Syntax);
+    }
+    if flags.nx && flags.xx {
+        return Err(CommandError::Other(
+            "XX and NX options at the same time are not compatible".into(),
+        ));
+    }
+    let elements = triples
+        .chunks(3)
+        .map(|triple| {
+            parse_lonlat(&triple[0], &triple[1])
+                .map(|(lon, lat)| (geo::encode(lon, lat) as f64, &triple[2]))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut guard = ctx.db.write().unwrap();
+    match guard.get(&args[1]) {
+        Some(value) => {
+            as_zset(value)?;
+        }
+        None if flags.xx => return Ok(Reply::Integer(0)),
+        None => {}
+    }
+    let zset = zset_or_create(&mut guard, &args[1])?;
+    let mut changed = 0;
+    for (score, member) in elements {
+        match zset.add(member, score, flags) {
+            AddOutcome::Added(_) => changed += 1,
+            AddOutcome::Updated(_) if ch => changed += 1,
+            _ => {}
+        }
+    }
+    notify(ctx, notify::ZSET, "zadd", &args[1]);
+    Ok(Reply::Integer(changed))
+}
+
+pub fn geopos(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let zset = guard.get(&args[1]).map(as_zset).transpose()?;
+    Ok(Reply::Array(
+        args[2..]
+            .iter()
+            .map(|member| match zset.and_then(|zset| zset.score(member)) {
+                Some(score) => {
+                    let (lon, lat) = geo::decode(score as u64);
+                    Reply::Array(vec![
+                        Reply::BulkString(format_coordinate(lon)),
+                        Reply::BulkString(format_coordinate(lat)),
+                    ])
+                }
+                None => Reply::NilArray,
+            })
+            .collect(),
+    ))
+}
+
+pub fn geodist(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let unit = match &args[4..] {
+        [] => 1.0,
+        [unit] => parse_unit(unit)?,
+        _ => return Err(CommandError::Syntax),
+    };
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Nil);
+    };
+    let zset = as_zset(value)?;
+    let (Some(from), Some(to)) = (zset.score(&args[2]), zset.score(&args[3])) else {
+        return Ok(Reply::Nil);
+    };
+    let distance = geo::distance(geo::decode(from as u64), geo::decode(to as u64)) / unit;
+    Ok(Reply::BulkString(format!("{distance:.4}").into_bytes()))
+}
+
+enum Center {
+    Member(Vec<u8>),
+    LonLat(f64, f64),
+}
+
+struct Search {
+    center: Center,
+    shape: Shape,
+    unit: f64,
+    order: Option<Ordering>,
+    count: Option<usize>,
+    any: bool,
+    withcoord: bool,
+    withdist: bool,
+    withhash: bool,
+    storedist: bool,
+}
+
+struct Match<'a> {
+    member: &'a [u8],
+    distance: f64,
+    hash: u64,
+}
+
+fn next<'a>(opts: &mut std::slice::Iter<'a, Vec<u8>>) -> Result<&'a Vec<u8>, CommandError> {
+    opts.next().ok_or(CommandError::Syntax)
+}
+
+fn parse_search(args: &[Vec<u8>], store: bool) -> Result<Search, CommandError> {
+    let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+    let mut center = None;
+    let mut shape = None;
+    let mut search = Search {
+        center: Center::LonLat(0.0, 0.0),
+        shape: Shape::Radius(0.0),
+        unit: 1.0,
+        order: None,
+        count: None,
+        any: false,
+        withcoord: false,
+        withdist: false,
+        withhash: false,
+        storedist: false,
+    };
+    let exactly_one_center = || {
+        CommandError::Other(format!(
+            "exactly one of FROMMEMBER or FROMLONLAT can be specified for {name}"
+        ))
+    };
+    let exactly_one_shape = || {
+        CommandError::Other(format!(
+            "exactly one of BYRADIUS and BYBOX can be specified for {name}"
+        ))
+    };
+    let start = if store { 3 } else { 2 };
+    let mut opts = args[start..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"FROMMEMBER" => {
+                if center.is_some() {
+                    return Err(exactly_one_center());
+                }
+                center = Some(Center::Member(next(&mut opts)?.clone()));
+            }
+            b"FROMLONLAT" => {
+                if center.is_some() {
+                    return Err(exactly_one_center());
+                }
+                let (lon, lat) = (next(&mut opts)?, next(&mut opts)?);
+                let (lon, lat) = parse_lonlat(lon, lat)?;
+                center = Some(Center::LonLat(lon, lat));
+            }
+            b"BYRADIUS" => {
+                if shape.is_some() {
+                    return Err(exactly_one_shape());
+                }
+                let radius = parse_float(next(&mut opts)?)?;
+                search.unit = parse_unit(next(&mut opts)?)?;
+                if radius < 0.0 {
+                    return Err(CommandError::Other("radius cannot be negative".into()));
+                }
+                shape = Some(Shape::Radius(radius * search.unit));
+            }
+            b"BYBOX" => {
+                if shape.is_some() {
+                    return Err(exactly_one_shape());
+                }
+                let width = parse_float(next(&mut opts)?)?;
+                let height = parse_float(next(&mut opts)?)?;
+                search.unit = parse_unit(next(&mut opts)?)?;
+                if width < 0.0 || height < 0.0 {
+                    return Err(CommandError::Other(
+                        "height or width cannot be negative".into(),
+                    ));
+                }
+                shape = Some(Shape::Box {
+                    width: width * search.unit,
+                    height: height * search.unit,
+                });
+            }
+            b"ASC" => search.order = Some(Ordering::Less),
+            b"DESC" => search.order = Some(Ordering::Greater),
+            b"COUNT" => {
+                let count: i64 = parse_int(next(&mut opts)?)?;
+                if count <= 0 {
+                    return Err(CommandError::Other("COUNT must be > 0".into()));
+                }
+                search.count = Some(count as usize);
+            }
+            b"ANY" => search.any = true,
+            b"WITHCOORD" if !store => search.withcoord = true,
+            b"WITHDIST" if !store => search.withdist = true,
+            b"WITHHASH" if !store => search.withhash = true,
+            b"STOREDIST" if store => search.storedist = true,
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    search.center = center.ok_or_else(exactly_one_center)?;
+    search.shape = shape.ok_or_else(exactly_one_shape)?;
+    if search.any && search.count.is_none() {
+        return Err(CommandError::Other(
+            "the ANY argument requires COUNT argument".into(),
+        ));
+    }
+    Ok(search)
+}
+
+fn run_search<'a>(search: &Search, zset: &'a SortedSet) -> Result<Vec<Match<'a>>, CommandError> {
+    let center = match &search.center {
+        Center::LonLat(lon, lat) => (*lon, *lat),
+        Center::Member(member) => zset
+            .score(member)
+            .map(|score| geo::decode(score as u64))
+            .ok_or_else(|| CommandError::Other("could not decode requested zset member".into()))?,
+    };
+    let mut matches: Vec<_> = zset
+        .entries()
+        .into_iter()
+        .filter_map(|(member, score)| {
+            let hash = score as u64;
+            geo::within(search.shape, center, geo::decode(hash)).map(|distance| Match {
+                member,
+                distance,
+                hash,
+            })
+        })
+        .collect();
+    // ANY keeps whatever was found first; otherwise COUNT implies the closest results.
+    if search.any {
+        matches.truncate(search.count.unwrap_or(usize::MAX));
+    }
+    let order = search
+        .order
+        .or((search.count.is_some() && !search.any).then_some(Ordering::Less));
+    match order {
+        Some(Ordering::Greater) => matches.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+        Some(_) => matches.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        None => {}
+    }
+    matches.truncate(search.count.unwrap_or(usize::MAX));
+    Ok(matches)
+}
+
+pub fn geosearch(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let search = parse_search(args, false)?;
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Array(vec![]));
+    };
+    let matches = run_search(&search, as_zset(value)?)?;
+    let with_any = search.withcoord || search.withdist || search.withhash;
+    Ok(Reply::Array(
+        matches
+            .into_iter()
+            .map(|found| {
+                if !with_any {
+                    return Reply::from(found.member);
+                }
+                let mut item = vec![Reply::from(found.member)];
+                if search.withdist {
+                    let distance = format!("{:.4}", found.distance / search.unit);
+                    item.push(Reply::BulkString(distance.into_bytes()));
+                }
+                if search.withhash {
+                    item.push(Reply::Integer(found.hash as i64));
+                }
+                if search.withcoord {
+                    let (lon, lat) = geo::decode(found.hash);
+                    item.push(Reply::Array(vec![
+                        Reply::BulkString(format_coordinate(lon)),
+                        Reply::BulkString(format_coordinate(lat)),
+                    ]));
+                }
+                Reply::Array(item)
+            })
+            .collect(),
+    ))
+}
+
+pub fn geosearchstore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let search = parse_search(args, true)?;
+    let (dest, source) = (&args[1], &args[2]);
+    let mut guard = ctx.db.write().unwrap();
+    let mut stored = SortedSet::new();
+    if let Some(value) = guard.get(source) {
+        for found in run_search(&search, as_zset(value)?)? {
+            let score = if search.storedist {
+                found.distance / search.unit
+            } else {
+                found.hash as f64
+            };
+            stored.add(found.member, score, AddFlags::default());
+        }
+    }
+    let len = stored.len();
+    if len == 0 {
+        if guard.remove(dest).is_some() {
+            notify(ctx, notify::GENERIC, "del", dest);
+        }
+    } else {
+        guard.insert(dest, MapValue::new(Value::SortedSet(stored)));
+        notify(ctx, notify::ZSET, "geosearchstore", dest);
+    }
+    Ok(Reply::Integer(len as i64))
+}
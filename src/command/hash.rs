@@ -0,0 +1,408 @@
+use super::{
+    expire::Condition,
+    keyspace::{scan_reply, ScanArgs},
+    notify, parse_float, parse_int, propagate, propagate_deadline, CommandError, CommandResult,
+    Context,
+};
+use crate::{
+    db::{self, now_millis, DataMap, MapValue, Value},
+    resp::Reply,
+    types::hash::Hash,
+};
+
+fn as_hash(value: &MapValue) -> Result<&Hash, CommandError> {
+    match &value.data {
+        Value::Hash(hash) => Ok(hash),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+fn hash_or_create<'a>(map: &'a mut DataMap, key: &[u8]) -> Result<&'a mut Hash, CommandError> {
+    match &mut map
+        .get_or_insert_with(key, || Value::Hash(Hash::new()))
+        .data
+    {
+        Value::Hash(hash) => Ok(hash),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+pub fn hset(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    if !args.len().is_multiple_of(2) {
+        return Err(CommandError::WrongArity("hset"));
+    }
+    let mut guard = ctx.db.write().unwrap();
+    let hash = hash_or_create(&mut guard, &args[1])?;
+    let added = args[2..]
+        .chunks_exact(2)
+        .filter(|pair| hash.insert(&pair[0], &pair[1]))
+        .count();
+    notify(ctx, notify::HASH, "hset", &args[1]);
+    Ok(Reply::Integer(added as i64))
+}
+
+pub fn hget(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Nil);
+    };
+    Ok(Reply::from(as_hash(value)?.get(&args[2]).cloned()))
+}
+
+pub fn hdel(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let Value::Hash(hash) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let removed = args[2..].iter().filter(|field| hash.remove(field)).count();
+    let emptied = hash.is_empty();
+    if removed > 0 {
+        notify(ctx, notify::HASH, "hdel", &args[1]);
+    }
+    if emptied {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    }
+    Ok(Reply::Integer(removed as i64))
+}
+
+// A map in RESP3; RESP2 clients see the same pairs flattened into an array.
+pub fn hgetall(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Map(vec![]));
+    };
+    Ok(Reply::Map(
+        as_hash(value)?
+            .iter()
+            .map(|(field, value)| (Reply::from(field.as_slice()), Reply::from(value.as_slice())))
+            .collect(),
+    ))
+}
+
+pub fn hmget(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let hash = guard.get(&args[1]).map(as_hash).transpose()?;
+    Ok(Reply::Array(
+        args[2..]
+            .iter()
+            .map(|field| Reply::from(hash.and_then(|hash| hash.get(field)).cloned()))
+            .collect(),
+    ))
+}
+
+fn list_generic(ctx: &mut Context, args: &[Vec<u8>], keys: bool) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Array(vec![]));
+    };
+    Ok(Reply::Array(
+        as_hash(value)?
+            .iter()
+            .map(|(field, value)| Reply::from(if keys { field } else { value }.as_slice()))
+            .collect(),
+    ))
+}
+
+pub fn hkeys(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    list_generic(ctx, args, true)
+}
+
+pub fn hvals(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    list_generic(ctx, args, false)
+}
+
+pub fn hlen(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let len = match guard.get(&args[1]) {
+        Some(value) => as_hash(value)?.len(),
+        None => 0,
+    };
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn hexists(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let exists = match guard.get(&args[1]) {
+        Some(value) => as_hash(value)?.get(&args[2]).is_some(),
+        None => false,
+    };
+    Ok(Reply::Integer(exists as i64))
+}
+
+pub fn hsetnx(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    if let Some(value) = guard.get(&args[1]) {
+        if as_hash(value)?.get(&args[2]).is_some() {
+            return Ok(Reply::Integer(0));
+        }
+    }
+    hash_or_create(&mut guard, &args[1])?.insert(&args[2], &args[3]);
+    notify(ctx, notify::HASH, "hset", &args[1]);
+    Ok(Reply::Integer(1))
+}
+
+pub fn hstrlen(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let len = match guard.get(&args[1]) {
+        Some(value) => as_hash(value)?.get(&args[2]).map_or(0, |value| value.len()),
+        None => 0,
+    };
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn hincrby(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let increment: i64 = parse_int(&args[3])?;
+    let mut guard = ctx.db.write().unwrap();
+    let hash = hash_or_create(&mut guard, &args[1])?;
+    let current: i64 = match hash.get(&args[2]) {
+        Some(value) => parse_int(value)
+            .map_err(|_| CommandError::Other("hash value is not an integer".into()))?,
+        None => 0,
+    };
+    let updated = current
+        .checked_add(increment)
+        .ok_or_else(|| CommandError::Other("increment or decrement would overflow".into()))?;
+    hash.insert(&args[2], updated.to_string().as_bytes());
+    notify(ctx, notify::HASH, "hincrby", &args[1]);
+    Ok(Reply::Integer(updated))
+}
+
+// Journaled as an HSET of the result, so a replay cannot round differently.
+pub fn hincrbyfloat(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let increment = parse_float(&args[3])?;
+    if increment.is_infinite() {
+        return Err(CommandError::Other("value is NaN or Infinity".into()));
+    }
+    let mut guard = ctx.db.write().unwrap();
+    let hash = hash_or_create(&mut guard, &args[1])?;
+    let current = match hash.get(&args[2]) {
+        Some(value) => parse_float(value)
+            .map_err(|_| CommandError::Other("hash value is not a float".into()))?,
+        None => 0.0,
+    };
+    let updated = current + increment;
+    if !updated.is_finite() {
+        return Err(CommandError::Other(
+            "increment would produce NaN or Infinity".into(),
+        ));
+    }
+    // Plain decimal without exponent, like Redis' %.17Lf with trailing zeros trimmed.
+    let formatted = format!("{updated}").into_bytes();
+    hash.insert(&args[2], &formatted);
+    notify(ctx, notify::HASH, "hincrbyfloat", &args[1]);
+    propagate(
+        ctx,
+        &[
+            b"HSET".to_vec(),
+            args[1].clone(),
+            args[2].clone(),
+            formatted.clone(),
+        ],
+    );
+    Ok(Reply::from(formatted))
+}
+
+pub fn hscan(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let scan = ScanArgs::parse(&args[2..])?;
+    if scan.type_filter.is_some() {
+        return Err(CommandError::Syntax);
+    }
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(scan_reply(0, vec![]));
+    };
+    let hash = as_hash(value)?;
+    let fields = hash
+        .iter()
+        .map(|(field, value)| (field.as_slice(), (field, value)));
+    let (next, batch) = db::scan_unindexed(fields, scan.cursor, scan.count);
+    let mut items = vec![];
+    for (field, value) in batch.into_iter().filter(|(field, _)| scan.matches(field)) {
+        items.push(Reply::from(field.as_slice()));
+        if !scan.novalues {
+            items.push(Reply::from(value.as_slice()));
+        }
+    }
+    Ok(scan_reply(next, items))
+}
+
+// The `FIELDS numfields field [field ...]` tail of the field expiration commands.
+fn parse_fields(args: &[Vec<u8>]) -> Result<&[Vec<u8>], CommandError> {
+    let [keyword, numfields, fields @ ..] = args else {
+        return Err(CommandError::Other(
+            "Mandatory argument FIELDS is missing or not at the right position".into(),
+        ));
+    };
+    if !keyword.eq_ignore_ascii_case(b"FIELDS") {
+        return Err(CommandError::Other(
+            "Mandatory argument FIELDS is missing or not at the right position".into(),
+        ));
+    }
+    let numfields: i64 = parse_int(numfields)?;
+    if numfields <= 0 {
+        return Err(CommandError::Other(
+            "Parameter `numFields` should be greater than 0".into(),
+        ));
+    }
+    if fields.len() as i64 != numfields {
+        return Err(CommandError::Other(
+            "The `numfields` parameter must match the number of arguments".into(),
+        ));
+    }
+    Ok(fields)
+}
+
+// Replies per field: -2 without such a field, 0 when the condition was not met, 1 when the
+// deadline was set and 2 when the field was deleted because the deadline already passed.
+// `unit_millis` and `relative` are as for EXPIRE.
+fn hexpire_generic(
+    ctx: &mut Context,
+    args: &[Vec<u8>],
+    unit_millis: i64,
+    relative: bool,
+) -> CommandResult {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let invalid = || CommandError::Other(format!("invalid expire time in '{name}' command"));
+    let amount: i64 = parse_int(&args[2])?;
+    if amount < 0 {
+        return Err(invalid());
+    }
+    let mut deadline = amount.checked_mul(unit_millis).ok_or_else(invalid)?;
+    if relative {
+        deadline = deadline.checked_add(now_millis()).ok_or_else(invalid)?;
+    }
+    let (condition, fields) = if args[3].eq_ignore_ascii_case(b"FIELDS") {
+        (Condition::default(), parse_fields(&args[3..])?)
+    } else {
+        (Condition::parse(&args[3..4])?, parse_fields(&args[4..])?)
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Array(
+            fields.iter().map(|_| Reply::Integer(-2)).collect(),
+        ));
+    };
+    let Value::Hash(hash) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let mut replies = vec![];
+    let (mut set, mut deleted) = (false, false);
+    for field in fields {
+        replies.push(Reply::Integer(if hash.get(field).is_none() {
+            -2
+        } else if !condition.allows(hash.expiry(field), deadline) {
+            0
+        } else if deadline <= now_millis() {
+            hash.remove(field);
+            deleted = true;
+            2
+        } else {
+            hash.set_expiry(field, Some(deadline));
+            set = true;
+            1
+        }));
+    }
+    let (empty, volatile) = (hash.is_empty(), hash.has_volatile_fields());
+    if set {
+        notify(ctx, notify::HASH, "hexpire", &args[1]);
+    }
+    if deleted {
+        notify(ctx, notify::HASH, "hexpired", &args[1]);
+    }
+    if empty {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    } else if volatile {
+        guard.track_volatile_hash(&args[1]);
+    }
+    if set || deleted {
+        propagate_deadline(ctx, args, deadline);
+    }
+    Ok(Reply::Array(replies))
+}
+
+pub fn hexpire(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    hexpire_generic(ctx, args, 1000, true)
+}
+
+pub fn hpexpire(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    hexpire_generic(ctx, args, 1, true)
+}
+
+pub fn hexpireat(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    hexpire_generic(ctx, args, 1000, false)
+}
+
+pub fn hpexpireat(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    hexpire_generic(ctx, args, 1, false)
+}
+
+// Remaining time to live per field, -1 for fields without a deadline and -2 for missing ones.
+fn httl_generic(ctx: &mut Context, args: &[Vec<u8>], unit_millis: i64) -> CommandResult {
+    let fields = parse_fields(&args[2..])?;
+    let guard = ctx.db.read().unwrap();
+    let hash = guard.get(&args[1]).map(as_hash).transpose()?;
+    Ok(Reply::Array(
+        fields
+            .iter()
+            .map(|field| {
+                let Some(hash) = hash.filter(|hash| hash.get(field).is_some()) else {
+                    return Reply::Integer(-2);
+                };
+                Reply::Integer(match hash.expiry(field) {
+                    None => -1,
+                    Some(deadline) => {
+                        let millis = (deadline - now_millis()).max(0);
+                        (millis + unit_millis / 2) / unit_millis
+                    }
+                })
+            })
+            .collect(),
+    ))
+}
+
+pub fn httl(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    httl_generic(ctx, args, 1000)
+}
+
+pub fn hpttl(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    httl_generic(ctx, args, 1)
+}
+
+// Per field: 1 when a deadline was removed, -1 without a deadline and -2 for missing fields.
+pub fn hpersist(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let fields = parse_fields(&args[2..])?;
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Array(
+            fields.iter().map(|_| Reply::Integer(-2)).collect(),
+        ));
+    };
+    let Value::Hash(hash) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let replies: Vec<Reply> = fields
+        .iter()
+        .map(|field| {
+            Reply::Integer(match hash.get(field) {
+                None => -2,
+                Some(_) if hash.expiry(field).is_none() => -1,
+                Some(_) => {
+                    hash.set_expiry(field, None);
+                    1
+                }
+            })
+        })
+        .collect();
+    if replies
+        .iter()
+        .any(|reply| matches!(reply, Reply::Integer(1)))
+    {
+        notify(ctx, notify::HASH, "hpersist", &args[1]);
+    }
+    Ok(Reply::Array(replies))
+}
@@ -0,0 +1,136 @@
+use super::{notify, string::as_string, CommandError, CommandResult, Context};
+use crate::{
+    db::{DataMap, MapValue, Value},
+    resp::Reply,
+    types::hll::{Encoding, HyperLogLog, SparseOp, SPARSE_MAX_BYTES},
+};
+
+fn load(map: &DataMap, key: &[u8]) -> Result<Option<HyperLogLog>, CommandError> {
+    match map.get(key) {
+        Some(value) => HyperLogLog::from_bytes(as_string(value)?)
+            .map(Some)
+            .ok_or(CommandError::InvalidHll),
+        None => Ok(None),
+    }
+}
+
+// Writes the HLL back in place so an existing expiration is kept.
+fn store(map: &mut DataMap, key: &[u8], hll: &HyperLogLog) {
+    let bytes = hll.to_bytes();
+    match map.get_mut(key) {
+        Some(value) => value.data = Value::String(bytes.into()),
+        None => {
+            map.insert(key, MapValue::new(Value::String(bytes.into())));
+        }
+    }
+}
+
+pub fn pfadd(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    let (mut hll, mut updated) = match load(&guard, &args[1])? {
+        Some(hll) => (hll, false),
+        None => (HyperLogLog::new(), true),
+    };
+    for element in &args[2..] {
+        updated |= hll.add(element, SPARSE_MAX_BYTES);
+    }
+    if updated {
+        store(&mut guard, &args[1], &hll);
+        notify(ctx, notify::STRING, "pfadd", &args[1]);
+    }
+    Ok(Reply::Integer(updated as i64))
+}
+
+pub fn pfcount(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    if args.len() > 2 {
+        // The union is estimated from merged registers and never cached anywhere.
+        let guard = ctx.db.read().unwrap();
+        let mut union = HyperLogLog::new();
+        for key in &args[1..] {
+            if let Some(hll) = load(&guard, key)? {
+                union.merge(&hll);
+            }
+        }
+        return Ok(Reply::Integer(union.count() as i64));
+    }
+    // Takes the write lock: a stale cached cardinality is refreshed in the stored header.
+    let mut guard = ctx.db.write().unwrap();
+    let Some(mut hll) = load(&guard, &args[1])? else {
+        return Ok(Reply::Integer(0));
+    };
+    let cached = hll.has_cached_cardinality();
+    let card = hll.count();
+    if !cached {
+        store(&mut guard, &args[1], &hll);
+    }
+    Ok(Reply::Integer(card as i64))
+}
+
+// The destination is one of the merged HLLs if it exists; the result is always dense.
+pub fn pfmerge(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    let mut merged = load(&guard, &args[1])?.unwrap_or_default();
+    for key in &args[2..] {
+        if let Some(hll) = load(&guard, key)? {
+            merged.merge(&hll);
+        }
+    }
+    merged.promote_to_dense();
+    store(&mut guard, &args[1], &merged);
+    notify(ctx, notify::STRING, "pfadd", &args[1]);
+    Ok(Reply::ok())
+}
+
+pub fn pfdebug(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let subcommand = args[1].to_ascii_uppercase();
+    let mut guard = ctx.db.write().unwrap();
+    let mut hll = load(&guard, &args[2])?
+        .ok_or_else(|| CommandError::Other("The specified key does not exist".into()))?;
+    match subcommand.as_slice() {
+        b"GETREG" => {
+            // Like Redis, reading the registers promotes a sparse HLL.
+            if hll.promote_to_dense() {
+                store(&mut guard, &args[2], &hll);
+            }
+            Ok(Reply::Array(
+                hll.registers()
+                    .iter()
+                    .map(|register| Reply::Integer(*register as i64))
+                    .collect(),
+            ))
+        }
+        b"DECODE" => {
+            let ops = hll
+                .sparse_ops()
+                .ok_or_else(|| CommandError::Other("HLL encoding is not sparse".into()))?;
+            let decoded = ops
+                .iter()
+                .map(|op| match op {
+                    SparseOp::Zero(len) => format!("z:{len}"),
+                    SparseOp::XZero(len) => format!("Z:{len}"),
+                    SparseOp::Val(value, len) => format!("v:{value},{len}"),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            Ok(Reply::SimpleString(decoded))
+        }
+        b"ENCODING" => Ok(Reply::SimpleString(
+            match hll.encoding {
+                Encoding::Dense => "dense",
+                Encoding::Sparse => "sparse",
+            }
+            .into(),
+        )),
+        b"TODENSE" => {
+            let converted = hll.promote_to_dense();
+            if converted {
+                store(&mut guard, &args[2], &hll);
+            }
+            Ok(Reply::Integer(converted as i64))
+        }
+        _ => Err(CommandError::Other(format!(
+            "Unknown PFDEBUG subcommand '{}'",
+            String::from_utf8_lossy(&args[1])
+        ))),
+    }
+}
@@ -0,0 +1,360 @@
+use std::time::Duration;
+
+use super::{
+    notify, parse_int, propagate, propagate_deadline, CommandError, CommandResult, Context,
+};
+use crate::{
+    db::{self, now_millis, MapValue},
+    glob, rdb,
+    resp::Reply,
+};
+
+const TYPE_NAMES: &[&str] = &["string", "list", "set", "zset", "hash", "stream"];
+
+// The `cursor [MATCH pattern] [COUNT count] ...` tail shared by SCAN, HSCAN, SSCAN and ZSCAN.
+// Options a command does not take are left for it to reject.
+pub struct ScanArgs<'a> {
+    pub cursor: u64,
+    pub pattern: Option<&'a [u8]>,
+    pub count: usize,
+    pub type_filter: Option<String>,
+    pub novalues: bool,
+}
+
+impl ScanArgs<'_> {
+    pub fn parse(args: &[Vec<u8>]) -> Result<ScanArgs<'_>, CommandError> {
+        let mut scan = ScanArgs {
+            cursor: parse_int(&args[0]).map_err(|_| CommandError::InvalidCursor)?,
+            pattern: None,
+            count: 10,
+            type_filter: None,
+            novalues: false,
+        };
+        let mut opts = args[1..].iter();
+        while let Some(opt) = opts.next() {
+            let opt = opt.to_ascii_uppercase();
+            if opt == b"NOVALUES" {
+                scan.novalues = true;
+                continue;
+            }
+            let val = opts.next().ok_or(CommandError::Syntax)?;
+            match opt.as_slice() {
+                b"MATCH" => scan.pattern = Some(val.as_slice()),
+                b"COUNT" => {
+                    scan.count = parse_int(val)?;
+                    if scan.count < 1 {
+                        return Err(CommandError::Syntax);
+                    }
+                }
+                b"TYPE" => {
+                    let name = String::from_utf8_lossy(val).to_lowercase();
+                    if !TYPE_NAMES.contains(&name.as_str()) {
+                        return Err(CommandError::Other(format!("unknown type name '{name}'")));
+                    }
+                    scan.type_filter = Some(name);
+                }
+                _ => return Err(CommandError::Syntax),
+            }
+        }
+        Ok(scan)
+    }
+    pub fn matches(&self, item: &[u8]) -> bool {
+        self.pattern
+            .is_none_or(|pattern| glob::matches(pattern, item))
+    }
+}
+
+pub fn scan_reply(next: u64, items: Vec<Reply>) -> Reply {
+    Reply::Array(vec![
+        Reply::BulkString(next.to_string().into_bytes()),
+        Reply::Array(items),
+    ])
+}
+
+pub fn scan(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let scan = ScanArgs::parse(&args[1..])?;
+    if scan.novalues {
+        return Err(CommandError::Syntax);
+    }
+    // The read lock is only held for this batch; the cursor is all the state that is kept.
+    let guard = ctx.db.read().unwrap();
+    let (next, batch) = guard.scan(scan.cursor, scan.count);
+    let keys = batch
+        .into_iter()
+        .filter(|(key, _)| scan.matches(key))
+        .filter(|(_, value)| {
+            scan.type_filter
+                .as_deref()
+                .is_none_or(|name| value.type_name() == name)
+        })
+        .map(|(key, _)| Reply::from(&key[..]))
+        .collect();
+    Ok(scan_reply(next, keys))
+}
+
+pub fn type_(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let name = guard
+        .peek(&args[1])
+        .map_or("none", |value| value.type_name());
+    Ok(Reply::SimpleString(name.into()))
+}
+
+pub fn object(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let subcommand = args[1].to_ascii_uppercase();
+    if !matches!(
+        subcommand.as_slice(),
+        b"ENCODING" | b"REFCOUNT" | b"IDLETIME"
+    ) || args.len() != 3
+    {
+        return Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "OBJECT",
+        ));
+    }
+    let guard = ctx.db.read().unwrap();
+    // Introspection must not count as an access, or IDLETIME would always read 0.
+    let Some(value) = guard.peek(&args[2]) else {
+        return Ok(Reply::Nil);
+    };
+    Ok(match subcommand.as_slice() {
+        b"ENCODING" => Reply::BulkString(value.data.encoding().as_bytes().to_vec()),
+        // Values are never shared between keys.
+        b"REFCOUNT" => Reply::Integer(1),
+        _ => Reply::Integer(value.last_access.idle().as_secs() as i64),
+    })
+}
+
+// Also serves UNLINK, as values are freed where they are removed either way.
+pub fn del(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    let mut deleted = 0;
+    for key in &args[1..] {
+        if guard.remove(key).is_some() {
+            deleted += 1;
+            notify(ctx, notify::GENERIC, "del", key);
+        }
+    }
+    if deleted > 0 {
+        propagate(ctx, args);
+    }
+    Ok(Reply::Integer(deleted))
+}
+
+// A key given more than once is counted each time, as in Redis.
+pub fn exists(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let found = args[1..]
+        .iter()
+        .filter(|key| guard.get(key).is_some())
+        .count();
+    Ok(Reply::Integer(found as i64))
+}
+
+pub fn touch(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let touched = args[1..]
+        .iter()
+        .filter(|key| guard.get(key).is_some())
+        .count();
+    Ok(Reply::Integer(touched as i64))
+}
+
+fn rename_generic(ctx: &mut Context, args: &[Vec<u8>], nx: bool) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    if guard.get(&args[1]).is_none() {
+        return Err(CommandError::Other("no such key".into()));
+    }
+    if nx && guard.get(&args[2]).is_some() {
+        return Ok(Reply::Integer(0));
+    }
+    if args[1] != args[2] {
+        let deadline = guard.expiry(&args[1]);
+        let value = guard.remove(&args[1]).expect("checked above");
+        guard.insert(&args[2], value);
+        guard.set_expiry(&args[2], deadline);
+    }
+    notify(ctx, notify::GENERIC, "rename_from", &args[1]);
+    notify(ctx, notify::GENERIC, "rename_to", &args[2]);
+    Ok(if nx { Reply::Integer(1) } else { Reply::ok() })
+}
+
+pub fn rename(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    rename_generic(ctx, args, false)
+}
+
+pub fn renamenx(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    rename_generic(ctx, args, true)
+}
+
+// The optional ASYNC|SYNC argument of the flush commands; true for ASYNC.
+pub fn parse_flush_mode(args: &[Vec<u8>]) -> Result<bool, CommandError> {
+    match args {
+        [] => Ok(false),
+        [mode] => match mode.to_ascii_uppercase().as_slice() {
+            b"ASYNC" => Ok(true),
+            b"SYNC" => Ok(false),
+            _ => Err(CommandError::Syntax),
+        },
+        _ => Err(CommandError::Syntax),
+    }
+}
+
+// ASYNC is accepted for compatibility; the keyspace is always flushed inline.
+pub fn flushall(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    parse_flush_mode(&args[1..])?;
+    db::flush_all(ctx.databases);
+    Ok(Reply::ok())
+}
+
+pub fn flushdb(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    parse_flush_mode(&args[1..])?;
+    ctx.db.write().unwrap().clear();
+    Ok(Reply::ok())
+}
+
+pub fn dbsize(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    Ok(Reply::Integer(ctx.db.read().unwrap().len() as i64))
+}
+
+pub fn keys(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut keys = vec![];
+    db::for_each_entry(ctx.db, .., |key, _, _| {
+        if glob::matches(&args[1], key) {
+            keys.push(Reply::from(&key[..]));
+        }
+    });
+    Ok(Reply::Array(keys))
+}
+
+pub fn randomkey(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    Ok(Reply::from(guard.random_key().map(|key| key.to_vec())))
+}
+
+pub fn copy(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let (source, dest) = (&args[1], &args[2]);
+    let (mut dest_db, mut dest_index) = (ctx.db, ctx.session.db);
+    let mut replace = false;
+    let mut opts = args[3..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"REPLACE" => replace = true,
+            b"DB" => {
+                let index: usize = parse_int(opts.next().ok_or(CommandError::Syntax)?)?;
+                dest_db = ctx
+                    .databases
+                    .get(index)
+                    .ok_or(CommandError::DbIndexOutOfRange)?;
+                dest_index = index;
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    let same_db = std::ptr::eq(ctx.db, dest_db);
+    if same_db && source == dest {
+        return Err(CommandError::Other(
+            "source and destination objects are the same".into(),
+        ));
+    }
+    // Clone under the source lock and release it before locking the destination, so two
+    // COPYs in opposite directions can't deadlock.
+    let (value, deadline) = {
+        let guard = ctx.db.read().unwrap();
+        let Some(value) = guard.get(source).cloned() else {
+            return Ok(Reply::Integer(0));
+        };
+        (value, guard.expiry(source))
+    };
+    let mut guard = dest_db.write().unwrap();
+    if !replace && guard.get(dest).is_some() {
+        return Ok(Reply::Integer(0));
+    }
+    guard.insert(dest, value);
+    guard.set_expiry(dest, deadline);
+    notify::notify(ctx.server, notify::GENERIC, "copy_to", dest, dest_index);
+    Ok(Reply::Integer(1))
+}
+
+pub fn dump(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    Ok(Reply::from(
+        guard.get(&args[1]).map(|value| rdb::dump(&value.data)),
+    ))
+}
+
+pub fn restore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let (key, payload) = (&args[1], &args[3]);
+    let mut replace = false;
+    let mut absttl = false;
+    let mut idle = None;
+    let mut opts = args[4..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"REPLACE" => replace = true,
+            b"ABSTTL" => absttl = true,
+            b"IDLETIME" => {
+                let seconds: i64 = parse_int(opts.next().ok_or(CommandError::Syntax)?)?;
+                if seconds < 0 {
+                    return Err(CommandError::Other(
+                        "Invalid IDLETIME value, must be >= 0".into(),
+                    ));
+                }
+                idle = Some(Duration::from_secs(seconds as u64));
+            }
+            b"FREQ" => {
+                let freq: i64 = parse_int(opts.next().ok_or(CommandError::Syntax)?)?;
+                if !(0..=255).contains(&freq) {
+                    return Err(CommandError::Other(
+                        "Invalid FREQ value, must be >= 0 and <= 255".into(),
+                    ));
+                }
+                // Only idle times are kept, as under an LRU policy; OBJECT FREQ isn't served either.
+                return Err(CommandError::Other(
+                    "An LFU maxmemory policy is not selected, access frequency not tracked".into(),
+                ));
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    let mut guard = ctx.db.write().unwrap();
+    if !replace && guard.get(key).is_some() {
+        return Err(CommandError::BusyKey);
+    }
+    let ttl: i64 = parse_int(&args[2])?;
+    if ttl < 0 {
+        return Err(CommandError::Other(
+            "Invalid TTL value, must be >= 0".into(),
+        ));
+    }
+    let body = rdb::verify_dump(payload)
+        .ok_or_else(|| CommandError::Other("DUMP payload version or checksum are wrong".into()))?;
+    let mut reader = rdb::Reader::new(body);
+    let data = reader
+        .byte()
+        .and_then(|value_type| reader.value(value_type))
+        .ok_or_else(|| CommandError::Other("Bad data format".into()))?;
+    let deadline = match (ttl, absttl) {
+        (0, _) => None,
+        (ttl, false) => Some(now_millis().saturating_add(ttl)),
+        (deadline, true) if deadline <= now_millis() => {
+            // Already expired: behaves as if the key was restored and deleted at once.
+            guard.remove(key);
+            propagate(ctx, args);
+            return Ok(Reply::ok());
+        }
+        (deadline, true) => Some(deadline),
+    };
+    let value = MapValue::new(data);
+    if let Some(idle) = idle {
+        value.last_access.set_idle(idle);
+    }
+    guard.insert(key, value);
+    guard.set_expiry(key, deadline);
+    notify(ctx, notify::GENERIC, "restore", key);
+    match deadline {
+        Some(deadline) => propagate_deadline(ctx, args, deadline),
+        None => propagate(ctx, args),
+    }
+    Ok(Reply::ok())
+}
@@ -0,0 +1,345 @@
+use super::{
+    block_on_keys, notify, parse_int, parse_numkeys, parse_timeout, propagate, CommandError,
+    CommandResult, Context,
+};
+use crate::{
+    db::{DataMap, MapValue, Value},
+    resp::Reply,
+    types::list::List,
+};
+
+fn as_list(value: &MapValue) -> Result<&List, CommandError> {
+    match &value.data {
+        Value::List(list) => Ok(list),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+fn list_or_create<'a>(map: &'a mut DataMap, key: &[u8]) -> Result<&'a mut List, CommandError> {
+    match &mut map
+        .get_or_insert_with(key, || Value::List(List::new()))
+        .data
+    {
+        Value::List(list) => Ok(list),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+// The X variants (`create` false) only push onto a list that already exists.
+fn push_generic(ctx: &mut Context, args: &[Vec<u8>], front: bool, create: bool) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    if !create && guard.get(&args[1]).is_none() {
+        return Ok(Reply::Integer(0));
+    }
+    let list = list_or_create(&mut guard, &args[1])?;
+    for item in &args[2..] {
+        if front {
+            list.push_front(item);
+        } else {
+            list.push_back(item);
+        }
+    }
+    let len = list.len();
+    notify(
+        ctx,
+        notify::LIST,
+        if front { "lpush" } else { "rpush" },
+        &args[1],
+    );
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn lpush(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    push_generic(ctx, args, true, true)
+}
+
+pub fn rpush(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    push_generic(ctx, args, false, true)
+}
+
+pub fn lpushx(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    push_generic(ctx, args, true, false)
+}
+
+pub fn rpushx(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    push_generic(ctx, args, false, false)
+}
+
+pub fn lrange(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let start: i64 = parse_int(&args[2])?;
+    let stop: i64 = parse_int(&args[3])?;
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Array(vec![]));
+    };
+    let items = as_list(value)?.range(start, stop);
+    Ok(Reply::Array(
+        items.map(|item| Reply::from(item.as_slice())).collect(),
+    ))
+}
+
+pub fn llen(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let len = match guard.get(&args[1]) {
+        Some(value) => as_list(value)?.len(),
+        None => 0,
+    };
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn lindex(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let index: i64 = parse_int(&args[2])?;
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Nil);
+    };
+    Ok(Reply::from(as_list(value)?.get(index).cloned()))
+}
+
+pub fn lset(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let index: i64 = parse_int(&args[2])?;
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Err(CommandError::Other("no such key".into()));
+    };
+    let Value::List(list) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    if !list.set(index, &args[3]) {
+        return Err(CommandError::Other("index out of range".into()));
+    }
+    notify(ctx, notify::LIST, "lset", &args[1]);
+    Ok(Reply::ok())
+}
+
+pub fn linsert(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let after = match args[2].to_ascii_uppercase().as_slice() {
+        b"BEFORE" => false,
+        b"AFTER" => true,
+        _ => return Err(CommandError::Syntax),
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let Value::List(list) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    if !list.insert(&args[3], &args[4], after) {
+        return Ok(Reply::Integer(-1));
+    }
+    let len = list.len();
+    notify(ctx, notify::LIST, "linsert", &args[1]);
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn lrem(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let count: i64 = parse_int(&args[2])?;
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let Value::List(list) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let removed = list.remove(&args[3], count);
+    let emptied = list.is_empty();
+    if removed > 0 {
+        notify(ctx, notify::LIST, "lrem", &args[1]);
+    }
+    if emptied {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    }
+    Ok(Reply::Integer(removed as i64))
+}
+
+pub fn ltrim(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let start: i64 = parse_int(&args[2])?;
+    let stop: i64 = parse_int(&args[3])?;
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::ok());
+    };
+    let Value::List(list) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    list.trim(start, stop);
+    let emptied = list.is_empty();
+    notify(ctx, notify::LIST, "ltrim", &args[1]);
+    if emptied {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    }
+    Ok(Reply::ok())
+}
+
+// Pops up to `count` items from the list at `key`, deleting it once empty; None if there is no
+// such key.
+fn pop_items(
+    ctx: &Context,
+    map: &mut DataMap,
+    key: &[u8],
+    front: bool,
+    count: usize,
+) -> Result<Option<Vec<Vec<u8>>>, CommandError> {
+    let Some(value) = map.get_mut(key) else {
+        return Ok(None);
+    };
+    let Value::List(list) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let mut popped = vec![];
+    while popped.len() < count {
+        let item = if front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+        match item {
+            Some(item) => popped.push(item),
+            None => break,
+        }
+    }
+    let emptied = list.is_empty();
+    if !popped.is_empty() {
+        notify(ctx, notify::LIST, if front { "lpop" } else { "rpop" }, key);
+    }
+    if emptied {
+        map.remove(key);
+        notify(ctx, notify::GENERIC, "del", key);
+    }
+    Ok(Some(popped))
+}
+
+fn pop_generic(ctx: &mut Context, args: &[Vec<u8>], front: bool) -> CommandResult {
+    if args.len() > 3 {
+        return Err(CommandError::WrongArity(if front {
+            "lpop"
+        } else {
+            "rpop"
+        }));
+    }
+    let count = match args.get(2) {
+        None => None,
+        Some(arg) => match parse_int::<i64>(arg) {
+            Ok(count) if count >= 0 => Some(count as usize),
+            _ => {
+                return Err(CommandError::Other(
+                    "value is out of range, must be positive".into(),
+                ))
+            }
+        },
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let Some(mut popped) = pop_items(ctx, &mut guard, &args[1], front, count.unwrap_or(1))? else {
+        return Ok(if count.is_some() {
+            Reply::NilArray
+        } else {
+            Reply::Nil
+        });
+    };
+    Ok(match count {
+        Some(_) => Reply::Array(popped.into_iter().map(Reply::BulkString).collect()),
+        None => Reply::from(popped.pop()),
+    })
+}
+
+pub fn lpop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    pop_generic(ctx, args, true)
+}
+
+pub fn rpop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    pop_generic(ctx, args, false)
+}
+
+fn blocking_pop(ctx: &mut Context, args: &[Vec<u8>], front: bool) -> CommandResult {
+    let (keys, timeout) = args[1..].split_at(args.len() - 2);
+    let deadline = parse_timeout(&timeout[0])?;
+    let ctx = &*ctx;
+    let served = block_on_keys(ctx, keys, deadline, |map| {
+        for key in keys {
+            if let Some(mut items) = pop_items(ctx, map, key, front, 1)? {
+                let pop: &[u8] = if front { b"LPOP" } else { b"RPOP" };
+                propagate(ctx, &[pop.to_vec(), key.clone()]);
+                return Ok(Some((key, items.pop())));
+            }
+        }
+        Ok(None)
+    })?;
+    Ok(match served {
+        Some((key, item)) => Reply::Array(vec![Reply::from(key.as_slice()), Reply::from(item)]),
+        None => Reply::NilArray,
+    })
+}
+
+pub fn blpop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    blocking_pop(ctx, args, true)
+}
+
+pub fn brpop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    blocking_pop(ctx, args, false)
+}
+
+// Parses `numkeys key [key ...] LEFT|RIGHT [COUNT count]` as LMPOP and BLMPOP take it.
+fn parse_mpop(args: &[Vec<u8>]) -> Result<(&[Vec<u8>], bool, usize), CommandError> {
+    let (keys, rest) = parse_numkeys(args)?;
+    let Some((direction, opts)) = rest.split_first() else {
+        return Err(CommandError::Syntax);
+    };
+    let front = match direction.to_ascii_uppercase().as_slice() {
+        b"LEFT" => true,
+        b"RIGHT" => false,
+        _ => return Err(CommandError::Syntax),
+    };
+    let count = match opts {
+        [] => 1,
+        [opt, count] if opt.eq_ignore_ascii_case(b"COUNT") => match parse_int::<i64>(count) {
+            Ok(count) if count > 0 => count as usize,
+            _ => return Err(CommandError::Other("count should be greater than 0".into())),
+        },
+        _ => return Err(CommandError::Syntax),
+    };
+    Ok((keys, front, count))
+}
+
+fn mpop_reply(popped: Option<(&Vec<u8>, Vec<Vec<u8>>)>) -> Reply {
+    match popped {
+        Some((key, items)) => Reply::Array(vec![
+            Reply::from(key.as_slice()),
+            Reply::Array(items.into_iter().map(Reply::BulkString).collect()),
+        ]),
+        None => Reply::NilArray,
+    }
+}
+
+pub fn lmpop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let (keys, front, count) = parse_mpop(&args[1..])?;
+    let mut guard = ctx.db.write().unwrap();
+    for key in keys {
+        if let Some(items) = pop_items(ctx, &mut guard, key, front, count)? {
+            return Ok(mpop_reply(Some((key, items))));
+        }
+    }
+    Ok(mpop_reply(None))
+}
+
+pub fn blmpop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let deadline = parse_timeout(&args[1])?;
+    let (keys, front, count) = parse_mpop(&args[2..])?;
+    let ctx = &*ctx;
+    let served = block_on_keys(ctx, keys, deadline, |map| {
+        for key in keys {
+            if let Some(items) = pop_items(ctx, map, key, front, count)? {
+                let pop: &[u8] = if front { b"LPOP" } else { b"RPOP" };
+                propagate(
+                    ctx,
+                    &[pop.to_vec(), key.clone(), count.to_string().into_bytes()],
+                );
+                return Ok(Some((key, items)));
+            }
+        }
+        Ok(None)
+    })?;
+    Ok(mpop_reply(served))
+}
@@ -0,0 +1,1605 @@
+mod bitmap;
+mod connection;
+mod expire;
+mod geo;
+mod hash;
+mod hll;
+mod keyspace;
+mod list;
+mod pubsub;
+mod replication;
+mod scripting;
+mod server;
+mod set;
+mod sort;
+mod stream;
+mod string;
+mod transaction;
+mod zset;
+
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    blocking::Waiter,
+    client::{ClientInfo, Outbox},
+    config::ConfigError,
+    db::{DataMap, Databases, ThreadSafeDataMap},
+    memory, notify,
+    resp::{Protocol, Reply},
+    server::Server,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("ERR unknown command '{0}', with args beginning with: {1}")]
+    Unknown(String, String),
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(&'static str),
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+    #[error("ERR invalid cursor")]
+    InvalidCursor,
+    #[error("ERR Invalid stream ID specified as stream command argument")]
+    InvalidStreamId,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR DB index is out of range")]
+    DbIndexOutOfRange,
+    #[error("WRONGTYPE Key is not a valid HyperLogLog string value.")]
+    InvalidHll,
+    #[error("BUSYKEY Target key name already exists.")]
+    BusyKey,
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    BusyGroup,
+    #[error("NOGROUP {0}")]
+    NoGroup(String),
+    #[error("NOPROTO unsupported protocol version")]
+    NoProto,
+    #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+    WrongPass,
+    #[error("ERR unknown subcommand or wrong number of arguments for '{0}'. Try {1} HELP.")]
+    UnknownSubcommand(String, &'static str),
+    #[error("BUSY {0}")]
+    Busy(&'static str),
+    #[error("ERR unsupported command '{0}'")]
+    Unsupported(&'static str),
+    #[error("NOTBUSY No scripts in execution right now.")]
+    NotBusy,
+    #[error(
+        "UNKILLABLE Sorry the script already executed write commands against the dataset. You \
+         can either wait the script termination or kill the server in a hard way using the \
+         SHUTDOWN NOSAVE command."
+    )]
+    Unkillable,
+    #[error(
+        "ERR Can't execute '{0}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET \
+         are allowed in this context"
+    )]
+    SubscriberMode(&'static str),
+    #[error("READONLY You can't write against a read only replica.")]
+    ReadOnly,
+    #[error("OOM command not allowed when used memory > 'maxmemory'.")]
+    OutOfMemory,
+    #[error("EXECABORT Transaction discarded because of previous errors.")]
+    ExecAbort,
+    #[error("NOSCRIPT No matching script. Please use EVAL.")]
+    NoScript,
+    // Raised by a script, error code and all.
+    #[error("{0}")]
+    Script(String),
+    #[error("ERR {0}")]
+    Config(#[from] ConfigError),
+    #[error("ERR {0}")]
+    Other(String),
+}
+
+pub type CommandResult = Result<Reply, CommandError>;
+
+// Per-connection state that outlives a single command.
+#[derive(Debug, Default)]
+pub struct Session {
+    pub id: u64,
+    pub protocol: Protocol,
+    pub name: Option<Vec<u8>>,
+    // Index of the selected database.
+    pub db: usize,
+    pub subscriptions: HashSet<Vec<u8>>,
+    pub outbox: Arc<Outbox>,
+    pub info: Arc<Mutex<ClientInfo>>,
+    // Set by a command to drop the connection once its reply is written.
+    pub close_after_reply: bool,
+    // Unparsed input behind the command being run, and commands run so far.
+    pub query_buffer: usize,
+    pub commands_processed: u64,
+    // Commands queued since MULTI, None outside a transaction.
+    pub transaction: Option<Vec<Vec<Vec<u8>>>>,
+    // Set when a command was refused instead of queued.
+    pub transaction_dirty: bool,
+    // Set while EXEC runs the queue, which already excludes every other command.
+    pub in_exec: bool,
+    // Keys under WATCH, by database, with the version they had when watched.
+    pub watched: Vec<(usize, Vec<u8>, u64)>,
+    // The address a replica announced with REPLCONF; port 0 until it does.
+    pub replica_ip: Option<String>,
+    pub replica_port: u16,
+}
+
+impl Session {
+    // RESP2 connections with subscriptions can only manage those, as their replies would be
+    // mixed up with the messages otherwise.
+    pub fn in_subscriber_mode(&self) -> bool {
+        self.protocol == Protocol::Resp2 && !self.subscriptions.is_empty()
+    }
+    // Publishes the session state for CLIENT LIST after running `command`.
+    pub fn refresh_info(&self, command: &[u8]) {
+        let mut info = self.info.lock().unwrap();
+        info.last_interaction = Instant::now();
+        info.name = self.name.clone().unwrap_or_default();
+        info.db = self.db;
+        info.protocol = self.protocol;
+        info.last_command = String::from_utf8_lossy(command).to_ascii_lowercase();
+        info.sub = self.subscriptions.len();
+        info.qbuf = self.query_buffer;
+        info.tot_cmds = self.commands_processed;
+        info.multi = self
+            .transaction
+            .as_ref()
+            .map_or(-1, |queued| queued.len() as i64);
+        info.watch = self.watched.len();
+    }
+    pub fn unwatch_all(&mut self, databases: &Databases) {
+        for (index, key, _) in self.watched.drain(..) {
+            databases[index].write().unwrap().unwatch(&key);
+        }
+    }
+}
+
+pub struct Context<'a> {
+    pub db: &'a ThreadSafeDataMap,
+    pub databases: &'a Databases,
+    pub server: &'a Server,
+    pub session: &'a mut Session,
+}
+
+type Handler = fn(&mut Context, &[Vec<u8>]) -> CommandResult;
+
+// Modifies the dataset; such commands are what gets journaled.
+pub const WRITE: u32 = 1 << 0;
+// The handler journals the commands it actually performed instead of its own arguments, e.g.
+// BLPOP, which is replayed as the LPOP that served it.
+pub const PROPAGATES_ITSELF: u32 = 1 << 1;
+// May wait for other clients; time spent blocked does not count against the time budget.
+pub const BLOCKING: u32 = 1 << 2;
+// Starts, runs or drops the connection's transaction, so it is never queued itself.
+pub const TRANSACTION: u32 = 1 << 3;
+// Runs with every other client held off, like EXEC, so takes the gate exclusively itself.
+pub const EXCLUSIVE: u32 = 1 << 4;
+// May grow the dataset, so is refused while used memory is over maxmemory.
+pub const DENYOOM: u32 = 1 << 5;
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    // Same convention as Redis: positive is an exact argument count, negative a minimum.
+    pub arity: i32,
+    handler: Handler,
+    pub flags: u32,
+}
+
+impl CommandSpec {
+    fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i32;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+}
+
+static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "auth",
+        arity: -2,
+        handler: connection::auth,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hello",
+        arity: -1,
+        handler: connection::hello,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "reset",
+        arity: 1,
+        handler: connection::reset,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "quit",
+        arity: -1,
+        handler: connection::quit,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "debug",
+        arity: -2,
+        handler: server::debug,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "config",
+        arity: -2,
+        handler: server::config,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "latency",
+        arity: -2,
+        handler: server::latency,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "memory",
+        arity: -2,
+        handler: server::memory,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "info",
+        arity: -1,
+        handler: server::info,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "shutdown",
+        arity: -1,
+        handler: server::shutdown,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "script",
+        arity: -2,
+        handler: scripting::script,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "client",
+        arity: -2,
+        handler: connection::client,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "select",
+        arity: 2,
+        handler: connection::select,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "ping",
+        arity: -1,
+        handler: connection::ping,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "copy",
+        arity: -3,
+        handler: keyspace::copy,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "dump",
+        arity: 2,
+        handler: keyspace::dump,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "echo",
+        arity: 2,
+        handler: connection::echo,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "set",
+        arity: -3,
+        handler: string::set,
+        flags: WRITE | DENYOOM | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "expire",
+        arity: -3,
+        handler: expire::expire,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "pexpire",
+        arity: -3,
+        handler: expire::pexpire,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "expireat",
+        arity: -3,
+        handler: expire::expireat,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "pexpireat",
+        arity: -3,
+        handler: expire::pexpireat,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "expiretime",
+        arity: 2,
+        handler: expire::expiretime,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "pexpiretime",
+        arity: 2,
+        handler: expire::pexpiretime,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "persist",
+        arity: 2,
+        handler: expire::persist,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "ttl",
+        arity: 2,
+        handler: expire::ttl,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "pttl",
+        arity: 2,
+        handler: expire::pttl,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "geoadd",
+        arity: -5,
+        handler: geo::geoadd,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "geopos",
+        arity: -2,
+        handler: geo::geopos,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "geodist",
+        arity: -4,
+        handler: geo::geodist,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "geosearch",
+        arity: -7,
+        handler: geo::geosearch,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "geosearchstore",
+        arity: -8,
+        handler: geo::geosearchstore,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "get",
+        arity: 2,
+        handler: string::get,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "bitcount",
+        arity: -2,
+        handler: bitmap::bitcount,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "bitop",
+        arity: -4,
+        handler: bitmap::bitop,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "bitpos",
+        arity: -3,
+        handler: bitmap::bitpos,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "setbit",
+        arity: 4,
+        handler: bitmap::setbit,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "getbit",
+        arity: 3,
+        handler: bitmap::getbit,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "bitfield",
+        arity: -2,
+        handler: bitmap::bitfield,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "bitfield_ro",
+        arity: -2,
+        handler: bitmap::bitfield_ro,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "pfadd",
+        arity: -2,
+        handler: hll::pfadd,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "pfcount",
+        arity: -2,
+        handler: hll::pfcount,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "pfmerge",
+        arity: -2,
+        handler: hll::pfmerge,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "pfdebug",
+        arity: 3,
+        handler: hll::pfdebug,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "object",
+        arity: -2,
+        handler: keyspace::object,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "flushall",
+        arity: -1,
+        handler: keyspace::flushall,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "flushdb",
+        arity: -1,
+        handler: keyspace::flushdb,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "dbsize",
+        arity: 1,
+        handler: keyspace::dbsize,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "keys",
+        arity: 2,
+        handler: keyspace::keys,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "randomkey",
+        arity: 1,
+        handler: keyspace::randomkey,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "rename",
+        arity: 3,
+        handler: keyspace::rename,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "renamenx",
+        arity: 3,
+        handler: keyspace::renamenx,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "restore",
+        arity: -4,
+        handler: keyspace::restore,
+        flags: WRITE | DENYOOM | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "scan",
+        arity: -2,
+        handler: keyspace::scan,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "del",
+        arity: -2,
+        handler: keyspace::del,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "unlink",
+        arity: -2,
+        handler: keyspace::del,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "exists",
+        arity: -2,
+        handler: keyspace::exists,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "touch",
+        arity: -2,
+        handler: keyspace::touch,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "sort",
+        arity: -2,
+        handler: sort::sort,
+        flags: WRITE | DENYOOM | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "sort_ro",
+        arity: -2,
+        handler: sort::sort_ro,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "type",
+        arity: 2,
+        handler: keyspace::type_,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "subscribe",
+        arity: -2,
+        handler: pubsub::subscribe,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "unsubscribe",
+        arity: -1,
+        handler: pubsub::unsubscribe,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "publish",
+        arity: 3,
+        handler: pubsub::publish,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "pubsub",
+        arity: -2,
+        handler: pubsub::pubsub,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "lpush",
+        arity: -3,
+        handler: list::lpush,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "rpush",
+        arity: -3,
+        handler: list::rpush,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "lpushx",
+        arity: -3,
+        handler: list::lpushx,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "rpushx",
+        arity: -3,
+        handler: list::rpushx,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "lpop",
+        arity: -2,
+        handler: list::lpop,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "rpop",
+        arity: -2,
+        handler: list::rpop,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "blpop",
+        arity: -3,
+        handler: list::blpop,
+        flags: WRITE | PROPAGATES_ITSELF | BLOCKING,
+    },
+    CommandSpec {
+        name: "brpop",
+        arity: -3,
+        handler: list::brpop,
+        flags: WRITE | PROPAGATES_ITSELF | BLOCKING,
+    },
+    CommandSpec {
+        name: "lmpop",
+        arity: -4,
+        handler: list::lmpop,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "blmpop",
+        arity: -5,
+        handler: list::blmpop,
+        flags: WRITE | PROPAGATES_ITSELF | BLOCKING,
+    },
+    CommandSpec {
+        name: "hset",
+        arity: -4,
+        handler: hash::hset,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "hget",
+        arity: 3,
+        handler: hash::hget,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hdel",
+        arity: -3,
+        handler: hash::hdel,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "hgetall",
+        arity: 2,
+        handler: hash::hgetall,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hmget",
+        arity: -3,
+        handler: hash::hmget,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hkeys",
+        arity: 2,
+        handler: hash::hkeys,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hvals",
+        arity: 2,
+        handler: hash::hvals,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hlen",
+        arity: 2,
+        handler: hash::hlen,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hexists",
+        arity: 3,
+        handler: hash::hexists,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hscan",
+        arity: -3,
+        handler: hash::hscan,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hsetnx",
+        arity: 4,
+        handler: hash::hsetnx,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "hstrlen",
+        arity: 3,
+        handler: hash::hstrlen,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hexpire",
+        arity: -6,
+        handler: hash::hexpire,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "hpexpire",
+        arity: -6,
+        handler: hash::hpexpire,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "hexpireat",
+        arity: -6,
+        handler: hash::hexpireat,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "hpexpireat",
+        arity: -6,
+        handler: hash::hpexpireat,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "httl",
+        arity: -5,
+        handler: hash::httl,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hpttl",
+        arity: -5,
+        handler: hash::hpttl,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "hpersist",
+        arity: -5,
+        handler: hash::hpersist,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "hincrby",
+        arity: 4,
+        handler: hash::hincrby,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "hincrbyfloat",
+        arity: 4,
+        handler: hash::hincrbyfloat,
+        flags: WRITE | DENYOOM | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "lrange",
+        arity: 4,
+        handler: list::lrange,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "llen",
+        arity: 2,
+        handler: list::llen,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "lindex",
+        arity: 3,
+        handler: list::lindex,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "lset",
+        arity: 4,
+        handler: list::lset,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "linsert",
+        arity: 5,
+        handler: list::linsert,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "lrem",
+        arity: 4,
+        handler: list::lrem,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "ltrim",
+        arity: 4,
+        handler: list::ltrim,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "sadd",
+        arity: -3,
+        handler: set::sadd,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "srem",
+        arity: -3,
+        handler: set::srem,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "smembers",
+        arity: 2,
+        handler: set::smembers,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "scard",
+        arity: 2,
+        handler: set::scard,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "spop",
+        arity: -2,
+        handler: set::spop,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "smismember",
+        arity: -3,
+        handler: set::smismember,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "sscan",
+        arity: -3,
+        handler: set::sscan,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "smove",
+        arity: 4,
+        handler: set::smove,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "sismember",
+        arity: 3,
+        handler: set::sismember,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "srandmember",
+        arity: -2,
+        handler: set::srandmember,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "sintercard",
+        arity: -3,
+        handler: set::sintercard,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zadd",
+        arity: -4,
+        handler: zset::zadd,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "zscore",
+        arity: 3,
+        handler: zset::zscore,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrange",
+        arity: -4,
+        handler: zset::zrange,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrangebyscore",
+        arity: -4,
+        handler: zset::zrangebyscore,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrevrangebyscore",
+        arity: -4,
+        handler: zset::zrevrangebyscore,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrangebylex",
+        arity: -4,
+        handler: zset::zrangebylex,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrevrangebylex",
+        arity: -4,
+        handler: zset::zrevrangebylex,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zcount",
+        arity: 4,
+        handler: zset::zcount,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zlexcount",
+        arity: 4,
+        handler: zset::zlexcount,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zpopmin",
+        arity: -2,
+        handler: zset::zpopmin,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "zpopmax",
+        arity: -2,
+        handler: zset::zpopmax,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "bzpopmin",
+        arity: -3,
+        handler: zset::bzpopmin,
+        flags: WRITE | PROPAGATES_ITSELF | BLOCKING,
+    },
+    CommandSpec {
+        name: "bzpopmax",
+        arity: -3,
+        handler: zset::bzpopmax,
+        flags: WRITE | PROPAGATES_ITSELF | BLOCKING,
+    },
+    CommandSpec {
+        name: "zrem",
+        arity: -3,
+        handler: zset::zrem,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "zremrangebyrank",
+        arity: 4,
+        handler: zset::zremrangebyrank,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "zremrangebyscore",
+        arity: 4,
+        handler: zset::zremrangebyscore,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "zremrangebylex",
+        arity: 4,
+        handler: zset::zremrangebylex,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "zunion",
+        arity: -3,
+        handler: zset::zunion,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zinter",
+        arity: -3,
+        handler: zset::zinter,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zdiff",
+        arity: -3,
+        handler: zset::zdiff,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zunionstore",
+        arity: -4,
+        handler: zset::zunionstore,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "zinterstore",
+        arity: -4,
+        handler: zset::zinterstore,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "zdiffstore",
+        arity: -4,
+        handler: zset::zdiffstore,
+        flags: WRITE | DENYOOM,
+    },
+    CommandSpec {
+        name: "zmscore",
+        arity: -3,
+        handler: zset::zmscore,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrandmember",
+        arity: -2,
+        handler: zset::zrandmember,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zscan",
+        arity: -3,
+        handler: zset::zscan,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrank",
+        arity: -3,
+        handler: zset::zrank,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zrevrank",
+        arity: -3,
+        handler: zset::zrevrank,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zcard",
+        arity: 2,
+        handler: zset::zcard,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "zintercard",
+        arity: -3,
+        handler: zset::zintercard,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "xadd",
+        arity: -5,
+        handler: stream::xadd,
+        flags: WRITE | DENYOOM | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "xrange",
+        arity: -4,
+        handler: stream::xrange,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "xrevrange",
+        arity: -4,
+        handler: stream::xrevrange,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "xlen",
+        arity: 2,
+        handler: stream::xlen,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "xdel",
+        arity: -3,
+        handler: stream::xdel,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "xread",
+        arity: -4,
+        handler: stream::xread,
+        flags: BLOCKING,
+    },
+    CommandSpec {
+        name: "xtrim",
+        arity: -4,
+        handler: stream::xtrim,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "xgroup",
+        arity: -2,
+        handler: stream::xgroup,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "xreadgroup",
+        arity: -7,
+        handler: stream::xreadgroup,
+        flags: WRITE | PROPAGATES_ITSELF | BLOCKING,
+    },
+    CommandSpec {
+        name: "xack",
+        arity: -4,
+        handler: stream::xack,
+        flags: WRITE,
+    },
+    CommandSpec {
+        name: "xpending",
+        arity: -3,
+        handler: stream::xpending,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "xclaim",
+        arity: -6,
+        handler: stream::xclaim,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "xautoclaim",
+        arity: -6,
+        handler: stream::xautoclaim,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "multi",
+        arity: 1,
+        handler: transaction::multi,
+        flags: TRANSACTION,
+    },
+    CommandSpec {
+        name: "exec",
+        arity: 1,
+        handler: transaction::exec,
+        flags: TRANSACTION,
+    },
+    CommandSpec {
+        name: "discard",
+        arity: 1,
+        handler: transaction::discard,
+        flags: TRANSACTION,
+    },
+    CommandSpec {
+        name: "watch",
+        arity: -2,
+        handler: transaction::watch,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "unwatch",
+        arity: 1,
+        handler: transaction::unwatch,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "eval",
+        arity: -3,
+        handler: scripting::eval,
+        flags: WRITE | PROPAGATES_ITSELF | EXCLUSIVE,
+    },
+    CommandSpec {
+        name: "evalsha",
+        arity: -3,
+        handler: scripting::evalsha,
+        flags: WRITE | PROPAGATES_ITSELF | EXCLUSIVE,
+    },
+    CommandSpec {
+        name: "function",
+        arity: -2,
+        handler: scripting::function,
+        flags: WRITE | PROPAGATES_ITSELF,
+    },
+    CommandSpec {
+        name: "fcall",
+        arity: -3,
+        handler: scripting::fcall,
+        flags: WRITE | PROPAGATES_ITSELF | EXCLUSIVE,
+    },
+    CommandSpec {
+        name: "fcall_ro",
+        arity: -3,
+        handler: scripting::fcall_ro,
+        flags: EXCLUSIVE,
+    },
+    CommandSpec {
+        name: "replconf",
+        arity: -1,
+        handler: replication::replconf,
+        flags: 0,
+    },
+    CommandSpec {
+        name: "psync",
+        arity: 3,
+        handler: replication::psync,
+        flags: EXCLUSIVE,
+    },
+];
+
+pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+}
+
+// Commands that still run while another connection keeps the server busy.
+fn allowed_while_busy(spec: &CommandSpec, args: &[Vec<u8>]) -> bool {
+    let has_arg = |wanted: &[u8]| args[1..].iter().any(|arg| arg.eq_ignore_ascii_case(wanted));
+    match spec.name {
+        "auth" | "hello" => true,
+        "shutdown" => has_arg(b"NOSAVE"),
+        "script" | "function" => args[1].eq_ignore_ascii_case(b"KILL"),
+        _ => false,
+    }
+}
+
+// What MULTI does not queue besides the transaction commands themselves.
+fn runs_in_multi(spec: &CommandSpec) -> bool {
+    spec.flags & TRANSACTION != 0 || matches!(spec.name, "watch" | "quit" | "reset")
+}
+
+fn allowed_while_subscribed(spec: &CommandSpec) -> bool {
+    matches!(
+        spec.name,
+        "subscribe" | "unsubscribe" | "ping" | "quit" | "reset"
+    )
+}
+
+// What redis.call refuses: commands that need a connection of their own or would run a script or
+// transaction inside another.
+fn allowed_in_script(spec: &CommandSpec) -> bool {
+    spec.flags & (TRANSACTION | EXCLUSIVE) == 0
+        && !matches!(
+            spec.name,
+            "watch"
+                | "unwatch"
+                | "subscribe"
+                | "unsubscribe"
+                | "quit"
+                | "reset"
+                | "hello"
+                | "auth"
+                | "script"
+                | "function"
+                | "shutdown"
+                | "replconf"
+        )
+}
+
+fn allow_listed(ctx: &Context, spec: &CommandSpec) -> bool {
+    let config = ctx.server.config.lock().unwrap();
+    config.command_allow_list.is_empty() || config.command_allow_list.contains(&spec.name)
+}
+
+// Whether a client's command would change a replica's dataset behind its master's back. Scripts
+// may still run there, and are refused the writes they attempt instead.
+fn writes_on_replica(spec: &CommandSpec, args: &[Vec<u8>]) -> bool {
+    match spec.name {
+        "eval" | "evalsha" | "fcall" => false,
+        "function" => matches!(
+            args[1].to_ascii_uppercase().as_slice(),
+            b"LOAD" | b"DELETE" | b"FLUSH" | b"RESTORE"
+        ),
+        _ => spec.flags & WRITE != 0,
+    }
+}
+
+// Whether a command is refused because used memory is over maxmemory. Scripts run, and are
+// refused the commands they call that would be.
+fn out_of_memory(ctx: &Context, spec: &CommandSpec, args: &[Vec<u8>]) -> bool {
+    let grows = match spec.name {
+        "function" => matches!(
+            args[1].to_ascii_uppercase().as_slice(),
+            b"LOAD" | b"RESTORE"
+        ),
+        _ => spec.flags & DENYOOM != 0,
+    };
+    if !grows {
+        return false;
+    }
+    let maxmemory = ctx.server.config.lock().unwrap().maxmemory;
+    maxmemory != 0 && memory::used() as u64 > maxmemory
+}
+
+// The command to run, unless it is refused before it gets to run or be queued.
+fn check(ctx: &Context, args: &[Vec<u8>]) -> Result<&'static CommandSpec, CommandError> {
+    match lookup(&args[0]) {
+        None => Err(CommandError::Unknown(
+            String::from_utf8_lossy(&args[0]).into_owned(),
+            args[1..]
+                .iter()
+                .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+                .collect(),
+        )),
+        Some(spec) if !allow_listed(ctx, spec) => Err(CommandError::Unsupported(spec.name)),
+        Some(spec) if !spec.accepts(args.len()) => Err(CommandError::WrongArity(spec.name)),
+        Some(spec) if ctx.session.in_subscriber_mode() && !allowed_while_subscribed(spec) => {
+            Err(CommandError::SubscriberMode(spec.name))
+        }
+        Some(spec) if ctx.server.master.is_some() && writes_on_replica(spec, args) => {
+            Err(CommandError::ReadOnly)
+        }
+        Some(spec) if out_of_memory(ctx, spec, args) => Err(CommandError::OutOfMemory),
+        Some(spec) => match ctx.server.busy() {
+            Some((reason, _)) if !allowed_while_busy(spec, args) => {
+                Err(CommandError::Busy(reason.message()))
+            }
+            _ => Ok(spec),
+        },
+    }
+}
+
+pub fn execute(ctx: &mut Context, args: &[Vec<u8>]) -> Reply {
+    let result = match check(ctx, args) {
+        Err(e) => {
+            // EXEC refuses a transaction that is missing a command.
+            if ctx.session.transaction.is_some() {
+                ctx.session.transaction_dirty = true;
+            }
+            Err(e)
+        }
+        Ok(spec) => match &mut ctx.session.transaction {
+            Some(queued) if !runs_in_multi(spec) => {
+                queued.push(args.to_vec());
+                Ok(Reply::SimpleString("QUEUED".into()))
+            }
+            _ => {
+                // Blocking commands hold the gate only while they try to serve themselves; EXEC
+                // and scripts take it exclusively. What still runs while the server is busy
+                // can't wait for it, as a busy script holds it.
+                let server = ctx.server;
+                let _gate = (spec.flags & (BLOCKING | TRANSACTION | EXCLUSIVE) == 0
+                    && !allowed_while_busy(spec, args))
+                .then(|| server.shared_gate());
+                call(ctx, spec, args)
+            }
+        },
+    };
+    result.unwrap_or_else(|e| Reply::Error(e.to_string()))
+}
+
+// Applies a command of the master's replication stream. The master already accepted it, so none
+// of the checks a client's command goes through apply, and it runs the way EXEC runs its queue.
+pub fn apply(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let spec = lookup(&args[0]).ok_or_else(|| {
+        CommandError::Unknown(
+            String::from_utf8_lossy(&args[0]).into_owned(),
+            String::new(),
+        )
+    })?;
+    if !spec.accepts(args.len()) {
+        return Err(CommandError::WrongArity(spec.name));
+    }
+    let server = ctx.server;
+    let _gate = server.exclusive_gate();
+    ctx.session.in_exec = true;
+    let result = call(ctx, spec, args);
+    ctx.session.in_exec = false;
+    result
+}
+
+// Runs a command that passed every check, on its own or as part of EXEC.
+fn call(ctx: &mut Context, spec: &CommandSpec, args: &[Vec<u8>]) -> CommandResult {
+    // Transactions and scripts run alone already, and blocking commands order each attempt.
+    let server = ctx.server;
+    let _order = (spec.flags & WRITE != 0
+        && spec.flags & (BLOCKING | EXCLUSIVE) == 0
+        && !ctx.session.in_exec)
+        .then(|| server.order_writes());
+    let (db, index) = (ctx.db, ctx.session.db);
+    let started = Instant::now();
+    let result = (spec.handler)(ctx, args);
+    if result.is_ok() && spec.flags & WRITE != 0 && spec.flags & PROPAGATES_ITSELF == 0 {
+        propagate(ctx, args);
+    }
+    if spec.flags & BLOCKING == 0 {
+        check_time_budget(ctx, args, started.elapsed());
+    }
+    notify::expired(ctx.server, db, index);
+    result
+}
+
+// Logs commands that ran over the configured budget with their (truncated) arguments and
+// records them as "command" latency events, closing the connection if so configured.
+fn check_time_budget(ctx: &mut Context, args: &[Vec<u8>], elapsed: Duration) {
+    const MAX_ARGS: usize = 32;
+    const MAX_ARG_BYTES: usize = 128;
+    let (budget, kill) = {
+        let config = ctx.server.config.lock().unwrap();
+        (config.command_time_budget, config.command_time_budget_kill)
+    };
+    let elapsed_ms = elapsed.as_millis() as u64;
+    if budget == 0 || elapsed_ms <= budget {
+        return;
+    }
+    ctx.server
+        .latency
+        .lock()
+        .unwrap()
+        .record("command", elapsed_ms);
+    let mut shown: Vec<String> = args
+        .iter()
+        .take(MAX_ARGS)
+        .map(|arg| match arg.len() {
+            len if len > MAX_ARG_BYTES => format!(
+                "{}... ({} more bytes)",
+                String::from_utf8_lossy(&arg[..MAX_ARG_BYTES]),
+                len - MAX_ARG_BYTES
+            ),
+            _ => String::from_utf8_lossy(arg).into_owned(),
+        })
+        .collect();
+    if args.len() > MAX_ARGS {
+        shown.push(format!("... ({} more arguments)", args.len() - MAX_ARGS));
+    }
+    println!(
+        "Command over the {budget} ms time budget ({elapsed_ms} ms, client id={}): {}",
+        ctx.session.id,
+        shown.join(" ")
+    );
+    if kill {
+        ctx.session.close_after_reply = true;
+    }
+}
+
+// Publishes a keyspace notification for a key of the selected database.
+pub fn notify(ctx: &Context, class: u32, event: &str, key: &[u8]) {
+    notify::notify(ctx.server, class, event, key, ctx.session.db);
+}
+
+pub fn propagate(ctx: &Context, args: &[Vec<u8>]) {
+    if let Some(journal) = &ctx.server.journal {
+        if let Err(e) = journal.lock().unwrap().append(ctx.session.db, args) {
+            println!("journal write failed: {e}");
+        }
+    }
+    ctx.server
+        .replicas
+        .lock()
+        .unwrap()
+        .feed(ctx.session.db, args);
+}
+
+// Propagates a write that set `deadline` as the form of its command taking the absolute time in
+// milliseconds: PEXPIREAT, HPEXPIREAT, SET ... PXAT and RESTORE ... ABSTTL. Replicas and the
+// journal then expire the key at the same instant, not relative to whenever they apply it.
+pub fn propagate_deadline(ctx: &Context, args: &[Vec<u8>], deadline: i64) {
+    let deadline = deadline.to_string().into_bytes();
+    let mut rewritten = args.to_vec();
+    match args[0].to_ascii_uppercase().as_slice() {
+        b"SET" => {
+            rewritten.truncate(3);
+            rewritten.extend([b"PXAT".to_vec(), deadline]);
+        }
+        b"RESTORE" => {
+            rewritten[2] = deadline;
+            if !args[4..]
+                .iter()
+                .any(|arg| arg.eq_ignore_ascii_case(b"ABSTTL"))
+            {
+                rewritten.push(b"ABSTTL".to_vec());
+            }
+        }
+        name => {
+            let absolute: &[u8] = if name.starts_with(b"H") {
+                b"HPEXPIREAT"
+            } else {
+                b"PEXPIREAT"
+            };
+            rewritten[0] = absolute.to_vec();
+            rewritten[2] = deadline;
+        }
+    }
+    propagate(ctx, &rewritten);
+}
+
+// The keys of a `numkeys key [key ...]` argument list and the arguments after them.
+type NumKeys<'a> = (&'a [Vec<u8>], &'a [Vec<u8>]);
+
+// Splits `numkeys key [key ...] rest...`, as LMPOP, SINTERCARD, ZINTERCARD and friends take
+// it, into the keys and whatever follows them.
+pub fn parse_numkeys(args: &[Vec<u8>]) -> Result<NumKeys<'_>, CommandError> {
+    let numkeys: i64 = parse_int(&args[0])?;
+    if numkeys <= 0 {
+        return Err(CommandError::Other(
+            "numkeys should be greater than 0".into(),
+        ));
+    }
+    let numkeys = numkeys as usize;
+    if args.len() - 1 < numkeys {
+        return Err(CommandError::Other(
+            "Number of keys can't be greater than number of args".into(),
+        ));
+    }
+    Ok((&args[1..=numkeys], &args[numkeys + 1..]))
+}
+
+// The optional `LIMIT limit` after the keys of SINTERCARD and ZINTERCARD; 0 means no limit.
+pub fn parse_card_limit(opts: &[Vec<u8>]) -> Result<usize, CommandError> {
+    match opts {
+        [] => Ok(0),
+        [opt, limit] if opt.eq_ignore_ascii_case(b"LIMIT") => {
+            let limit: i64 = parse_int(limit)?;
+            if limit < 0 {
+                return Err(CommandError::Other("LIMIT can't be negative".into()));
+            }
+            Ok(limit as usize)
+        }
+        _ => Err(CommandError::Syntax),
+    }
+}
+
+// Timeout in seconds as BLPOP and friends take it, as a deadline; 0 blocks forever.
+pub fn parse_timeout(arg: &[u8]) -> Result<Option<Instant>, CommandError> {
+    let seconds = parse_float(arg)
+        .ok()
+        .filter(|seconds| seconds.is_finite())
+        .ok_or_else(|| CommandError::Other("timeout is not a float or out of range".into()))?;
+    if seconds < 0.0 {
+        return Err(CommandError::Other("timeout is negative".into()));
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    let timeout = Duration::try_from_secs_f64(seconds)
+        .map_err(|_| CommandError::Other("timeout is out of range".into()))?;
+    Ok(Some(Instant::now() + timeout))
+}
+
+// Runs `attempt` under the write lock until it serves the client, parking the connection on
+// `keys` in between; None once the deadline passes. An error ends the wait. Inside EXEC there
+// is a single attempt, as a transaction never waits.
+pub fn block_on_keys<T>(
+    ctx: &Context,
+    keys: &[Vec<u8>],
+    deadline: Option<Instant>,
+    mut attempt: impl FnMut(&mut DataMap) -> Result<Option<T>, CommandError>,
+) -> Result<Option<T>, CommandError> {
+    let deadline = if ctx.session.in_exec {
+        Some(Instant::now())
+    } else {
+        deadline
+    };
+    let waiter = Arc::new(Waiter::default());
+    loop {
+        {
+            let _gate = (!ctx.session.in_exec).then(|| ctx.server.shared_gate());
+            let _order = (!ctx.session.in_exec).then(|| ctx.server.order_writes());
+            let mut guard = ctx.db.write().unwrap();
+            let result = attempt(&mut guard);
+            if !matches!(result, Ok(None)) {
+                guard.unblock(keys, &waiter);
+                return result;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                guard.unblock(keys, &waiter);
+                return Ok(None);
+            }
+            // Registered under the lock, so a write right after it is released still wakes us.
+            guard.block(keys, &waiter);
+        }
+        waiter.wait(deadline);
+    }
+}
+
+pub fn parse_int<T: FromStr>(arg: &[u8]) -> Result<T, CommandError> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(CommandError::NotInteger)
+}
+
+// Accepts the same spellings as Redis (including inf/-inf), but never NaN.
+pub fn parse_float(arg: &[u8]) -> Result<f64, CommandError> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|f| !f.is_nan())
+        .ok_or(CommandError::NotFloat)
+}
@@ -0,0 +1,101 @@
+use super::{CommandError, CommandResult, Context};
+use crate::{
+    glob,
+    pubsub::{self, Subscriber},
+    resp::Reply,
+};
+
+// Pushes in RESP3, plain arrays in RESP2.
+fn confirmation(kind: &str, channel: Option<&[u8]>, count: usize) -> Reply {
+    Reply::Push(vec![
+        Reply::BulkString(kind.as_bytes().to_vec()),
+        channel.map_or(Reply::Nil, Reply::from),
+        Reply::Integer(count as i64),
+    ])
+}
+
+pub fn subscribe(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut registry = ctx.server.pubsub.lock().unwrap();
+    let session = &mut *ctx.session;
+    let subscriber = Subscriber {
+        outbox: session.outbox.clone(),
+        info: session.info.clone(),
+    };
+    let mut replies = vec![];
+    for channel in &args[1..] {
+        if session.subscriptions.insert(channel.clone()) {
+            registry.subscribe(channel, session.id, &subscriber);
+        }
+        replies.push(confirmation(
+            "subscribe",
+            Some(channel),
+            session.subscriptions.len(),
+        ));
+    }
+    Ok(Reply::Sequence(replies))
+}
+
+pub fn unsubscribe(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut registry = ctx.server.pubsub.lock().unwrap();
+    let session = &mut *ctx.session;
+    let channels: Vec<Vec<u8>> = if args.len() > 1 {
+        args[1..].to_vec()
+    } else {
+        session.subscriptions.iter().cloned().collect()
+    };
+    if channels.is_empty() {
+        return Ok(confirmation("unsubscribe", None, 0));
+    }
+    let mut replies = vec![];
+    for channel in channels {
+        if session.subscriptions.remove(&channel) {
+            registry.unsubscribe(&channel, session.id);
+        }
+        replies.push(confirmation(
+            "unsubscribe",
+            Some(&channel),
+            session.subscriptions.len(),
+        ));
+    }
+    Ok(Reply::Sequence(replies))
+}
+
+// Replies with the number of subscribers the message was queued for.
+pub fn publish(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let receivers = pubsub::publish(ctx.server, &args[1], &args[2]);
+    Ok(Reply::Integer(receivers))
+}
+
+pub fn pubsub(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let registry = ctx.server.pubsub.lock().unwrap();
+    match (args[1].to_ascii_uppercase().as_slice(), args.len()) {
+        (b"CHANNELS", 2..=3) => Ok(Reply::Array(
+            registry
+                .channels()
+                .filter(|channel| {
+                    args.get(2)
+                        .is_none_or(|pattern| glob::matches(pattern, channel))
+                })
+                .map(|channel| Reply::from(channel.as_slice()))
+                .collect(),
+        )),
+        (b"NUMSUB", _) => Ok(Reply::Map(
+            args[2..]
+                .iter()
+                .map(|channel| {
+                    let count = registry.count(channel);
+                    (
+                        Reply::from(channel.as_slice()),
+                        Reply::Integer(count as i64),
+                    )
+                })
+                .collect(),
+        )),
+        // There are no pattern subscriptions to count.
+        (b"NUMPAT", 2) => Ok(Reply::Integer(0)),
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "PUBSUB",
+        )),
+    }
+}
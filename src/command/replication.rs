@@ -0,0 +1,70 @@
+use super::{parse_int, CommandError, CommandResult, Context};
+use crate::{rdb, replication::Replica, resp::Reply};
+
+pub fn replconf(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    if args.len().is_multiple_of(2) {
+        return Err(CommandError::Syntax);
+    }
+    for pair in args[1..].chunks(2) {
+        match pair[0].to_ascii_lowercase().as_slice() {
+            b"listening-port" => ctx.session.replica_port = parse_int(&pair[1])?,
+            b"ip-address" => {
+                ctx.session.replica_ip = Some(String::from_utf8_lossy(&pair[1]).into_owned())
+            }
+            b"capa" => {}
+            // Replicas report how far into the stream they are, and expect no reply.
+            b"ack" if args.len() == 3 => {
+                let offset = parse_int(&pair[1])?;
+                let id = ctx.session.id;
+                ctx.server.replicas.lock().unwrap().acknowledge(id, offset);
+                return Ok(Reply::Sequence(vec![]));
+            }
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "Unrecognized REPLCONF option: {}",
+                    String::from_utf8_lossy(&pair[0])
+                )))
+            }
+        }
+    }
+    Ok(Reply::ok())
+}
+
+// Partial resyncs are never possible, so every replica gets a full one: the stream position,
+// then a snapshot, after which it is fed every write.
+pub fn psync(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    let server = ctx.server;
+    // Holding every other client off keeps writes from slipping in between the snapshot and
+    // the replica joining the stream.
+    let _gate = (!ctx.session.in_exec).then(|| server.exclusive_gate());
+    let snapshot = {
+        let functions = server.functions.lock().unwrap();
+        rdb::snapshot(
+            ctx.databases,
+            functions.iter().map(|library| &library.code[..]),
+        )
+    };
+    let mut replicas = server.replicas.lock().unwrap();
+    let mut out = format!("+FULLRESYNC {} {}\r\n", replicas.replid, replicas.offset).into_bytes();
+    // A bulk string without the trailing CRLF.
+    out.extend_from_slice(format!("${}\r\n", snapshot.len()).as_bytes());
+    out.extend_from_slice(&snapshot);
+    // Queued here rather than returned, so the stream that follows can't overtake it; Redis
+    // writes it straight to the socket too.
+    ctx.session.outbox.push(out, None);
+    let addr = ctx.session.info.lock().unwrap().addr.clone();
+    let ip = match &ctx.session.replica_ip {
+        Some(ip) => ip.clone(),
+        None => addr
+            .rsplit_once(':')
+            .map_or(addr.clone(), |(ip, _)| ip.to_string()),
+    };
+    replicas.attach(Replica {
+        id: ctx.session.id,
+        ip,
+        port: ctx.session.replica_port,
+        ack: 0,
+        outbox: ctx.session.outbox.clone(),
+    });
+    Ok(Reply::Sequence(vec![]))
+}
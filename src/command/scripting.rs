@@ -0,0 +1,596 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use super::{
+    allow_listed, allowed_in_script, call, keyspace::parse_flush_mode, lookup, out_of_memory,
+    parse_int, propagate, CommandError, CommandResult, Context, WRITE,
+};
+use crate::{
+    glob,
+    lua::{self, to_display, Host, Interp, LuaError, Table, Value, Watchdog},
+    rdb,
+    resp::{format_double, Reply},
+    scripting::{compile_library, Libraries},
+    server::BusyReason,
+};
+
+pub fn script(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    match args[1].to_ascii_uppercase().as_slice() {
+        b"KILL" if args.len() == 2 => kill(ctx, BusyReason::Script),
+        b"LOAD" if args.len() == 3 => {
+            let sha = ctx.server.scripts.lock().unwrap().load(&args[2]);
+            Ok(Reply::BulkString(sha.into_bytes()))
+        }
+        b"EXISTS" if args.len() >= 3 => {
+            let scripts = ctx.server.scripts.lock().unwrap();
+            Ok(Reply::Array(
+                args[2..]
+                    .iter()
+                    .map(|sha| Reply::Integer(scripts.contains(sha) as i64))
+                    .collect(),
+            ))
+        }
+        b"FLUSH" if args.len() <= 3 => {
+            let asynchronous = parse_flush_mode(&args[2..])?;
+            let flushed = std::mem::take(&mut *ctx.server.scripts.lock().unwrap());
+            // Freeing a large cache is left to another thread with ASYNC.
+            if asynchronous {
+                std::thread::spawn(move || drop(flushed));
+            }
+            Ok(Reply::ok())
+        }
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "SCRIPT",
+        )),
+    }
+}
+
+// Stops the script keeping the server busy. One that already wrote is left to finish, as
+// stopping it halfway would break its atomicity.
+fn kill(ctx: &Context, reason: BusyReason) -> CommandResult {
+    let server = ctx.server;
+    if !matches!(server.busy(), Some((busy, _)) if busy == reason) {
+        return Err(CommandError::NotBusy);
+    }
+    if server.script_wrote.load(Ordering::Relaxed) {
+        return Err(CommandError::Unkillable);
+    }
+    server.script_kill.store(true, Ordering::Relaxed);
+    Ok(Reply::ok())
+}
+
+pub fn eval(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let sha = ctx.server.scripts.lock().unwrap().load(&args[1]);
+    run_script(ctx, Script::Eval(&args[1], &sha), &args[2..], false)
+}
+
+pub fn evalsha(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let body = ctx.server.scripts.lock().unwrap().get(&args[1]);
+    let Some(body) = body else {
+        return Err(CommandError::NoScript);
+    };
+    let sha = String::from_utf8_lossy(&args[1]).to_ascii_lowercase();
+    run_script(ctx, Script::Eval(&body, &sha), &args[2..], false)
+}
+
+pub fn fcall(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    fcall_generic(ctx, args, false)
+}
+
+pub fn fcall_ro(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    fcall_generic(ctx, args, true)
+}
+
+// Functions flagged no-writes run read-only however they are called, and are the only ones
+// FCALL_RO runs.
+fn fcall_generic(ctx: &mut Context, args: &[Vec<u8>], read_only: bool) -> CommandResult {
+    let name = String::from_utf8_lossy(&args[1]).into_owned();
+    let (code, no_writes) = {
+        let functions = ctx.server.functions.lock().unwrap();
+        let Some((library, info)) = functions.find(&name) else {
+            return Err(CommandError::Other("Function not found".into()));
+        };
+        let no_writes = info.flags.iter().any(|flag| flag == "no-writes");
+        (library.code.clone(), no_writes)
+    };
+    if read_only && !no_writes {
+        return Err(CommandError::Other(
+            "Can not execute a script with write flag using *_ro command.".into(),
+        ));
+    }
+    run_script(ctx, Script::Function(&code, &name), &args[2..], no_writes)
+}
+
+enum Script<'s> {
+    // EVAL and EVALSHA: the body and its digest.
+    Eval(&'s [u8], &'s str),
+    // FCALL: the code of the library and the function to call from it.
+    Function(&'s [u8], &'s str),
+}
+
+// Runs a script with the keys and arguments of `numkeys key [key ...] arg [arg ...]`, holding
+// every other client off until it is done.
+fn run_script(
+    ctx: &mut Context,
+    script: Script,
+    args: &[Vec<u8>],
+    read_only: bool,
+) -> CommandResult {
+    let numkeys: i64 = parse_int(&args[0])?;
+    if numkeys < 0 {
+        return Err(CommandError::Other(
+            "Number of keys can't be negative".into(),
+        ));
+    }
+    if numkeys as usize > args.len() - 1 {
+        return Err(CommandError::Other(
+            "Number of keys can't be greater than number of args".into(),
+        ));
+    }
+    let (keys, argv) = args[1..].split_at(numkeys as usize);
+    let server = ctx.server;
+    let _gate = (!ctx.session.in_exec).then(|| server.exclusive_gate());
+    // Past the threshold the server turns busy, so other clients get an error instead of
+    // waiting, and SCRIPT KILL or FUNCTION KILL can stop the script.
+    let (reason, killed) = match script {
+        Script::Eval(..) => (BusyReason::Script, "SCRIPT KILL"),
+        Script::Function(..) => (BusyReason::Function, "FUNCTION KILL"),
+    };
+    let threshold = Duration::from_millis(server.config.lock().unwrap().busy_reply_threshold);
+    let started = Instant::now();
+    let mut busy = None;
+    server.script_kill.store(false, Ordering::Relaxed);
+    server.script_wrote.store(false, Ordering::Relaxed);
+    let watchdog = move || {
+        if busy.is_none() && started.elapsed() >= threshold {
+            busy = server.begin_busy(reason);
+        }
+        server
+            .script_kill
+            .swap(false, Ordering::Relaxed)
+            .then(|| format!("Script killed by user with {killed}..."))
+    };
+    // Scripts run their commands the way EXEC does, and a SELECT in one stays in it.
+    let (db, in_exec) = (ctx.session.db, ctx.session.in_exec);
+    ctx.session.in_exec = true;
+    let mut host = ScriptHost {
+        ctx: &mut *ctx,
+        read_only,
+    };
+    let result = lua::on_script_stack(|| run(&mut host, Box::new(watchdog), script, keys, argv))
+        .unwrap_or_else(|e| {
+            Err(CommandError::Other(format!(
+                "failed to start the script: {e}"
+            )))
+        });
+    ctx.session.db = db;
+    ctx.db = &ctx.databases[db];
+    ctx.session.in_exec = in_exec;
+    result
+}
+
+fn run(
+    host: &mut dyn Host,
+    watchdog: Watchdog,
+    script: Script,
+    keys: &[Vec<u8>],
+    argv: &[Vec<u8>],
+) -> CommandResult {
+    let mut interp = Interp::new(Some(host));
+    interp.watchdog = Some(watchdog);
+    let (f, args, name) = match script {
+        Script::Eval(body, sha) => {
+            let f = interp.load(body).map_err(|e| {
+                CommandError::Other(format!("Error compiling script (new function): {e}"))
+            })?;
+            let mut globals = interp.globals.borrow_mut();
+            globals.set_str("KEYS", strings(keys));
+            globals.set_str("ARGV", strings(argv));
+            drop(globals);
+            (f, vec![], sha)
+        }
+        Script::Function(code, name) => {
+            let registered = interp
+                .register_functions(code)
+                .map_err(CommandError::Other)?;
+            let Some(function) = registered
+                .into_iter()
+                .find(|function| function.name == name)
+            else {
+                return Err(CommandError::Other("Function not found".into()));
+            };
+            (function.callback, vec![strings(keys), strings(argv)], name)
+        }
+    };
+    match interp.call(&f, args) {
+        Ok(values) => Ok(to_reply(&values.into_iter().next().unwrap_or_default(), 0)),
+        Err(LuaError(error)) => {
+            let location = format!("script: {name}, on @{}:{}.", interp.chunk, interp.line);
+            Err(CommandError::Script(match error_message(&error) {
+                Some(message) => format!("{message} {location}"),
+                None => format!(
+                    "ERR {} {location}",
+                    String::from_utf8_lossy(&to_display(&error))
+                ),
+            }))
+        }
+    }
+}
+
+pub fn function(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let other = |message: String| CommandError::Other(message);
+    match args[1].to_ascii_uppercase().as_slice() {
+        b"KILL" if args.len() == 2 => kill(ctx, BusyReason::Function),
+        b"LOAD" if matches!(args.len(), 3 | 4) => {
+            let replace = match &args[2..] {
+                [_] => false,
+                [option, _] if option.eq_ignore_ascii_case(b"REPLACE") => true,
+                [option, _] => {
+                    return Err(other(format!(
+                        "Unknown option given: {}",
+                        String::from_utf8_lossy(option)
+                    )))
+                }
+                _ => unreachable!(),
+            };
+            let library = compile_library(&args[args.len() - 1]).map_err(other)?;
+            let name = library.name.clone();
+            ctx.server
+                .functions
+                .lock()
+                .unwrap()
+                .add(library, replace)
+                .map_err(other)?;
+            propagate(ctx, args);
+            Ok(Reply::BulkString(name.into_bytes()))
+        }
+        b"DELETE" if args.len() == 3 => {
+            let name = String::from_utf8_lossy(&args[2]);
+            if !ctx.server.functions.lock().unwrap().remove(&name) {
+                return Err(other("Library not found".into()));
+            }
+            propagate(ctx, args);
+            Ok(Reply::ok())
+        }
+        b"LIST" => {
+            let mut with_code = false;
+            let mut pattern = None;
+            let mut opts = args[2..].iter();
+            while let Some(opt) = opts.next() {
+                match opt.to_ascii_uppercase().as_slice() {
+                    b"WITHCODE" => with_code = true,
+                    b"LIBRARYNAME" => {
+                        let Some(value) = opts.next() else {
+                            return Err(other("library name argument was not given".into()));
+                        };
+                        pattern = Some(value);
+                    }
+                    _ => {
+                        return Err(other(format!(
+                            "Unknown argument {}",
+                            String::from_utf8_lossy(opt)
+                        )))
+                    }
+                }
+            }
+            let field = |s: &str| Reply::BulkString(s.as_bytes().to_vec());
+            let functions = ctx.server.functions.lock().unwrap();
+            Ok(Reply::Array(
+                functions
+                    .iter()
+                    .filter(|library| {
+                        pattern
+                            .is_none_or(|pattern| glob::matches(pattern, library.name.as_bytes()))
+                    })
+                    .map(|library| {
+                        let mut entries = vec![
+                            (field("library_name"), field(&library.name)),
+                            (field("engine"), field("LUA")),
+                            (
+                                field("functions"),
+                                Reply::Array(
+                                    library
+                                        .functions
+                                        .iter()
+                                        .map(|info| {
+                                            Reply::Map(vec![
+                                                (field("name"), field(&info.name)),
+                                                (
+                                                    field("description"),
+                                                    info.description
+                                                        .as_deref()
+                                                        .map_or(Reply::Nil, field),
+                                                ),
+                                                (
+                                                    field("flags"),
+                                                    Reply::Set(
+                                                        info.flags
+                                                            .iter()
+                                                            .map(|f| field(f))
+                                                            .collect(),
+                                                    ),
+                                                ),
+                                            ])
+                                        })
+                                        .collect(),
+                                ),
+                            ),
+                        ];
+                        if with_code {
+                            entries.push((field("library_code"), library.code.clone().into()));
+                        }
+                        Reply::Map(entries)
+                    })
+                    .collect(),
+            ))
+        }
+        b"DUMP" if args.len() == 2 => {
+            let functions = ctx.server.functions.lock().unwrap();
+            let payload = rdb::dump_functions(functions.iter().map(|library| &library.code[..]));
+            Ok(Reply::BulkString(payload))
+        }
+        b"RESTORE" if matches!(args.len(), 3 | 4) => {
+            let policy = args.get(3).map(|policy| policy.to_ascii_uppercase());
+            if !matches!(
+                policy.as_deref(),
+                None | Some(b"APPEND" | b"REPLACE" | b"FLUSH")
+            ) {
+                return Err(other(
+                    "Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+                        .into(),
+                ));
+            }
+            let codes = rdb::verify_dump(&args[2])
+                .and_then(rdb::read_functions)
+                .ok_or_else(|| other("payload version or checksum are wrong".into()))?;
+            let libraries = codes
+                .iter()
+                .map(|code| compile_library(code))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(other)?;
+            let mut functions = ctx.server.functions.lock().unwrap();
+            // Applied to a copy, so a conflict halfway leaves everything as it was.
+            let mut restored = match policy.as_deref() {
+                Some(b"FLUSH") => Libraries::default(),
+                _ => functions.clone(),
+            };
+            let replace = policy.as_deref() == Some(b"REPLACE");
+            for library in libraries {
+                restored.add(library, replace).map_err(other)?;
+            }
+            *functions = restored;
+            drop(functions);
+            propagate(ctx, args);
+            Ok(Reply::ok())
+        }
+        b"FLUSH" if args.len() <= 3 => {
+            parse_flush_mode(&args[2..])?;
+            *ctx.server.functions.lock().unwrap() = Libraries::default();
+            propagate(ctx, args);
+            Ok(Reply::ok())
+        }
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "FUNCTION",
+        )),
+    }
+}
+
+fn strings(items: &[Vec<u8>]) -> Value {
+    Value::table(Table::from_array(items.iter().map(Value::str).collect()))
+}
+
+// The message of an {err=...} table, as redis.call raises and redis.error_reply makes.
+fn error_message(value: &Value) -> Option<String> {
+    let Value::Table(table) = value else {
+        return None;
+    };
+    let message = table.borrow().get_str("err").to_bytes()?;
+    Some(String::from_utf8_lossy(&message).into_owned())
+}
+
+struct ScriptHost<'c, 'a> {
+    ctx: &'c mut Context<'a>,
+    read_only: bool,
+}
+
+impl Host for ScriptHost<'_, '_> {
+    fn call(&mut self, args: Vec<Vec<u8>>) -> Value {
+        let ctx = &mut *self.ctx;
+        let result = match lookup(&args[0]) {
+            None => Err(CommandError::Other(
+                "Unknown Redis command called from script".into(),
+            )),
+            Some(spec) if !spec.accepts(args.len()) => Err(CommandError::Other(
+                "Wrong number of args calling Redis command from script".into(),
+            )),
+            Some(spec) if !allowed_in_script(spec) => Err(CommandError::Other(
+                "This Redis command is not allowed from script".into(),
+            )),
+            Some(spec) if !allow_listed(ctx, spec) => Err(CommandError::Unsupported(spec.name)),
+            Some(spec) if self.read_only && spec.flags & WRITE != 0 => Err(CommandError::Other(
+                "Write commands are not allowed from read-only scripts.".into(),
+            )),
+            // Only clients run scripts on a replica; the master streams a script's effects.
+            Some(spec) if ctx.server.master.is_some() && spec.flags & WRITE != 0 => {
+                Err(CommandError::ReadOnly)
+            }
+            Some(spec) if out_of_memory(ctx, spec, &args) => Err(CommandError::OutOfMemory),
+            Some(spec) => {
+                if spec.flags & WRITE != 0 {
+                    ctx.server.script_wrote.store(true, Ordering::Relaxed);
+                }
+                let databases = ctx.databases;
+                ctx.db = &databases[ctx.session.db];
+                call(ctx, spec, &args)
+            }
+        };
+        to_lua(result.unwrap_or_else(|e| Reply::Error(e.to_string())))
+    }
+}
+
+// Replies as a script sees them: RESP2 types, with status and error replies as {ok=...} and
+// {err=...} tables and nil as false.
+fn to_lua(reply: Reply) -> Value {
+    let single = |field: &str, message: String| {
+        let mut table = Table::default();
+        table.set_str(field, Value::str(message));
+        Value::table(table)
+    };
+    let array = |items: Vec<Reply>| {
+        Value::table(Table::from_array(items.into_iter().map(to_lua).collect()))
+    };
+    match reply {
+        Reply::SimpleString(s) => single("ok", s),
+        Reply::Error(e) => single("err", e),
+        Reply::Integer(n) => Value::Num(n as f64),
+        Reply::BulkString(s) | Reply::Verbatim(_, s) => Value::str(s),
+        Reply::Nil | Reply::NilArray | Reply::Attribute(_) => Value::Bool(false),
+        Reply::Array(items) | Reply::Set(items) | Reply::Push(items) | Reply::Sequence(items) => {
+            array(items)
+        }
+        Reply::Map(pairs) => array(pairs.into_iter().flat_map(|(k, v)| [k, v]).collect()),
+        Reply::Double(d) => Value::str(format_double(d)),
+        Reply::BigNumber(n) => Value::str(n),
+        Reply::Boolean(b) => Value::Num(b as i64 as f64),
+    }
+}
+
+// Tables nested deeper than this in a script's result are replaced with an error, which also
+// stops a table that contains itself.
+const MAX_REPLY_DEPTH: usize = 100;
+
+// What a script returns, as the client gets it: numbers truncated to integers, arrays up to the
+// first nil, and true as 1.
+fn to_reply(value: &Value, depth: usize) -> Reply {
+    match value {
+        Value::Num(n) => Reply::Integer(*n as i64),
+        Value::Str(s) => Reply::BulkString(s.to_vec()),
+        Value::Bool(true) => Reply::Integer(1),
+        Value::Bool(false) | Value::Nil | Value::Function(_) => Reply::Nil,
+        Value::Table(_) if depth == MAX_REPLY_DEPTH => {
+            Reply::Error("ERR reached lua stack limit".into())
+        }
+        Value::Table(table) => {
+            let table = table.borrow();
+            if let Some(message) = error_message(value) {
+                return Reply::Error(message);
+            }
+            if let Some(status) = table.get_str("ok").to_bytes() {
+                return Reply::SimpleString(String::from_utf8_lossy(&status).into_owned());
+            }
+            Reply::Array(
+                table
+                    .array()
+                    .iter()
+                    .take_while(|item| !matches!(item, Value::Nil))
+                    .map(|item| to_reply(item, depth + 1))
+                    .collect(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Answers every redis.call with the same reply.
+    struct Replier(Reply);
+
+    impl Host for Replier {
+        fn call(&mut self, _args: Vec<Vec<u8>>) -> Value {
+            to_lua(self.0.clone())
+        }
+    }
+
+    fn eval(body: &str, reply: Reply) -> Reply {
+        lua::on_script_stack(|| {
+            let mut host = Replier(reply);
+            let script = Script::Eval(body.as_bytes(), "test");
+            run(&mut host, Box::new(|| None), script, &[], &[])
+        })
+        .unwrap()
+        .unwrap_or_else(|e| Reply::Error(e.to_string()))
+    }
+
+    fn bulk(s: &str) -> Reply {
+        Reply::BulkString(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn replies_round_trip() {
+        let script = "return redis.pcall('x')";
+        for reply in [
+            Reply::Integer(-3),
+            bulk("value"),
+            Reply::SimpleString("OK".into()),
+            Reply::Error("ERR wrong".into()),
+            Reply::Array(vec![
+                Reply::Integer(1),
+                bulk("a"),
+                Reply::Array(vec![bulk("b")]),
+            ]),
+        ] {
+            assert_eq!(eval(script, reply.clone()), reply);
+        }
+        // Nil comes in as false, which goes back as nil.
+        assert_eq!(eval(script, Reply::Nil), Reply::Nil);
+        assert_eq!(
+            eval(script, Reply::Array(vec![Reply::Nil, Reply::Integer(2)])),
+            Reply::Array(vec![Reply::Nil, Reply::Integer(2)])
+        );
+        // Maps come in flattened, and RESP3 scalars as the closest Lua value.
+        assert_eq!(
+            eval(script, Reply::Map(vec![(bulk("k"), bulk("v"))])),
+            Reply::Array(vec![bulk("k"), bulk("v")])
+        );
+        assert_eq!(eval(script, Reply::Boolean(true)), Reply::Integer(1));
+        assert_eq!(eval(script, Reply::Double(1.5)), bulk("1.5"));
+    }
+
+    #[test]
+    fn lua_values_as_replies() {
+        let eval = |body| eval(body, Reply::Nil);
+        assert_eq!(eval("return 3.99"), Reply::Integer(3));
+        assert_eq!(eval("return true"), Reply::Integer(1));
+        assert_eq!(eval("return false"), Reply::Nil);
+        assert_eq!(eval("return 'x'"), bulk("x"));
+        assert_eq!(
+            eval("return {1, 'a', nil, 2}"),
+            Reply::Array(vec![Reply::Integer(1), bulk("a")])
+        );
+        assert_eq!(eval("return {k = 1}"), Reply::Array(vec![]));
+        assert_eq!(
+            eval("return redis.status_reply('FINE')"),
+            Reply::SimpleString("FINE".into())
+        );
+        assert_eq!(
+            eval("return {err = 'ERR custom'}"),
+            Reply::Error("ERR custom".into())
+        );
+    }
+
+    #[test]
+    fn nesting_is_bounded() {
+        let depth = |mut reply: &Reply| {
+            let mut depth = 0;
+            while let Reply::Array(items) = reply {
+                depth += 1;
+                reply = &items[0];
+            }
+            (depth, reply.clone())
+        };
+        let own = eval("local t = {} t[1] = t return t", Reply::Nil);
+        let limit = Reply::Error("ERR reached lua stack limit".into());
+        assert_eq!(depth(&own), (MAX_REPLY_DEPTH, limit.clone()));
+        let deep = eval(
+            "local t = {} for i = 1, 300 do t = {t} end return t",
+            Reply::Nil,
+        );
+        assert_eq!(depth(&deep), (MAX_REPLY_DEPTH, limit));
+    }
+}
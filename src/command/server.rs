@@ -0,0 +1,356 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use super::{connection::SERVER_VERSION, parse_float, CommandError, CommandResult, Context};
+use crate::{
+    db, memory, notify,
+    resp::{Protocol, Reply},
+    server::BusyReason,
+};
+
+fn server_section(ctx: &Context) -> Vec<(String, String)> {
+    let server = ctx.server;
+    let uptime = server.uptime().as_secs();
+    let (busy, reason, duration) = match server.busy() {
+        Some((reason, elapsed)) => (1, reason.name(), elapsed.as_millis()),
+        None => (0, "", 0),
+    };
+    vec![
+        ("redis_version".into(), SERVER_VERSION.into()),
+        ("redis_mode".into(), "standalone".into()),
+        ("process_id".into(), std::process::id().to_string()),
+        ("tcp_port".into(), server.port.to_string()),
+        ("uptime_in_seconds".into(), uptime.to_string()),
+        ("uptime_in_days".into(), (uptime / 86400).to_string()),
+        ("busy".into(), busy.to_string()),
+        ("busy_reason".into(), reason.into()),
+        ("busy_duration_ms".into(), duration.to_string()),
+        (
+            "bulk_load".into(),
+            (server.bulk_load.load(Ordering::Relaxed) as u8).to_string(),
+        ),
+    ]
+}
+
+fn memory_section(ctx: &Context) -> Vec<(String, String)> {
+    let maxmemory = ctx.server.config.lock().unwrap().maxmemory;
+    let used = memory::used();
+    let scripts = ctx.server.scripts.lock().unwrap();
+    let functions = ctx.server.functions.lock().unwrap();
+    vec![
+        ("used_memory".into(), used.to_string()),
+        (
+            "used_memory_peak".into(),
+            memory::peak().max(used).to_string(),
+        ),
+        (
+            "used_memory_scripts_eval".into(),
+            scripts.memory().to_string(),
+        ),
+        ("number_of_cached_scripts".into(), scripts.len().to_string()),
+        (
+            "used_memory_functions".into(),
+            functions.memory().to_string(),
+        ),
+        (
+            "number_of_functions".into(),
+            functions.function_count().to_string(),
+        ),
+        ("number_of_libraries".into(), functions.len().to_string()),
+        (
+            "used_memory_scripts".into(),
+            (scripts.memory() + functions.memory()).to_string(),
+        ),
+        ("maxmemory".into(), maxmemory.to_string()),
+        ("maxmemory_policy".into(), "noeviction".into()),
+    ]
+}
+
+fn stats_section(ctx: &Context) -> Vec<(String, String)> {
+    let server = ctx.server;
+    let rejected = server.rejected_connections.load(Ordering::Relaxed);
+    let evicted = server.pubsub_clients_evicted.load(Ordering::Relaxed);
+    vec![
+        ("rejected_connections".into(), rejected.to_string()),
+        ("pubsub_clients_evicted".into(), evicted.to_string()),
+    ]
+}
+
+fn replication_section(ctx: &Context) -> Vec<(String, String)> {
+    let server = ctx.server;
+    let Some((host, port)) = &server.master else {
+        let replicas = server.replicas.lock().unwrap();
+        let mut fields = vec![
+            ("role".into(), "master".into()),
+            (
+                "connected_slaves".into(),
+                replicas.iter().count().to_string(),
+            ),
+        ];
+        for (index, replica) in replicas.iter().enumerate() {
+            fields.push((
+                format!("slave{index}"),
+                format!(
+                    "ip={},port={},state=online,offset={}",
+                    replica.ip, replica.port, replica.ack
+                ),
+            ));
+        }
+        fields.push(("master_replid".into(), replicas.replid.clone()));
+        fields.push(("master_repl_offset".into(), replicas.offset.to_string()));
+        return fields;
+    };
+    let link = server.master_link.lock().unwrap();
+    let (status, replid, offset) = match &*link {
+        Some(link) => ("up", link.replid.clone(), link.offset),
+        None => ("down", String::new(), 0),
+    };
+    vec![
+        ("role".into(), "slave".into()),
+        ("master_host".into(), host.clone()),
+        ("master_port".into(), port.to_string()),
+        ("master_link_status".into(), status.into()),
+        ("slave_repl_offset".into(), offset.to_string()),
+        ("master_replid".into(), replid),
+    ]
+}
+
+type Section = fn(&Context) -> Vec<(String, String)>;
+
+static SECTIONS: &[(&str, Section)] = &[
+    ("server", server_section),
+    ("memory", memory_section),
+    ("stats", stats_section),
+    ("replication", replication_section),
+];
+
+pub fn info(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let requested: Vec<String> = args[1..]
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg).to_ascii_lowercase())
+        .collect();
+    let everything = requested.is_empty()
+        || requested
+            .iter()
+            .any(|name| matches!(name.as_str(), "default" | "all" | "everything"));
+    let mut out = String::new();
+    for (name, section) in SECTIONS {
+        if !everything && !requested.iter().any(|requested| requested == name) {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str("\r\n");
+        }
+        let mut title = name.to_string();
+        title[..1].make_ascii_uppercase();
+        out.push_str(&format!("# {title}\r\n"));
+        for (field, value) in section(ctx) {
+            out.push_str(&format!("{field}:{value}\r\n"));
+        }
+    }
+    Ok(Reply::Verbatim("txt", out.into_bytes()))
+}
+
+pub fn config(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    match args[1].to_ascii_uppercase().as_slice() {
+        b"GET" if args.len() >= 3 => {
+            let config = ctx.server.config.lock().unwrap();
+            Ok(Reply::Map(
+                config
+                    .get(&args[2..])
+                    .into_iter()
+                    .map(|(name, value)| {
+                        (
+                            Reply::from(name.as_bytes()),
+                            Reply::from(value.into_bytes()),
+                        )
+                    })
+                    .collect(),
+            ))
+        }
+        b"SET" if args.len() >= 4 && args.len().is_multiple_of(2) => {
+            let pairs: Vec<_> = args[2..].chunks_exact(2).collect();
+            for (i, pair) in pairs.iter().enumerate() {
+                if pairs[..i]
+                    .iter()
+                    .any(|seen| seen[0].eq_ignore_ascii_case(&pair[0]))
+                {
+                    return Err(CommandError::Other(format!(
+                        "CONFIG SET failed - duplicate parameter '{}'",
+                        String::from_utf8_lossy(&pair[0])
+                    )));
+                }
+            }
+            // All or nothing: the changes are applied to a copy that replaces the live config
+            // only if every parameter was accepted.
+            let mut config = ctx.server.config.lock().unwrap();
+            let mut updated = config.clone();
+            for pair in pairs {
+                updated.set(&pair[0], &pair[1])?;
+            }
+            *config = updated;
+            Ok(Reply::ok())
+        }
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "CONFIG",
+        )),
+    }
+}
+
+pub fn latency(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut monitor = ctx.server.latency.lock().unwrap();
+    match args[1].to_ascii_uppercase().as_slice() {
+        b"LATEST" if args.len() == 2 => Ok(Reply::Array(
+            monitor
+                .latest()
+                .map(|(name, event)| {
+                    Reply::Array(vec![
+                        Reply::from(name.as_bytes()),
+                        Reply::Integer(event.time),
+                        Reply::Integer(event.latest_ms as i64),
+                        Reply::Integer(event.max_ms as i64),
+                    ])
+                })
+                .collect(),
+        )),
+        b"RESET" => Ok(Reply::Integer(monitor.reset(&args[2..]) as i64)),
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "LATENCY",
+        )),
+    }
+}
+
+pub fn memory(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    match args[1].to_ascii_uppercase().as_slice() {
+        b"STATS" if args.len() == 2 => {
+            let keys: usize = ctx
+                .databases
+                .iter()
+                .map(|db| db.read().unwrap().len())
+                .sum();
+            let scripts = ctx.server.scripts.lock().unwrap().memory();
+            let functions = ctx.server.functions.lock().unwrap().memory();
+            let used = memory::used();
+            let stat = |name: &str, value: usize| {
+                (Reply::from(name.as_bytes()), Reply::Integer(value as i64))
+            };
+            Ok(Reply::Map(vec![
+                stat("peak.allocated", memory::peak().max(used)),
+                stat("total.allocated", used),
+                stat("keys.count", keys),
+                stat("lua.caches", scripts),
+                stat("functions.caches", functions),
+            ]))
+        }
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "MEMORY",
+        )),
+    }
+}
+
+pub fn debug(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    match args[1].to_ascii_uppercase().as_slice() {
+        b"SLEEP" if args.len() == 3 => {
+            let seconds = parse_float(&args[2])?;
+            let duration = Duration::try_from_secs_f64(seconds).unwrap_or_default();
+            // Other connections see the server as busy while this one sleeps.
+            let _busy = ctx
+                .server
+                .begin_busy(BusyReason::DebugSleep)
+                .ok_or_else(|| CommandError::Other("server is already busy".into()))?;
+            std::thread::sleep(duration);
+            Ok(Reply::ok())
+        }
+        b"DIGEST" if args.len() == 2 => {
+            let digest = ctx
+                .databases
+                .iter()
+                .fold(0, |digest, db| digest ^ db::digest(db));
+            Ok(Reply::SimpleString(format!("{digest:016x}")))
+        }
+        b"PROTOCOL" if args.len() == 3 => debug_protocol(ctx, &args[2]),
+        b"LOADING-MODE" if args.len() == 3 => {
+            let on = match args[2].to_ascii_uppercase().as_slice() {
+                b"ON" => true,
+                b"OFF" => false,
+                _ => return Err(CommandError::Syntax),
+            };
+            let was_on = ctx.server.bulk_load.swap(on, Ordering::Relaxed);
+            if was_on && !on {
+                // Catch up on everything the active expire cycle skipped in one sweep.
+                db::remove_all_expired(ctx.databases);
+                for (index, db) in ctx.databases.iter().enumerate() {
+                    notify::expired(ctx.server, db, index);
+                }
+            }
+            Ok(Reply::ok())
+        }
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "DEBUG",
+        )),
+    }
+}
+
+// One sample of each reply type, for client libraries to test their decoders against. The
+// samples are the same as Redis', 3.141 included.
+#[allow(clippy::approx_constant)]
+fn debug_protocol(ctx: &Context, name: &[u8]) -> CommandResult {
+    let text = |s: &str| Reply::BulkString(s.as_bytes().to_vec());
+    Ok(match name.to_ascii_lowercase().as_slice() {
+        b"string" => text("Hello World"),
+        b"integer" => Reply::Integer(12345),
+        b"double" => Reply::Double(3.141),
+        b"bignum" => Reply::BigNumber("1234567999999999999999999999999999999".into()),
+        b"null" => Reply::Nil,
+        b"array" => Reply::Array((0..3).map(Reply::Integer).collect()),
+        b"set" => Reply::Set((0..3).map(Reply::Integer).collect()),
+        b"map" => Reply::Map(
+            (0..3)
+                .map(|i| (Reply::Integer(i), Reply::Boolean(i == 1)))
+                .collect(),
+        ),
+        b"attrib" => Reply::Sequence(vec![
+            Reply::Attribute(vec![(
+                text("key-popularity"),
+                Reply::Array(vec![text("key:123"), Reply::Integer(90)]),
+            )]),
+            text("Some real reply following the attribute"),
+        ]),
+        b"push" if ctx.session.protocol == Protocol::Resp2 => {
+            return Err(CommandError::Other(
+                "RESP2 is not supported by this command".into(),
+            ))
+        }
+        // Followed by a regular reply, so clients that skip pushes still get an answer.
+        b"push" => Reply::Sequence(vec![
+            Reply::Push(vec![text("server-cpu-usage"), Reply::Integer(42)]),
+            text("Some real reply following the push reply"),
+        ]),
+        b"verbatim" => Reply::Verbatim("txt", b"This is a verbatim\nstring".to_vec()),
+        b"true" => Reply::Boolean(true),
+        b"false" => Reply::Boolean(false),
+        _ => {
+            return Err(CommandError::Other(
+                "Wrong protocol type name. Please use one of the following: \
+                 string|integer|double|bignum|null|array|set|map|attrib|push|verbatim|true|false"
+                    .into(),
+            ))
+        }
+    })
+}
+
+pub fn shutdown(_ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    for arg in &args[1..] {
+        match arg.to_ascii_uppercase().as_slice() {
+            // Nothing is persisted yet, so SAVE and NOSAVE behave the same.
+            b"NOSAVE" | b"SAVE" | b"NOW" | b"FORCE" => {}
+            b"ABORT" => return Err(CommandError::Other("No shutdown in progress.".into())),
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    println!("shutting down");
+    std::process::exit(0)
+}
@@ -0,0 +1,256 @@
+use super::{
+    keyspace::{scan_reply, ScanArgs},
+    notify, parse_card_limit, parse_int, parse_numkeys, propagate, CommandError, CommandResult,
+    Context,
+};
+use crate::{
+    db::{self, DataMap, MapValue, Value},
+    resp::Reply,
+    types::set::{Set, MAX_REPEATED_SAMPLE},
+};
+
+fn as_set(value: &MapValue) -> Result<&Set, CommandError> {
+    match &value.data {
+        Value::Set(set) => Ok(set),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+fn set_or_create<'a>(map: &'a mut DataMap, key: &[u8]) -> Result<&'a mut Set, CommandError> {
+    match &mut map.get_or_insert_with(key, || Value::Set(Set::new())).data {
+        Value::Set(set) => Ok(set),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+pub fn sadd(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    let set = set_or_create(&mut guard, &args[1])?;
+    let added = args[2..].iter().filter(|member| set.insert(member)).count();
+    if added > 0 {
+        notify(ctx, notify::SET, "sadd", &args[1]);
+    }
+    Ok(Reply::Integer(added as i64))
+}
+
+pub fn srem(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let Value::Set(set) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let removed = args[2..].iter().filter(|member| set.remove(member)).count();
+    let emptied = set.is_empty();
+    if removed > 0 {
+        notify(ctx, notify::SET, "srem", &args[1]);
+    }
+    if emptied {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    }
+    Ok(Reply::Integer(removed as i64))
+}
+
+// Both keys are checked and updated under one write lock, so no reader ever sees the member
+// in neither or both sets.
+pub fn smove(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let (source, destination, member) = (&args[1], &args[2], &args[3]);
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get(source) else {
+        return Ok(Reply::Integer(0));
+    };
+    let present = as_set(value)?.contains(member);
+    if let Some(value) = guard.get(destination) {
+        as_set(value)?;
+    }
+    if !present {
+        return Ok(Reply::Integer(0));
+    }
+    if source == destination {
+        return Ok(Reply::Integer(1));
+    }
+    if let Some(Value::Set(set)) = guard.get_mut(source).map(|value| &mut value.data) {
+        set.remove(member);
+        let emptied = set.is_empty();
+        notify(ctx, notify::SET, "srem", source);
+        if emptied {
+            guard.remove(source);
+            notify(ctx, notify::GENERIC, "del", source);
+        }
+    }
+    set_or_create(&mut guard, destination)?.insert(member);
+    notify(ctx, notify::SET, "sadd", destination);
+    Ok(Reply::Integer(1))
+}
+
+// A set in RESP3; RESP2 clients see a plain array.
+pub fn smembers(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Set(vec![]));
+    };
+    Ok(Reply::Set(
+        as_set(value)?
+            .members()
+            .into_iter()
+            .map(Reply::BulkString)
+            .collect(),
+    ))
+}
+
+pub fn scard(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let len = match guard.get(&args[1]) {
+        Some(value) => as_set(value)?.len(),
+        None => 0,
+    };
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn sismember(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let found = match guard.get(&args[1]) {
+        Some(value) => as_set(value)?.contains(&args[2]),
+        None => false,
+    };
+    Ok(Reply::Integer(found as i64))
+}
+
+// Journaled as an SREM of the members it happened to pick.
+pub fn spop(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let count = match args {
+        [_, _] => None,
+        [_, _, count] => match parse_int::<i64>(count) {
+            Ok(count) if count >= 0 => Some(count as usize),
+            _ => {
+                return Err(CommandError::Other(
+                    "value is out of range, must be positive".into(),
+                ))
+            }
+        },
+        _ => return Err(CommandError::Syntax),
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(if count.is_some() {
+            Reply::Set(vec![])
+        } else {
+            Reply::Nil
+        });
+    };
+    let Value::Set(set) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let popped = set.pop(count.unwrap_or(1));
+    let emptied = set.is_empty();
+    if !popped.is_empty() {
+        notify(ctx, notify::SET, "spop", &args[1]);
+    }
+    if emptied {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    }
+    if !popped.is_empty() {
+        let mut srem = vec![b"SREM".to_vec(), args[1].clone()];
+        srem.extend(popped.iter().cloned());
+        propagate(ctx, &srem);
+    }
+    Ok(match count {
+        Some(_) => Reply::Set(popped.into_iter().map(Reply::BulkString).collect()),
+        None => Reply::from(popped.into_iter().next()),
+    })
+}
+
+pub fn smismember(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let set = guard.get(&args[1]).map(as_set).transpose()?;
+    Ok(Reply::Array(
+        args[2..]
+            .iter()
+            .map(|member| Reply::Integer(set.is_some_and(|set| set.contains(member)) as i64))
+            .collect(),
+    ))
+}
+
+// The count of SRANDMEMBER and ZRANDMEMBER: how many members, and whether they may repeat, as
+// a negative count asks for.
+pub fn parse_sample_count(arg: &[u8]) -> Result<(usize, bool), CommandError> {
+    let count: i64 = parse_int(arg)?;
+    if count == i64::MIN || count < 0 && count.unsigned_abs() as usize > MAX_REPEATED_SAMPLE {
+        return Err(CommandError::Other("value is out of range".into()));
+    }
+    Ok((count.unsigned_abs() as usize, count < 0))
+}
+
+pub fn srandmember(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let count = match args {
+        [_, _] => None,
+        [_, _, count] => Some(parse_sample_count(count)?),
+        _ => return Err(CommandError::Syntax),
+    };
+    let guard = ctx.db.read().unwrap();
+    let set = match guard.get(&args[1]) {
+        Some(value) => Some(as_set(value)?),
+        None => None,
+    };
+    match (set, count) {
+        (None, None) => Ok(Reply::Nil),
+        (None, Some(_)) => Ok(Reply::Array(vec![])),
+        (Some(set), None) => Ok(Reply::from(set.random_member())),
+        (Some(set), Some((count, allow_duplicates))) => Ok(Reply::Array(
+            set.sample(count, allow_duplicates)
+                .into_iter()
+                .map(Reply::from)
+                .collect(),
+        )),
+    }
+}
+
+// Counts the members of the intersection, stopping early once LIMIT is reached.
+pub fn sintercard(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let (keys, opts) = parse_numkeys(&args[1..])?;
+    let limit = parse_card_limit(opts)?;
+    let guard = ctx.db.read().unwrap();
+    let mut sets = vec![];
+    for key in keys {
+        match guard.get(key) {
+            Some(value) => sets.push(as_set(value)?),
+            None => return Ok(Reply::Integer(0)),
+        }
+    }
+    sets.sort_by_key(|set| set.len());
+    let mut count = 0;
+    for member in sets[0].members() {
+        if sets[1..].iter().all(|set| set.contains(&member)) {
+            count += 1;
+            if count == limit {
+                break;
+            }
+        }
+    }
+    Ok(Reply::Integer(count as i64))
+}
+
+pub fn sscan(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let scan = ScanArgs::parse(&args[2..])?;
+    if scan.type_filter.is_some() || scan.novalues {
+        return Err(CommandError::Syntax);
+    }
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(scan_reply(0, vec![]));
+    };
+    let members = as_set(value)?.members();
+    let members = members.iter().map(|member| (member.as_slice(), member));
+    let (next, batch) = db::scan_unindexed(members, scan.cursor, scan.count);
+    Ok(scan_reply(
+        next,
+        batch
+            .into_iter()
+            .filter(|member| scan.matches(member))
+            .map(|member| Reply::from(member.as_slice()))
+            .collect(),
+    ))
+}
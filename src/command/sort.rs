@@ -0,0 +1,194 @@
+use std::cmp::Ordering;
+
+use super::{notify, parse_int, propagate, CommandError, CommandResult, Context};
+use crate::{
+    db::{DataMap, MapValue, Value},
+    resp::Reply,
+};
+
+#[derive(Default)]
+struct Options<'a> {
+    by: Option<&'a [u8]>,
+    gets: Vec<&'a [u8]>,
+    limit: Option<(i64, i64)>,
+    desc: bool,
+    alpha: bool,
+    store: Option<&'a Vec<u8>>,
+}
+
+impl<'a> Options<'a> {
+    fn parse(args: &'a [Vec<u8>]) -> Result<Self, CommandError> {
+        let mut options = Self::default();
+        let mut opts = args.iter();
+        while let Some(opt) = opts.next() {
+            let mut value = || opts.next().ok_or(CommandError::Syntax);
+            match opt.to_ascii_uppercase().as_slice() {
+                b"ASC" => options.desc = false,
+                b"DESC" => options.desc = true,
+                b"ALPHA" => options.alpha = true,
+                b"BY" => options.by = Some(value()?),
+                b"GET" => options.gets.push(value()?),
+                b"STORE" => options.store = Some(value()?),
+                b"LIMIT" => {
+                    let offset = parse_int(value()?)?;
+                    let count = parse_int(value()?)?;
+                    options.limit = Some((offset, count));
+                }
+                _ => return Err(CommandError::Syntax),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// Resolves a BY/GET pattern for one element: `#` is the element itself, otherwise the first
+// `*` is replaced by the element and the resulting key must hold a string. A `->field` suffix
+// addresses a hash field.
+fn lookup(map: &DataMap, pattern: &[u8], element: &[u8]) -> Option<Vec<u8>> {
+    if pattern == b"#" {
+        return Some(element.to_vec());
+    }
+    let star = pattern.iter().position(|b| *b == b'*')?;
+    let rest = &pattern[star + 1..];
+    let (suffix, field) = match rest.windows(2).position(|w| w == b"->") {
+        Some(arrow) if arrow + 2 < rest.len() => (&rest[..arrow], Some(&rest[arrow + 2..])),
+        _ => (rest, None),
+    };
+    let key = [&pattern[..star], element, suffix].concat();
+    match (&map.get(&key)?.data, field) {
+        (Value::String(data), None) => Some(data.to_vec()),
+        (Value::Hash(hash), Some(field)) => hash.get(field).cloned(),
+        _ => None,
+    }
+}
+
+fn elements(value: &MapValue) -> Result<Vec<Vec<u8>>, CommandError> {
+    match &value.data {
+        Value::List(list) => Ok(list.iter().cloned().collect()),
+        Value::Set(set) => Ok(set.members()),
+        Value::SortedSet(zset) => Ok(zset
+            .entries()
+            .into_iter()
+            .map(|(member, _)| member.to_vec())
+            .collect()),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+enum Weight {
+    Score(f64),
+    Bytes(Option<Vec<u8>>),
+}
+
+fn run(map: &DataMap, key: &[u8], options: &Options) -> Result<Vec<Option<Vec<u8>>>, CommandError> {
+    let mut items = match map.get(key) {
+        Some(value) => elements(value)?,
+        None => vec![],
+    };
+    // A BY pattern that can't reference the element (e.g. "nosort") skips sorting.
+    let sort = options.by.is_none_or(|pattern| pattern.contains(&b'*'));
+    if sort {
+        let mut weighted = items
+            .into_iter()
+            .map(|item| {
+                let weight = match options.by {
+                    Some(pattern) => lookup(map, pattern, &item),
+                    None => Some(item.clone()),
+                };
+                let weight = if options.alpha {
+                    Weight::Bytes(weight)
+                } else {
+                    let score = match weight {
+                        Some(weight) => std::str::from_utf8(&weight)
+                            .ok()
+                            .and_then(|s| s.trim_start().parse::<f64>().ok())
+                            .filter(|score| !score.is_nan())
+                            .ok_or_else(|| {
+                                CommandError::Other(
+                                    "One or more scores can't be converted into double".into(),
+                                )
+                            })?,
+                        None => 0.0,
+                    };
+                    Weight::Score(score)
+                };
+                Ok((weight, item))
+            })
+            .collect::<Result<Vec<_>, CommandError>>()?;
+        weighted.sort_by(|(a, item_a), (b, item_b)| {
+            let ordering = match (a, b) {
+                (Weight::Score(a), Weight::Score(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                (Weight::Bytes(a), Weight::Bytes(b)) => a.cmp(b),
+                _ => Ordering::Equal,
+            };
+            let ordering = ordering.then_with(|| item_a.cmp(item_b));
+            if options.desc {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        items = weighted.into_iter().map(|(_, item)| item).collect();
+    } else if options.desc {
+        items.reverse();
+    }
+    if let Some((offset, count)) = options.limit {
+        let offset = (offset.max(0) as usize).min(items.len());
+        let count = if count < 0 {
+            items.len()
+        } else {
+            count as usize
+        };
+        items = items.into_iter().skip(offset).take(count).collect();
+    }
+    if options.gets.is_empty() {
+        return Ok(items.into_iter().map(Some).collect());
+    }
+    Ok(items
+        .iter()
+        .flat_map(|item| {
+            options
+                .gets
+                .iter()
+                .map(|pattern| lookup(map, pattern, item))
+        })
+        .collect())
+}
+
+fn sort_read(ctx: &Context, key: &[u8], options: &Options) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let result = run(&guard, key, options)?;
+    Ok(Reply::Array(result.into_iter().map(Reply::from).collect()))
+}
+
+// Only a SORT with STORE is a write, so it is the only one propagated.
+pub fn sort(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let options = Options::parse(&args[2..])?;
+    let Some(dest) = options.store else {
+        return sort_read(ctx, &args[1], &options);
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let result = run(&guard, &args[1], &options)?;
+    let len = result.len();
+    if len == 0 {
+        if guard.remove(dest).is_some() {
+            notify(ctx, notify::GENERIC, "del", dest);
+        }
+    } else {
+        // Missing GET lookups are stored as empty strings.
+        let items = result.into_iter().map(Option::unwrap_or_default).collect();
+        guard.insert(dest, MapValue::new(Value::List(items)));
+        notify(ctx, notify::LIST, "sortstore", dest);
+    }
+    propagate(ctx, args);
+    Ok(Reply::Integer(len as i64))
+}
+
+// SORT without STORE, which replicas serve like any read.
+pub fn sort_ro(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let options = Options::parse(&args[2..])?;
+    if options.store.is_some() {
+        return Err(CommandError::Syntax);
+    }
+    sort_read(ctx, &args[1], &options)
+}
@@ -0,0 +1,1119 @@
+use std::{
+    ops::Bound,
+    time::{Duration, Instant},
+};
+
+use super::{block_on_keys, notify, parse_int, propagate, CommandError, CommandResult, Context};
+use crate::{
+    db::{now_millis, DataMap, MapValue, Value},
+    resp::{Protocol, Reply},
+    types::stream::{ConsumerGroup, Entry, PendingEntry, Stream, StreamId, Trim},
+};
+
+pub fn as_stream(value: &MapValue) -> Result<&Stream, CommandError> {
+    match &value.data {
+        Value::Stream(stream) => Ok(stream),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+pub fn stream_or_create<'a>(
+    map: &'a mut DataMap,
+    key: &[u8],
+) -> Result<&'a mut Stream, CommandError> {
+    match &mut map
+        .get_or_insert_with(key, || Value::Stream(Stream::new()))
+        .data
+    {
+        Value::Stream(stream) => Ok(stream),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+// `ms-seq`, or just `ms` with the sequence number defaulting to `missing_seq`.
+fn parse_id(arg: &[u8], missing_seq: u64) -> Result<StreamId, CommandError> {
+    let number = |part: &[u8]| -> Result<u64, CommandError> {
+        std::str::from_utf8(part)
+            .ok()
+            .filter(|part| part.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|part| part.parse().ok())
+            .ok_or(CommandError::InvalidStreamId)
+    };
+    match arg.iter().position(|b| *b == b'-') {
+        Some(dash) => Ok(StreamId::new(
+            number(&arg[..dash])?,
+            number(&arg[dash + 1..])?,
+        )),
+        None => Ok(StreamId::new(number(arg)?, missing_seq)),
+    }
+}
+
+// One end of an XRANGE interval: `-`, `+`, an ID, or an ID after `(` to exclude it. A missing
+// sequence number selects the whole millisecond.
+fn parse_range_bound(arg: &[u8], start: bool) -> Result<StreamId, CommandError> {
+    match (arg, start) {
+        (b"-", _) => return Ok(StreamId::MIN),
+        (b"+", _) => return Ok(StreamId::MAX),
+        _ => {}
+    }
+    let missing_seq = if start { 0 } else { u64::MAX };
+    let Some(excluded) = arg.strip_prefix(b"(") else {
+        return parse_id(arg, missing_seq);
+    };
+    let id = parse_id(excluded, missing_seq)?;
+    let bound = if start { id.next() } else { id.prev() };
+    bound.ok_or_else(|| {
+        let end = if start { "start" } else { "end" };
+        CommandError::Other(format!("invalid {end} ID for the interval"))
+    })
+}
+
+pub fn entry_reply(entry: &Entry) -> Reply {
+    Reply::Array(vec![
+        Reply::from(entry.id.to_string().into_bytes()),
+        Reply::Array(
+            entry
+                .fields
+                .iter()
+                .map(|item| Reply::from(item.as_slice()))
+                .collect(),
+        ),
+    ])
+}
+
+fn range_generic(ctx: &mut Context, args: &[Vec<u8>], rev: bool) -> CommandResult {
+    let (start, end) = if rev {
+        (&args[3], &args[2])
+    } else {
+        (&args[2], &args[3])
+    };
+    let start = parse_range_bound(start, true)?;
+    let end = parse_range_bound(end, false)?;
+    let count = match &args[4..] {
+        [] => usize::MAX,
+        [opt, count] if opt.eq_ignore_ascii_case(b"COUNT") => {
+            parse_int::<i64>(count)?.max(0) as usize
+        }
+        _ => return Err(CommandError::Syntax),
+    };
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Array(vec![]));
+    };
+    Ok(Reply::Array(
+        as_stream(value)?
+            .range(start, end, rev)
+            .take(count)
+            .map(entry_reply)
+            .collect(),
+    ))
+}
+
+pub fn xrange(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    range_generic(ctx, args, false)
+}
+
+pub fn xrevrange(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    range_generic(ctx, args, true)
+}
+
+// How many entries an approximate trim evicts at most without LIMIT: 100 nodes of the default
+// stream-node-max-entries, as in Redis.
+const DEFAULT_TRIM_LIMIT: usize = 100 * 100;
+
+// The `MAXLEN|MINID [=|~] threshold [LIMIT count]` options of XTRIM and XADD.
+#[derive(Default)]
+struct TrimOptions {
+    trim: Option<Trim>,
+    approximate: bool,
+    limit: Option<usize>,
+}
+
+impl TrimOptions {
+    // Consumes the option at `args[*idx]`; returns false if it is not a trimming option.
+    fn parse_option(&mut self, args: &[Vec<u8>], idx: &mut usize) -> Result<bool, CommandError> {
+        let keyword = args[*idx].to_ascii_uppercase();
+        if !matches!(keyword.as_slice(), b"MAXLEN" | b"MINID" | b"LIMIT") {
+            return Ok(false);
+        }
+        let mut next = || {
+            *idx += 1;
+            args.get(*idx).ok_or(CommandError::Syntax)
+        };
+        let mut threshold = next()?;
+        if keyword != b"LIMIT" && matches!(threshold.as_slice(), b"=" | b"~") {
+            self.approximate = threshold == b"~";
+            threshold = next()?;
+        }
+        *idx += 1;
+        let trim = match keyword.as_slice() {
+            b"LIMIT" => {
+                self.limit = match parse_int::<i64>(threshold)? {
+                    limit if limit < 0 => {
+                        return Err(CommandError::Other(
+                            "The LIMIT argument must be >= 0.".into(),
+                        ))
+                    }
+                    0 => Some(usize::MAX),
+                    limit => Some(limit as usize),
+                };
+                return Ok(true);
+            }
+            b"MAXLEN" => match parse_int::<i64>(threshold)? {
+                max_len if max_len < 0 => {
+                    return Err(CommandError::Other(
+                        "The MAXLEN argument must be >= 0.".into(),
+                    ))
+                }
+                max_len => Trim::MaxLen(max_len as usize),
+            },
+            _ => Trim::MinId(parse_id(threshold, 0)?),
+        };
+        if matches!(
+            (self.trim, trim),
+            (Some(Trim::MaxLen(_)), Trim::MinId(_)) | (Some(Trim::MinId(_)), Trim::MaxLen(_))
+        ) {
+            return Err(CommandError::Other(
+                "syntax error, MAXLEN and MINID options at the same time are not compatible".into(),
+            ));
+        }
+        self.trim = Some(trim);
+        Ok(true)
+    }
+    // The trim to apply and how many entries it may evict.
+    fn finish(self) -> Result<Option<(Trim, usize)>, CommandError> {
+        if self.limit.is_some() && !self.approximate {
+            return Err(CommandError::Other(
+                "syntax error, LIMIT cannot be used without the special ~ option".into(),
+            ));
+        }
+        let limit = match (self.approximate, self.limit) {
+            (false, _) => usize::MAX,
+            (true, limit) => limit.unwrap_or(DEFAULT_TRIM_LIMIT),
+        };
+        Ok(self.trim.map(|trim| (trim, limit)))
+    }
+}
+
+pub fn xtrim(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut options = TrimOptions::default();
+    let mut idx = 2;
+    while idx < args.len() {
+        if !options.parse_option(args, &mut idx)? {
+            return Err(CommandError::Syntax);
+        }
+    }
+    let Some((trim, limit)) = options.finish()? else {
+        return Err(CommandError::Syntax);
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let Value::Stream(stream) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let trimmed = stream.trim(trim, limit);
+    if trimmed > 0 {
+        notify(ctx, notify::STREAM, "xtrim", &args[1]);
+    }
+    Ok(Reply::Integer(trimmed as i64))
+}
+
+// The ID argument of XADD: `*`, `ms-*` or an explicit ID.
+enum NewId {
+    Auto,
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl NewId {
+    fn parse(arg: &[u8]) -> Result<Self, CommandError> {
+        match arg {
+            b"*" => Ok(NewId::Auto),
+            [ms @ .., b'-', b'*'] => Ok(NewId::AutoSeq(parse_id(ms, 0)?.ms)),
+            _ => parse_id(arg, 0).map(NewId::Explicit),
+        }
+    }
+    fn assign(&self, last: StreamId) -> Result<StreamId, CommandError> {
+        let too_small = || {
+            CommandError::Other(
+                "The ID specified in XADD is equal or smaller than the target stream top item"
+                    .into(),
+            )
+        };
+        let id = match *self {
+            NewId::Auto => {
+                let now = now_millis().max(0) as u64;
+                if now > last.ms {
+                    StreamId::new(now, 0)
+                } else {
+                    // The clock has not moved past the last entry yet.
+                    last.next().ok_or_else(too_small)?
+                }
+            }
+            NewId::AutoSeq(ms) if ms == last.ms => last.next().ok_or_else(too_small)?,
+            NewId::AutoSeq(ms) => StreamId::new(ms, 0),
+            NewId::Explicit(id) => id,
+        };
+        if id == StreamId::MIN {
+            return Err(CommandError::Other(
+                "The ID specified in XADD must be greater than 0-0".into(),
+            ));
+        }
+        if id <= last {
+            return Err(too_small());
+        }
+        Ok(id)
+    }
+}
+
+pub fn xadd(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut nomkstream = false;
+    let mut options = TrimOptions::default();
+    let mut idx = 2;
+    while idx < args.len() {
+        if args[idx].eq_ignore_ascii_case(b"NOMKSTREAM") {
+            nomkstream = true;
+            idx += 1;
+        } else if !options.parse_option(args, &mut idx)? {
+            break;
+        }
+    }
+    let trim = options.finish()?;
+    let Some((id_arg, fields)) = args[idx..].split_first() else {
+        return Err(CommandError::WrongArity("xadd"));
+    };
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return Err(CommandError::WrongArity("xadd"));
+    }
+    let new_id = NewId::parse(id_arg)?;
+    let mut guard = ctx.db.write().unwrap();
+    let last = match guard.get(&args[1]) {
+        Some(value) => as_stream(value)?.last_id(),
+        None if nomkstream => return Ok(Reply::Nil),
+        None => StreamId::MIN,
+    };
+    // Assigned before the key is created, so a rejected ID leaves no empty stream behind.
+    let id = new_id.assign(last)?;
+    let stream = stream_or_create(&mut guard, &args[1])?;
+    stream.append(id, fields.to_vec());
+    let trimmed = trim.map_or(0, |(trim, limit)| stream.trim(trim, limit));
+    notify(ctx, notify::STREAM, "xadd", &args[1]);
+    if trimmed > 0 {
+        notify(ctx, notify::STREAM, "xtrim", &args[1]);
+    }
+    // Creating the key already wakes blocked readers; appending to an existing one must too.
+    guard.signal_ready(&args[1]);
+    // Replicas and the journal must store the same ID, not generate their own.
+    let mut propagated = args.to_vec();
+    propagated[idx] = id.to_string().into_bytes();
+    propagate(ctx, &propagated);
+    Ok(Reply::from(id.to_string().into_bytes()))
+}
+
+pub fn xlen(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let len = match guard.get(&args[1]) {
+        Some(value) => as_stream(value)?.len(),
+        None => 0,
+    };
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn xdel(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    // Every ID is validated before anything is deleted.
+    let ids = args[2..]
+        .iter()
+        .map(|arg| parse_id(arg, 0))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let Value::Stream(stream) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let deleted = ids.into_iter().filter(|id| stream.delete(*id)).count();
+    if deleted > 0 {
+        notify(ctx, notify::STREAM, "xdel", &args[1]);
+    }
+    Ok(Reply::Integer(deleted as i64))
+}
+
+// The options and the `STREAMS key [key ...] id [id ...]` tail of XREAD and XREADGROUP.
+struct Read<'a> {
+    count: usize,
+    // Set with BLOCK; the inner None waits forever.
+    block: Option<Option<Instant>>,
+    // XREADGROUP's group and consumer names.
+    group: Option<(&'a [u8], &'a [u8])>,
+    noack: bool,
+    keys: &'a [Vec<u8>],
+    ids: &'a [Vec<u8>],
+}
+
+impl<'a> Read<'a> {
+    fn parse(args: &'a [Vec<u8>], xreadgroup: bool) -> Result<Self, CommandError> {
+        let mut count = usize::MAX;
+        let mut block = None;
+        let mut group = None;
+        let mut noack = false;
+        let mut idx = 1;
+        loop {
+            let Some(opt) = args.get(idx) else {
+                return Err(CommandError::Syntax);
+            };
+            match opt.to_ascii_uppercase().as_slice() {
+                b"COUNT" => {
+                    let arg = args.get(idx + 1).ok_or(CommandError::Syntax)?;
+                    // Like Redis, a count of 0 or less means no limit.
+                    count = match parse_int::<i64>(arg)? {
+                        count if count > 0 => count as usize,
+                        _ => usize::MAX,
+                    };
+                    idx += 2;
+                }
+                b"BLOCK" => {
+                    let arg = args.get(idx + 1).ok_or(CommandError::Syntax)?;
+                    let ms = parse_int::<i64>(arg).map_err(|_| {
+                        CommandError::Other("timeout is not an integer or out of range".into())
+                    })?;
+                    block = Some(match ms {
+                        0 => None,
+                        ms if ms < 0 => {
+                            return Err(CommandError::Other("timeout is negative".into()))
+                        }
+                        ms => Some(Instant::now() + Duration::from_millis(ms as u64)),
+                    });
+                    idx += 2;
+                }
+                b"GROUP" if !xreadgroup => {
+                    return Err(CommandError::Other(
+                        "The GROUP option is only supported by XREADGROUP. You called XREAD \
+                         instead."
+                            .into(),
+                    ))
+                }
+                b"GROUP" => {
+                    let (Some(name), Some(consumer)) = (args.get(idx + 1), args.get(idx + 2))
+                    else {
+                        return Err(CommandError::Syntax);
+                    };
+                    group = Some((name.as_slice(), consumer.as_slice()));
+                    idx += 3;
+                }
+                b"NOACK" if xreadgroup => {
+                    noack = true;
+                    idx += 1;
+                }
+                b"STREAMS" => break,
+                _ => return Err(CommandError::Syntax),
+            }
+        }
+        let streams = &args[idx + 1..];
+        if streams.is_empty() || !streams.len().is_multiple_of(2) {
+            let (name, id) = if xreadgroup {
+                ("xreadgroup", '>')
+            } else {
+                ("xread", '$')
+            };
+            return Err(CommandError::Other(format!(
+                "Unbalanced '{name}' list of streams: for each stream key an ID or '{id}' must \
+                 be specified."
+            )));
+        }
+        if xreadgroup && group.is_none() {
+            return Err(CommandError::Other(
+                "Missing GROUP option for XREADGROUP".into(),
+            ));
+        }
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+        Ok(Self {
+            count,
+            block,
+            group,
+            noack,
+            keys,
+            ids,
+        })
+    }
+    // The ID after which each stream is read; `$` stands for the stream's current last ID.
+    fn after(&self, map: &DataMap) -> Result<Vec<StreamId>, CommandError> {
+        self.keys
+            .iter()
+            .zip(self.ids)
+            .map(|(key, id)| match id.as_slice() {
+                b"$" => match map.get(key) {
+                    Some(value) => Ok(as_stream(value)?.last_id()),
+                    None => Ok(StreamId::MIN),
+                },
+                b">" => Err(CommandError::Other(
+                    "The > ID can be specified only when calling XREADGROUP using the GROUP \
+                     <group> <consumer> option."
+                        .into(),
+                )),
+                _ => parse_id(id, 0),
+            })
+            .collect()
+    }
+    // Each stream with entries past its ID, along with up to `count` of those entries.
+    fn entries(
+        &self,
+        map: &DataMap,
+        after: &[StreamId],
+    ) -> Result<Vec<(Reply, Reply)>, CommandError> {
+        let mut found = vec![];
+        for (key, after) in self.keys.iter().zip(after) {
+            let Some(value) = map.get(key) else {
+                continue;
+            };
+            let stream = as_stream(value)?;
+            let Some(start) = after.next() else {
+                continue;
+            };
+            let entries: Vec<Reply> = stream
+                .range(start, StreamId::MAX, false)
+                .take(self.count)
+                .map(entry_reply)
+                .collect();
+            if !entries.is_empty() {
+                found.push((Reply::from(key.as_slice()), Reply::Array(entries)));
+            }
+        }
+        Ok(found)
+    }
+    // For each stream, None to read entries never delivered to the group (`>`), or the ID
+    // after which to replay the consumer's pending entries.
+    fn group_starts(&self) -> Result<Vec<Option<StreamId>>, CommandError> {
+        self.ids
+            .iter()
+            .map(|id| match id.as_slice() {
+                b">" => Ok(None),
+                b"$" => Err(CommandError::Other(
+                    "The $ ID is meaningless in the context of XREADGROUP: you want to read the \
+                     history of this consumer by specifying a proper ID, or use the > ID to get \
+                     new messages. The $ ID would just return an empty result set."
+                        .into(),
+                )),
+                _ => parse_id(id, 0).map(Some),
+            })
+            .collect()
+    }
+    // Delivers entries to the consumer, creating it if needed. None if every stream is read
+    // with `>` and none has new entries; history is always answered, even when empty.
+    fn deliver(
+        &self,
+        map: &mut DataMap,
+        starts: &[Option<StreamId>],
+    ) -> Result<Option<Vec<(Reply, Reply)>>, CommandError> {
+        let (group_name, consumer_name) = self.group.unwrap();
+        for key in self.keys {
+            let stream = map.get(key).map(as_stream).transpose()?;
+            if stream.and_then(|stream| stream.group(group_name)).is_none() {
+                return Err(CommandError::NoGroup(format!(
+                    "No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(group_name)
+                )));
+            }
+        }
+        let now = now_millis();
+        let mut found = vec![];
+        let mut history = false;
+        for (key, start) in self.keys.iter().zip(starts) {
+            let Some(Value::Stream(stream)) = map.get_mut(key).map(|value| &mut value.data) else {
+                unreachable!()
+            };
+            let group = stream.group(group_name).unwrap();
+            // Replies are built first, as the group can't be updated while entries are borrowed.
+            let (ids, entries): (Vec<StreamId>, Vec<Reply>) = match start {
+                None => match group.last_delivered.next() {
+                    Some(start) => stream
+                        .range(start, StreamId::MAX, false)
+                        .take(self.count)
+                        .map(|entry| (entry.id, entry_reply(entry)))
+                        .unzip(),
+                    None => (vec![], vec![]),
+                },
+                Some(after) => group
+                    .consumers
+                    .get(consumer_name)
+                    .into_iter()
+                    .flat_map(|consumer| {
+                        consumer
+                            .pending
+                            .range((Bound::Excluded(*after), Bound::Unbounded))
+                    })
+                    .take(self.count)
+                    .map(|id| match stream.get(*id) {
+                        Some(entry) => (*id, entry_reply(entry)),
+                        // Deleted since it was delivered: the ID with no fields.
+                        None => (
+                            *id,
+                            Reply::Array(vec![
+                                Reply::from(id.to_string().into_bytes()),
+                                Reply::NilArray,
+                            ]),
+                        ),
+                    })
+                    .unzip(),
+            };
+            let exists: Vec<bool> = ids.iter().map(|id| stream.get(*id).is_some()).collect();
+            let group = stream.group_mut(group_name).unwrap();
+            group.create_consumer(consumer_name, now);
+            for (id, exists) in ids.iter().zip(exists) {
+                match start {
+                    None => {
+                        group.last_delivered = *id;
+                        if !self.noack {
+                            group.deliver(*id, consumer_name, now);
+                        }
+                    }
+                    Some(_) if exists => {
+                        let pending = group.pending.get_mut(id).unwrap();
+                        pending.delivery_time = now;
+                        pending.delivery_count += 1;
+                    }
+                    Some(_) => {}
+                }
+            }
+            let consumer = group.consumers.get_mut(consumer_name).unwrap();
+            consumer.seen_time = now;
+            if !ids.is_empty() {
+                consumer.active_time = now;
+            }
+            history |= start.is_some();
+            if start.is_some() || !entries.is_empty() {
+                found.push((Reply::from(key.as_slice()), Reply::Array(entries)));
+            }
+        }
+        Ok((history || !found.is_empty()).then_some(found))
+    }
+}
+// A map from key to entries for RESP3 clients, a list of [key, entries] pairs for RESP2 ones.
+fn streams_reply(protocol: Protocol, found: Vec<(Reply, Reply)>) -> Reply {
+    if found.is_empty() {
+        return Reply::NilArray;
+    }
+    match protocol {
+        Protocol::Resp2 => Reply::Array(
+            found
+                .into_iter()
+                .map(|(key, entries)| Reply::Array(vec![key, entries]))
+                .collect(),
+        ),
+        Protocol::Resp3 => Reply::Map(found),
+    }
+}
+
+pub fn xread(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let read = Read::parse(args, false)?;
+    // `$` is resolved once, so a blocked reader gets exactly the entries added while it waits.
+    let after = read.after(&ctx.db.read().unwrap())?;
+    // Without BLOCK, a single attempt.
+    let deadline = read.block.unwrap_or(Some(Instant::now()));
+    let found = block_on_keys(ctx, read.keys, deadline, |map| {
+        let found = read.entries(map, &after)?;
+        Ok((!found.is_empty()).then_some(found))
+    })?;
+    Ok(streams_reply(
+        ctx.session.protocol,
+        found.unwrap_or_default(),
+    ))
+}
+
+pub fn xreadgroup(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let read = Read::parse(args, true)?;
+    let starts = read.group_starts()?;
+    let deadline = read.block.unwrap_or(Some(Instant::now()));
+    // Replayed without BLOCK, by the attempt that served it, so it lands after the entries it
+    // served and before any later write.
+    let (group, consumer) = read.group.unwrap();
+    let mut propagated = vec![
+        b"XREADGROUP".to_vec(),
+        b"GROUP".to_vec(),
+        group.to_vec(),
+        consumer.to_vec(),
+    ];
+    if read.count != usize::MAX {
+        propagated.extend([b"COUNT".to_vec(), read.count.to_string().into_bytes()]);
+    }
+    if read.noack {
+        propagated.push(b"NOACK".to_vec());
+    }
+    propagated.push(b"STREAMS".to_vec());
+    propagated.extend(read.keys.iter().chain(read.ids).cloned());
+    let ctx = &*ctx;
+    let found = block_on_keys(ctx, read.keys, deadline, |map| {
+        let found = read.deliver(map, &starts)?;
+        if found.is_some() {
+            propagate(ctx, &propagated);
+        }
+        Ok(found)
+    })?;
+    if found.is_none() {
+        // Even a read that timed out created its consumer.
+        let _order = ctx.server.order_writes();
+        propagate(ctx, &propagated);
+    }
+    Ok(streams_reply(
+        ctx.session.protocol,
+        found.unwrap_or_default(),
+    ))
+}
+
+fn stream_mut<'a>(map: &'a mut DataMap, key: &[u8]) -> Result<&'a mut Stream, CommandError> {
+    match map.get_mut(key).map(|value| &mut value.data) {
+        Some(Value::Stream(stream)) => Ok(stream),
+        Some(_) => Err(CommandError::WrongType),
+        None => Err(CommandError::Other(
+            "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want \
+             to use the MKSTREAM option to create an empty stream automatically."
+                .into(),
+        )),
+    }
+}
+
+fn xgroup_create(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mkstream = match &args[5..] {
+        [] => false,
+        [opt] if opt.eq_ignore_ascii_case(b"MKSTREAM") => true,
+        _ => return Err(CommandError::Syntax),
+    };
+    let id = match args[4].as_slice() {
+        b"$" => None,
+        id => Some(parse_id(id, 0)?),
+    };
+    let mut guard = ctx.db.write().unwrap();
+    let stream = if mkstream {
+        stream_or_create(&mut guard, &args[2])?
+    } else {
+        stream_mut(&mut guard, &args[2])?
+    };
+    let last_delivered = id.unwrap_or(stream.last_id());
+    if !stream.create_group(&args[3], ConsumerGroup::new(last_delivered)) {
+        return Err(CommandError::BusyGroup);
+    }
+    notify(ctx, notify::STREAM, "xgroup-create", &args[2]);
+    Ok(Reply::ok())
+}
+
+pub fn xgroup(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    match (args[1].to_ascii_uppercase().as_slice(), args.len()) {
+        (b"CREATE", 5..=6) => xgroup_create(ctx, args),
+        (b"DESTROY", 4) => {
+            let mut guard = ctx.db.write().unwrap();
+            let destroyed = stream_mut(&mut guard, &args[2])?.destroy_group(&args[3]);
+            // Consumers blocked on the group get to see that it is gone.
+            if destroyed {
+                guard.signal_ready(&args[2]);
+                notify(ctx, notify::STREAM, "xgroup-destroy", &args[2]);
+            }
+            Ok(Reply::Integer(destroyed as i64))
+        }
+        (b"CREATECONSUMER", 5) => {
+            let mut guard = ctx.db.write().unwrap();
+            let Some(group) = stream_mut(&mut guard, &args[2])?.group_mut(&args[3]) else {
+                return Err(CommandError::NoGroup(format!(
+                    "No such consumer group '{}' for key name '{}'",
+                    String::from_utf8_lossy(&args[3]),
+                    String::from_utf8_lossy(&args[2])
+                )));
+            };
+            let created = group.create_consumer(&args[4], now_millis());
+            if created {
+                notify(ctx, notify::STREAM, "xgroup-createconsumer", &args[2]);
+            }
+            Ok(Reply::Integer(created as i64))
+        }
+        _ => Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(&args[1]).into_owned(),
+            "XGROUP",
+        )),
+    }
+}
+
+pub fn xack(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    // Every ID is validated before anything is acknowledged.
+    let ids = args[3..]
+        .iter()
+        .map(|arg| parse_id(arg, 0))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut guard = ctx.db.write().unwrap();
+    let Some(value) = guard.get_mut(&args[1]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let Value::Stream(stream) = &mut value.data else {
+        return Err(CommandError::WrongType);
+    };
+    let Some(group) = stream.group_mut(&args[2]) else {
+        return Ok(Reply::Integer(0));
+    };
+    let acked = ids.into_iter().filter(|id| group.ack(*id)).count();
+    Ok(Reply::Integer(acked as i64))
+}
+
+fn no_such_group(key: &[u8], group: &[u8]) -> CommandError {
+    CommandError::NoGroup(format!(
+        "No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    ))
+}
+
+// XPENDING's extended form: `[IDLE min-idle-time] start end count [consumer]`.
+struct PendingQuery<'a> {
+    min_idle: i64,
+    start: StreamId,
+    end: StreamId,
+    count: usize,
+    consumer: Option<&'a [u8]>,
+}
+
+impl<'a> PendingQuery<'a> {
+    fn parse(args: &'a [Vec<u8>]) -> Result<Self, CommandError> {
+        let (min_idle, rest) = match args {
+            [opt, min_idle, rest @ ..] if opt.eq_ignore_ascii_case(b"IDLE") => {
+                (parse_int(min_idle)?, rest)
+            }
+            _ => (0, args),
+        };
+        let (start, end, count, consumer) = match rest {
+            [start, end, count] => (start, end, count, None),
+            [start, end, count, consumer] => (start, end, count, Some(consumer.as_slice())),
+            _ => return Err(CommandError::Syntax),
+        };
+        let count = parse_int::<i64>(count)?.max(0) as usize;
+        Ok(Self {
+            min_idle,
+            start: parse_range_bound(start, true)?,
+            end: parse_range_bound(end, false)?,
+            count,
+            consumer,
+        })
+    }
+    fn reply(&self, group: &ConsumerGroup) -> Reply {
+        if self.start > self.end {
+            return Reply::Array(vec![]);
+        }
+        let ids: Box<dyn Iterator<Item = &StreamId>> = match self.consumer {
+            Some(name) => match group.consumers.get(name) {
+                Some(consumer) => Box::new(consumer.pending.range(self.start..=self.end)),
+                None => return Reply::Array(vec![]),
+            },
+            None => Box::new(group.pending.range(self.start..=self.end).map(|(id, _)| id)),
+        };
+        let now = now_millis();
+        Reply::Array(
+            ids.map(|id| (id, &group.pending[id]))
+                .map(|(id, entry)| (id, entry, (now - entry.delivery_time).max(0)))
+                .filter(|(_, _, idle)| *idle >= self.min_idle)
+                .take(self.count)
+                .map(|(id, entry, idle)| {
+                    Reply::Array(vec![
+                        Reply::from(id.to_string().into_bytes()),
+                        Reply::from(entry.consumer.as_slice()),
+                        Reply::Integer(idle),
+                        Reply::Integer(entry.delivery_count as i64),
+                    ])
+                })
+                .collect(),
+        )
+    }
+}
+
+// The number of pending entries, the smallest and greatest pending ID, and how many entries
+// each consumer has pending.
+fn pending_summary(group: &ConsumerGroup) -> Reply {
+    let (Some((first, _)), Some((last, _))) = (
+        group.pending.first_key_value(),
+        group.pending.last_key_value(),
+    ) else {
+        return Reply::Array(vec![
+            Reply::Integer(0),
+            Reply::Nil,
+            Reply::Nil,
+            Reply::NilArray,
+        ]);
+    };
+    let consumers = group
+        .consumers
+        .iter()
+        .filter(|(_, consumer)| !consumer.pending.is_empty())
+        .map(|(name, consumer)| {
+            Reply::Array(vec![
+                Reply::from(name.as_slice()),
+                // A bulk string, as in Redis.
+                Reply::from(consumer.pending.len().to_string().into_bytes()),
+            ])
+        })
+        .collect();
+    Reply::Array(vec![
+        Reply::Integer(group.pending.len() as i64),
+        Reply::from(first.to_string().into_bytes()),
+        Reply::from(last.to_string().into_bytes()),
+        Reply::Array(consumers),
+    ])
+}
+
+pub fn xpending(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let query = match &args[3..] {
+        [] => None,
+        rest => Some(PendingQuery::parse(rest)?),
+    };
+    let guard = ctx.db.read().unwrap();
+    let stream = guard.get(&args[1]).map(as_stream).transpose()?;
+    let Some(group) = stream.and_then(|stream| stream.group(&args[2])) else {
+        return Err(no_such_group(&args[1], &args[2]));
+    };
+    Ok(match query {
+        Some(query) => query.reply(group),
+        None => pending_summary(group),
+    })
+}
+
+// The stream at `key`, provided it has the consumer group `group`.
+fn stream_with_group<'a>(
+    map: &'a mut DataMap,
+    key: &[u8],
+    group: &[u8],
+) -> Result<&'a mut Stream, CommandError> {
+    match map.get_mut(key).map(|value| &mut value.data) {
+        Some(Value::Stream(stream)) if stream.group(group).is_some() => Ok(stream),
+        Some(Value::Stream(_)) | None => Err(no_such_group(key, group)),
+        Some(_) => Err(CommandError::WrongType),
+    }
+}
+
+// Claims are replicated with their outcome spelled out, since idle times differ on replay.
+fn claim_propagation(args: &[Vec<u8>], id: StreamId, pending: &PendingEntry) -> Vec<Vec<u8>> {
+    vec![
+        b"XCLAIM".to_vec(),
+        args[1].clone(),
+        args[2].clone(),
+        args[3].clone(),
+        b"0".to_vec(),
+        id.to_string().into_bytes(),
+        b"TIME".to_vec(),
+        pending.delivery_time.to_string().into_bytes(),
+        b"RETRYCOUNT".to_vec(),
+        pending.delivery_count.to_string().into_bytes(),
+        b"FORCE".to_vec(),
+        b"JUSTID".to_vec(),
+    ]
+}
+
+fn ack_propagation(args: &[Vec<u8>], id: StreamId) -> Vec<Vec<u8>> {
+    vec![
+        b"XACK".to_vec(),
+        args[1].clone(),
+        args[2].clone(),
+        id.to_string().into_bytes(),
+    ]
+}
+
+fn consumer_propagation(args: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    vec![
+        b"XGROUP".to_vec(),
+        b"CREATECONSUMER".to_vec(),
+        args[1].clone(),
+        args[2].clone(),
+        args[3].clone(),
+    ]
+}
+
+pub fn xclaim(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let min_idle = parse_int::<i64>(&args[4])
+        .map_err(|_| CommandError::Other("Invalid min-idle-time argument for XCLAIM".into()))?
+        .max(0);
+    // IDs run up to the first argument that isn't one; the options follow.
+    let mut idx = 5;
+    let mut ids = vec![];
+    while let Some(Ok(id)) = args.get(idx).map(|arg| parse_id(arg, 0)) {
+        ids.push(id);
+        idx += 1;
+    }
+    let now = now_millis();
+    let mut delivery_time = now;
+    let mut retry_count = None;
+    let mut force = false;
+    let mut justid = false;
+    let mut last_id = None;
+    let invalid =
+        |opt: &str| CommandError::Other(format!("Invalid {opt} option argument for XCLAIM"));
+    while let Some(opt) = args.get(idx) {
+        match (opt.to_ascii_uppercase().as_slice(), args.get(idx + 1)) {
+            (b"FORCE", _) => force = true,
+            (b"JUSTID", _) => justid = true,
+            (b"IDLE", Some(ms)) => {
+                delivery_time = now - parse_int::<i64>(ms).map_err(|_| invalid("IDLE"))?;
+                idx += 1;
+            }
+            (b"TIME", Some(ms)) => {
+                delivery_time = parse_int(ms).map_err(|_| invalid("TIME"))?;
+                idx += 1;
+            }
+            (b"RETRYCOUNT", Some(count)) => {
+                retry_count = Some(parse_int::<u64>(count).map_err(|_| invalid("RETRYCOUNT"))?);
+                idx += 1;
+            }
+            (b"LASTID", Some(id)) => {
+                last_id = Some(parse_id(id, 0)?);
+                idx += 1;
+            }
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "Unrecognized XCLAIM option '{}'",
+                    String::from_utf8_lossy(opt)
+                )))
+            }
+        }
+        idx += 1;
+    }
+    if !(0..=now).contains(&delivery_time) {
+        delivery_time = now;
+    }
+    let mut guard = ctx.db.write().unwrap();
+    let stream = stream_with_group(&mut guard, &args[1], &args[2])?;
+    let entries: Vec<Option<Reply>> = ids
+        .iter()
+        .map(|id| stream.get(*id).map(entry_reply))
+        .collect();
+    let group = stream.group_mut(&args[2]).unwrap();
+    let mut propagated = vec![];
+    if group.create_consumer(&args[3], now) {
+        propagated.push(consumer_propagation(args));
+    }
+    if let Some(last_id) = last_id.filter(|last_id| *last_id > group.last_delivered) {
+        group.last_delivered = last_id;
+        // 0-0 is never pending, so this replays as nothing but the LASTID update.
+        propagated.push(vec![
+            b"XCLAIM".to_vec(),
+            args[1].clone(),
+            args[2].clone(),
+            args[3].clone(),
+            b"0".to_vec(),
+            b"0-0".to_vec(),
+            b"LASTID".to_vec(),
+            last_id.to_string().into_bytes(),
+        ]);
+    }
+    let mut claimed = vec![];
+    for (id, entry) in ids.into_iter().zip(entries) {
+        let delivery_count = match group.pending.get(&id) {
+            Some(pending) if now - pending.delivery_time < min_idle => continue,
+            Some(pending) => pending.delivery_count,
+            None if force && entry.is_some() => 1,
+            None => continue,
+        };
+        let Some(entry) = entry else {
+            // Deleted from the stream since it was delivered, so there is nothing to claim.
+            group.ack(id);
+            propagated.push(ack_propagation(args, id));
+            continue;
+        };
+        let delivery_count = match retry_count {
+            Some(count) => count,
+            None if justid => delivery_count,
+            None => delivery_count + 1,
+        };
+        group.claim(id, &args[3], delivery_time, delivery_count);
+        propagated.push(claim_propagation(args, id, &group.pending[&id]));
+        claimed.push(if justid {
+            Reply::from(id.to_string().into_bytes())
+        } else {
+            entry
+        });
+    }
+    let consumer = group.consumers.get_mut(args[3].as_slice()).unwrap();
+    consumer.seen_time = now;
+    if !claimed.is_empty() {
+        consumer.active_time = now;
+    }
+    drop(guard);
+    for args in &propagated {
+        propagate(ctx, args);
+    }
+    Ok(Reply::Array(claimed))
+}
+
+pub fn xautoclaim(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let min_idle = parse_int::<i64>(&args[4])
+        .map_err(|_| CommandError::Other("Invalid min-idle-time argument for XAUTOCLAIM".into()))?
+        .max(0);
+    let start = parse_range_bound(&args[5], true)?;
+    let mut count = 100;
+    let mut justid = false;
+    let mut opts = args[6..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"COUNT" => {
+                let arg = opts.next().ok_or(CommandError::Syntax)?;
+                count = match parse_int::<i64>(arg) {
+                    Ok(count) if (1..=i64::MAX / 10).contains(&count) => count as usize,
+                    _ => return Err(CommandError::Other("COUNT must be > 0".into())),
+                };
+            }
+            b"JUSTID" => justid = true,
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    let now = now_millis();
+    let mut guard = ctx.db.write().unwrap();
+    let stream = stream_with_group(&mut guard, &args[1], &args[2])?;
+    let group = stream.group(&args[2]).unwrap();
+    // Like Redis, at most ten pending entries are looked at per entry asked for.
+    let mut attempts = count * 10;
+    let mut scan = group.pending.range(start..).peekable();
+    let mut claimed = vec![];
+    let mut deleted = vec![];
+    while attempts > 0 && claimed.len() < count {
+        let Some((id, pending)) = scan.next() else {
+            break;
+        };
+        attempts -= 1;
+        if now - pending.delivery_time < min_idle {
+            continue;
+        }
+        match stream.get(*id) {
+            Some(entry) => claimed.push((*id, entry_reply(entry))),
+            None => deleted.push(*id),
+        }
+    }
+    let cursor = scan.peek().map_or(StreamId::MIN, |(id, _)| **id);
+    let group = stream.group_mut(&args[2]).unwrap();
+    let mut propagated = vec![];
+    if group.create_consumer(&args[3], now) {
+        propagated.push(consumer_propagation(args));
+    }
+    for id in &deleted {
+        group.ack(*id);
+        propagated.push(ack_propagation(args, *id));
+    }
+    for (id, _) in &claimed {
+        let delivery_count = group.pending[id].delivery_count + !justid as u64;
+        group.claim(*id, &args[3], now, delivery_count);
+        propagated.push(claim_propagation(args, *id, &group.pending[id]));
+    }
+    let consumer = group.consumers.get_mut(args[3].as_slice()).unwrap();
+    consumer.seen_time = now;
+    if !claimed.is_empty() {
+        consumer.active_time = now;
+    }
+    drop(guard);
+    for args in &propagated {
+        propagate(ctx, args);
+    }
+    let id_reply = |id: &StreamId| Reply::from(id.to_string().into_bytes());
+    Ok(Reply::Array(vec![
+        id_reply(&cursor),
+        Reply::Array(
+            claimed
+                .into_iter()
+                .map(|(id, entry)| if justid { id_reply(&id) } else { entry })
+                .collect(),
+        ),
+        Reply::Array(deleted.iter().map(id_reply).collect()),
+    ]))
+}
@@ -0,0 +1,50 @@
+use super::{
+    notify, parse_int, propagate, propagate_deadline, CommandError, CommandResult, Context,
+};
+use crate::{
+    db::{now_millis, MapValue, Value},
+    resp::Reply,
+};
+
+pub fn as_string(value: &MapValue) -> Result<&[u8], CommandError> {
+    match &value.data {
+        Value::String(data) => Ok(data),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+pub fn set(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let value = MapValue::new(Value::String(args[2].as_slice().into()));
+    let mut deadline = None;
+    let mut opts = args[3..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"PX" => {
+                let millis: i64 = parse_int(opts.next().ok_or(CommandError::Syntax)?)?;
+                deadline = Some(now_millis().saturating_add(millis));
+            }
+            b"PXAT" => deadline = Some(parse_int(opts.next().ok_or(CommandError::Syntax)?)?),
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    let mut guard = ctx.db.write().unwrap();
+    guard.insert(&args[1], value);
+    guard.set_expiry(&args[1], deadline);
+    notify(ctx, notify::STRING, "set", &args[1]);
+    match deadline {
+        Some(deadline) => {
+            notify(ctx, notify::GENERIC, "expire", &args[1]);
+            propagate_deadline(ctx, args, deadline);
+        }
+        None => propagate(ctx, args),
+    }
+    Ok(Reply::ok())
+}
+
+pub fn get(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    match guard.get(&args[1]) {
+        None => Ok(Reply::Nil),
+        Some(value) => Ok(Reply::from(as_string(value)?)),
+    }
+}
@@ -0,0 +1,79 @@
+use super::{call, lookup, CommandError, CommandResult, Context};
+use crate::resp::Reply;
+
+pub fn multi(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    if ctx.session.transaction.is_some() {
+        return Err(CommandError::Other("MULTI calls can not be nested".into()));
+    }
+    ctx.session.transaction = Some(vec![]);
+    ctx.session.transaction_dirty = false;
+    Ok(Reply::ok())
+}
+
+// Runs the queue with every other client held off, replying with one reply per command; a nil
+// reply without running anything if a watched key changed.
+pub fn exec(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    let Some(queued) = ctx.session.transaction.take() else {
+        return Err(CommandError::Other("EXEC without MULTI".into()));
+    };
+    if ctx.session.transaction_dirty {
+        ctx.session.unwatch_all(ctx.databases);
+        return Err(CommandError::ExecAbort);
+    }
+    let server = ctx.server;
+    let _gate = server.exclusive_gate();
+    let changed = ctx.session.watched.iter().any(|(index, key, version)| {
+        ctx.databases[*index].write().unwrap().watched_version(key) != Some(*version)
+    });
+    ctx.session.unwatch_all(ctx.databases);
+    if changed {
+        return Ok(Reply::NilArray);
+    }
+    ctx.session.in_exec = true;
+    let mut replies = vec![];
+    for args in &queued {
+        // A queued SELECT applies to the commands after it.
+        let databases = ctx.databases;
+        ctx.db = &databases[ctx.session.db];
+        let spec = lookup(&args[0]).expect("checked when queued");
+        replies.push(call(ctx, spec, args).unwrap_or_else(|e| Reply::Error(e.to_string())));
+    }
+    ctx.session.in_exec = false;
+    Ok(Reply::Array(replies))
+}
+
+pub fn discard(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    if ctx.session.transaction.take().is_none() {
+        return Err(CommandError::Other("DISCARD without MULTI".into()));
+    }
+    ctx.session.unwatch_all(ctx.databases);
+    Ok(Reply::ok())
+}
+
+pub fn watch(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    if ctx.session.transaction.is_some() {
+        return Err(CommandError::Other(
+            "WATCH inside MULTI is not allowed".into(),
+        ));
+    }
+    let index = ctx.session.db;
+    let mut guard = ctx.db.write().unwrap();
+    let session = &mut *ctx.session;
+    for key in &args[1..] {
+        if session
+            .watched
+            .iter()
+            .any(|(i, k, _)| *i == index && k == key)
+        {
+            continue;
+        }
+        let version = guard.watch(key);
+        session.watched.push((index, key.clone(), version));
+    }
+    Ok(Reply::ok())
+}
+
+pub fn unwatch(ctx: &mut Context, _args: &[Vec<u8>]) -> CommandResult {
+    ctx.session.unwatch_all(ctx.databases);
+    Ok(Reply::ok())
+}
@@ -0,0 +1,990 @@
+use std::collections::HashMap;
+
+use super::{
+    block_on_keys,
+    keyspace::{scan_reply, ScanArgs},
+    notify, parse_card_limit, parse_float, parse_int, parse_numkeys, parse_timeout, propagate,
+    set::parse_sample_count,
+    CommandError, CommandResult, Context,
+};
+use crate::{
+    db::{self, DataMap, MapValue, Value},
+    resp::{format_double, Protocol, Reply},
+    types::{
+        set::Set,
+        zset::{AddFlags, AddOutcome, LexBound, LexRange, ScoreBound, ScoreRange, SortedSet},
+    },
+};
+
+pub fn as_zset(value: &MapValue) -> Result<&SortedSet, CommandError> {
+    match &value.data {
+        Value::SortedSet(zset) => Ok(zset),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+pub fn zset_or_create<'a>(
+    map: &'a mut DataMap,
+    key: &[u8],
+) -> Result<&'a mut SortedSet, CommandError> {
+    match &mut map
+        .get_or_insert_with(key, || Value::SortedSet(SortedSet::new()))
+        .data
+    {
+        Value::SortedSet(zset) => Ok(zset),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+fn zset_mut<'a>(
+    map: &'a mut DataMap,
+    key: &[u8],
+) -> Result<Option<&'a mut SortedSet>, CommandError> {
+    match map.get_mut(key) {
+        Some(value) => match &mut value.data {
+            Value::SortedSet(zset) => Ok(Some(zset)),
+            _ => Err(CommandError::WrongType),
+        },
+        None => Ok(None),
+    }
+}
+
+// An input of the multi-key sorted set commands, which also accept plain sets whose members
+// all score 1.
+enum Source<'a> {
+    Set(&'a Set),
+    SortedSet(&'a SortedSet),
+}
+
+impl Source<'_> {
+    fn from_value(value: &MapValue) -> Result<Source<'_>, CommandError> {
+        match &value.data {
+            Value::Set(set) => Ok(Source::Set(set)),
+            Value::SortedSet(zset) => Ok(Source::SortedSet(zset)),
+            _ => Err(CommandError::WrongType),
+        }
+    }
+    fn len(&self) -> usize {
+        match self {
+            Source::Set(set) => set.len(),
+            Source::SortedSet(zset) => zset.len(),
+        }
+    }
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Source::Set(set) => set.contains(member).then_some(1.0),
+            Source::SortedSet(zset) => zset.score(member),
+        }
+    }
+    fn members(&self) -> Vec<Vec<u8>> {
+        match self {
+            Source::Set(set) => set.members(),
+            Source::SortedSet(zset) => zset
+                .entries()
+                .into_iter()
+                .map(|(member, _)| member.to_vec())
+                .collect(),
+        }
+    }
+}
+
+fn score_reply(score: Option<f64>) -> Reply {
+    score.map_or(Reply::Nil, Reply::Double)
+}
+
+pub fn zadd(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut flags = AddFlags::default();
+    let mut ch = false;
+    let mut idx = 2;
+    while let Some(opt) = args.get(idx) {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"NX" => flags.nx = true,
+            b"XX" => flags.xx = true,
+            b"GT" => flags.gt = true,
+            b"LT" => flags.lt = true,
+            b"CH" => ch = true,
+            b"INCR" => flags.incr = true,
+            _ => break,
+        }
+        idx += 1;
+    }
+    let pairs = &args[idx..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Err(CommandError::Syntax);
+    }
+    if flags.nx && flags.xx {
+        return Err(CommandError::Other(
+            "XX and NX options at the same time are not compatible".into(),
+        ));
+    }
+    if [flags.gt, flags.lt, flags.nx]
+        .iter()
+        .filter(|f| **f)
+        .count()
+        > 1
+    {
+        return Err(CommandError::Other(
+            "GT, LT, and/or NX options at the same time are not compatible".into(),
+        ));
+    }
+    if flags.incr && pairs.len() > 2 {
+        return Err(CommandError::Other(
+            "INCR option supports a single increment-element pair".into(),
+        ));
+    }
+    // Every score is validated before the set is touched.
+    let elements = pairs
+        .chunks(2)
+        .map(|pair| parse_float(&pair[0]).map(|score| (score, &pair[1])))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut guard = ctx.db.write().unwrap();
+    match guard.get(&args[1]) {
+        Some(value) => {
+            as_zset(value)?;
+        }
+        None if flags.xx => {
+            return Ok(if flags.incr {
+                Reply::Nil
+            } else {
+                Reply::Integer(0)
+            })
+        }
+        None => {}
+    }
+    let zset = zset_or_create(&mut guard, &args[1])?;
+    let mut added = 0;
+    let mut updated = 0;
+    let mut incr_score = None;
+    for (score, member) in elements {
+        match zset.add(member, score, flags) {
+            AddOutcome::Added(score) => {
+                added += 1;
+                incr_score = Some(score);
+            }
+            AddOutcome::Updated(score) => {
+                updated += 1;
+                incr_score = Some(score);
+            }
+            AddOutcome::Unchanged(score) => incr_score = Some(score),
+            AddOutcome::Skipped => incr_score = None,
+            AddOutcome::Nan => {
+                return Err(CommandError::Other(
+                    "resulting score is not a number (NaN)".into(),
+                ))
+            }
+        }
+    }
+    if added + updated > 0 {
+        notify(
+            ctx,
+            notify::ZSET,
+            if flags.incr { "zincr" } else { "zadd" },
+            &args[1],
+        );
+    }
+    if flags.incr {
+        Ok(score_reply(incr_score))
+    } else {
+        Ok(Reply::Integer(if ch { added + updated } else { added }))
+    }
+}
+
+pub fn zscore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let score = match guard.get(&args[1]) {
+        Some(value) => as_zset(value)?.score(&args[2]),
+        None => None,
+    };
+    Ok(score_reply(score))
+}
+
+fn rank_generic(ctx: &mut Context, args: &[Vec<u8>], rev: bool) -> CommandResult {
+    let with_score = match args {
+        [_, _, _] => false,
+        [_, _, _, opt] if opt.eq_ignore_ascii_case(b"WITHSCORE") => true,
+        _ => return Err(CommandError::Syntax),
+    };
+    let guard = ctx.db.read().unwrap();
+    let zset = guard.get(&args[1]).map(as_zset).transpose()?;
+    let Some((zset, rank)) = zset.and_then(|zset| Some((zset, zset.rank(&args[2], rev)?))) else {
+        return Ok(if with_score {
+            Reply::NilArray
+        } else {
+            Reply::Nil
+        });
+    };
+    if !with_score {
+        return Ok(Reply::Integer(rank as i64));
+    }
+    Ok(Reply::Array(vec![
+        Reply::Integer(rank as i64),
+        score_reply(zset.score(&args[2])),
+    ]))
+}
+
+pub fn zrank(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    rank_generic(ctx, args, false)
+}
+
+pub fn zrevrank(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    rank_generic(ctx, args, true)
+}
+
+pub fn zcard(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let len = match guard.get(&args[1]) {
+        Some(value) => as_zset(value)?.len(),
+        None => 0,
+    };
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn zmscore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let guard = ctx.db.read().unwrap();
+    let zset = guard.get(&args[1]).map(as_zset).transpose()?;
+    Ok(Reply::Array(
+        args[2..]
+            .iter()
+            .map(|member| score_reply(zset.and_then(|zset| zset.score(member))))
+            .collect(),
+    ))
+}
+
+pub fn zrandmember(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let (count, with_scores) = match args {
+        [_, _] => (None, false),
+        [_, _, count] => (Some(parse_sample_count(count)?), false),
+        [_, _, count, opt] if opt.eq_ignore_ascii_case(b"WITHSCORES") => {
+            (Some(parse_sample_count(count)?), true)
+        }
+        _ => return Err(CommandError::Syntax),
+    };
+    let guard = ctx.db.read().unwrap();
+    let zset = guard.get(&args[1]).map(as_zset).transpose()?;
+    match (zset, count) {
+        (None, None) => Ok(Reply::Nil),
+        (None, Some(_)) => Ok(Reply::Array(vec![])),
+        (Some(zset), None) => Ok(zset
+            .sample(1, false)
+            .pop()
+            .map_or(Reply::Nil, |(member, _)| Reply::from(member))),
+        (Some(zset), Some((count, allow_duplicates))) => {
+            let entries = zset.sample(count, allow_duplicates);
+            Ok(entries_reply(ctx.session.protocol, entries, with_scores))
+        }
+    }
+}
+
+pub fn zscan(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let scan = ScanArgs::parse(&args[2..])?;
+    if scan.type_filter.is_some() || scan.novalues {
+        return Err(CommandError::Syntax);
+    }
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(scan_reply(0, vec![]));
+    };
+    let entries = as_zset(value)?
+        .entries()
+        .into_iter()
+        .map(|entry| (entry.0, entry));
+    let (next, batch) = db::scan_unindexed(entries, scan.cursor, scan.count);
+    let mut items = vec![];
+    for (member, score) in batch.into_iter().filter(|(member, _)| scan.matches(member)) {
+        items.push(Reply::from(member));
+        // Scores stay bulk strings under RESP3 too, as in Redis.
+        items.push(Reply::BulkString(format_double(score).into_bytes()));
+    }
+    Ok(scan_reply(next, items))
+}
+
+// Counts the members of the intersection, stopping early once LIMIT is reached.
+pub fn zintercard(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let (keys, opts) = parse_numkeys(&args[1..])?;
+    let limit = parse_card_limit(opts)?;
+    let guard = ctx.db.read().unwrap();
+    let mut sources = vec![];
+    for key in keys {
+        match guard.get(key) {
+            Some(value) => sources.push(Source::from_value(value)?),
+            None => return Ok(Reply::Integer(0)),
+        }
+    }
+    sources.sort_by_key(Source::len);
+    let mut count = 0;
+    for member in sources[0].members() {
+        if sources[1..]
+            .iter()
+            .all(|source| source.score(&member).is_some())
+        {
+            count += 1;
+            if count == limit {
+                break;
+            }
+        }
+    }
+    Ok(Reply::Integer(count as i64))
+}
+
+fn parse_score_bound(arg: &[u8]) -> Result<ScoreBound, CommandError> {
+    let (score, exclusive) = match arg.strip_prefix(b"(") {
+        Some(score) => (score, true),
+        None => (arg, false),
+    };
+    let score =
+        parse_float(score).map_err(|_| CommandError::Other("min or max is not a float".into()))?;
+    Ok(ScoreBound { score, exclusive })
+}
+
+fn parse_lex_bound(arg: &[u8]) -> Result<LexBound, CommandError> {
+    match arg {
+        b"-" => Ok(LexBound::NegativeInfinity),
+        b"+" => Ok(LexBound::PositiveInfinity),
+        [b'[', member @ ..] => Ok(LexBound::Inclusive(member.to_vec())),
+        [b'(', member @ ..] => Ok(LexBound::Exclusive(member.to_vec())),
+        _ => Err(CommandError::Other(
+            "min or max not valid string range item".into(),
+        )),
+    }
+}
+
+enum RangeBy {
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+struct RangeQuery {
+    by: RangeBy,
+    rev: bool,
+    // Offset and count; a negative count takes everything after the offset.
+    limit: Option<(i64, i64)>,
+}
+
+impl RangeQuery {
+    // `start` and `stop` as ZRANGE takes them: with REV, score and lex ranges are given from
+    // the high end to the low end.
+    fn new(by: &[u8], start: &[u8], stop: &[u8], rev: bool) -> Result<Self, CommandError> {
+        let (low, high) = if rev { (stop, start) } else { (start, stop) };
+        let by = match by {
+            b"BYSCORE" => RangeBy::Score(ScoreRange {
+                min: parse_score_bound(low)?,
+                max: parse_score_bound(high)?,
+            }),
+            b"BYLEX" => RangeBy::Lex(LexRange {
+                min: parse_lex_bound(low)?,
+                max: parse_lex_bound(high)?,
+            }),
+            _ => RangeBy::Rank(parse_int(start)?, parse_int(stop)?),
+        };
+        Ok(Self {
+            by,
+            rev,
+            limit: None,
+        })
+    }
+    fn select<'a>(&'a self, zset: &'a SortedSet) -> Vec<(&'a [u8], f64)> {
+        match &self.by {
+            RangeBy::Rank(start, stop) => {
+                let len = zset.len() as i64;
+                let start = if *start < 0 {
+                    (start + len).max(0)
+                } else {
+                    *start
+                };
+                let stop = if *stop < 0 {
+                    stop + len
+                } else {
+                    (*stop).min(len - 1)
+                };
+                if start > stop || start >= len {
+                    return vec![];
+                }
+                zset.iter_from_rank(start as usize, self.rev)
+                    .take((stop - start + 1) as usize)
+                    .collect()
+            }
+            RangeBy::Score(range) => self.limited(zset.range(range, self.rev)),
+            RangeBy::Lex(range) => self.limited(zset.range(range, self.rev)),
+        }
+    }
+    fn limited<'a>(&self, entries: impl Iterator<Item = (&'a [u8], f64)>) -> Vec<(&'a [u8], f64)> {
+        match self.limit {
+            None => entries.collect(),
+            Some((offset, _)) if offset < 0 => vec![],
+            Some((offset, count)) => entries
+                .skip(offset as usize)
+                .take(if count < 0 {
+                    usize::MAX
+                } else {
+                    count as usize
+                })
+                .collect(),
+        }
+    }
+}
+
+// Members only, or with their scores: as [member, score] pairs for RESP3 clients and
+// flattened into one array for RESP2 ones.
+fn entries_reply(protocol: Protocol, entries: Vec<(&[u8], f64)>, with_scores: bool) -> Reply {
+    let members = entries.into_iter();
+    Reply::Array(match (with_scores, protocol) {
+        (false, _) => members.map(|(member, _)| Reply::from(member)).collect(),
+        (true, Protocol::Resp2) => members
+            .flat_map(|(member, score)| [Reply::from(member), Reply::Double(score)])
+            .collect(),
+        (true, Protocol::Resp3) => members
+            .map(|(member, score)| Reply::Array(vec![Reply::from(member), Reply::Double(score)]))
+            .collect(),
+    })
+}
+
+// ZRANGE and its legacy forms, which fix BYSCORE/BYLEX and REV by name instead of taking them
+// as options.
+fn range_generic(
+    ctx: &mut Context,
+    args: &[Vec<u8>],
+    mut by: &'static [u8],
+    mut rev: bool,
+    legacy: bool,
+) -> CommandResult {
+    let mut limit = None;
+    let mut with_scores = false;
+    let mut opts = args[4..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"BYSCORE" if !legacy => by = b"BYSCORE",
+            b"BYLEX" if !legacy => by = b"BYLEX",
+            b"REV" if !legacy => rev = true,
+            b"WITHSCORES" if !(legacy && by == b"BYLEX") => with_scores = true,
+            b"LIMIT" => {
+                let (Some(offset), Some(count)) = (opts.next(), opts.next()) else {
+                    return Err(CommandError::Syntax);
+                };
+                limit = Some((parse_int(offset)?, parse_int(count)?));
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    if limit.is_some() && by.is_empty() {
+        return Err(CommandError::Other(
+            "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .into(),
+        ));
+    }
+    if with_scores && by == b"BYLEX" {
+        return Err(CommandError::Other(
+            "syntax error, WITHSCORES not supported in combination with BYLEX".into(),
+        ));
+    }
+    let mut query = RangeQuery::new(by, &args[2], &args[3], rev)?;
+    query.limit = limit;
+    let guard = ctx.db.read().unwrap();
+    let Some(value) = guard.get(&args[1]) else {
+        return Ok(Reply::Array(vec![]));
+    };
+    let entries = query.select(as_zset(value)?);
+    Ok(entries_reply(ctx.session.protocol, entries, with_scores))
+}
+
+pub fn zcount(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let range = ScoreRange {
+        min: parse_score_bound(&args[2])?,
+        max: parse_score_bound(&args[3])?,
+    };
+    let guard = ctx.db.read().unwrap();
+    let count = match guard.get(&args[1]) {
+        Some(value) => as_zset(value)?.count(&range),
+        None => 0,
+    };
+    Ok(Reply::Integer(count as i64))
+}
+
+pub fn zlexcount(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let range = LexRange {
+        min: parse_lex_bound(&args[2])?,
+        max: parse_lex_bound(&args[3])?,
+    };
+    let guard = ctx.db.read().unwrap();
+    let count = match guard.get(&args[1]) {
+        Some(value) => as_zset(value)?.count(&range),
+        None => 0,
+    };
+    Ok(Reply::Integer(count as i64))
+}
+
+pub fn zrange(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    range_generic(ctx, args, b"", false, false)
+}
+
+pub fn zrangebyscore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    range_generic(ctx, args, b"BYSCORE", false, true)
+}
+
+pub fn zrevrangebyscore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    range_generic(ctx, args, b"BYSCORE", true, true)
+}
+
+pub fn zrangebylex(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    range_generic(ctx, args, b"BYLEX", false, true)
+}
+
+pub fn zrevrangebylex(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    range_generic(ctx, args, b"BYLEX", true, true)
+}
+
+type Popped = Vec<(Vec<u8>, f64)>;
+
+fn pop_entries(
+    ctx: &Context,
+    map: &mut DataMap,
+    key: &[u8],
+    max: bool,
+    count: usize,
+) -> Result<Option<Popped>, CommandError> {
+    let Some(zset) = zset_mut(map, key)? else {
+        return Ok(None);
+    };
+    let popped = zset.pop(max, count);
+    let emptied = zset.len() == 0;
+    if !popped.is_empty() {
+        notify(
+            ctx,
+            notify::ZSET,
+            if max { "zpopmax" } else { "zpopmin" },
+            key,
+        );
+    }
+    if emptied {
+        map.remove(key);
+        notify(ctx, notify::GENERIC, "del", key);
+    }
+    Ok(Some(popped))
+}
+
+fn pop_generic(ctx: &mut Context, args: &[Vec<u8>], max: bool) -> CommandResult {
+    let count = match args.get(2) {
+        None => None,
+        Some(count) => match parse_int::<i64>(count) {
+            Ok(count) if count >= 0 => Some(count as usize),
+            _ => {
+                return Err(CommandError::Other(
+                    "value is out of range, must be positive".into(),
+                ))
+            }
+        },
+    };
+    if args.len() > 3 {
+        return Err(CommandError::Syntax);
+    }
+    let mut guard = ctx.db.write().unwrap();
+    let popped =
+        pop_entries(ctx, &mut guard, &args[1], max, count.unwrap_or(1))?.unwrap_or_default();
+    let entries = popped
+        .iter()
+        .map(|(member, score)| (member.as_slice(), *score))
+        .collect();
+    // Without COUNT, RESP3 still gets a flat member/score pair rather than a nested one.
+    let protocol = match count {
+        Some(_) => ctx.session.protocol,
+        None => Protocol::Resp2,
+    };
+    Ok(entries_reply(protocol, entries, true))
+}
+
+pub fn zpopmin(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    pop_generic(ctx, args, false)
+}
+
+pub fn zpopmax(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    pop_generic(ctx, args, true)
+}
+
+fn blocking_pop(ctx: &mut Context, args: &[Vec<u8>], max: bool) -> CommandResult {
+    let (keys, timeout) = args[1..].split_at(args.len() - 2);
+    let deadline = parse_timeout(&timeout[0])?;
+    let ctx = &*ctx;
+    let served = block_on_keys(ctx, keys, deadline, |map| {
+        for key in keys {
+            if let Some(mut popped) = pop_entries(ctx, map, key, max, 1)? {
+                let pop: &[u8] = if max { b"ZPOPMAX" } else { b"ZPOPMIN" };
+                propagate(ctx, &[pop.to_vec(), key.clone()]);
+                return Ok(popped.pop().map(|entry| (key, entry)));
+            }
+        }
+        Ok(None)
+    })?;
+    Ok(match served {
+        Some((key, (member, score))) => Reply::Array(vec![
+            Reply::from(key.as_slice()),
+            Reply::BulkString(member),
+            Reply::Double(score),
+        ]),
+        None => Reply::NilArray,
+    })
+}
+
+pub fn bzpopmin(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    blocking_pop(ctx, args, false)
+}
+
+pub fn bzpopmax(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    blocking_pop(ctx, args, true)
+}
+
+pub fn zrem(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    let mut guard = ctx.db.write().unwrap();
+    let Some(zset) = zset_mut(&mut guard, &args[1])? else {
+        return Ok(Reply::Integer(0));
+    };
+    let removed = args[2..]
+        .iter()
+        .filter(|member| zset.remove(member))
+        .count();
+    let emptied = zset.len() == 0;
+    if removed > 0 {
+        notify(ctx, notify::ZSET, "zrem", &args[1]);
+    }
+    if emptied {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    }
+    Ok(Reply::Integer(removed as i64))
+}
+
+fn remrange_generic(ctx: &mut Context, args: &[Vec<u8>], by: &[u8]) -> CommandResult {
+    let query = RangeQuery::new(by, &args[2], &args[3], false)?;
+    let mut guard = ctx.db.write().unwrap();
+    let Some(zset) = zset_mut(&mut guard, &args[1])? else {
+        return Ok(Reply::Integer(0));
+    };
+    let selected: Vec<Vec<u8>> = query
+        .select(zset)
+        .into_iter()
+        .map(|(member, _)| member.to_vec())
+        .collect();
+    for member in &selected {
+        zset.remove(member);
+    }
+    let emptied = zset.len() == 0;
+    if !selected.is_empty() {
+        let event = match by {
+            b"BYSCORE" => "zremrangebyscore",
+            b"BYLEX" => "zremrangebylex",
+            _ => "zremrangebyrank",
+        };
+        notify(ctx, notify::ZSET, event, &args[1]);
+    }
+    if emptied {
+        guard.remove(&args[1]);
+        notify(ctx, notify::GENERIC, "del", &args[1]);
+    }
+    Ok(Reply::Integer(selected.len() as i64))
+}
+
+pub fn zremrangebyrank(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    remrange_generic(ctx, args, b"")
+}
+
+pub fn zremrangebyscore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    remrange_generic(ctx, args, b"BYSCORE")
+}
+
+pub fn zremrangebylex(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    remrange_generic(ctx, args, b"BYLEX")
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SetOp {
+    Union,
+    Inter,
+    Diff,
+}
+
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, current: f64, score: f64) -> f64 {
+        match self {
+            // inf + -inf counts as 0, like in Redis.
+            Aggregate::Sum => Some(current + score)
+                .filter(|sum| !sum.is_nan())
+                .unwrap_or(0.0),
+            Aggregate::Min => current.min(score),
+            Aggregate::Max => current.max(score),
+        }
+    }
+}
+
+struct Combine {
+    op: SetOp,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+    with_scores: bool,
+}
+
+// Parses the options after the keys; ZDIFF takes neither WEIGHTS nor AGGREGATE, and the STORE
+// forms do not take WITHSCORES.
+fn parse_combine(
+    op: SetOp,
+    keys: usize,
+    opts: &[Vec<u8>],
+    store: bool,
+) -> Result<Combine, CommandError> {
+    let mut combine = Combine {
+        op,
+        weights: vec![1.0; keys],
+        aggregate: Aggregate::Sum,
+        with_scores: false,
+    };
+    let mut idx = 0;
+    while let Some(opt) = opts.get(idx) {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"WEIGHTS" if op != SetOp::Diff => {
+                let weights = opts
+                    .get(idx + 1..idx + 1 + keys)
+                    .ok_or(CommandError::Syntax)?;
+                for (slot, weight) in combine.weights.iter_mut().zip(weights) {
+                    *slot = parse_float(weight)
+                        .map_err(|_| CommandError::Other("weight value is not a float".into()))?;
+                }
+                idx += keys;
+            }
+            b"AGGREGATE" if op != SetOp::Diff => {
+                let aggregate = opts.get(idx + 1).ok_or(CommandError::Syntax)?;
+                combine.aggregate = match aggregate.to_ascii_uppercase().as_slice() {
+                    b"SUM" => Aggregate::Sum,
+                    b"MIN" => Aggregate::Min,
+                    b"MAX" => Aggregate::Max,
+                    _ => return Err(CommandError::Syntax),
+                };
+                idx += 1;
+            }
+            b"WITHSCORES" if !store => combine.with_scores = true,
+            _ => return Err(CommandError::Syntax),
+        }
+        idx += 1;
+    }
+    Ok(combine)
+}
+
+impl Combine {
+    // Missing keys count as empty inputs.
+    fn run(&self, sources: &[Option<Source>]) -> SortedSet {
+        let weighted = |score: f64, idx: usize| {
+            // 0 * inf counts as 0 as well.
+            Some(score * self.weights[idx])
+                .filter(|score| !score.is_nan())
+                .unwrap_or(0.0)
+        };
+        let mut scores: HashMap<Vec<u8>, f64> = HashMap::new();
+        match self.op {
+            SetOp::Union => {
+                for (idx, source) in sources.iter().enumerate() {
+                    let Some(source) = source else { continue };
+                    for member in source.members() {
+                        let score = weighted(source.score(&member).unwrap(), idx);
+                        scores
+                            .entry(member)
+                            .and_modify(|current| *current = self.aggregate.apply(*current, score))
+                            .or_insert(score);
+                    }
+                }
+            }
+            SetOp::Inter => {
+                // A missing key empties the whole intersection.
+                let present: Option<Vec<&Source>> = sources.iter().map(Option::as_ref).collect();
+                if let Some((first, others)) = present.as_deref().and_then(<[_]>::split_first) {
+                    'members: for member in first.members() {
+                        let mut score = weighted(first.score(&member).unwrap(), 0);
+                        for (idx, source) in others.iter().enumerate() {
+                            let Some(other) = source.score(&member) else {
+                                continue 'members;
+                            };
+                            score = self.aggregate.apply(score, weighted(other, idx + 1));
+                        }
+                        scores.insert(member, score);
+                    }
+                }
+            }
+            SetOp::Diff => {
+                if let Some(Some(first)) = sources.first() {
+                    for member in first.members() {
+                        if sources[1..]
+                            .iter()
+                            .flatten()
+                            .all(|source| source.score(&member).is_none())
+                        {
+                            let score = first.score(&member).unwrap();
+                            scores.insert(member, score);
+                        }
+                    }
+                }
+            }
+        }
+        let mut combined = SortedSet::new();
+        for (member, score) in scores {
+            combined.add(&member, score, AddFlags::default());
+        }
+        combined
+    }
+}
+
+fn combine_generic(ctx: &mut Context, args: &[Vec<u8>], op: SetOp, store: bool) -> CommandResult {
+    let first = if store { 2 } else { 1 };
+    let (keys, opts) = parse_numkeys(&args[first..])?;
+    let combine = parse_combine(op, keys.len(), opts, store)?;
+    let mut guard = ctx.db.write().unwrap();
+    let combined = {
+        let mut sources = vec![];
+        for key in keys {
+            sources.push(guard.get(key).map(Source::from_value).transpose()?);
+        }
+        combine.run(&sources)
+    };
+    if !store {
+        let entries = combined.entries();
+        return Ok(entries_reply(
+            ctx.session.protocol,
+            entries,
+            combine.with_scores,
+        ));
+    }
+    let len = combined.len();
+    if len == 0 {
+        if guard.remove(&args[1]).is_some() {
+            notify(ctx, notify::GENERIC, "del", &args[1]);
+        }
+    } else {
+        guard.insert(&args[1], MapValue::new(Value::SortedSet(combined)));
+        let event = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+        notify(ctx, notify::ZSET, &event, &args[1]);
+    }
+    Ok(Reply::Integer(len as i64))
+}
+
+pub fn zunion(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    combine_generic(ctx, args, SetOp::Union, false)
+}
+
+pub fn zinter(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    combine_generic(ctx, args, SetOp::Inter, false)
+}
+
+pub fn zdiff(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    combine_generic(ctx, args, SetOp::Diff, false)
+}
+
+pub fn zunionstore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    combine_generic(ctx, args, SetOp::Union, true)
+}
+
+pub fn zinterstore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    combine_generic(ctx, args, SetOp::Inter, true)
+}
+
+pub fn zdiffstore(ctx: &mut Context, args: &[Vec<u8>]) -> CommandResult {
+    combine_generic(ctx, args, SetOp::Diff, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command::Session, db::new_databases, server::Server};
+
+    // What ZADD does to a member, by its flags: on a missing member, then on one scored 5 given
+    // 3, 5 and 7. `A` added, `U` updated, `=` already had that score, `-` skipped.
+    const CASES: &[(&[&str], [char; 4])] = &[
+        (&[], ['A', 'U', '=', 'U']),
+        (&["NX"], ['A', '-', '-', '-']),
+        (&["XX"], ['-', 'U', '=', 'U']),
+        (&["GT"], ['A', '-', '-', 'U']),
+        (&["LT"], ['A', 'U', '-', '-']),
+        (&["XX", "GT"], ['-', '-', '-', 'U']),
+        (&["XX", "LT"], ['-', 'U', '-', '-']),
+    ];
+    const CURRENT: f64 = 5.0;
+
+    fn outcome(expected: char, score: f64) -> AddOutcome {
+        match expected {
+            'A' => AddOutcome::Added(score),
+            'U' => AddOutcome::Updated(score),
+            '=' => AddOutcome::Unchanged(score),
+            _ => AddOutcome::Skipped,
+        }
+    }
+
+    // Another member keeps the key around when `m` is missing.
+    fn starting_set(existing: bool) -> SortedSet {
+        let mut zset = SortedSet::new();
+        zset.add(b"other", 1.0, AddFlags::default());
+        if existing {
+            zset.add(b"m", CURRENT, AddFlags::default());
+        }
+        zset
+    }
+
+    #[test]
+    fn add_flags() {
+        let server = Server::new(0);
+        let databases = new_databases();
+        let mut session = Session::default();
+        let mut ctx = Context {
+            db: &databases[0],
+            databases: &databases,
+            server: &server,
+            session: &mut session,
+        };
+        for (options, expected) in CASES {
+            for (column, new) in [7.0, 3.0, 5.0, 7.0].into_iter().enumerate() {
+                let existing = column > 0;
+                for (ch, incr) in [(false, false), (true, false), (false, true), (true, true)] {
+                    let case = format!("{options:?} ch={ch} incr={incr} column {column}");
+                    let flags = AddFlags {
+                        nx: options.contains(&"NX"),
+                        xx: options.contains(&"XX"),
+                        gt: options.contains(&"GT"),
+                        lt: options.contains(&"LT"),
+                        incr,
+                    };
+                    // With INCR the argument is what takes the member from 5 to the new score.
+                    let argument = if incr && existing { new - CURRENT } else { new };
+                    let result = outcome(expected[column], new);
+                    let score = match result {
+                        AddOutcome::Added(_) | AddOutcome::Updated(_) => Some(new),
+                        _ => existing.then_some(CURRENT),
+                    };
+
+                    let mut zset = starting_set(existing);
+                    assert_eq!(zset.add(b"m", argument, flags), result, "{case}");
+                    assert_eq!(zset.score(b"m"), score, "{case}");
+
+                    let zset = MapValue::new(Value::SortedSet(starting_set(existing)));
+                    ctx.db.write().unwrap().insert(b"z", zset);
+                    let mut args = vec![b"ZADD".to_vec(), b"z".to_vec()];
+                    args.extend(options.iter().map(|option| option.as_bytes().to_vec()));
+                    args.extend(ch.then(|| b"CH".to_vec()));
+                    args.extend(incr.then(|| b"INCR".to_vec()));
+                    args.extend([argument.to_string().into_bytes(), b"m".to_vec()]);
+                    let reply = match (incr, result) {
+                        (true, AddOutcome::Skipped) => Reply::Nil,
+                        (true, _) => Reply::Double(new),
+                        (false, AddOutcome::Added(_)) => Reply::Integer(1),
+                        (false, AddOutcome::Updated(_)) => Reply::Integer(ch as i64),
+                        (false, _) => Reply::Integer(0),
+                    };
+                    assert_eq!(zadd(&mut ctx, &args).ok(), Some(reply), "{case}");
+                    let guard = ctx.db.read().unwrap();
+                    let zset = as_zset(guard.get(b"z").unwrap()).unwrap();
+                    assert_eq!(zset.score(b"m"), score, "{case}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{command, glob, notify};
+
+// Snapshot after `seconds` if at least `changes` writes happened.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+// Runtime-tunable settings, read and written through CONFIG GET/SET.
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Empty when snapshots are disabled (`save ""`).
+    pub save: Vec<SaveRule>,
+    // Address a replica reports to its master instead of the one the connection comes from,
+    // for replicas behind NAT or port forwarding. Port 0 means the listening port.
+    pub replica_announce_ip: Option<String>,
+    pub replica_announce_port: u16,
+    // Commands running longer than this many milliseconds are logged and recorded as a
+    // latency event; 0 disables the check. With the kill switch on, the offending connection
+    // is also closed after its reply.
+    pub command_time_budget: u64,
+    pub command_time_budget_kill: bool,
+    // Milliseconds a script runs before other clients are told the server is busy, and it can
+    // be stopped with SCRIPT KILL or FUNCTION KILL.
+    pub busy_reply_threshold: u64,
+    // When not empty, the only commands that are dispatched; anything else is refused, so
+    // embedders can pin their tests to a known set of behaviours.
+    pub command_allow_list: Vec<&'static str>,
+    // Longest string a command may create, in bytes.
+    pub proto_max_bulk_len: u64,
+    // Event classes published to the keyspace and keyevent channels, as notify::* bits.
+    pub notify_keyspace_events: u32,
+    // Bytes of used memory past which commands that grow the dataset are refused; 0 is no limit.
+    // Nothing is evicted, as under Redis's noeviction policy, the only one there is.
+    pub maxmemory: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            save: vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1,
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 100,
+                },
+                SaveRule {
+                    seconds: 60,
+                    changes: 10000,
+                },
+            ],
+            replica_announce_ip: None,
+            replica_announce_port: 0,
+            command_time_budget: 0,
+            command_time_budget_kill: false,
+            busy_reply_threshold: 5000,
+            command_allow_list: vec![],
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            notify_keyspace_events: 0,
+            maxmemory: 0,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Unknown option or number of arguments for CONFIG SET - '{0}'")]
+    Unknown(String),
+    #[error("CONFIG SET failed (possibly related to argument '{0}') - {1}")]
+    Invalid(&'static str, &'static str),
+}
+
+struct Parameter {
+    name: &'static str,
+    get: fn(&Config) -> String,
+    set: fn(&mut Config, &[u8]) -> Result<(), &'static str>,
+}
+
+static PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "save",
+        get: |config| format_save(&config.save),
+        set: |config, value| {
+            config.save = parse_save(value).ok_or("Invalid save parameters")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "replica-announce-ip",
+        get: |config| config.replica_announce_ip.clone().unwrap_or_default(),
+        set: |config, value| {
+            let ip = std::str::from_utf8(value).map_err(|_| "argument must be a valid string")?;
+            config.replica_announce_ip = (!ip.is_empty()).then(|| ip.to_string());
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "replica-announce-port",
+        get: |config| config.replica_announce_port.to_string(),
+        set: |config, value| {
+            config.replica_announce_port = std::str::from_utf8(value)
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .ok_or("argument must be between 0 and 65535 inclusive")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "command-time-budget",
+        get: |config| config.command_time_budget.to_string(),
+        set: |config, value| {
+            config.command_time_budget = std::str::from_utf8(value)
+                .ok()
+                .and_then(|ms| ms.parse().ok())
+                .ok_or("argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "command-time-budget-kill",
+        get: |config| format_bool(config.command_time_budget_kill),
+        set: |config, value| {
+            config.command_time_budget_kill = parse_bool(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "busy-reply-threshold",
+        get: |config| config.busy_reply_threshold.to_string(),
+        set: |config, value| {
+            config.busy_reply_threshold = std::str::from_utf8(value)
+                .ok()
+                .and_then(|ms| ms.parse().ok())
+                .ok_or("argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "command-allow-list",
+        get: |config| config.command_allow_list.join(" "),
+        set: |config, value| {
+            config.command_allow_list = value
+                .split(u8::is_ascii_whitespace)
+                .filter(|word| !word.is_empty())
+                .map(|word| command::lookup(word).map(|spec| spec.name))
+                .collect::<Option<_>>()
+                .ok_or("argument must only name known commands")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "proto-max-bulk-len",
+        get: |config| config.proto_max_bulk_len.to_string(),
+        set: |config, value| {
+            config.proto_max_bulk_len = std::str::from_utf8(value)
+                .ok()
+                .and_then(|len| len.parse().ok())
+                .filter(|len| (1024 * 1024..=i64::MAX as u64).contains(len))
+                .ok_or("argument must be between 1048576 and 9223372036854775807 inclusive")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "notify-keyspace-events",
+        get: |config| notify::format_classes(config.notify_keyspace_events),
+        set: |config, value| {
+            config.notify_keyspace_events = notify::parse_classes(value)
+                .ok_or("Invalid event class character. Use 'Ag$lshzxeKEt'.")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory",
+        get: |config| config.maxmemory.to_string(),
+        set: |config, value| {
+            config.maxmemory = std::str::from_utf8(value)
+                .ok()
+                .and_then(|bytes| bytes.parse().ok())
+                .ok_or("argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory-policy",
+        get: |_| "noeviction".into(),
+        set: |_, value| match value.eq_ignore_ascii_case(b"noeviction") {
+            true => Ok(()),
+            false => Err("argument must be 'noeviction'"),
+        },
+    },
+];
+
+fn parse_bool(value: &[u8]) -> Result<bool, &'static str> {
+    match value.to_ascii_lowercase().as_slice() {
+        b"yes" => Ok(true),
+        b"no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'"),
+    }
+}
+
+fn format_bool(value: bool) -> String {
+    if value { "yes" } else { "no" }.into()
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    PARAMETERS.iter().map(|param| param.name)
+}
+
+// Parses the "<seconds> <changes> [<seconds> <changes> ...]" format; "" disables saving.
+fn parse_save(value: &[u8]) -> Option<Vec<SaveRule>> {
+    let numbers = value
+        .split(u8::is_ascii_whitespace)
+        .filter(|word| !word.is_empty())
+        .map(|word| std::str::from_utf8(word).ok()?.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if numbers.len() % 2 != 0 {
+        return None;
+    }
+    Some(
+        numbers
+            .chunks_exact(2)
+            .map(|pair| SaveRule {
+                seconds: pair[0],
+                changes: pair[1],
+            })
+            .collect(),
+    )
+}
+
+fn format_save(rules: &[SaveRule]) -> String {
+    rules
+        .iter()
+        .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Config {
+    // Parameters matching any of the glob patterns, each listed once.
+    pub fn get(&self, patterns: &[Vec<u8>]) -> Vec<(&'static str, String)> {
+        PARAMETERS
+            .iter()
+            .filter(|param| {
+                patterns.iter().any(|pattern| {
+                    glob::matches(&pattern.to_ascii_lowercase(), param.name.as_bytes())
+                })
+            })
+            .map(|param| (param.name, (param.get)(self)))
+            .collect()
+    }
+    pub fn set(&mut self, name: &[u8], value: &[u8]) -> Result<(), ConfigError> {
+        let lowered = name.to_ascii_lowercase();
+        let param = PARAMETERS
+            .iter()
+            .find(|param| param.name.as_bytes() == lowered.as_slice())
+            .ok_or_else(|| ConfigError::Unknown(String::from_utf8_lossy(name).into_owned()))?;
+        (param.set)(self, value).map_err(|e| ConfigError::Invalid(param.name, e))
+    }
+}
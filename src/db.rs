@@ -0,0 +1,721 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap, HashSet},
+    hash::{Hash as _, Hasher},
+    ops::{Bound, RangeBounds},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    blocking::Waiter,
+    random, rdb,
+    types::{hash::Hash, list::List, set::Set, stream::Stream, string::Str, zset::SortedSet},
+};
+
+// Shared between the map and every index that mentions the key, so each key is stored once.
+pub type Key = Arc<[u8]>;
+
+// How far cluster_testing has moved the clock ahead of the wall clock.
+static CLOCK_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+// Makes deadlines pass as if `by` had elapsed. The clock is the whole process', so every server
+// in it sees the jump.
+pub fn advance_clock(by: Duration) {
+    CLOCK_OFFSET.fetch_add(by.as_millis() as i64, Ordering::Relaxed);
+}
+
+// Current Unix time in milliseconds, the unit expiration deadlines are stored in so they can be
+// persisted and replicated. Never moves backwards: if the wall clock is stepped back, time
+// holds at the latest reading instead of resurrecting keys that already expired.
+pub fn now_millis() -> i64 {
+    static LATEST: AtomicI64 = AtomicI64::new(i64::MIN);
+    let wall = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+        + CLOCK_OFFSET.load(Ordering::Relaxed);
+    let previous = LATEST.fetch_max(wall, Ordering::Relaxed);
+    wall.max(previous)
+}
+
+#[derive(Clone)]
+pub enum Value {
+    String(Str),
+    List(List),
+    Set(Set),
+    SortedSet(SortedSet),
+    Hash(Hash),
+    Stream(Stream),
+}
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Hash(_) => "hash",
+            Value::Stream(_) => "stream",
+        }
+    }
+    // Name of the representation as OBJECT ENCODING reports it.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(data) => data.encoding(),
+            Value::List(_) => "quicklist",
+            Value::Set(set) => set.encoding(),
+            Value::SortedSet(_) => "skiplist",
+            Value::Hash(_) => "hashtable",
+            Value::Stream(_) => "stream",
+        }
+    }
+    // Encodings within their thresholds, and no empty aggregates: those are deleted instead.
+    pub fn is_well_formed(&self) -> bool {
+        match self {
+            Value::String(data) => data.is_well_formed(),
+            Value::List(list) => !list.is_empty(),
+            Value::Set(set) => !set.is_empty() && set.is_well_formed(),
+            Value::SortedSet(zset) => zset.len() > 0 && zset.is_well_formed(),
+            Value::Hash(hash) => !hash.is_empty(),
+            // Unlike the other aggregates, a stream stays around when its last entry goes.
+            Value::Stream(stream) => stream.is_well_formed(),
+        }
+    }
+}
+// Milliseconds since server start; atomic so reads under a shared lock can still update it.
+pub struct AccessTime(AtomicU64);
+impl AccessTime {
+    fn clock() -> u64 {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed().as_millis() as u64
+    }
+    fn now() -> Self {
+        Self(AtomicU64::new(Self::clock()))
+    }
+    pub fn touch(&self) {
+        self.0.store(Self::clock(), Ordering::Relaxed);
+    }
+    // The stored access time may predate the clock's epoch, so the difference wraps.
+    pub fn idle(&self) -> Duration {
+        Duration::from_millis(Self::clock().wrapping_sub(self.0.load(Ordering::Relaxed)))
+    }
+    pub fn set_idle(&self, idle: Duration) {
+        let accessed = Self::clock().wrapping_sub(idle.as_millis() as u64);
+        self.0.store(accessed, Ordering::Relaxed);
+    }
+}
+impl Clone for AccessTime {
+    fn clone(&self) -> Self {
+        Self(AtomicU64::new(self.0.load(Ordering::Relaxed)))
+    }
+}
+#[derive(Clone)]
+pub struct MapValue {
+    pub data: Value,
+    pub last_access: AccessTime,
+}
+impl MapValue {
+    pub fn new(data: Value) -> Self {
+        Self {
+            data,
+            last_access: AccessTime::now(),
+        }
+    }
+    pub fn type_name(&self) -> &'static str {
+        self.data.type_name()
+    }
+}
+
+// Stable position of a key in the scan order; DefaultHasher::new() uses fixed keys.
+fn scan_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// The SCAN cursor scheme for collections without a scan index of their own (hash fields, set
+// and sorted set members): items are ordered by scan_hash on every call, which keeps cursors
+// valid across changes at the cost of a pass over the whole collection.
+pub fn scan_unindexed<'a, T>(
+    items: impl Iterator<Item = (&'a [u8], T)>,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<T>) {
+    let mut positioned: Vec<(u64, T)> = items
+        .map(|(name, item)| (scan_hash(name), item))
+        .filter(|(hash, _)| *hash >= cursor)
+        .collect();
+    positioned.sort_by_key(|(hash, _)| *hash);
+    let mut batch = vec![];
+    let mut last_hash = None;
+    for (hash, item) in positioned {
+        if batch.len() >= count.max(1) && last_hash != Some(hash) {
+            return (hash, batch);
+        }
+        last_hash = Some(hash);
+        batch.push(item);
+    }
+    (0, batch)
+}
+
+struct Slot {
+    value: MapValue,
+    // Position of the key in DataMap::sampling.
+    position: usize,
+}
+
+#[derive(Default)]
+pub struct DataMap {
+    entries: HashMap<Key, Slot>,
+    // Keys ordered by scan_hash, so a SCAN cursor survives inserts and removals between calls.
+    scan_index: BTreeSet<(u64, Key)>,
+    // Dense list of keys for O(1) uniform sampling; removals swap the last key into the hole.
+    sampling: Vec<Key>,
+    // Deadlines live outside the values, mirrored in an index ordered by deadline so the
+    // active expire cycle only ever visits keys that are actually due.
+    expires: HashMap<Key, i64>,
+    expiry_index: BTreeSet<(i64, Key)>,
+    // Expired keys noticed by readers holding only a shared lock; the next writer deletes them.
+    expired_on_read: Mutex<Vec<Key>>,
+    // Keys deleted for having expired, until they are announced to keyspace notifications.
+    expired: Mutex<Vec<Key>>,
+    // Connections blocked until these keys are created, in the order they blocked.
+    blocked: HashMap<Key, Vec<Arc<Waiter>>>,
+    // Hashes with field deadlines, which the active expire cycle visits.
+    volatile_hashes: HashSet<Key>,
+    // Keys under WATCH: how many connections watch each, and a version that every write to
+    // the key bumps, so EXEC can tell whether it changed.
+    watched: HashMap<Key, (usize, u64)>,
+}
+
+impl DataMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Like Redis' DBSIZE, this counts keys that expired but were not deleted yet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    // Looks up a live key without counting as an access.
+    pub fn peek(&self, key: &[u8]) -> Option<&MapValue> {
+        let (key, slot) = self.entries.get_key_value(key)?;
+        if self.is_expired(key) {
+            self.expired_on_read.lock().unwrap().push(key.clone());
+            return None;
+        }
+        Some(&slot.value)
+    }
+    pub fn get(&self, key: &[u8]) -> Option<&MapValue> {
+        let value = self.peek(key)?;
+        value.last_access.touch();
+        Some(value)
+    }
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut MapValue> {
+        self.remove_expired_on_read();
+        if self.is_expired(key) {
+            self.remove_expired_key(key);
+            return None;
+        }
+        self.touch_watched(key);
+        let value = &mut self.entries.get_mut(key)?.value;
+        value.last_access.touch();
+        Some(value)
+    }
+    fn remove_expired_on_read(&mut self) {
+        let keys = std::mem::take(self.expired_on_read.get_mut().unwrap());
+        for key in keys {
+            // The key may have been written again since the reader saw it expired.
+            if self.is_expired(&key) {
+                self.remove_expired_key(&key);
+            }
+        }
+    }
+    pub fn get_or_insert_with(&mut self, key: &[u8], f: impl FnOnce() -> Value) -> &mut MapValue {
+        if self.get(key).is_none() {
+            self.insert(key, MapValue::new(f()));
+        }
+        self.touch_watched(key);
+        let value = &mut self.entries.get_mut(key).unwrap().value;
+        value.last_access.touch();
+        value
+    }
+    // Like Redis' setKey, storing a new value discards any expiration the key had.
+    pub fn insert(&mut self, key: &[u8], value: MapValue) -> Option<MapValue> {
+        self.remove_expired_on_read();
+        self.touch_watched(key);
+        self.set_expiry(key, None);
+        let volatile = matches!(&value.data, Value::Hash(hash) if hash.has_volatile_fields());
+        if let Some(slot) = self.entries.get_mut(key) {
+            let old = std::mem::replace(&mut slot.value, value);
+            if volatile {
+                self.track_volatile_hash(key);
+            }
+            return Some(old);
+        }
+        let key = Key::from(key);
+        if volatile {
+            self.volatile_hashes.insert(key.clone());
+        }
+        self.signal_ready(&key);
+        let position = self.sampling.len();
+        self.sampling.push(key.clone());
+        self.scan_index.insert((scan_hash(&key), key.clone()));
+        self.entries.insert(key, Slot { value, position });
+        None
+    }
+    pub fn remove(&mut self, key: &[u8]) -> Option<MapValue> {
+        self.set_expiry(key, None);
+        let (key, Slot { value, position }) = self.entries.remove_entry(key)?;
+        self.touch_watched(&key);
+        self.sampling.swap_remove(position);
+        if let Some(moved) = self.sampling.get(position) {
+            self.entries.get_mut(moved).unwrap().position = position;
+        }
+        self.volatile_hashes.remove(&key);
+        self.scan_index.remove(&(scan_hash(&key), key));
+        Some(value)
+    }
+    fn remove_expired_key(&mut self, key: &[u8]) {
+        if let Some((key, _)) = self.entries.get_key_value(key) {
+            let key = key.clone();
+            self.remove(&key);
+            self.expired.get_mut().unwrap().push(key);
+        }
+    }
+    // Keys deleted for having expired since the last call.
+    pub fn take_expired(&self) -> Vec<Key> {
+        std::mem::take(&mut *self.expired.lock().unwrap())
+    }
+    fn is_expired(&self, key: &[u8]) -> bool {
+        self.expires
+            .get(key)
+            .is_some_and(|deadline| *deadline <= now_millis())
+    }
+    // Absolute deadline in Unix milliseconds.
+    pub fn expiry(&self, key: &[u8]) -> Option<i64> {
+        self.expires.get(key).copied()
+    }
+    // Replaces the deadline of an existing key; returns false if there is no such key.
+    pub fn set_expiry(&mut self, key: &[u8], deadline: Option<i64>) -> bool {
+        if let Some((key, old)) = self.expires.remove_entry(key) {
+            self.expiry_index.remove(&(old, key));
+        }
+        let Some((key, _)) = self.entries.get_key_value(key) else {
+            return false;
+        };
+        let key = key.clone();
+        self.touch_watched(&key);
+        if let Some(deadline) = deadline {
+            self.expires.insert(key.clone(), deadline);
+            self.expiry_index.insert((deadline, key.clone()));
+        }
+        true
+    }
+    // Registers a hash that was given field deadlines with the active expire cycle.
+    pub fn track_volatile_hash(&mut self, key: &[u8]) {
+        if let Some((key, _)) = self.entries.get_key_value(key) {
+            self.volatile_hashes.insert(key.clone());
+        }
+    }
+    // Deletes the expired fields of every tracked hash, and hashes left empty by that. Returns
+    // the number of fields deleted.
+    pub fn remove_expired_fields(&mut self) -> usize {
+        let mut removed = 0;
+        let keys: Vec<Key> = self.volatile_hashes.iter().cloned().collect();
+        for key in keys {
+            let Some(Value::Hash(hash)) =
+                self.entries.get_mut(&key).map(|slot| &mut slot.value.data)
+            else {
+                // Overwritten by a value of another type.
+                self.volatile_hashes.remove(&key);
+                continue;
+            };
+            let expired = hash.remove_expired();
+            let (empty, volatile) = (hash.is_empty(), hash.has_volatile_fields());
+            removed += expired;
+            if expired > 0 {
+                self.touch_watched(&key);
+            }
+            if empty {
+                self.remove(&key);
+            } else if !volatile {
+                self.volatile_hashes.remove(&key);
+            }
+        }
+        removed
+    }
+    // Empties the keyspace; connections blocked on keys stay blocked, and keys watched stay
+    // watched, changed if they existed.
+    pub fn clear(&mut self) {
+        let blocked = std::mem::take(&mut self.blocked);
+        let mut watched = std::mem::take(&mut self.watched);
+        for (key, (_, version)) in &mut watched {
+            if self.entries.contains_key(key) {
+                *version += 1;
+            }
+        }
+        *self = Self {
+            blocked,
+            watched,
+            ..Self::default()
+        };
+    }
+    fn touch_watched(&mut self, key: &[u8]) {
+        if let Some((_, version)) = self.watched.get_mut(key) {
+            *version += 1;
+        }
+    }
+    // Starts watching `key` for one more connection; returns its current version.
+    pub fn watch(&mut self, key: &[u8]) -> u64 {
+        // A key that expired since its last access changes now, not under the watcher.
+        if self.is_expired(key) {
+            self.remove_expired_key(key);
+        }
+        let (watchers, version) = self.watched.entry(Key::from(key)).or_default();
+        *watchers += 1;
+        *version
+    }
+    pub fn unwatch(&mut self, key: &[u8]) {
+        if let Some((watchers, _)) = self.watched.get_mut(key) {
+            *watchers -= 1;
+            if *watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+    // Version of a watched key; a key that expired since it was watched counts as changed.
+    pub fn watched_version(&mut self, key: &[u8]) -> Option<u64> {
+        if self.is_expired(key) {
+            self.remove_expired_key(key);
+        }
+        self.watched.get(key).map(|(_, version)| *version)
+    }
+    pub fn block(&mut self, keys: &[Vec<u8>], waiter: &Arc<Waiter>) {
+        for key in keys {
+            let waiters = self.blocked.entry(Key::from(key.as_slice())).or_default();
+            if !waiters.iter().any(|w| Arc::ptr_eq(w, waiter)) {
+                waiters.push(waiter.clone());
+            }
+        }
+    }
+    pub fn unblock(&mut self, keys: &[Vec<u8>], waiter: &Arc<Waiter>) {
+        for key in keys {
+            if let Some(waiters) = self.blocked.get_mut(key.as_slice()) {
+                waiters.retain(|w| !Arc::ptr_eq(w, waiter));
+                if waiters.is_empty() {
+                    self.blocked.remove(key.as_slice());
+                }
+            }
+        }
+    }
+    // Wakes the connections blocked on `key`, e.g. after it was created.
+    pub fn signal_ready(&self, key: &[u8]) {
+        for waiter in self.blocked.get(key).into_iter().flatten() {
+            waiter.wake();
+        }
+    }
+    // Deletes up to `limit` keys whose deadline has passed, earliest first.
+    pub fn remove_expired(&mut self, limit: usize) -> usize {
+        self.remove_expired_on_read();
+        let now = now_millis();
+        let mut removed = 0;
+        while removed < limit {
+            let Some((deadline, key)) = self.expiry_index.first() else {
+                break;
+            };
+            if *deadline > now {
+                break;
+            }
+            let key = key.clone();
+            self.remove_expired_key(&key);
+            removed += 1;
+        }
+        removed
+    }
+    // Visits at least `count` index entries starting at `cursor`, never splitting a run of keys
+    // sharing the same hash. Returns the next cursor (0 once the keyspace is exhausted).
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Key, &MapValue)>) {
+        let mut batch = vec![];
+        let mut last_hash = None;
+        for (visited, (hash, key)) in self
+            .scan_index
+            .range((cursor, Key::from(&[][..]))..)
+            .enumerate()
+        {
+            if visited >= count.max(1) && last_hash != Some(*hash) {
+                return (*hash, batch);
+            }
+            last_hash = Some(*hash);
+            if self.peek(key).is_some() {
+                let (key, slot) = self.entries.get_key_value(key).unwrap();
+                batch.push((key, &slot.value));
+            }
+        }
+        (0, batch)
+    }
+    // Cross-checks the entries against every index kept beside them, for --sanity-check.
+    // Returns a description of each violation found.
+    pub fn sanity_check(&self) -> Vec<String> {
+        let mut violations = vec![];
+        let name = |key: &[u8]| String::from_utf8_lossy(key).into_owned();
+        if self.sampling.len() != self.entries.len() || self.scan_index.len() != self.entries.len()
+        {
+            violations.push(format!(
+                "{} entries, but {} sampled and {} scan-indexed keys",
+                self.entries.len(),
+                self.sampling.len(),
+                self.scan_index.len()
+            ));
+        }
+        for (key, slot) in &self.entries {
+            if self.sampling.get(slot.position) != Some(key) {
+                violations.push(format!("'{}' is not at its sampling position", name(key)));
+            }
+            if !self.scan_index.contains(&(scan_hash(key), key.clone())) {
+                violations.push(format!("'{}' is missing from the scan index", name(key)));
+            }
+            if !slot.value.data.is_well_formed() {
+                violations.push(format!(
+                    "'{}' is a malformed {} ({})",
+                    name(key),
+                    slot.value.type_name(),
+                    slot.value.data.encoding()
+                ));
+            }
+            if let Value::Hash(hash) = &slot.value.data {
+                if hash.has_volatile_fields() && !self.volatile_hashes.contains(key) {
+                    violations.push(format!("'{}' has untracked field deadlines", name(key)));
+                }
+            }
+            let dump = rdb::dump(&slot.value.data);
+            let mut reader = rdb::Reader::new(&dump);
+            let reloaded = reader
+                .byte()
+                .and_then(|value_type| reader.value(value_type));
+            if reloaded.is_none_or(|data| data.type_name() != slot.value.type_name()) {
+                violations.push(format!("'{}' does not survive DUMP and reload", name(key)));
+            }
+        }
+        for (hash, key) in &self.scan_index {
+            if *hash != scan_hash(key) || !self.entries.contains_key(key) {
+                violations.push(format!("stale scan index entry for '{}'", name(key)));
+            }
+        }
+        if self.expiry_index.len() != self.expires.len() {
+            violations.push(format!(
+                "{} deadlines, but {} expiry index entries",
+                self.expires.len(),
+                self.expiry_index.len()
+            ));
+        }
+        for (key, deadline) in &self.expires {
+            if !self.entries.contains_key(key) {
+                violations.push(format!("deadline set for missing key '{}'", name(key)));
+            }
+            if !self.expiry_index.contains(&(*deadline, key.clone())) {
+                violations.push(format!("deadline of '{}' is not indexed", name(key)));
+            }
+        }
+        violations
+    }
+    pub fn random_key(&self) -> Option<&Key> {
+        const MAX_ATTEMPTS: usize = 100;
+        if self.sampling.is_empty() {
+            return None;
+        }
+        let live = |key: &&Key| self.peek(key).is_some();
+        (0..MAX_ATTEMPTS)
+            .map(|_| &self.sampling[random::below(self.sampling.len())])
+            .find(live)
+            // Mostly expired keyspace: fall back to walking from a random offset.
+            .or_else(|| {
+                let offset = random::below(self.sampling.len());
+                self.sampling[offset..]
+                    .iter()
+                    .chain(&self.sampling[..offset])
+                    .find(live)
+            })
+    }
+}
+
+pub type ThreadSafeDataMap = Arc<RwLock<DataMap>>;
+
+pub const DATABASES: usize = 16;
+pub type Databases = Arc<Vec<ThreadSafeDataMap>>;
+
+// One pass of the active expire cycle: delete due keys in small batches, within a fixed
+// time budget per pass.
+pub fn active_expire_cycle(databases: &Databases) {
+    const KEYS_PER_LOOP: usize = 20;
+    const TIME_BUDGET: Duration = Duration::from_millis(25);
+    let start = Instant::now();
+    for db in databases.iter() {
+        db.write().unwrap().remove_expired_fields();
+        loop {
+            // Re-acquired per batch so clients are not starved while a large db is swept.
+            let removed = db.write().unwrap().remove_expired(KEYS_PER_LOOP);
+            if removed < KEYS_PER_LOOP || start.elapsed() >= TIME_BUDGET {
+                break;
+            }
+        }
+        if start.elapsed() >= TIME_BUDGET {
+            return;
+        }
+    }
+}
+
+// Deletes every key whose deadline has passed, however many there are.
+pub fn remove_all_expired(databases: &Databases) -> usize {
+    databases
+        .iter()
+        .map(|db| {
+            let mut guard = db.write().unwrap();
+            guard.remove_expired_fields();
+            guard.remove_expired(usize::MAX)
+        })
+        .sum()
+}
+
+// Empties every database, one at a time; used by FLUSHALL and before loading a full resync.
+pub fn flush_all(databases: &Databases) {
+    for db in databases.iter() {
+        db.write().unwrap().clear();
+    }
+}
+
+// Visits the live entries whose scan position falls in `range`, with their deadline. The read
+// lock is held for one batch at a time, so writers get in between batches and a walk over a
+// large db never stalls the clients using it; entries changed meanwhile may or may not be seen.
+pub fn for_each_entry(
+    db: &ThreadSafeDataMap,
+    range: impl RangeBounds<u64>,
+    mut f: impl FnMut(&Key, &MapValue, Option<i64>),
+) {
+    const ENTRIES_PER_BATCH: usize = 128;
+    let mut cursor = match range.start_bound() {
+        Bound::Included(start) => *start,
+        Bound::Excluded(start) if *start == u64::MAX => return,
+        Bound::Excluded(start) => start + 1,
+        Bound::Unbounded => 0,
+    };
+    loop {
+        let guard = db.read().unwrap();
+        let (next, batch) = guard.scan(cursor, ENTRIES_PER_BATCH);
+        for (key, value) in batch {
+            if !range.contains(&scan_hash(key)) {
+                return;
+            }
+            f(key, value, guard.expiry(key));
+        }
+        if next == 0 || !range.contains(&next) {
+            return;
+        }
+        cursor = next;
+    }
+}
+
+// Order-independent fingerprint of the live contents, for comparing two datasets. Only the
+// presence of a deadline is included, since replays compute different absolute deadlines.
+pub fn digest(db: &ThreadSafeDataMap) -> u64 {
+    // Length-prefixed, so items can't run into each other.
+    let item = |crc, item: &[u8]| rdb::crc64(rdb::crc64(crc, &item.len().to_le_bytes()), item);
+    let mut digest = 0;
+    for_each_entry(db, .., |key, value, deadline| {
+        let mut crc = rdb::crc64(0, key);
+        crc = match &value.data {
+            // Equal sets and hashes can list their members in different orders.
+            Value::Set(set) => {
+                let mut members = set.members();
+                members.sort();
+                members
+                    .iter()
+                    .fold(rdb::crc64(crc, b"set"), |crc, member| item(crc, member))
+            }
+            Value::Hash(hash) => {
+                let mut fields: Vec<_> = hash.iter().collect();
+                fields.sort();
+                fields
+                    .iter()
+                    .fold(rdb::crc64(crc, b"hash"), |crc, (field, value)| {
+                        item(item(crc, field), value)
+                    })
+            }
+            // Not a DUMP payload: it ends in a CRC of its own, and a CRC run over a message
+            // followed by its CRC comes out the same whatever the message.
+            data => {
+                let mut serialized = vec![];
+                rdb::write_value(&mut serialized, data);
+                rdb::crc64(crc, &serialized)
+            }
+        };
+        crc = rdb::crc64(crc, &[deadline.is_some() as u8]);
+        digest ^= crc;
+    });
+    digest
+}
+
+pub fn new_databases() -> Databases {
+    Arc::new(
+        (0..DATABASES)
+            .map(|_| Arc::new(RwLock::new(DataMap::new())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_of(entries: &[(&str, Value)]) -> ThreadSafeDataMap {
+        let mut map = DataMap::new();
+        for (key, value) in entries {
+            map.insert(key.as_bytes(), MapValue::new(value.clone()));
+        }
+        Arc::new(RwLock::new(map))
+    }
+
+    fn hash(fields: &[&str]) -> Value {
+        let mut hash = Hash::default();
+        for field in fields {
+            hash.insert(field.as_bytes(), b"v");
+        }
+        Value::Hash(hash)
+    }
+
+    fn set(members: &[&str]) -> Value {
+        let mut set = Set::default();
+        for member in members {
+            set.insert(member.as_bytes());
+        }
+        Value::Set(set)
+    }
+
+    #[test]
+    fn digest_covers_values() {
+        let string = |s: &str| Value::String(s.as_bytes().into());
+        let a = digest(&db_of(&[("k", string("a"))]));
+        assert_ne!(a, digest(&db_of(&[("k", string("b"))])));
+        assert_ne!(a, digest(&db_of(&[("j", string("a"))])));
+        assert_eq!(a, digest(&db_of(&[("k", string("a"))])));
+        assert_ne!(
+            digest(&db_of(&[("h", hash(&["f"]))])),
+            digest(&db_of(&[("h", hash(&["g"]))]))
+        );
+    }
+
+    #[test]
+    fn digest_ignores_member_order() {
+        let fields: Vec<String> = (0..100).map(|i| format!("f{i}")).collect();
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        let reversed: Vec<&str> = fields.iter().rev().copied().collect();
+        assert_eq!(
+            digest(&db_of(&[("h", hash(&fields)), ("s", set(&fields))])),
+            digest(&db_of(&[("s", set(&reversed)), ("h", hash(&reversed))]))
+        );
+    }
+}
@@ -0,0 +1,66 @@
+// Glob-style matching with the same rules as Redis' stringmatchlen: `*`, `?`, `[...]` classes
+// (with `^` negation and `a-z` ranges) and `\` escapes.
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => string.is_empty(),
+        Some((b'*', rest)) => {
+            let rest = {
+                let mut rest = rest;
+                while let Some((b'*', tl)) = rest.split_first() {
+                    rest = tl;
+                }
+                rest
+            };
+            if rest.is_empty() {
+                return true;
+            }
+            (0..=string.len()).any(|skip| matches(rest, &string[skip..]))
+        }
+        Some((b'?', rest)) => !string.is_empty() && matches(rest, &string[1..]),
+        Some((b'[', rest)) => {
+            let Some((&c, string_rest)) = string.split_first() else {
+                return false;
+            };
+            let (negate, mut class) = match rest.split_first() {
+                Some((b'^', tl)) => (true, tl),
+                _ => (false, rest),
+            };
+            let mut found = false;
+            loop {
+                match class {
+                    [] => break,
+                    [b']', tl @ ..] => {
+                        class = tl;
+                        break;
+                    }
+                    [b'\\', escaped, tl @ ..] => {
+                        found |= *escaped == c;
+                        class = tl;
+                    }
+                    [start, b'-', end, tl @ ..] if *end != b']' => {
+                        let (lo, hi) = if start <= end {
+                            (*start, *end)
+                        } else {
+                            (*end, *start)
+                        };
+                        found |= (lo..=hi).contains(&c);
+                        class = tl;
+                    }
+                    [hd, tl @ ..] => {
+                        found |= *hd == c;
+                        class = tl;
+                    }
+                }
+            }
+            found != negate && matches(class, string_rest)
+        }
+        Some((b'\\', rest)) if !rest.is_empty() => match string.split_first() {
+            Some((c, string_rest)) => *c == rest[0] && matches(&rest[1..], string_rest),
+            None => false,
+        },
+        Some((p, rest)) => match string.split_first() {
+            Some((c, string_rest)) => c == p && matches(rest, string_rest),
+            None => false,
+        },
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use crate::db::now_millis;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    // Unix time in seconds of the latest sample.
+    pub time: i64,
+    pub latest_ms: u64,
+    pub max_ms: u64,
+}
+
+// Latest and worst latency per event name, as LATENCY LATEST reports them.
+#[derive(Default)]
+pub struct Monitor {
+    events: BTreeMap<&'static str, Event>,
+}
+
+impl Monitor {
+    pub fn record(&mut self, name: &'static str, latency_ms: u64) {
+        let time = now_millis() / 1000;
+        self.events
+            .entry(name)
+            .and_modify(|event| {
+                event.time = time;
+                event.latest_ms = latency_ms;
+                event.max_ms = event.max_ms.max(latency_ms);
+            })
+            .or_insert(Event {
+                time,
+                latest_ms: latency_ms,
+                max_ms: latency_ms,
+            });
+    }
+    pub fn latest(&self) -> impl Iterator<Item = (&'static str, Event)> + '_ {
+        self.events.iter().map(|(name, event)| (*name, *event))
+    }
+    // Forgets the given events, or all of them; returns how many were dropped.
+    pub fn reset(&mut self, names: &[Vec<u8>]) -> usize {
+        if names.is_empty() {
+            let count = self.events.len();
+            self.events.clear();
+            return count;
+        }
+        let before = self.events.len();
+        self.events
+            .retain(|name, _| !names.iter().any(|n| n.as_slice() == name.as_bytes()));
+        before - self.events.len()
+    }
+}
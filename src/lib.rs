@@ -0,0 +1,25 @@
+#![allow(clippy::pedantic)]
+// The server itself; main.rs is its command line, and cluster_testing runs several of it in one
+// process.
+mod blocking;
+mod client;
+pub mod cluster_testing;
+mod command;
+pub mod config;
+pub mod db;
+mod glob;
+pub mod journal;
+mod latency;
+mod lua;
+mod memory;
+pub mod network;
+mod notify;
+mod pubsub;
+mod random;
+mod rdb;
+mod replication;
+pub mod resp;
+mod scripting;
+pub mod server;
+mod sha1;
+mod types;
@@ -0,0 +1,447 @@
+// The cjson library as Redis configures it: JSON null decodes to the cjson.null sentinel, tables
+// with only positive integer keys encode as arrays (holes as null) unless too sparse, and numbers
+// print as %.14g.
+use std::rc::Rc;
+
+use super::{
+    format_number,
+    stdlib::{check_str, Native},
+    Interp, LuaError, NativeFn, Table, Value,
+};
+
+pub const FUNCTIONS: &[(&str, NativeFn)] = &[("decode", decode), ("encode", encode)];
+
+// Nesting deeper than this is refused both ways.
+const MAX_DEPTH: usize = 1000;
+// Past its first SPARSE_SAFE slots, an array may be at most 1/SPARSE_RATIO filled.
+const SPARSE_RATIO: usize = 2;
+const SPARSE_SAFE: usize = 10;
+
+fn encode(interp: &mut Interp, args: Vec<Value>) -> Native {
+    if args.len() != 1 {
+        return Err(interp.error("bad argument #1 to 'encode' (expected 1 argument)"));
+    }
+    let mut out = vec![];
+    encode_value(interp, &args[0], 0, &mut out)?;
+    Ok(vec![Value::str(out)])
+}
+
+fn encode_value(
+    interp: &Interp,
+    value: &Value,
+    depth: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), LuaError> {
+    match value {
+        Value::Nil => out.extend_from_slice(b"null"),
+        Value::Bool(b) => out.extend_from_slice(if *b { b"true" } else { b"false" }),
+        Value::Num(n) if !n.is_finite() => {
+            return Err(interp.error("Cannot serialise number: must not be NaN or Inf"))
+        }
+        Value::Num(n) => out.extend_from_slice(format_number(*n).as_bytes()),
+        Value::Str(s) => encode_string(s, out),
+        Value::Table(table) if Rc::ptr_eq(table, &interp.json_null) => {
+            out.extend_from_slice(b"null")
+        }
+        Value::Table(table) => {
+            if depth + 1 > MAX_DEPTH {
+                return Err(interp.error(format!(
+                    "Cannot serialise, excessive nesting ({})",
+                    depth + 1
+                )));
+            }
+            encode_table(interp, &table.borrow(), depth + 1, out)?;
+        }
+        Value::Function(_) => {
+            return Err(interp.error("Cannot serialise function: type not supported"))
+        }
+    }
+    Ok(())
+}
+
+fn encode_table(
+    interp: &Interp,
+    table: &Table,
+    depth: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), LuaError> {
+    let entries = entries(table)?;
+    if let Some(len) = array_length(interp, &entries)? {
+        out.push(b'[');
+        for i in 1..=len {
+            if i > 1 {
+                out.push(b',');
+            }
+            encode_value(interp, &table.get(&Value::Num(i as f64)), depth, out)?;
+        }
+        out.push(b']');
+        return Ok(());
+    }
+    out.push(b'{');
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        match key {
+            Value::Str(s) => encode_string(s, out),
+            Value::Num(n) => encode_string(format_number(*n).as_bytes(), out),
+            _ => {
+                return Err(
+                    interp.error("Cannot serialise table: table key must be a number or string")
+                )
+            }
+        }
+        out.push(b':');
+        encode_value(interp, value, depth, out)?;
+    }
+    out.push(b'}');
+    Ok(())
+}
+
+fn entries(table: &Table) -> Result<Vec<(Value, Value)>, LuaError> {
+    let mut entries = vec![];
+    let mut key = Value::Nil;
+    while let Some((k, v)) = table.next(&key)? {
+        key = k.clone();
+        entries.push((k, v));
+    }
+    Ok(entries)
+}
+
+// The length of a table encoded as an array, None if it has to be an object. An empty table is
+// an object.
+fn array_length(interp: &Interp, entries: &[(Value, Value)]) -> Result<Option<usize>, LuaError> {
+    let mut max = 0;
+    for (key, _) in entries {
+        match key {
+            Value::Num(n) if n.fract() == 0.0 && *n >= 1.0 => max = max.max(*n as usize),
+            _ => return Ok(None),
+        }
+    }
+    if max > SPARSE_SAFE && max > entries.len() * SPARSE_RATIO {
+        return Err(interp.error("Cannot serialise table: excessively sparse array"));
+    }
+    Ok((max > 0).then_some(max))
+}
+
+fn encode_string(s: &[u8], out: &mut Vec<u8>) {
+    out.push(b'"');
+    for &c in s {
+        match c {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'/' => out.extend_from_slice(b"\\/"),
+            b'\x08' => out.extend_from_slice(b"\\b"),
+            b'\x0c' => out.extend_from_slice(b"\\f"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            c if c < 0x20 => out.extend_from_slice(format!("\\u{c:04x}").as_bytes()),
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
+
+fn decode(interp: &mut Interp, args: Vec<Value>) -> Native {
+    if args.len() != 1 {
+        return Err(interp.error("bad argument #1 to 'decode' (expected 1 argument)"));
+    }
+    let text = check_str(interp, &args, 0, "decode")?;
+    let mut decoder = Decoder {
+        interp,
+        text: &text,
+        pos: 0,
+        depth: 0,
+    };
+    let token = decoder.token();
+    let value = decoder.value(token)?;
+    let (at, token) = decoder.token();
+    if !matches!(token, Token::End) {
+        return Err(decoder.unexpected("the end", at, &token));
+    }
+    Ok(vec![value])
+}
+
+enum Token {
+    ObjectBegin,
+    ObjectEnd,
+    ArrayBegin,
+    ArrayEnd,
+    Str(Vec<u8>),
+    Num(f64),
+    Bool(bool),
+    Null,
+    Colon,
+    Comma,
+    End,
+    // What was wrong with the text, as it goes into the error.
+    Error(&'static str),
+}
+
+impl Token {
+    // How errors name a token, after cjson's token types.
+    fn name(&self) -> &'static str {
+        match self {
+            Token::ObjectBegin => "T_OBJ_BEGIN",
+            Token::ObjectEnd => "T_OBJ_END",
+            Token::ArrayBegin => "T_ARR_BEGIN",
+            Token::ArrayEnd => "T_ARR_END",
+            Token::Str(_) => "T_STRING",
+            Token::Num(_) => "T_NUMBER",
+            Token::Bool(_) => "T_BOOLEAN",
+            Token::Null => "T_NULL",
+            Token::Colon => "T_COLON",
+            Token::Comma => "T_COMMA",
+            Token::End => "T_END",
+            Token::Error(message) => message,
+        }
+    }
+}
+
+struct Decoder<'i, 'h, 't> {
+    interp: &'i Interp<'h>,
+    text: &'t [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Decoder<'_, '_, '_> {
+    // The next token and the offset it starts at.
+    fn token(&mut self) -> (usize, Token) {
+        while matches!(self.text.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+        let at = self.pos;
+        let Some(&c) = self.text.get(at) else {
+            return (at, Token::End);
+        };
+        self.pos += 1;
+        let token = match c {
+            b'{' => Token::ObjectBegin,
+            b'}' => Token::ObjectEnd,
+            b'[' => Token::ArrayBegin,
+            b']' => Token::ArrayEnd,
+            b':' => Token::Colon,
+            b',' => Token::Comma,
+            b'"' => self.string(),
+            b'-' | b'0'..=b'9' => {
+                let end = self.text[at..]
+                    .iter()
+                    .position(|c| !matches!(c, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'))
+                    .map_or(self.text.len(), |len| at + len);
+                self.pos = end;
+                std::str::from_utf8(&self.text[at..end])
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .map_or(Token::Error("invalid number"), Token::Num)
+            }
+            _ => {
+                self.pos = at;
+                self.literal()
+            }
+        };
+        (at, token)
+    }
+    fn literal(&mut self) -> Token {
+        for (word, token) in [
+            (&b"true"[..], Token::Bool(true)),
+            (b"false", Token::Bool(false)),
+            (b"null", Token::Null),
+        ] {
+            if self.text[self.pos..].starts_with(word) {
+                self.pos += word.len();
+                return token;
+            }
+        }
+        Token::Error("invalid token")
+    }
+    // The rest of a string whose opening quote was read.
+    fn string(&mut self) -> Token {
+        let mut out = vec![];
+        loop {
+            let Some(&c) = self.text.get(self.pos) else {
+                return Token::Error("unexpected end of string");
+            };
+            self.pos += 1;
+            match c {
+                b'"' => return Token::Str(out),
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.pos) else {
+                        return Token::Error("unexpected end of string");
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'"' | b'\\' | b'/' => out.push(escape),
+                        b'b' => out.push(b'\x08'),
+                        b'f' => out.push(b'\x0c'),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'u' => match self.unicode_escape() {
+                            Some(c) => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                            None => return Token::Error("invalid unicode escape code"),
+                        },
+                        _ => return Token::Error("invalid escape code"),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+    // The character of a \uXXXX escape whose "\u" was read, joining a surrogate pair.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high);
+        }
+        if !self.text[self.pos..].starts_with(b"\\u") {
+            return None;
+        }
+        self.pos += 2;
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+    }
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.pos..self.pos + 4)?;
+        let digits = std::str::from_utf8(digits).ok()?;
+        let n = u32::from_str_radix(digits, 16).ok()?;
+        self.pos += 4;
+        Some(n)
+    }
+    fn unexpected(&self, expected: &str, at: usize, token: &Token) -> LuaError {
+        self.interp.error(format!(
+            "Expected {expected} but found {} at character {}",
+            token.name(),
+            at + 1
+        ))
+    }
+    fn value(&mut self, (at, token): (usize, Token)) -> Result<Value, LuaError> {
+        Ok(match token {
+            Token::ObjectBegin => self.nested(at, |decoder| decoder.object())?,
+            Token::ArrayBegin => self.nested(at, |decoder| decoder.array())?,
+            Token::Str(s) => Value::str(s),
+            Token::Num(n) => Value::Num(n),
+            Token::Bool(b) => Value::Bool(b),
+            Token::Null => Value::Table(self.interp.json_null.clone()),
+            token => return Err(self.unexpected("value", at, &token)),
+        })
+    }
+    fn nested(
+        &mut self,
+        at: usize,
+        f: impl FnOnce(&mut Self) -> Result<Table, LuaError>,
+    ) -> Result<Value, LuaError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.interp.error(format!(
+                "Found too many nested data structures ({}) at character {}",
+                self.depth,
+                at + 1
+            )));
+        }
+        let table = f(self)?;
+        self.depth -= 1;
+        Ok(Value::table(table))
+    }
+    fn object(&mut self) -> Result<Table, LuaError> {
+        let mut table = Table::default();
+        let mut next = self.token();
+        if matches!(next.1, Token::ObjectEnd) {
+            return Ok(table);
+        }
+        loop {
+            let (at, token) = next;
+            let Token::Str(key) = token else {
+                return Err(self.unexpected("object key string", at, &token));
+            };
+            let (at, token) = self.token();
+            if !matches!(token, Token::Colon) {
+                return Err(self.unexpected("colon", at, &token));
+            }
+            let token = self.token();
+            let value = self.value(token)?;
+            table.set(Value::str(key), value)?;
+            match self.token() {
+                (_, Token::Comma) => next = self.token(),
+                (_, Token::ObjectEnd) => return Ok(table),
+                (at, token) => return Err(self.unexpected("comma or object end", at, &token)),
+            }
+        }
+    }
+    fn array(&mut self) -> Result<Table, LuaError> {
+        let mut table = Table::default();
+        let mut next = self.token();
+        if matches!(next.1, Token::ArrayEnd) {
+            return Ok(table);
+        }
+        loop {
+            let value = self.value(next)?;
+            table.push(value);
+            match self.token() {
+                (_, Token::Comma) => next = self.token(),
+                (_, Token::ArrayEnd) => return Ok(table),
+                (at, token) => return Err(self.unexpected("comma or array end", at, &token)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lua::{on_script_stack, to_display, Interp, LuaError, Value};
+
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        on_script_stack(|| {
+            let mut interp = Interp::new(None);
+            let f = interp.load(source.as_bytes())?;
+            let show = |value: &Value| String::from_utf8_lossy(&to_display(value)).into_owned();
+            match interp.call(&f, vec![]) {
+                Ok(values) => Ok(values.iter().map(show).collect()),
+                Err(LuaError(error)) => Err(show(&error)),
+            }
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let encoded = run(r#"return cjson.encode({1, "two", {a = true}, cjson.null, 2.5})"#);
+        assert_eq!(encoded, Ok(vec![r#"[1,"two",{"a":true},null,2.5]"#.into()]));
+        let decoded = run(
+            r#"local t = cjson.decode('{"a":[1,2,{"b":null}],"s":"é\/"}')
+            return t.a[2], t.a[3].b == cjson.null, t.s, #t.a"#,
+        );
+        assert_eq!(
+            decoded,
+            Ok(vec!["2".into(), "true".into(), "é/".into(), "3".into()])
+        );
+        assert_eq!(run("return cjson.encode({})"), Ok(vec!["{}".into()]));
+        assert_eq!(
+            run(r#"return cjson.encode({[1] = 1, [3] = 3})"#),
+            Ok(vec!["[1,null,3]".into()])
+        );
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        let error = run("return cjson.decode('[1,]')").unwrap_err();
+        assert!(
+            error.ends_with("Expected value but found T_ARR_END at character 4"),
+            "{error}"
+        );
+        let error = run("return cjson.decode('{} x')").unwrap_err();
+        assert!(error.ends_with("Expected the end but found invalid token at character 4"));
+        let error = run("return cjson.encode({[1] = 1, [100] = 2})").unwrap_err();
+        assert!(error.ends_with("excessively sparse array"), "{error}");
+        let error = run("return cjson.encode(function() end)").unwrap_err();
+        assert!(error.ends_with("type not supported"), "{error}");
+        let deep = format!("return cjson.decode('{}')", "[".repeat(1001));
+        assert!(run(&deep)
+            .unwrap_err()
+            .contains("too many nested data structures"));
+    }
+}
@@ -0,0 +1,977 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{
+    parse::{parse, BinOp, Block, Expr, Field, StatKind, UnOp},
+    stdlib, Function, Host, LuaError, Registered, Table, Value, STACK_SIZE,
+};
+
+// Locals in scope, innermost first; closures keep the list they were created under.
+pub type Env = Option<Rc<Local>>;
+
+pub struct Local {
+    name: Rc<str>,
+    value: RefCell<Value>,
+    parent: Env,
+}
+
+fn lookup<'e>(env: &'e Env, name: &str) -> Option<&'e Local> {
+    let mut scope = env.as_deref();
+    while let Some(local) = scope {
+        if &*local.name == name {
+            return Some(local);
+        }
+        scope = local.parent.as_deref();
+    }
+    None
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        release(vec![self.value.take()], vec![self.parent.take()]);
+    }
+}
+
+// Frees values and scopes one at a time rather than recursively, as a long enough chain of nested
+// tables or closures would overflow the stack otherwise.
+pub fn release(mut values: Vec<Value>, mut scopes: Vec<Env>) {
+    loop {
+        if let Some(scope) = scopes.pop() {
+            if let Some(local) = scope.and_then(|local| Rc::try_unwrap(local).ok()) {
+                values.push(local.value.take());
+                scopes.push(local.parent.clone());
+            }
+            continue;
+        }
+        let Some(value) = values.pop() else {
+            return;
+        };
+        match value {
+            Value::Table(table) => {
+                if let Ok(table) = Rc::try_unwrap(table) {
+                    values.extend(table.into_inner().take_all());
+                }
+            }
+            Value::Function(f) => match Rc::try_unwrap(f) {
+                Ok(Function::Lua(_, env)) => scopes.push(env),
+                Ok(Function::Bound(_, state)) => values.push(state),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+fn declare(env: &Env, name: Rc<str>, value: Value) -> Env {
+    Some(Rc::new(Local {
+        name,
+        value: RefCell::new(value),
+        parent: env.clone(),
+    }))
+}
+
+// The address of a local of the caller's frame; stacks grow down.
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+// Stack a script may use before "stack overflow", leaving the rest of the script thread's to
+// the native functions and nested expressions below the deepest call.
+const STACK_BUDGET: usize = STACK_SIZE - 8 * 1024 * 1024;
+// Loop iterations and calls between two looks at the watchdog.
+const TICKS_PER_CHECK: u32 = 1000;
+
+// How many tables an __index or __newindex lookup may pass through before giving up.
+const MAX_META_CHAIN: usize = 100;
+
+// Called every so often while a script runs; a message stops the script with that error.
+pub type Watchdog<'h> = Box<dyn FnMut() -> Option<String> + 'h>;
+
+pub struct Interp<'h> {
+    pub globals: Rc<RefCell<Table>>,
+    // The string library, which also serves method calls on strings.
+    pub strings: Rc<RefCell<Table>>,
+    // What getmetatable gives for a string: {__index = string}.
+    pub string_meta: Rc<RefCell<Table>>,
+    pub host: Option<&'h mut dyn Host>,
+    // The chunk name errors are reported against, as in "user_script:1: ...".
+    pub chunk: &'static str,
+    // The line being run, for error positions.
+    pub line: u32,
+    // What redis.register_function collected, None where it may not be called.
+    pub registered: Option<Vec<Registered>>,
+    pub watchdog: Option<Watchdog<'h>>,
+    // Set once the watchdog stopped the script, so pcall lets the error through.
+    pub aborted: bool,
+    // What cjson.null stands for, told apart from other tables by identity.
+    pub json_null: Rc<RefCell<Table>>,
+    // The state of math.random, an lrand48 generator seeded afresh for every script.
+    pub random: u64,
+    // Where the stack stood when the interpreter was created.
+    stack_base: usize,
+    ticks: u32,
+}
+
+impl<'h> Interp<'h> {
+    pub fn new(host: Option<&'h mut dyn Host>) -> Interp<'h> {
+        let mut interp = Interp {
+            globals: Rc::default(),
+            strings: Rc::default(),
+            string_meta: Rc::default(),
+            host,
+            chunk: "user_script",
+            line: 0,
+            registered: None,
+            watchdog: None,
+            aborted: false,
+            json_null: Rc::default(),
+            random: 0,
+            stack_base: stack_position(),
+            ticks: 0,
+        };
+        stdlib::install(&mut interp);
+        interp
+    }
+
+    // Compiles a chunk into a function, or an error message with its position.
+    pub fn load(&self, source: &[u8]) -> Result<Value, String> {
+        let body =
+            parse(source).map_err(|(message, line)| format!("{}:{line}: {message}", self.chunk))?;
+        Ok(Value::Function(Rc::new(Function::Lua(body, None))))
+    }
+
+    // Runs the code of a function library, returning what it registers. Errors then and later
+    // are reported against "user_function", and redis.call is out of reach while it runs.
+    pub fn register_functions(&mut self, code: &[u8]) -> Result<Vec<Registered>, String> {
+        self.chunk = "user_function";
+        let chunk = self
+            .load(code)
+            .map_err(|e| format!("Error compiling function: {e}"))?;
+        let host = self.host.take();
+        self.registered = Some(vec![]);
+        let result = self.call(&chunk, vec![]);
+        self.host = host;
+        let registered = self.registered.take().unwrap_or_default();
+        if let Err(LuaError(error)) = result {
+            let message = match &error {
+                Value::Table(table) => table.borrow().get_str("err"),
+                _ => error,
+            };
+            return Err(format!(
+                "Error registering functions: {}",
+                String::from_utf8_lossy(&stdlib::to_display(&message))
+            ));
+        }
+        Ok(registered)
+    }
+
+    // An error positioned at the line being run.
+    pub fn error(&self, message: impl AsRef<str>) -> LuaError {
+        self.error_at(self.line, message)
+    }
+    fn error_at(&self, line: u32, message: impl AsRef<str>) -> LuaError {
+        LuaError::new(format!("{}:{line}: {}", self.chunk, message.as_ref()))
+    }
+
+    fn tick(&mut self) -> Result<(), LuaError> {
+        self.ticks = self.ticks.wrapping_add(1);
+        if !self.ticks.is_multiple_of(TICKS_PER_CHECK) {
+            return Ok(());
+        }
+        match self.watchdog.as_mut().and_then(|watchdog| watchdog()) {
+            Some(message) => {
+                self.aborted = true;
+                Err(LuaError::new(message))
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn call(&mut self, f: &Value, mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        let Value::Function(f) = f else {
+            let handler = metamethod(f, "__call");
+            if !matches!(handler, Value::Function(_)) {
+                return Err(self.error(format!("attempt to call a {} value", f.type_name())));
+            }
+            args.insert(0, f.clone());
+            return self.call(&handler, args);
+        };
+        self.check_stack()?;
+        let native = match &**f {
+            Function::Native(native) => native,
+            Function::Bound(native, state) => {
+                args.insert(0, state.clone());
+                native
+            }
+            Function::Lua(body, env) => {
+                self.tick()?;
+                let mut env = env.clone();
+                let mut args = args.into_iter();
+                for param in &body.params {
+                    env = declare(&env, param.clone(), args.next().unwrap_or_default());
+                }
+                let varargs: Vec<Value> = if body.vararg { args.collect() } else { vec![] };
+                let flow = self.exec_block(&body.body, &env, &varargs);
+                return match flow? {
+                    Flow::Return(values) => Ok(values),
+                    _ => Ok(vec![]),
+                };
+            }
+        };
+        let line = self.line;
+        let result = native(self, args);
+        self.line = line;
+        result
+    }
+
+    fn exec_block(
+        &mut self,
+        block: &Block,
+        env: &Env,
+        varargs: &[Value],
+    ) -> Result<Flow, LuaError> {
+        self.exec_scoped(block, env, varargs).map(|(flow, _)| flow)
+    }
+    // Runs a block, also handing back its innermost scope for `repeat ... until`.
+    fn exec_scoped(
+        &mut self,
+        block: &Block,
+        env: &Env,
+        varargs: &[Value],
+    ) -> Result<(Flow, Env), LuaError> {
+        let mut env = env.clone();
+        for stat in block {
+            self.line = stat.line;
+            match &stat.kind {
+                StatKind::Local(names, exprs) => {
+                    let mut values = self.eval_all(exprs, &env, varargs)?;
+                    values.resize(names.len(), Value::Nil);
+                    for (name, value) in names.iter().zip(values) {
+                        env = declare(&env, name.clone(), value);
+                    }
+                }
+                StatKind::LocalFunction(name, body) => {
+                    env = declare(&env, name.clone(), Value::Nil);
+                    let f = Function::Lua(body.clone(), env.clone());
+                    *env.as_ref().unwrap().value.borrow_mut() = Value::Function(Rc::new(f));
+                }
+                StatKind::Assign(targets, exprs) => {
+                    let mut values = self.eval_all(exprs, &env, varargs)?;
+                    values.resize(targets.len(), Value::Nil);
+                    for (target, value) in targets.iter().zip(values) {
+                        self.assign(target, value, &env, varargs)?;
+                    }
+                }
+                StatKind::Call(expr) => {
+                    self.eval_multi(expr, &env, varargs)?;
+                }
+                StatKind::Do(body) => match self.exec_block(body, &env, varargs)? {
+                    Flow::Normal => {}
+                    flow => return Ok((flow, env)),
+                },
+                StatKind::While(condition, body) => {
+                    while self.eval(condition, &env, varargs)?.truthy() {
+                        self.tick()?;
+                        match self.exec_block(body, &env, varargs)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok((flow, env)),
+                        }
+                    }
+                }
+                StatKind::Repeat(body, condition) => loop {
+                    self.tick()?;
+                    let (flow, inner) = self.exec_scoped(body, &env, varargs)?;
+                    match flow {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok((flow, env)),
+                    }
+                    if self.eval(condition, &inner, varargs)?.truthy() {
+                        break;
+                    }
+                },
+                StatKind::If(branches, otherwise) => {
+                    let mut chosen = otherwise.as_ref();
+                    for (condition, body) in branches {
+                        if self.eval(condition, &env, varargs)?.truthy() {
+                            chosen = Some(body);
+                            break;
+                        }
+                    }
+                    if let Some(body) = chosen {
+                        match self.exec_block(body, &env, varargs)? {
+                            Flow::Normal => {}
+                            flow => return Ok((flow, env)),
+                        }
+                    }
+                }
+                StatKind::NumericFor(name, start, limit, step, body) => {
+                    let mut number = |expr: Option<&Expr>, what: &str| match expr {
+                        None => Ok(1.0),
+                        Some(expr) => self
+                            .eval(expr, &env, varargs)?
+                            .to_number()
+                            .ok_or_else(|| self.error(format!("'for' {what} must be a number"))),
+                    };
+                    let mut i = number(Some(start), "initial value")?;
+                    let limit = number(Some(limit), "limit")?;
+                    let step = number(step.as_ref(), "step")?;
+                    while (step > 0.0 && i <= limit) || (step <= 0.0 && i >= limit) {
+                        self.tick()?;
+                        let inner = declare(&env, name.clone(), Value::Num(i));
+                        match self.exec_block(body, &inner, varargs)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok((flow, env)),
+                        }
+                        i += step;
+                    }
+                }
+                StatKind::GenericFor(names, exprs, body) => {
+                    let mut values = self.eval_all(exprs, &env, varargs)?.into_iter();
+                    let f = values.next().unwrap_or_default();
+                    let state = values.next().unwrap_or_default();
+                    let mut control = values.next().unwrap_or_default();
+                    if !callable(&f) {
+                        return Err(
+                            self.error(format!("attempt to call a {} value", f.type_name()))
+                        );
+                    }
+                    loop {
+                        self.tick()?;
+                        let line = self.line;
+                        let mut results = self.call(&f, vec![state.clone(), control])?;
+                        self.line = line;
+                        results.resize(names.len(), Value::Nil);
+                        if matches!(results[0], Value::Nil) {
+                            break;
+                        }
+                        control = results[0].clone();
+                        let mut inner = env.clone();
+                        for (name, value) in names.iter().zip(results) {
+                            inner = declare(&inner, name.clone(), value);
+                        }
+                        match self.exec_block(body, &inner, varargs)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok((flow, env)),
+                        }
+                    }
+                }
+                StatKind::Return(exprs) => {
+                    return Ok((Flow::Return(self.eval_all(exprs, &env, varargs)?), env))
+                }
+                StatKind::Break => return Ok((Flow::Break, env)),
+            }
+        }
+        Ok((Flow::Normal, env))
+    }
+
+    fn assign(
+        &mut self,
+        target: &Expr,
+        value: Value,
+        env: &Env,
+        varargs: &[Value],
+    ) -> Result<(), LuaError> {
+        match target {
+            Expr::Name(name) => match lookup(env, name) {
+                Some(local) => *local.value.borrow_mut() = value,
+                None => {
+                    let globals = self.globals.clone();
+                    self.set_field(&globals, Value::str(&**name), value)?;
+                }
+            },
+            Expr::Index(object, key) => {
+                let table = self.eval(object, env, varargs)?;
+                let key = self.eval(key, env, varargs)?;
+                let Value::Table(table) = table else {
+                    return Err(self.misuse("index", object, &table, env));
+                };
+                self.set_index(table, key, value)?;
+            }
+            _ => unreachable!("checked by the parser"),
+        }
+        Ok(())
+    }
+    // Assigns table[key], going through __newindex when the key is absent.
+    fn set_index(
+        &mut self,
+        mut table: Rc<RefCell<Table>>,
+        key: Value,
+        value: Value,
+    ) -> Result<(), LuaError> {
+        for _ in 0..MAX_META_CHAIN {
+            let present = !matches!(table.borrow().get(&key), Value::Nil);
+            let handler = match present {
+                true => Value::Nil,
+                false => metamethod(&Value::Table(table.clone()), "__newindex"),
+            };
+            match handler {
+                Value::Nil => return self.set_field(&table, key, value),
+                Value::Table(next) => table = next,
+                handler => {
+                    self.call(&handler, vec![Value::Table(table), key, value])?;
+                    return Ok(());
+                }
+            }
+        }
+        Err(self.error("loop in settable"))
+    }
+    // Assigns table[key] as rawset does, refusing read-only tables.
+    pub fn set_field(
+        &self,
+        table: &Rc<RefCell<Table>>,
+        key: Value,
+        value: Value,
+    ) -> Result<(), LuaError> {
+        let mut table = table.borrow_mut();
+        if table.readonly {
+            return Err(self.error("Attempt to modify a readonly table"));
+        }
+        table.set(key, value).map_err(|LuaError(message)| {
+            self.error(String::from_utf8_lossy(&message.to_bytes().unwrap()))
+        })
+    }
+
+    // How an operand is named in errors, as in "attempt to index local 'x' (a nil value)".
+    fn misuse(&self, action: &str, expr: &Expr, value: &Value, env: &Env) -> LuaError {
+        let name = match expr {
+            Expr::Name(name) if lookup(env, name).is_some() => Some(format!("local '{name}'")),
+            Expr::Name(name) => Some(format!("global '{name}'")),
+            Expr::Index(_, key) => match &**key {
+                Expr::Str(key) => Some(format!("field '{}'", String::from_utf8_lossy(key))),
+                _ => None,
+            },
+            Expr::Method(_, name, _, _) => {
+                Some(format!("method '{}'", String::from_utf8_lossy(name)))
+            }
+            _ => None,
+        };
+        let kind = value.type_name();
+        self.error(match name {
+            Some(name) => format!("attempt to {action} {name} (a {kind} value)"),
+            None => format!("attempt to {action} a {kind} value"),
+        })
+    }
+
+    // Every value of a list, the last expression contributing all of its values.
+    fn eval_all(
+        &mut self,
+        exprs: &[Expr],
+        env: &Env,
+        varargs: &[Value],
+    ) -> Result<Vec<Value>, LuaError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (i, expr) in exprs.iter().enumerate() {
+            if i + 1 == exprs.len() {
+                values.extend(self.eval_multi(expr, env, varargs)?);
+            } else {
+                values.push(self.eval(expr, env, varargs)?);
+            }
+        }
+        Ok(values)
+    }
+    fn eval_multi(
+        &mut self,
+        expr: &Expr,
+        env: &Env,
+        varargs: &[Value],
+    ) -> Result<Vec<Value>, LuaError> {
+        match expr {
+            Expr::Vararg => Ok(varargs.to_vec()),
+            Expr::Call(callee, args, line) => {
+                let f = self.eval(callee, env, varargs)?;
+                if !callable(&f) {
+                    self.line = *line;
+                    return Err(self.misuse("call", callee, &f, env));
+                }
+                let args = self.eval_all(args, env, varargs)?;
+                self.line = *line;
+                let results = self.call(&f, args);
+                self.line = *line;
+                results
+            }
+            Expr::Method(object, name, args, line) => {
+                let object_value = self.eval(object, env, varargs)?;
+                self.line = *line;
+                let f = self.index(&object_value, &Value::Str(name.clone()), object, env)?;
+                if !callable(&f) {
+                    return Err(self.misuse("call", expr, &f, env));
+                }
+                let mut all = vec![object_value];
+                all.extend(self.eval_all(args, env, varargs)?);
+                self.line = *line;
+                let results = self.call(&f, all);
+                self.line = *line;
+                results
+            }
+            _ => Ok(vec![self.eval(expr, env, varargs)?]),
+        }
+    }
+    // Reads object[key], following __index when a table lacks the key.
+    fn index(
+        &mut self,
+        object: &Value,
+        key: &Value,
+        expr: &Expr,
+        env: &Env,
+    ) -> Result<Value, LuaError> {
+        let mut object = object.clone();
+        for depth in 0..MAX_META_CHAIN {
+            let handler = match &object {
+                Value::Table(table) => match table.borrow().get(key) {
+                    Value::Nil => metamethod(&object, "__index"),
+                    value => return Ok(value),
+                },
+                Value::Str(_) => return Ok(self.strings.borrow().get(key)),
+                _ if depth == 0 => return Err(self.misuse("index", expr, &object, env)),
+                _ => {
+                    return Err(
+                        self.error(format!("attempt to index a {} value", object.type_name()))
+                    )
+                }
+            };
+            match handler {
+                Value::Nil => return Ok(Value::Nil),
+                Value::Function(_) => {
+                    let results = self.call(&handler, vec![object, key.clone()])?;
+                    return Ok(results.into_iter().next().unwrap_or_default());
+                }
+                handler => object = handler,
+            }
+        }
+        Err(self.error("loop in gettable"))
+    }
+    fn check_stack(&self) -> Result<(), LuaError> {
+        if self.stack_base.saturating_sub(stack_position()) > STACK_BUDGET {
+            return Err(self.error("stack overflow"));
+        }
+        Ok(())
+    }
+    fn eval(&mut self, expr: &Expr, env: &Env, varargs: &[Value]) -> Result<Value, LuaError> {
+        // Chains like `a+b+c` nest as deep as they are long.
+        if matches!(expr, Expr::Bin(..) | Expr::Index(..)) {
+            self.check_stack()?;
+        }
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Bool(true),
+            Expr::False => Value::Bool(false),
+            Expr::Num(n) => Value::Num(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Vararg => varargs.first().cloned().unwrap_or_default(),
+            Expr::Function(body) => {
+                Value::Function(Rc::new(Function::Lua(body.clone(), env.clone())))
+            }
+            Expr::Name(name) => match lookup(env, name) {
+                Some(local) => local.value.borrow().clone(),
+                None => match self.globals.borrow().get_str(name) {
+                    Value::Nil if stdlib::UNSUPPORTED.contains(&&**name) => {
+                        return Err(self.error(format!("the '{name}' library is not supported")))
+                    }
+                    Value::Nil => {
+                        return Err(self.error(format!(
+                            "Script attempted to access nonexistent global variable '{name}'"
+                        )))
+                    }
+                    value => value,
+                },
+            },
+            Expr::Index(object, key) => {
+                let object_value = self.eval(object, env, varargs)?;
+                let key = self.eval(key, env, varargs)?;
+                self.index(&object_value, &key, object, env)?
+            }
+            Expr::Call(..) | Expr::Method(..) => self
+                .eval_multi(expr, env, varargs)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Table(fields, line) => {
+                let mut table = Table::default();
+                let mut positional = 0;
+                for (i, field) in fields.iter().enumerate() {
+                    match field {
+                        Field::Positional(expr) if i + 1 == fields.len() => {
+                            for value in self.eval_multi(expr, env, varargs)? {
+                                positional += 1;
+                                table.set(Value::Num(positional as f64), value)?;
+                            }
+                        }
+                        Field::Positional(expr) => {
+                            positional += 1;
+                            let value = self.eval(expr, env, varargs)?;
+                            table.set(Value::Num(positional as f64), value)?;
+                        }
+                        Field::Keyed(key, value) => {
+                            let key = self.eval(key, env, varargs)?;
+                            let value = self.eval(value, env, varargs)?;
+                            table.set(key, value).map_err(|LuaError(message)| {
+                                self.error_at(
+                                    *line,
+                                    String::from_utf8_lossy(&message.to_bytes().unwrap()),
+                                )
+                            })?;
+                        }
+                    }
+                }
+                Value::table(table)
+            }
+            Expr::Paren(inner) => self.eval(inner, env, varargs)?,
+            Expr::Un(op, operand, line) => {
+                let value = self.eval(operand, env, varargs)?;
+                self.line = *line;
+                match op {
+                    UnOp::Not => Value::Bool(!value.truthy()),
+                    UnOp::Neg => match value.to_number() {
+                        Some(n) => Value::Num(-n),
+                        None => match metamethod(&value, "__unm") {
+                            Value::Nil => {
+                                return Err(self.misuse(
+                                    "perform arithmetic on",
+                                    operand,
+                                    &value,
+                                    env,
+                                ))
+                            }
+                            handler => self.first(&handler, vec![value.clone(), value])?,
+                        },
+                    },
+                    UnOp::Len => match &value {
+                        Value::Str(s) => Value::Num(s.len() as f64),
+                        Value::Table(t) => Value::Num(t.borrow().len() as f64),
+                        _ => return Err(self.misuse("get length of", operand, &value, env)),
+                    },
+                }
+            }
+            Expr::Bin(BinOp::And, left, right, _) => {
+                let left = self.eval(left, env, varargs)?;
+                if !left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(right, env, varargs)?
+            }
+            Expr::Bin(BinOp::Or, left, right, _) => {
+                let left = self.eval(left, env, varargs)?;
+                if left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(right, env, varargs)?
+            }
+            Expr::Bin(op, left, right, line) => {
+                let a = self.eval(left, env, varargs)?;
+                let b = self.eval(right, env, varargs)?;
+                self.line = *line;
+                self.binary(*op, a, b, left, right, env)?
+            }
+        })
+    }
+    fn binary(
+        &mut self,
+        op: BinOp,
+        a: Value,
+        b: Value,
+        left: &Expr,
+        right: &Expr,
+        env: &Env,
+    ) -> Result<Value, LuaError> {
+        match op {
+            BinOp::Eq => return Ok(Value::Bool(self.equals(&a, &b)?)),
+            BinOp::Ne => return Ok(Value::Bool(!self.equals(&a, &b)?)),
+            BinOp::Concat => {
+                return match (a.to_bytes(), b.to_bytes()) {
+                    (Some(x), Some(y)) => Ok(Value::str([&x[..], &y[..]].concat())),
+                    _ => match self.binary_handler(&a, &b, "__concat") {
+                        Some(handler) => self.first(&handler, vec![a, b]),
+                        None if a.to_bytes().is_none() => {
+                            Err(self.misuse("concatenate", left, &a, env))
+                        }
+                        None => Err(self.misuse("concatenate", right, &b, env)),
+                    },
+                }
+            }
+            BinOp::Lt => return Ok(Value::Bool(self.less_than(&a, &b)?)),
+            BinOp::Le => return Ok(Value::Bool(self.less_equal(&a, &b)?)),
+            BinOp::Gt => return Ok(Value::Bool(self.less_than(&b, &a)?)),
+            BinOp::Ge => return Ok(Value::Bool(self.less_equal(&b, &a)?)),
+            _ => {}
+        }
+        let (Some(x), Some(y)) = (a.to_number(), b.to_number()) else {
+            let event = match op {
+                BinOp::Add => "__add",
+                BinOp::Sub => "__sub",
+                BinOp::Mul => "__mul",
+                BinOp::Div => "__div",
+                BinOp::Mod => "__mod",
+                _ => "__pow",
+            };
+            if let Some(handler) = self.binary_handler(&a, &b, event) {
+                return self.first(&handler, vec![a, b]);
+            }
+            return Err(match a.to_number() {
+                None => self.misuse("perform arithmetic on", left, &a, env),
+                Some(_) => self.misuse("perform arithmetic on", right, &b, env),
+            });
+        };
+        Ok(Value::Num(match op {
+            BinOp::Add => x + y,
+            BinOp::Sub => x - y,
+            BinOp::Mul => x * y,
+            BinOp::Div => x / y,
+            BinOp::Mod => x - (x / y).floor() * y,
+            BinOp::Pow => x.powf(y),
+            _ => unreachable!(),
+        }))
+    }
+    // The first result of a call, nil if there are none.
+    fn first(&mut self, f: &Value, args: Vec<Value>) -> Result<Value, LuaError> {
+        Ok(self.call(f, args)?.into_iter().next().unwrap_or_default())
+    }
+    // The handler of an arithmetic or concatenation event: the left operand's, else the right's.
+    fn binary_handler(&self, a: &Value, b: &Value, event: &str) -> Option<Value> {
+        [a, b]
+            .into_iter()
+            .map(|operand| metamethod(operand, event))
+            .find(|handler| !matches!(handler, Value::Nil))
+    }
+    // The handler of a comparison event, which both operands must share.
+    fn comparison_handler(&self, a: &Value, b: &Value, event: &str) -> Option<Value> {
+        let handler = metamethod(a, event);
+        match handler {
+            Value::Nil => None,
+            _ if handler.raw_equals(&metamethod(b, event)) => Some(handler),
+            _ => None,
+        }
+    }
+    fn equals(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        if a.raw_equals(b) {
+            return Ok(true);
+        }
+        if !matches!((a, b), (Value::Table(_), Value::Table(_))) {
+            return Ok(false);
+        }
+        match self.comparison_handler(a, b, "__eq") {
+            Some(handler) => Ok(self.first(&handler, vec![a.clone(), b.clone()])?.truthy()),
+            None => Ok(false),
+        }
+    }
+    fn compare_error(&self, a: &Value, b: &Value) -> LuaError {
+        if a.type_name() == b.type_name() {
+            self.error(format!("attempt to compare two {} values", a.type_name()))
+        } else {
+            self.error(format!(
+                "attempt to compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ))
+        }
+    }
+    // a < b, as the operator and table.sort see it.
+    pub fn less_than(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Num(x), Value::Num(y)) => Ok(x < y),
+            (Value::Str(x), Value::Str(y)) => Ok(x < y),
+            _ if a.type_name() != b.type_name() => Err(self.compare_error(a, b)),
+            _ => match self.comparison_handler(a, b, "__lt") {
+                Some(handler) => Ok(self.first(&handler, vec![a.clone(), b.clone()])?.truthy()),
+                None => Err(self.compare_error(a, b)),
+            },
+        }
+    }
+    // a <= b, falling back on not (b < a) when there is no __le.
+    fn less_equal(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Num(x), Value::Num(y)) => Ok(x <= y),
+            (Value::Str(x), Value::Str(y)) => Ok(x <= y),
+            _ if a.type_name() != b.type_name() => Err(self.compare_error(a, b)),
+            _ => {
+                if let Some(handler) = self.comparison_handler(a, b, "__le") {
+                    return Ok(self.first(&handler, vec![a.clone(), b.clone()])?.truthy());
+                }
+                match self.comparison_handler(b, a, "__lt") {
+                    Some(handler) => {
+                        Ok(!self.first(&handler, vec![b.clone(), a.clone()])?.truthy())
+                    }
+                    None => Err(self.compare_error(a, b)),
+                }
+            }
+        }
+    }
+}
+
+// The handler a value's metatable has for `event`, nil if none. Only tables have metatables of
+// their own; strings are indexed through the string library directly.
+pub fn metamethod(value: &Value, event: &str) -> Value {
+    match value {
+        Value::Table(table) => match &table.borrow().meta {
+            Some(meta) => meta.borrow().get_str(event),
+            None => Value::Nil,
+        },
+        _ => Value::Nil,
+    }
+}
+
+fn callable(value: &Value) -> bool {
+    matches!(value, Value::Function(_)) || matches!(metamethod(value, "__call"), Value::Function(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{on_script_stack, to_display};
+
+    // Runs a chunk the way scripts run, with its results or error as displayed by tostring.
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        on_script_stack(|| {
+            let mut interp = Interp::new(None);
+            let f = interp.load(source.as_bytes())?;
+            let show = |value: &Value| String::from_utf8_lossy(&to_display(value)).into_owned();
+            match interp.call(&f, vec![]) {
+                Ok(values) => Ok(values.iter().map(show).collect()),
+                Err(LuaError(error)) => Err(show(&error)),
+            }
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn unbounded_recursion_is_an_error() {
+        let error = run("local function f(n) return f(n + 1) + 1 end return f(1)").unwrap_err();
+        assert!(error.ends_with("stack overflow"), "{error}");
+        let caught = run("return pcall(function() local function f() return f() end f() end)");
+        assert_eq!(caught.unwrap()[0], "false");
+    }
+
+    #[test]
+    fn long_chains_are_an_error() {
+        let sum = format!("return 1{}", "+1".repeat(1_000_000));
+        assert!(run(&sum).unwrap_err().ends_with("stack overflow"));
+        let index = format!("local t = {{}} t.a = t return t{}", ".a".repeat(1_000_000));
+        assert!(run(&index).unwrap_err().ends_with("stack overflow"));
+    }
+
+    #[test]
+    fn deep_structures_are_freed() {
+        assert_eq!(
+            run("local t = {} for i = 1, 300000 do t = {t} end return 1"),
+            Ok(vec!["1".into()])
+        );
+        let returned = run("local t = {} for i = 1, 300000 do t = {k = t} end return t");
+        assert!(returned.unwrap()[0].starts_with("table: "));
+        let closures = "local f = function() end \
+            for i = 1, 300000 do local g = f f = function() return g end end return 1";
+        assert_eq!(run(closures), Ok(vec!["1".into()]));
+    }
+
+    #[test]
+    fn shallow_recursion_works() {
+        let fib = "local function fib(n) if n < 2 then return n end \
+            return fib(n - 1) + fib(n - 2) end return fib(20)";
+        assert_eq!(run(fib), Ok(vec!["6765".into()]));
+        let depth = "local function f(n) if n == 0 then return 0 end return f(n - 1) + 1 end \
+            return f(1000)";
+        assert_eq!(run(depth), Ok(vec!["1000".into()]));
+    }
+
+    #[test]
+    fn metatables_drive_indexing_calls_and_operators() {
+        let script = "local V = {} V.__index = V \
+            V.__add = function(a, b) return setmetatable({x = a.x + b.x}, V) end \
+            V.__eq = function(a, b) return a.x == b.x end \
+            V.__lt = function(a, b) return a.x < b.x end \
+            V.__call = function(self, n) return self.x * n end \
+            V.__tostring = function(self) return 'V(' .. self.x .. ')' end \
+            function V.double(self) return self.x * 2 end \
+            local a, b = setmetatable({x = 1}, V), setmetatable({x = 2}, V) \
+            local log = {} \
+            local proxy = setmetatable({}, {__newindex = function(t, k, v) log[#log + 1] = k end}) \
+            proxy.y = 1 \
+            return (a + b).x, a:double(), a(10), a == setmetatable({x = 1}, V), a < b, a <= b, \
+                tostring(b), getmetatable(a) == V, rawget(proxy, 'y'), log[1]";
+        assert_eq!(
+            run(script),
+            Ok(
+                ["3", "2", "10", "true", "true", "true", "V(2)", "true", "nil", "y"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+        let protected = "local t = setmetatable({}, {__metatable = 'locked'}) \
+            return getmetatable(t), pcall(setmetatable, t, {})";
+        let results = run(protected).unwrap();
+        assert_eq!(results[..2], ["locked", "false"]);
+        assert!(results[2].ends_with("cannot change a protected metatable"));
+        assert_eq!(
+            run("return getmetatable('').__index == string"),
+            Ok(vec!["true".into()])
+        );
+        let error = run("setmetatable(string, {})").unwrap_err();
+        assert!(
+            error.ends_with("Attempt to modify a readonly table"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn bit_library_wraps_to_32_bits() {
+        let script = "return bit.band(0xff, 0x0f), bit.bor(1, 2, 4), bit.bxor(5, 3), bit.bnot(0), \
+            bit.lshift(1, 31), bit.rshift(-1, 28), bit.arshift(-256, 4), bit.tobit(2^32 + 5), \
+            bit.tohex(255), bit.tohex(-1, -4), bit.rol(0x80000000, 1), bit.bswap(0x12345678)";
+        assert_eq!(
+            run(script),
+            Ok([
+                "15",
+                "7",
+                "6",
+                "-1",
+                "-2147483648",
+                "15",
+                "-16",
+                "5",
+                "000000ff",
+                "FFFF",
+                "1",
+                "2018915346"
+            ]
+            .map(String::from)
+            .to_vec())
+        );
+    }
+
+    #[test]
+    fn the_rest_of_the_base_library() {
+        let script = "local seq = {} for i = 1, 3 do seq[i] = math.random(100) end \
+            math.randomseed(0) local again = math.random(100) \
+            local f = loadstring('return 1 + 1') local _, message = loadstring('return +') \
+            local ok, handled = xpcall(function() error({code = 7}) end, \
+                function(e) return e.code end) \
+            return again == seq[1], f(), message ~= nil, ok, handled, math.random() < 1";
+        assert_eq!(
+            run(script),
+            Ok(["true", "2", "true", "false", "7", "true"]
+                .map(String::from)
+                .to_vec())
+        );
+        let error = run("return math.random(2, 1)").unwrap_err();
+        assert!(error.ends_with("bad argument #2 to 'random' (interval is empty)"));
+        let error = run("return cmsgpack.pack(1)").unwrap_err();
+        assert!(
+            error.ends_with("the 'cmsgpack' library is not supported"),
+            "{error}"
+        );
+        let error = run("return struct.pack('i', 1)").unwrap_err();
+        assert!(
+            error.ends_with("the 'struct' library is not supported"),
+            "{error}"
+        );
+    }
+}
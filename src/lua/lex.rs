@@ -0,0 +1,250 @@
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(Rc<str>),
+    Str(Rc<[u8]>),
+    Num(f64),
+    // Keywords and punctuation.
+    Sym(&'static str),
+    Eof,
+}
+
+impl Token {
+    // How the token is quoted in error messages, as in "unexpected symbol near 'x'".
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Name(name) => format!("'{name}'"),
+            Token::Str(s) => format!("'{}'", String::from_utf8_lossy(s)),
+            Token::Num(n) => format!("'{}'", super::format_number(*n)),
+            Token::Sym(sym) => format!("'{sym}'"),
+            Token::Eof => "<eof>".into(),
+        }
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// Longest first, so "..." is not read as ".." and ".".
+const SYMBOLS: &[&str] = &[
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+// Tokens with the line each starts on.
+pub fn tokenize(source: &[u8]) -> Result<Vec<(Token, u32)>, (String, u32)> {
+    let mut lexer = Lexer {
+        src: source,
+        pos: 0,
+        line: 1,
+    };
+    // A leading #! line is skipped, as by the standalone interpreter.
+    if source.starts_with(b"#") {
+        while lexer.peek(0).is_some_and(|c| c != b'\n') {
+            lexer.pos += 1;
+        }
+    }
+    let mut tokens = vec![];
+    loop {
+        lexer.skip_space()?;
+        let line = lexer.line;
+        let token = lexer.next_token()?;
+        let eof = token == Token::Eof;
+        tokens.push((token, line));
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: u32,
+}
+
+impl Lexer<'_> {
+    fn peek(&self, ahead: usize) -> Option<u8> {
+        self.src.get(self.pos + ahead).copied()
+    }
+    fn error(&self, message: &str) -> (String, u32) {
+        (message.to_string(), self.line)
+    }
+    fn skip_space(&mut self) -> Result<(), (String, u32)> {
+        while let Some(c) = self.peek(0) {
+            match c {
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                b' ' | b'\t' | b'\r' => self.pos += 1,
+                b'-' if self.peek(1) == Some(b'-') => {
+                    self.pos += 2;
+                    if let Some(level) = self.long_bracket_level() {
+                        self.long_string(level)?;
+                    } else {
+                        while self.peek(0).is_some_and(|c| c != b'\n') {
+                            self.pos += 1;
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+    // The level of a `[==[` opening at the current position, if there is one.
+    fn long_bracket_level(&self) -> Option<usize> {
+        if self.peek(0) != Some(b'[') {
+            return None;
+        }
+        let level = self.src[self.pos + 1..]
+            .iter()
+            .take_while(|c| **c == b'=')
+            .count();
+        (self.peek(1 + level) == Some(b'[')).then_some(level)
+    }
+    fn long_string(&mut self, level: usize) -> Result<Vec<u8>, (String, u32)> {
+        self.pos += level + 2;
+        // A newline right after the opening bracket is not part of the string.
+        if self.peek(0) == Some(b'\r') {
+            self.pos += 1;
+        }
+        if self.peek(0) == Some(b'\n') {
+            self.line += 1;
+            self.pos += 1;
+        }
+        let mut out = vec![];
+        loop {
+            match self.peek(0) {
+                None => return Err(self.error("unfinished long string")),
+                Some(b']')
+                    if self.src[self.pos + 1..]
+                        .iter()
+                        .take_while(|c| **c == b'=')
+                        .count()
+                        == level
+                        && self.peek(1 + level) == Some(b']') =>
+                {
+                    self.pos += level + 2;
+                    return Ok(out);
+                }
+                Some(c) => {
+                    if c == b'\n' {
+                        self.line += 1;
+                    }
+                    out.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+    fn quoted_string(&mut self, quote: u8) -> Result<Vec<u8>, (String, u32)> {
+        self.pos += 1;
+        let mut out = vec![];
+        loop {
+            let Some(c) = self.peek(0) else {
+                return Err(self.error("unfinished string"));
+            };
+            self.pos += 1;
+            match c {
+                b'\n' => return Err(self.error("unfinished string")),
+                _ if c == quote => return Ok(out),
+                b'\\' => {
+                    let Some(e) = self.peek(0) else {
+                        return Err(self.error("unfinished string"));
+                    };
+                    self.pos += 1;
+                    match e {
+                        b'n' => out.push(b'\n'),
+                        b't' => out.push(b'\t'),
+                        b'r' => out.push(b'\r'),
+                        b'a' => out.push(7),
+                        b'b' => out.push(8),
+                        b'f' => out.push(12),
+                        b'v' => out.push(11),
+                        b'\n' => {
+                            self.line += 1;
+                            out.push(b'\n');
+                        }
+                        b'0'..=b'9' => {
+                            let mut value = (e - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek(0) {
+                                    Some(d @ b'0'..=b'9') => {
+                                        value = value * 10 + (d - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            if value > 255 {
+                                return Err(self.error("escape sequence too large"));
+                            }
+                            out.push(value as u8);
+                        }
+                        _ => out.push(e),
+                    }
+                }
+                _ => out.push(c),
+            }
+        }
+    }
+    fn number(&mut self) -> Result<f64, (String, u32)> {
+        let start = self.pos;
+        while let Some(c) = self.peek(0) {
+            let exponent_sign =
+                (c == b'+' || c == b'-') && matches!(self.src[self.pos - 1], b'e' | b'E');
+            if c.is_ascii_alphanumeric() || c == b'.' || exponent_sign {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let text = &self.src[start..self.pos];
+        super::parse_number(text).ok_or_else(|| {
+            self.error(&format!(
+                "malformed number near '{}'",
+                String::from_utf8_lossy(text)
+            ))
+        })
+    }
+    fn next_token(&mut self) -> Result<Token, (String, u32)> {
+        let Some(c) = self.peek(0) else {
+            return Ok(Token::Eof);
+        };
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let start = self.pos;
+            while self
+                .peek(0)
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+            {
+                self.pos += 1;
+            }
+            let word = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+            return Ok(match KEYWORDS.iter().find(|keyword| **keyword == word) {
+                Some(keyword) => Token::Sym(keyword),
+                None => Token::Name(word.into()),
+            });
+        }
+        if c.is_ascii_digit() || (c == b'.' && self.peek(1).is_some_and(|d| d.is_ascii_digit())) {
+            return self.number().map(Token::Num);
+        }
+        if c == b'"' || c == b'\'' {
+            return self.quoted_string(c).map(|s| Token::Str(s.into()));
+        }
+        if let Some(level) = self.long_bracket_level() {
+            return self.long_string(level).map(|s| Token::Str(s.into()));
+        }
+        for sym in SYMBOLS {
+            if self.src[self.pos..].starts_with(sym.as_bytes()) {
+                self.pos += sym.len();
+                return Ok(Token::Sym(sym));
+            }
+        }
+        Err(self.error(&format!("unexpected symbol near '{}'", c as char)))
+    }
+}
@@ -0,0 +1,364 @@
+// A small Lua 5.1 interpreter for EVAL: a tree-walking evaluator over the whole language except
+// coroutines, with the parts of the standard library scripts reach for plus the cjson and bit
+// libraries Redis bundles. cmsgpack and struct are not provided; a script naming them gets an
+// error saying so rather than a missing global.
+mod cjson;
+mod interp;
+mod lex;
+mod parse;
+mod pattern;
+mod stdlib;
+
+use std::{cell::RefCell, collections::HashMap, io, rc::Rc, thread};
+
+pub use interp::{Interp, Watchdog};
+use parse::FuncBody;
+pub use stdlib::to_display;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Bool(bool),
+    Num(f64),
+    Str(Rc<[u8]>),
+    Table(Rc<RefCell<Table>>),
+    Function(Rc<Function>),
+}
+
+pub type NativeFn = fn(&mut Interp, Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+pub enum Function {
+    Lua(Rc<FuncBody>, interp::Env),
+    Native(NativeFn),
+    // A native function that gets `state` ahead of its arguments, for iterators like gmatch.
+    Bound(NativeFn, Value),
+}
+
+// What a script raised: usually a string, but `error` takes any value.
+pub struct LuaError(pub Value);
+
+// A function a library registered while being loaded.
+pub struct Registered {
+    pub name: String,
+    pub callback: Value,
+    pub flags: Vec<String>,
+    pub description: Option<String>,
+}
+
+// The Redis side of an interpreter, reached by redis.call and redis.pcall.
+pub trait Host {
+    // Runs a command, an error reply coming back as an {err=...} table.
+    fn call(&mut self, args: Vec<Vec<u8>>) -> Value;
+}
+
+// Stack of the thread scripts run on; deep recursion needs far more than a connection thread has.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// Runs `f` on a thread of its own with a stack of STACK_SIZE, which every Interp must run on.
+pub fn on_script_stack<T: Send>(f: impl FnOnce() -> T + Send) -> io::Result<T> {
+    thread::scope(|scope| {
+        let script = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)?;
+        Ok(script
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+    })
+}
+
+impl Value {
+    pub fn str(s: impl AsRef<[u8]>) -> Value {
+        Value::Str(s.as_ref().into())
+    }
+    pub fn table(table: Table) -> Value {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Num(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+    // Numbers and numeric strings, as arithmetic accepts them.
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Str(s) => parse_number(s.trim_ascii()),
+            _ => None,
+        }
+    }
+    // Strings and numbers, as concatenation accepts them.
+    pub fn to_bytes(&self) -> Option<Rc<[u8]>> {
+        match self {
+            Value::Str(s) => Some(s.clone()),
+            Value::Num(n) => Some(format_number(*n).as_bytes().into()),
+            _ => None,
+        }
+    }
+    pub fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl LuaError {
+    pub fn new(message: impl AsRef<[u8]>) -> LuaError {
+        LuaError(Value::str(message))
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Bool(bool),
+    Num(u64),
+    Str(Rc<[u8]>),
+    Ref(usize),
+}
+
+impl Key {
+    fn of(value: &Value) -> Option<Key> {
+        Some(match value {
+            Value::Nil => return None,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Num(n) if n.is_nan() => return None,
+            // 0.0 and -0.0 are the same key.
+            Value::Num(n) => Key::Num((n + 0.0).to_bits()),
+            Value::Str(s) => Key::Str(s.clone()),
+            Value::Table(t) => Key::Ref(Rc::as_ptr(t) as *const u8 as usize),
+            Value::Function(f) => Key::Ref(Rc::as_ptr(f) as *const u8 as usize),
+        })
+    }
+}
+
+// Keys 1..n live in `array`; everything else in `entries`, in insertion order so `next` can walk
+// them. Removed entries stay behind as nil values until the table is dropped.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+    pub readonly: bool,
+    pub meta: Option<Rc<RefCell<Table>>>,
+}
+
+impl Table {
+    pub fn from_array(values: Vec<Value>) -> Table {
+        let mut table = Table::default();
+        table.array = values;
+        table.trim();
+        table
+    }
+    fn array_index(&self, key: &Value) -> Option<usize> {
+        match key {
+            Value::Num(n) if n.fract() == 0.0 && *n >= 1.0 && *n <= self.array.len() as f64 => {
+                Some(*n as usize - 1)
+            }
+            _ => None,
+        }
+    }
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(i) = self.array_index(key) {
+            return self.array[i].clone();
+        }
+        Key::of(key)
+            .and_then(|k| self.index.get(&k))
+            .map_or(Value::Nil, |i| self.entries[*i].1.clone())
+    }
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::str(key))
+    }
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), LuaError> {
+        if let Some(i) = self.array_index(&key) {
+            self.array[i] = value;
+            self.trim();
+            return Ok(());
+        }
+        let Some(k) = Key::of(&key) else {
+            let what = if matches!(key, Value::Nil) {
+                "nil"
+            } else {
+                "NaN"
+            };
+            return Err(LuaError::new(format!("table index is {what}")));
+        };
+        if let Some(i) = self.index.get(&k) {
+            self.entries[*i].1 = value;
+            return Ok(());
+        }
+        if !matches!(value, Value::Nil) {
+            if matches!(key, Value::Num(n) if n == (self.array.len() + 1) as f64) {
+                self.array.push(value);
+                self.migrate();
+            } else {
+                self.index.insert(k, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        // Only nil and NaN keys are refused.
+        let _ = self.set(Value::str(key), value);
+    }
+    pub fn push(&mut self, value: Value) {
+        let len = self.len();
+        let _ = self.set(Value::Num((len + 1) as f64), value);
+    }
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+    // Drops trailing nils so the length stays a border.
+    fn trim(&mut self) {
+        while matches!(self.array.last(), Some(Value::Nil)) {
+            self.array.pop();
+        }
+    }
+    // Moves keys that now follow the array part over from the entries.
+    fn migrate(&mut self) {
+        loop {
+            let next = Key::Num(((self.array.len() + 1) as f64).to_bits());
+            let Some(i) = self.index.remove(&next) else {
+                return;
+            };
+            let value = std::mem::take(&mut self.entries[i].1);
+            self.entries[i].0 = Value::Nil;
+            if matches!(value, Value::Nil) {
+                return;
+            }
+            self.array.push(value);
+        }
+    }
+    pub fn insert(&mut self, pos: usize, value: Value) {
+        self.array.insert(pos - 1, value);
+        self.migrate();
+    }
+    pub fn remove(&mut self, pos: usize) -> Value {
+        self.array.remove(pos - 1)
+    }
+    // Empties the table, handing back its keys and values.
+    fn take_all(&mut self) -> Vec<Value> {
+        let mut values = std::mem::take(&mut self.array);
+        values.extend(self.entries.drain(..).flat_map(|(key, value)| [key, value]));
+        values.extend(self.meta.take().map(Value::Table));
+        self.index.clear();
+        values
+    }
+    pub fn array(&self) -> &[Value] {
+        &self.array
+    }
+    // The key and value after `key` in traversal order, None at the end, Err for a foreign key.
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, LuaError> {
+        let mut from = match key {
+            Value::Nil => 0,
+            _ => match self.array_index(key) {
+                Some(i) => i + 1,
+                None => {
+                    let i = Key::of(key)
+                        .and_then(|k| self.index.get(&k).copied())
+                        .ok_or_else(|| LuaError::new("invalid key to 'next'"))?;
+                    self.array.len() + i + 1
+                }
+            },
+        };
+        while from < self.array.len() {
+            if !matches!(self.array[from], Value::Nil) {
+                return Ok(Some((
+                    Value::Num((from + 1) as f64),
+                    self.array[from].clone(),
+                )));
+            }
+            from += 1;
+        }
+        Ok(self.entries[from - self.array.len()..]
+            .iter()
+            .find(|(k, v)| !matches!(k, Value::Nil) && !matches!(v, Value::Nil))
+            .cloned())
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        interp::release(self.take_all(), vec![]);
+    }
+}
+
+// Numbers print as %.14g, like the reference implementation.
+pub fn format_number(n: f64) -> String {
+    format_general(n, 14, false)
+}
+
+// C's %g with precision `precision`; `alternate` keeps trailing zeros like %#g.
+pub fn format_general(n: f64, precision: usize, alternate: bool) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.into();
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.into();
+    }
+    let precision = precision.max(1);
+    let scientific = format!("{:.*e}", precision - 1, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let trim = |s: String| {
+        if alternate || !s.contains('.') {
+            s
+        } else {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        }
+    };
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim(mantissa.to_string()), exponent.abs())
+    } else {
+        trim(format!(
+            "{:.*}",
+            (precision as i32 - 1 - exponent) as usize,
+            n
+        ))
+    }
+}
+
+// Decimal and hexadecimal numerals, as the lexer and tonumber read them.
+pub fn parse_number(text: &[u8]) -> Option<f64> {
+    let text = std::str::from_utf8(text).ok()?;
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        if hex.is_empty() || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        hex.bytes().fold(0.0, |n, c| {
+            n * 16.0 + (c as char).to_digit(16).unwrap() as f64
+        })
+    } else {
+        // Rust also takes "inf" and "nan", which are not Lua numerals.
+        if !digits
+            .bytes()
+            .all(|c| c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'))
+        {
+            return None;
+        }
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
@@ -0,0 +1,612 @@
+use std::rc::Rc;
+
+use super::lex::{tokenize, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Num(f64),
+    Str(Rc<[u8]>),
+    Vararg,
+    Function(Rc<FuncBody>),
+    Name(Rc<str>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>, u32),
+    Method(Box<Expr>, Rc<[u8]>, Vec<Expr>, u32),
+    Table(Vec<Field>, u32),
+    Bin(BinOp, Box<Expr>, Box<Expr>, u32),
+    Un(UnOp, Box<Expr>, u32),
+    // Parentheses cut a call or `...` down to its first value.
+    Paren(Box<Expr>),
+}
+
+impl Expr {
+    // Takes out the operand a chain like `a+b+c` or `a.b.c` is built along, which unlike the
+    // others isn't bounded by MAX_DEPTH.
+    fn take_chain(&mut self) -> Option<Expr> {
+        match self {
+            Expr::Bin(_, operand, ..)
+            | Expr::Index(operand, _)
+            | Expr::Call(operand, ..)
+            | Expr::Method(operand, ..) => Some(std::mem::replace(&mut **operand, Expr::Nil)),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut next = self.take_chain();
+        while let Some(mut expr) = next {
+            next = expr.take_chain();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Field {
+    Positional(Expr),
+    Keyed(Expr, Expr),
+}
+
+#[derive(Debug)]
+pub struct FuncBody {
+    pub params: Vec<Rc<str>>,
+    pub vararg: bool,
+    pub body: Block,
+}
+
+pub type Block = Vec<Stat>;
+
+#[derive(Debug)]
+pub struct Stat {
+    pub kind: StatKind,
+    pub line: u32,
+}
+
+#[derive(Debug)]
+pub enum StatKind {
+    Local(Vec<Rc<str>>, Vec<Expr>),
+    LocalFunction(Rc<str>, Rc<FuncBody>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Do(Block),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    NumericFor(Rc<str>, Expr, Expr, Option<Expr>, Block),
+    GenericFor(Vec<Rc<str>>, Vec<Expr>, Block),
+    Return(Vec<Expr>),
+    Break,
+}
+
+// Deep enough for any sane script, shallow enough not to run out of stack evaluating it.
+const MAX_DEPTH: usize = 200;
+
+// Parses a chunk, reporting errors as a message and the line it was found on.
+pub fn parse(source: &[u8]) -> Result<Rc<FuncBody>, (String, u32)> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        depth: 0,
+    };
+    let body = parser.block()?;
+    if parser.peek() != &Token::Eof {
+        return Err(parser.error(&format!(
+            "'<eof>' expected near {}",
+            parser.peek().describe()
+        )));
+    }
+    Ok(Rc::new(FuncBody {
+        params: vec![],
+        vararg: true,
+        body,
+    }))
+}
+
+struct Parser {
+    tokens: Vec<(Token, u32)>,
+    pos: usize,
+    depth: usize,
+}
+
+fn binary_op(token: &Token) -> Option<(BinOp, u8, u8)> {
+    // Left and right binding power, as in the reference implementation.
+    let Token::Sym(sym) = token else {
+        return None;
+    };
+    Some(match *sym {
+        "or" => (BinOp::Or, 1, 1),
+        "and" => (BinOp::And, 2, 2),
+        "<" => (BinOp::Lt, 3, 3),
+        ">" => (BinOp::Gt, 3, 3),
+        "<=" => (BinOp::Le, 3, 3),
+        ">=" => (BinOp::Ge, 3, 3),
+        "~=" => (BinOp::Ne, 3, 3),
+        "==" => (BinOp::Eq, 3, 3),
+        ".." => (BinOp::Concat, 5, 4),
+        "+" => (BinOp::Add, 6, 6),
+        "-" => (BinOp::Sub, 6, 6),
+        "*" => (BinOp::Mul, 7, 7),
+        "/" => (BinOp::Div, 7, 7),
+        "%" => (BinOp::Mod, 7, 7),
+        "^" => (BinOp::Pow, 10, 9),
+        _ => return None,
+    })
+}
+
+const UNARY_PRIORITY: u8 = 8;
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+    fn line(&self) -> u32 {
+        self.tokens[self.pos].1
+    }
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+    fn error(&self, message: &str) -> (String, u32) {
+        (message.to_string(), self.line())
+    }
+    fn check(&self, sym: &str) -> bool {
+        matches!(self.peek(), Token::Sym(s) if *s == sym)
+    }
+    fn accept(&mut self, sym: &str) -> bool {
+        let found = self.check(sym);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+    fn expect(&mut self, sym: &str) -> Result<(), (String, u32)> {
+        if self.accept(sym) {
+            return Ok(());
+        }
+        Err(self.error(&format!("'{sym}' expected near {}", self.peek().describe())))
+    }
+    // Expects the keyword closing a construct opened by `opener` on `line`.
+    fn expect_closing(&mut self, sym: &str, opener: &str, line: u32) -> Result<(), (String, u32)> {
+        if line == self.line() {
+            return self.expect(sym);
+        }
+        if self.accept(sym) {
+            return Ok(());
+        }
+        Err(self.error(&format!(
+            "'{sym}' expected (to close '{opener}' at line {line}) near {}",
+            self.peek().describe()
+        )))
+    }
+    fn name(&mut self) -> Result<Rc<str>, (String, u32)> {
+        match self.advance() {
+            Token::Name(name) => Ok(name),
+            token => {
+                self.pos -= 1;
+                Err(self.error(&format!("<name> expected near {}", token.describe())))
+            }
+        }
+    }
+    fn enter(&mut self) -> Result<(), (String, u32)> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("chunk has too many syntax levels"));
+        }
+        Ok(())
+    }
+
+    fn block_ends(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Eof | Token::Sym("end" | "else" | "elseif" | "until")
+        )
+    }
+    fn block(&mut self) -> Result<Block, (String, u32)> {
+        self.enter()?;
+        let mut block = vec![];
+        while !self.block_ends() {
+            let line = self.line();
+            if self.accept("return") {
+                let values = if self.block_ends() || self.check(";") {
+                    vec![]
+                } else {
+                    self.expr_list()?
+                };
+                self.accept(";");
+                block.push(Stat {
+                    kind: StatKind::Return(values),
+                    line,
+                });
+                if !self.block_ends() {
+                    return Err(
+                        self.error(&format!("'end' expected near {}", self.peek().describe()))
+                    );
+                }
+                break;
+            }
+            if self.accept("break") {
+                self.accept(";");
+                block.push(Stat {
+                    kind: StatKind::Break,
+                    line,
+                });
+                if !self.block_ends() {
+                    return Err(
+                        self.error(&format!("'end' expected near {}", self.peek().describe()))
+                    );
+                }
+                break;
+            }
+            let kind = self.statement()?;
+            block.push(Stat { kind, line });
+            self.accept(";");
+        }
+        self.depth -= 1;
+        Ok(block)
+    }
+    fn statement(&mut self) -> Result<StatKind, (String, u32)> {
+        let line = self.line();
+        match self.peek() {
+            Token::Sym("do") => {
+                self.pos += 1;
+                let body = self.block()?;
+                self.expect_closing("end", "do", line)?;
+                Ok(StatKind::Do(body))
+            }
+            Token::Sym("while") => {
+                self.pos += 1;
+                let condition = self.expr()?;
+                self.expect("do")?;
+                let body = self.block()?;
+                self.expect_closing("end", "while", line)?;
+                Ok(StatKind::While(condition, body))
+            }
+            Token::Sym("repeat") => {
+                self.pos += 1;
+                let body = self.block()?;
+                self.expect_closing("until", "repeat", line)?;
+                Ok(StatKind::Repeat(body, self.expr()?))
+            }
+            Token::Sym("if") => {
+                self.pos += 1;
+                let mut branches = vec![];
+                let condition = self.expr()?;
+                self.expect("then")?;
+                branches.push((condition, self.block()?));
+                let mut otherwise = None;
+                loop {
+                    if self.accept("elseif") {
+                        let condition = self.expr()?;
+                        self.expect("then")?;
+                        branches.push((condition, self.block()?));
+                    } else if self.accept("else") {
+                        otherwise = Some(self.block()?);
+                        self.expect_closing("end", "if", line)?;
+                        break;
+                    } else {
+                        self.expect_closing("end", "if", line)?;
+                        break;
+                    }
+                }
+                Ok(StatKind::If(branches, otherwise))
+            }
+            Token::Sym("for") => {
+                self.pos += 1;
+                let first = self.name()?;
+                if self.accept("=") {
+                    let start = self.expr()?;
+                    self.expect(",")?;
+                    let limit = self.expr()?;
+                    let step = if self.accept(",") {
+                        Some(self.expr()?)
+                    } else {
+                        None
+                    };
+                    self.expect("do")?;
+                    let body = self.block()?;
+                    self.expect_closing("end", "for", line)?;
+                    return Ok(StatKind::NumericFor(first, start, limit, step, body));
+                }
+                let mut names = vec![first];
+                while self.accept(",") {
+                    names.push(self.name()?);
+                }
+                if !self.accept("in") {
+                    return Err(self.error(&format!(
+                        "'=' or 'in' expected near {}",
+                        self.peek().describe()
+                    )));
+                }
+                let iterators = self.expr_list()?;
+                self.expect("do")?;
+                let body = self.block()?;
+                self.expect_closing("end", "for", line)?;
+                Ok(StatKind::GenericFor(names, iterators, body))
+            }
+            Token::Sym("function") => {
+                self.pos += 1;
+                let mut target = Expr::Name(self.name()?);
+                let mut method = false;
+                loop {
+                    if self.accept(".") {
+                        let key = self.name()?;
+                        target = Expr::Index(Box::new(target), Box::new(name_key(&key)));
+                    } else if self.accept(":") {
+                        let key = self.name()?;
+                        target = Expr::Index(Box::new(target), Box::new(name_key(&key)));
+                        method = true;
+                        break;
+                    } else {
+                        break;
+                    }
+                }
+                let body = self.function_body(method, line)?;
+                Ok(StatKind::Assign(vec![target], vec![Expr::Function(body)]))
+            }
+            Token::Sym("local") => {
+                self.pos += 1;
+                if self.accept("function") {
+                    let name = self.name()?;
+                    return Ok(StatKind::LocalFunction(
+                        name,
+                        self.function_body(false, line)?,
+                    ));
+                }
+                let mut names = vec![self.name()?];
+                while self.accept(",") {
+                    names.push(self.name()?);
+                }
+                let values = if self.accept("=") {
+                    self.expr_list()?
+                } else {
+                    vec![]
+                };
+                Ok(StatKind::Local(names, values))
+            }
+            _ => {
+                let first = self.suffixed_expr()?;
+                if self.check("=") || self.check(",") {
+                    let mut targets = vec![first];
+                    while self.accept(",") {
+                        targets.push(self.suffixed_expr()?);
+                    }
+                    for target in &targets {
+                        if !matches!(target, Expr::Name(_) | Expr::Index(..)) {
+                            return Err(self.error("syntax error near '='"));
+                        }
+                    }
+                    self.expect("=")?;
+                    return Ok(StatKind::Assign(targets, self.expr_list()?));
+                }
+                if !matches!(first, Expr::Call(..) | Expr::Method(..)) {
+                    return Err(
+                        self.error(&format!("syntax error near {}", self.peek().describe()))
+                    );
+                }
+                Ok(StatKind::Call(first))
+            }
+        }
+    }
+    fn function_body(&mut self, method: bool, line: u32) -> Result<Rc<FuncBody>, (String, u32)> {
+        let mut params = vec![];
+        if method {
+            params.push("self".into());
+        }
+        let mut vararg = false;
+        self.expect("(")?;
+        if !self.check(")") {
+            loop {
+                if self.accept("...") {
+                    vararg = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+        let body = self.block()?;
+        self.expect_closing("end", "function", line)?;
+        Ok(Rc::new(FuncBody {
+            params,
+            vararg,
+            body,
+        }))
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, (String, u32)> {
+        let mut exprs = vec![self.expr()?];
+        while self.accept(",") {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+    fn expr(&mut self) -> Result<Expr, (String, u32)> {
+        self.binary_expr(0)
+    }
+    fn binary_expr(&mut self, limit: u8) -> Result<Expr, (String, u32)> {
+        self.enter()?;
+        let line = self.line();
+        let mut left = match self.peek() {
+            Token::Sym("not") => {
+                self.pos += 1;
+                Expr::Un(UnOp::Not, Box::new(self.binary_expr(UNARY_PRIORITY)?), line)
+            }
+            Token::Sym("-") => {
+                self.pos += 1;
+                match self.binary_expr(UNARY_PRIORITY)? {
+                    Expr::Num(n) => Expr::Num(-n),
+                    operand => Expr::Un(UnOp::Neg, Box::new(operand), line),
+                }
+            }
+            Token::Sym("#") => {
+                self.pos += 1;
+                Expr::Un(UnOp::Len, Box::new(self.binary_expr(UNARY_PRIORITY)?), line)
+            }
+            _ => self.simple_expr()?,
+        };
+        while let Some((op, left_priority, right_priority)) = binary_op(self.peek()) {
+            if left_priority <= limit {
+                break;
+            }
+            let line = self.line();
+            self.pos += 1;
+            let right = self.binary_expr(right_priority)?;
+            left = Expr::Bin(op, Box::new(left), Box::new(right), line);
+        }
+        self.depth -= 1;
+        Ok(left)
+    }
+    fn simple_expr(&mut self) -> Result<Expr, (String, u32)> {
+        let line = self.line();
+        let expr = match self.peek() {
+            Token::Num(n) => Expr::Num(*n),
+            Token::Str(s) => Expr::Str(s.clone()),
+            Token::Sym("nil") => Expr::Nil,
+            Token::Sym("true") => Expr::True,
+            Token::Sym("false") => Expr::False,
+            Token::Sym("...") => Expr::Vararg,
+            Token::Sym("function") => {
+                self.pos += 1;
+                return Ok(Expr::Function(self.function_body(false, line)?));
+            }
+            Token::Sym("{") => return self.table(),
+            _ => return self.suffixed_expr(),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+    fn primary_expr(&mut self) -> Result<Expr, (String, u32)> {
+        match self.advance() {
+            Token::Name(name) => Ok(Expr::Name(name)),
+            Token::Sym("(") => {
+                let inner = self.expr()?;
+                self.expect(")")?;
+                Ok(Expr::Paren(Box::new(inner)))
+            }
+            token => {
+                self.pos -= 1;
+                Err(self.error(&format!("unexpected symbol near {}", token.describe())))
+            }
+        }
+    }
+    fn suffixed_expr(&mut self) -> Result<Expr, (String, u32)> {
+        let mut expr = self.primary_expr()?;
+        loop {
+            let line = self.line();
+            match self.peek() {
+                Token::Sym(".") => {
+                    self.pos += 1;
+                    let key = self.name()?;
+                    expr = Expr::Index(Box::new(expr), Box::new(name_key(&key)));
+                }
+                Token::Sym("[") => {
+                    self.pos += 1;
+                    let key = self.expr()?;
+                    self.expect("]")?;
+                    expr = Expr::Index(Box::new(expr), Box::new(key));
+                }
+                Token::Sym(":") => {
+                    self.pos += 1;
+                    let name = self.name()?;
+                    let args = self.call_args()?;
+                    expr = Expr::Method(Box::new(expr), name.as_bytes().into(), args, line);
+                }
+                Token::Sym("(" | "{") | Token::Str(_) => {
+                    let args = self.call_args()?;
+                    expr = Expr::Call(Box::new(expr), args, line);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+    fn call_args(&mut self) -> Result<Vec<Expr>, (String, u32)> {
+        match self.peek() {
+            Token::Str(s) => {
+                let arg = Expr::Str(s.clone());
+                self.pos += 1;
+                Ok(vec![arg])
+            }
+            Token::Sym("{") => Ok(vec![self.table()?]),
+            Token::Sym("(") => {
+                self.pos += 1;
+                if self.accept(")") {
+                    return Ok(vec![]);
+                }
+                let args = self.expr_list()?;
+                self.expect(")")?;
+                Ok(args)
+            }
+            token => Err(self.error(&format!(
+                "function arguments expected near {}",
+                token.describe()
+            ))),
+        }
+    }
+    fn table(&mut self) -> Result<Expr, (String, u32)> {
+        let line = self.line();
+        self.expect("{")?;
+        let mut fields = vec![];
+        while !self.check("}") {
+            if self.accept("[") {
+                let key = self.expr()?;
+                self.expect("]")?;
+                self.expect("=")?;
+                fields.push(Field::Keyed(key, self.expr()?));
+            } else if matches!(self.peek(), Token::Name(_))
+                && matches!(self.tokens[self.pos + 1].0, Token::Sym("="))
+            {
+                let key = self.name()?;
+                self.pos += 1;
+                fields.push(Field::Keyed(name_key(&key), self.expr()?));
+            } else {
+                fields.push(Field::Positional(self.expr()?));
+            }
+            if !self.accept(",") && !self.accept(";") {
+                break;
+            }
+        }
+        self.expect_closing("}", "{", line)?;
+        Ok(Expr::Table(fields, line))
+    }
+}
+
+fn name_key(name: &str) -> Expr {
+    Expr::Str(name.as_bytes().into())
+}
@@ -0,0 +1,327 @@
+// Lua patterns for string.find, match, gmatch and gsub, after the reference lstrlib.c.
+const MAX_CAPTURES: usize = 32;
+const MAX_DEPTH: usize = 200;
+
+const UNFINISHED: isize = -1;
+const POSITION: isize = -2;
+
+pub enum Capture {
+    Bytes(usize, usize),
+    // An empty capture `()`, which yields a 1-based position.
+    Position(usize),
+}
+
+pub struct Pattern<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    pub anchored: bool,
+    level: usize,
+    capture: [(usize, isize); MAX_CAPTURES],
+    depth: usize,
+}
+
+impl<'a> Pattern<'a> {
+    pub fn new(src: &'a [u8], pat: &'a [u8]) -> Pattern<'a> {
+        let anchored = pat.first() == Some(&b'^');
+        Pattern {
+            src,
+            pat: if anchored { &pat[1..] } else { pat },
+            anchored,
+            level: 0,
+            capture: [(0, 0); MAX_CAPTURES],
+            depth: 0,
+        }
+    }
+
+    // Where a match starting at `s` ends, if there is one.
+    pub fn match_at(&mut self, s: usize) -> Result<Option<usize>, String> {
+        self.level = 0;
+        self.depth = 0;
+        self.do_match(s, 0)
+    }
+
+    // The first match at or after `init`, as its bounds.
+    pub fn find(&mut self, init: usize) -> Result<Option<(usize, usize)>, String> {
+        let mut s = init;
+        loop {
+            if let Some(end) = self.match_at(s)? {
+                return Ok(Some((s, end)));
+            }
+            s += 1;
+            if self.anchored || s > self.src.len() {
+                return Ok(None);
+            }
+        }
+    }
+
+    // The captures of the last match; the whole match stands in when there are none and `whole`.
+    pub fn captures(&self, s: usize, e: usize, whole: bool) -> Result<Vec<Capture>, String> {
+        if self.level == 0 && whole {
+            return Ok(vec![Capture::Bytes(s, e)]);
+        }
+        (0..self.level)
+            .map(|i| match self.capture[i] {
+                (_, UNFINISHED) => Err("unfinished capture".to_string()),
+                (start, POSITION) => Ok(Capture::Position(start + 1)),
+                (start, len) => Ok(Capture::Bytes(start, start + len as usize)),
+            })
+            .collect()
+    }
+
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("pattern too complex".into());
+        }
+        let result = self.match_here(s, p);
+        self.depth -= 1;
+        result
+    }
+    fn match_here(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        loop {
+            let Some(&c) = self.pat.get(p) else {
+                return Ok(Some(s));
+            };
+            match c {
+                b'(' if self.pat.get(p + 1) == Some(&b')') => {
+                    return self.start_capture(s, p + 2, POSITION)
+                }
+                b'(' => return self.start_capture(s, p + 1, UNFINISHED),
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => return Ok((s == self.src.len()).then_some(s)),
+                b'%' if self.pat.get(p + 1) == Some(&b'b') => {
+                    match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                b'%' if self.pat.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pat.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".into());
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket(previous, p, ep - 1)
+                        && self.match_bracket(current, p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                b'%' if self.pat.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    match self.match_capture(s, self.pat[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => {}
+            }
+            let ep = self.class_end(p)?;
+            let matched = s < self.src.len() && self.single_match(self.src[s], p, ep);
+            match self.pat.get(ep) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                            return Ok(Some(end));
+                        }
+                    }
+                    p = ep + 1;
+                }
+                Some(b'*') => return self.max_expand(s, p, ep),
+                Some(b'+') if matched => return self.max_expand(s + 1, p, ep),
+                Some(b'+') => return Ok(None),
+                Some(b'-') => return self.min_expand(s, p, ep),
+                _ if matched => {
+                    s += 1;
+                    p = ep;
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pat[p];
+        p += 1;
+        if c == b'%' {
+            if p >= self.pat.len() {
+                return Err("malformed pattern (ends with '%')".into());
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if self.pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            loop {
+                if p >= self.pat.len() {
+                    return Err("malformed pattern (missing ']')".into());
+                }
+                let c = self.pat[p];
+                p += 1;
+                if c == b'%' && p < self.pat.len() {
+                    p += 1;
+                }
+                if self.pat.get(p) == Some(&b']') {
+                    return Ok(p + 1);
+                }
+            }
+        }
+        Ok(p)
+    }
+    fn single_match(&self, c: u8, p: usize, ep: usize) -> bool {
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket(c, p, ep - 1),
+            literal => literal == c,
+        }
+    }
+    // Whether `c` is in the set from the `[` at `p` to the `]` at `ec`.
+    fn match_bracket(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let mut inside = true;
+        if self.pat[p + 1] == b'^' {
+            inside = false;
+            p += 1;
+        }
+        p += 1;
+        while p < ec {
+            if self.pat[p] == b'%' {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return inside;
+                }
+            } else if self.pat[p + 1] == b'-' && p + 2 < ec {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return inside;
+                }
+                p += 2;
+            } else if self.pat[p] == c {
+                return inside;
+            }
+            p += 1;
+        }
+        !inside
+    }
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        let mut count = 0;
+        while s + count < self.src.len() && self.single_match(self.src[s + count], p, ep) {
+            count += 1;
+        }
+        loop {
+            if let Some(end) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if s < self.src.len() && self.single_match(self.src[s], p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+    fn start_capture(&mut self, s: usize, p: usize, what: isize) -> Result<Option<usize>, String> {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".into());
+        }
+        self.capture[self.level] = (s, what);
+        self.level += 1;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let Some(open) = (0..self.level)
+            .rev()
+            .find(|i| self.capture[*i].1 == UNFINISHED)
+        else {
+            return Err("invalid pattern capture".into());
+        };
+        self.capture[open].1 = (s - self.capture[open].0) as isize;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.capture[open].1 = UNFINISHED;
+        }
+        Ok(result)
+    }
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        if p + 1 >= self.pat.len() {
+            return Err("missing arguments to '%b'".into());
+        }
+        let (open, close) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, c) in self.src.iter().enumerate().skip(s + 1) {
+            if *c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if *c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let index = (digit as usize).wrapping_sub(b'1' as usize);
+        if index >= self.level || self.capture[index].1 == UNFINISHED {
+            return Err("invalid capture index".into());
+        }
+        let (start, len) = self.capture[index];
+        let captured = &self.src[start..start + len.max(0) as usize];
+        Ok(self.src[s..]
+            .starts_with(captured)
+            .then_some(s + captured.len()))
+    }
+}
+
+fn match_class(c: u8, class: u8) -> bool {
+    let matched = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 11,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !matched
+    } else {
+        matched
+    }
+}
+
+// Whether a pattern uses any special characters, so find can fall back to a plain search.
+pub fn is_plain(pat: &[u8]) -> bool {
+    !pat.iter().any(|c| b"^$*+?.([%-".contains(c))
+}
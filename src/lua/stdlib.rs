@@ -0,0 +1,1291 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{
+    cjson, format_general, format_number,
+    interp::metamethod,
+    pattern::{is_plain, Capture, Pattern},
+    Function, Interp, LuaError, NativeFn, Registered, Table, Value,
+};
+use crate::sha1;
+
+pub(super) type Native = Result<Vec<Value>, LuaError>;
+
+// Libraries Redis bundles that are not provided here, named in the error a script gets instead.
+pub const UNSUPPORTED: &[&str] = &["cmsgpack", "struct"];
+
+const BASE: &[(&str, NativeFn)] = &[
+    ("assert", assert),
+    ("error", error),
+    ("getmetatable", getmetatable),
+    ("ipairs", ipairs),
+    ("loadstring", loadstring),
+    ("next", next),
+    ("pairs", pairs),
+    ("pcall", pcall),
+    ("rawequal", rawequal),
+    ("rawget", rawget),
+    ("rawset", rawset),
+    ("select", select),
+    ("setmetatable", setmetatable),
+    ("tonumber", tonumber),
+    ("tostring", tostring),
+    ("type", type_of),
+    ("unpack", unpack),
+    ("xpcall", xpcall),
+];
+
+const STRING: &[(&str, NativeFn)] = &[
+    ("byte", string_byte),
+    ("char", string_char),
+    ("find", string_find),
+    ("format", string_format),
+    ("gmatch", string_gmatch),
+    ("gsub", string_gsub),
+    ("len", string_len),
+    ("lower", string_lower),
+    ("match", string_match),
+    ("rep", string_rep),
+    ("reverse", string_reverse),
+    ("sub", string_sub),
+    ("upper", string_upper),
+];
+
+const TABLE: &[(&str, NativeFn)] = &[
+    ("concat", table_concat),
+    ("getn", table_getn),
+    ("insert", table_insert),
+    ("remove", table_remove),
+    ("sort", table_sort),
+];
+
+const MATH: &[(&str, NativeFn)] = &[
+    ("abs", |i, a| math1(i, a, "abs", f64::abs)),
+    ("ceil", |i, a| math1(i, a, "ceil", f64::ceil)),
+    ("exp", |i, a| math1(i, a, "exp", f64::exp)),
+    ("floor", |i, a| math1(i, a, "floor", f64::floor)),
+    ("fmod", math_fmod),
+    ("log", |i, a| math1(i, a, "log", f64::ln)),
+    ("log10", |i, a| math1(i, a, "log10", f64::log10)),
+    ("max", math_max),
+    ("min", math_min),
+    ("pow", math_pow),
+    ("random", math_random),
+    ("randomseed", math_randomseed),
+    ("sqrt", |i, a| math1(i, a, "sqrt", f64::sqrt)),
+];
+
+const BIT: &[(&str, NativeFn)] = &[
+    ("arshift", |i, a| bit_shift(i, a, "arshift", |x, n| x >> n)),
+    ("band", |i, a| bit_fold(i, a, "band", |x, y| x & y)),
+    ("bnot", |i, a| {
+        Ok(vec![bit_value(!check_bit(i, &a, 0, "bnot")?)])
+    }),
+    ("bor", |i, a| bit_fold(i, a, "bor", |x, y| x | y)),
+    ("bswap", |i, a| {
+        Ok(vec![bit_value(check_bit(i, &a, 0, "bswap")?.swap_bytes())])
+    }),
+    ("bxor", |i, a| bit_fold(i, a, "bxor", |x, y| x ^ y)),
+    ("lshift", |i, a| bit_shift(i, a, "lshift", |x, n| x << n)),
+    ("rol", |i, a| {
+        bit_shift(i, a, "rol", |x, n| x.rotate_left(n))
+    }),
+    ("ror", |i, a| {
+        bit_shift(i, a, "ror", |x, n| x.rotate_right(n))
+    }),
+    ("rshift", |i, a| {
+        bit_shift(i, a, "rshift", |x, n| ((x as u32) >> n) as i32)
+    }),
+    ("tobit", |i, a| {
+        Ok(vec![bit_value(check_bit(i, &a, 0, "tobit")?)])
+    }),
+    ("tohex", bit_tohex),
+];
+
+const REDIS: &[(&str, NativeFn)] = &[
+    ("call", |i, a| redis_call(i, a, true)),
+    ("error_reply", redis_error_reply),
+    ("log", redis_log),
+    ("pcall", |i, a| redis_call(i, a, false)),
+    ("register_function", redis_register_function),
+    ("sha1hex", redis_sha1hex),
+    ("status_reply", redis_status_reply),
+];
+
+fn library(functions: &[(&str, NativeFn)]) -> Table {
+    let mut table = Table::default();
+    for (name, f) in functions {
+        table.set_str(name, Value::Function(Rc::new(Function::Native(*f))));
+    }
+    table
+}
+
+// Fills the globals, leaving them and every library read-only as Redis does.
+pub fn install(interp: &mut Interp) {
+    let mut globals = library(BASE);
+    let mut strings = library(STRING);
+    strings.readonly = true;
+    let strings = Rc::new(RefCell::new(strings));
+    globals.set_str("string", Value::Table(strings.clone()));
+    let mut string_meta = Table::default();
+    string_meta.set_str("__index", Value::Table(strings.clone()));
+    string_meta.readonly = true;
+    interp.string_meta = Rc::new(RefCell::new(string_meta));
+    interp.strings = strings;
+    let mut table = library(TABLE);
+    table.readonly = true;
+    globals.set_str("table", Value::table(table));
+    let mut math = library(MATH);
+    math.set_str("huge", Value::Num(f64::INFINITY));
+    math.set_str("pi", Value::Num(std::f64::consts::PI));
+    math.readonly = true;
+    globals.set_str("math", Value::table(math));
+    let mut bit = library(BIT);
+    bit.readonly = true;
+    globals.set_str("bit", Value::table(bit));
+    let mut cjson = library(cjson::FUNCTIONS);
+    interp.json_null.borrow_mut().readonly = true;
+    cjson.set_str("null", Value::Table(interp.json_null.clone()));
+    cjson.readonly = true;
+    globals.set_str("cjson", Value::table(cjson));
+    interp.random = seed(0);
+    let mut redis = library(REDIS);
+    for (i, level) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+        .iter()
+        .enumerate()
+    {
+        redis.set_str(level, Value::Num(i as f64));
+    }
+    redis.readonly = true;
+    globals.set_str("redis", Value::table(redis));
+    globals.readonly = true;
+    interp.globals = Rc::new(RefCell::new(globals));
+}
+
+fn arg(args: &[Value], i: usize) -> Value {
+    args.get(i).cloned().unwrap_or_default()
+}
+
+fn bad_argument(interp: &Interp, args: &[Value], i: usize, name: &str, expected: &str) -> LuaError {
+    let got = match args.get(i) {
+        Some(value) => value.type_name(),
+        None => "no value",
+    };
+    interp.error(format!(
+        "bad argument #{} to '{name}' ({expected} expected, got {got})",
+        i + 1
+    ))
+}
+
+pub(super) fn check_str(
+    interp: &Interp,
+    args: &[Value],
+    i: usize,
+    name: &str,
+) -> Result<Rc<[u8]>, LuaError> {
+    arg(args, i)
+        .to_bytes()
+        .ok_or_else(|| bad_argument(interp, args, i, name, "string"))
+}
+
+fn check_num(interp: &Interp, args: &[Value], i: usize, name: &str) -> Result<f64, LuaError> {
+    arg(args, i)
+        .to_number()
+        .ok_or_else(|| bad_argument(interp, args, i, name, "number"))
+}
+
+fn opt_num(
+    interp: &Interp,
+    args: &[Value],
+    i: usize,
+    name: &str,
+    default: f64,
+) -> Result<f64, LuaError> {
+    match args.get(i) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_num(interp, args, i, name),
+    }
+}
+
+fn check_table(
+    interp: &Interp,
+    args: &[Value],
+    i: usize,
+    name: &str,
+) -> Result<Rc<RefCell<Table>>, LuaError> {
+    match args.get(i) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => Err(bad_argument(interp, args, i, name, "table")),
+    }
+}
+
+fn assert(interp: &mut Interp, args: Vec<Value>) -> Native {
+    if arg(&args, 0).truthy() {
+        return Ok(args);
+    }
+    match args.get(1) {
+        Some(message) => Err(LuaError(message.clone())),
+        None => Err(interp.error("assertion failed!")),
+    }
+}
+
+fn error(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let level = opt_num(interp, &args, 1, "error", 1.0)?;
+    match arg(&args, 0) {
+        Value::Str(message) if level > 0.0 => Err(interp.error(String::from_utf8_lossy(&message))),
+        value => Err(LuaError(value)),
+    }
+}
+
+// A table's metatable, or its __metatable field when it has one; strings share one whose __index
+// is the string library.
+fn getmetatable(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let meta = match arg(&args, 0) {
+        Value::Table(table) => match &table.borrow().meta {
+            Some(meta) => match meta.borrow().get_str("__metatable") {
+                Value::Nil => Value::Table(meta.clone()),
+                protected => protected,
+            },
+            None => Value::Nil,
+        },
+        Value::Str(_) => Value::Table(interp.string_meta.clone()),
+        _ => Value::Nil,
+    };
+    Ok(vec![meta])
+}
+
+fn setmetatable(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "setmetatable")?;
+    let meta = match arg(&args, 1) {
+        Value::Nil => None,
+        Value::Table(meta) => Some(meta),
+        _ => {
+            return Err(bad_argument(
+                interp,
+                &args,
+                1,
+                "setmetatable",
+                "nil or table",
+            ))
+        }
+    };
+    let protected = matches!(&table.borrow().meta,
+        Some(old) if !matches!(old.borrow().get_str("__metatable"), Value::Nil));
+    if protected {
+        return Err(interp.error("cannot change a protected metatable"));
+    }
+    if table.borrow().readonly {
+        return Err(interp.error("Attempt to modify a readonly table"));
+    }
+    table.borrow_mut().meta = meta;
+    Ok(vec![Value::Table(table)])
+}
+
+fn ipairs(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "ipairs")?;
+    let step: NativeFn = |_, args| {
+        let i = arg(&args, 1).to_number().unwrap_or(0.0) + 1.0;
+        let Value::Table(table) = arg(&args, 0) else {
+            return Ok(vec![Value::Nil]);
+        };
+        let value = table.borrow().get(&Value::Num(i));
+        Ok(match value {
+            Value::Nil => vec![Value::Nil],
+            value => vec![Value::Num(i), value],
+        })
+    };
+    Ok(vec![
+        Value::Function(Rc::new(Function::Native(step))),
+        Value::Table(table),
+        Value::Num(0.0),
+    ])
+}
+
+// Compiles a chunk sharing the script's globals; a syntax error comes back as nil and a message.
+fn loadstring(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let source = check_str(interp, &args, 0, "loadstring")?;
+    Ok(match interp.load(&source) {
+        Ok(f) => vec![f],
+        Err(message) => vec![Value::Nil, Value::str(message)],
+    })
+}
+
+fn next(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "next")?;
+    let entry = table
+        .borrow()
+        .next(&arg(&args, 1))
+        .map_err(|_| interp.error("invalid key to 'next'"))?;
+    Ok(match entry {
+        Some((key, value)) => vec![key, value],
+        None => vec![Value::Nil],
+    })
+}
+
+fn pairs(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "pairs")?;
+    Ok(vec![
+        Value::Function(Rc::new(Function::Native(next))),
+        Value::Table(table),
+        Value::Nil,
+    ])
+}
+
+fn pcall(interp: &mut Interp, mut args: Vec<Value>) -> Native {
+    if args.is_empty() {
+        return Err(bad_argument(interp, &args, 0, "pcall", "value"));
+    }
+    let f = args.remove(0);
+    Ok(match interp.call(&f, args) {
+        Ok(mut results) => {
+            results.insert(0, Value::Bool(true));
+            results
+        }
+        // A script being stopped is not an error it can handle.
+        Err(error) if interp.aborted => return Err(error),
+        Err(LuaError(error)) => vec![Value::Bool(false), error],
+    })
+}
+
+// Like pcall, but the error goes through `handler` first; 5.1's xpcall passes `f` no arguments.
+fn xpcall(interp: &mut Interp, args: Vec<Value>) -> Native {
+    if args.len() < 2 {
+        return Err(bad_argument(interp, &args, 1, "xpcall", "value"));
+    }
+    Ok(match interp.call(&args[0], vec![]) {
+        Ok(mut results) => {
+            results.insert(0, Value::Bool(true));
+            results
+        }
+        Err(error) if interp.aborted => return Err(error),
+        Err(LuaError(error)) => {
+            let handled = interp.call(&args[1], vec![error])?;
+            vec![
+                Value::Bool(false),
+                handled.into_iter().next().unwrap_or_default(),
+            ]
+        }
+    })
+}
+
+fn rawequal(_: &mut Interp, args: Vec<Value>) -> Native {
+    Ok(vec![Value::Bool(arg(&args, 0).raw_equals(&arg(&args, 1)))])
+}
+
+fn rawget(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "rawget")?;
+    let value = table.borrow().get(&arg(&args, 1));
+    Ok(vec![value])
+}
+
+fn rawset(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "rawset")?;
+    interp.set_field(&table, arg(&args, 1), arg(&args, 2))?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let rest = args.len().saturating_sub(1) as f64;
+    if matches!(args.first(), Some(Value::Str(s)) if &**s == b"#") {
+        return Ok(vec![Value::Num(rest)]);
+    }
+    let n = check_num(interp, &args, 0, "select")?.trunc();
+    let from = match n {
+        _ if n < 0.0 && -n <= rest => rest + n,
+        _ if n >= 1.0 => (n - 1.0).min(rest),
+        _ => return Err(interp.error("bad argument #1 to 'select' (index out of range)")),
+    };
+    Ok(args[1 + from as usize..].to_vec())
+}
+
+fn tonumber(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let value = arg(&args, 0);
+    let base = opt_num(interp, &args, 1, "tonumber", 10.0)?;
+    if base == 10.0 {
+        return Ok(vec![value.to_number().map_or(Value::Nil, Value::Num)]);
+    }
+    if !(2.0..=36.0).contains(&base) {
+        return Err(interp.error("bad argument #2 to 'tonumber' (base out of range)"));
+    }
+    let digits = check_str(interp, &args, 0, "tonumber")?;
+    let digits = std::str::from_utf8(digits.trim_ascii()).unwrap_or("");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits),
+    };
+    Ok(vec![match i64::from_str_radix(digits, base as u32) {
+        Ok(n) if !digits.starts_with('+') => Value::Num(if negative { -n } else { n } as f64),
+        _ => Value::Nil,
+    }])
+}
+
+pub fn to_display(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Nil => b"nil".to_vec(),
+        Value::Bool(b) => b.to_string().into_bytes(),
+        Value::Num(n) => format_number(*n).into_bytes(),
+        Value::Str(s) => s.to_vec(),
+        Value::Table(t) => format!("table: {:p}", Rc::as_ptr(t)).into_bytes(),
+        Value::Function(f) => format!("function: {:p}", Rc::as_ptr(f)).into_bytes(),
+    }
+}
+
+fn tostring(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let value = arg(&args, 0);
+    match metamethod(&value, "__tostring") {
+        Value::Nil => Ok(vec![Value::str(to_display(&value))]),
+        handler => Ok(interp
+            .call(&handler, vec![value])?
+            .into_iter()
+            .take(1)
+            .collect()),
+    }
+}
+
+fn type_of(interp: &mut Interp, args: Vec<Value>) -> Native {
+    if args.is_empty() {
+        return Err(bad_argument(interp, &args, 0, "type", "value"));
+    }
+    Ok(vec![Value::str(args[0].type_name())])
+}
+
+fn unpack(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "unpack")?;
+    let table = table.borrow();
+    let first = opt_num(interp, &args, 1, "unpack", 1.0)? as i64;
+    let last = opt_num(interp, &args, 2, "unpack", table.len() as f64)? as i64;
+    if last - first >= 8000 {
+        return Err(interp.error("too many results to unpack"));
+    }
+    Ok((first..=last)
+        .map(|i| table.get(&Value::Num(i as f64)))
+        .collect())
+}
+
+// A 1-based, possibly negative string position made 0-based and clamped to `len`.
+fn position(pos: f64, len: usize) -> i64 {
+    let pos = pos as i64;
+    if pos < 0 {
+        (len as i64 + pos + 1).max(0)
+    } else {
+        pos
+    }
+}
+
+fn string_len(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let s = check_str(interp, &args, 0, "len")?;
+    Ok(vec![Value::Num(s.len() as f64)])
+}
+
+fn string_sub(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let s = check_str(interp, &args, 0, "sub")?;
+    let start = position(opt_num(interp, &args, 1, "sub", 1.0)?, s.len()).max(1);
+    let end = position(opt_num(interp, &args, 2, "sub", -1.0)?, s.len()).min(s.len() as i64);
+    Ok(vec![if start <= end {
+        Value::str(&s[start as usize - 1..end as usize])
+    } else {
+        Value::str("")
+    }])
+}
+
+fn string_upper(interp: &mut Interp, args: Vec<Value>) -> Native {
+    Ok(vec![Value::str(
+        check_str(interp, &args, 0, "upper")?.to_ascii_uppercase(),
+    )])
+}
+
+fn string_lower(interp: &mut Interp, args: Vec<Value>) -> Native {
+    Ok(vec![Value::str(
+        check_str(interp, &args, 0, "lower")?.to_ascii_lowercase(),
+    )])
+}
+
+fn string_reverse(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let mut s = check_str(interp, &args, 0, "reverse")?.to_vec();
+    s.reverse();
+    Ok(vec![Value::str(s)])
+}
+
+// Bigger results than any Redis string can hold are refused rather than attempted.
+const MAX_STRING: f64 = 512.0 * 1024.0 * 1024.0;
+
+fn string_rep(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let s = check_str(interp, &args, 0, "rep")?;
+    let n = check_num(interp, &args, 1, "rep")?.max(0.0);
+    if n * s.len() as f64 > MAX_STRING {
+        return Err(interp.error("resulting string too large"));
+    }
+    Ok(vec![Value::str(s.repeat(n as usize))])
+}
+
+fn string_byte(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let s = check_str(interp, &args, 0, "byte")?;
+    let start = opt_num(interp, &args, 1, "byte", 1.0)?;
+    let start = position(start, s.len()).max(1);
+    let end = position(opt_num(interp, &args, 2, "byte", start as f64)?, s.len());
+    let end = end.min(s.len() as i64);
+    Ok((start..=end)
+        .map(|i| Value::Num(s[i as usize - 1] as f64))
+        .collect())
+}
+
+fn string_char(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let mut out = Vec::with_capacity(args.len());
+    for i in 0..args.len() {
+        let c = check_num(interp, &args, i, "char")?;
+        if !(0.0..256.0).contains(&c) {
+            return Err(interp.error(format!("bad argument #{} to 'char' (invalid value)", i + 1)));
+        }
+        out.push(c as u8);
+    }
+    Ok(vec![Value::str(out)])
+}
+
+fn capture_values(s: &[u8], captures: Vec<Capture>) -> Vec<Value> {
+    captures
+        .into_iter()
+        .map(|capture| match capture {
+            Capture::Bytes(start, end) => Value::str(&s[start..end]),
+            Capture::Position(pos) => Value::Num(pos as f64),
+        })
+        .collect()
+}
+
+fn find_generic(interp: &mut Interp, args: Vec<Value>, name: &str, find: bool) -> Native {
+    let s = check_str(interp, &args, 0, name)?;
+    let pat = check_str(interp, &args, 1, name)?;
+    let init = position(opt_num(interp, &args, 2, name, 1.0)?, s.len()).max(1) as usize - 1;
+    if init > s.len() {
+        return Ok(vec![Value::Nil]);
+    }
+    if find && (arg(&args, 3).truthy() || is_plain(&pat)) {
+        let found = if pat.is_empty() {
+            Some(init)
+        } else {
+            s[init..]
+                .windows(pat.len())
+                .position(|window| window == &pat[..])
+                .map(|i| init + i)
+        };
+        return Ok(match found {
+            Some(i) => vec![
+                Value::Num((i + 1) as f64),
+                Value::Num((i + pat.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+    let mut pattern = Pattern::new(&s, &pat);
+    let found = pattern.find(init).map_err(|e| interp.error(e))?;
+    let Some((start, end)) = found else {
+        return Ok(vec![Value::Nil]);
+    };
+    let captures = pattern
+        .captures(start, end, !find)
+        .map_err(|e| interp.error(e))?;
+    let mut results = vec![];
+    if find {
+        results.push(Value::Num((start + 1) as f64));
+        results.push(Value::Num(end as f64));
+    }
+    results.extend(capture_values(&s, captures));
+    Ok(results)
+}
+
+fn string_find(interp: &mut Interp, args: Vec<Value>) -> Native {
+    find_generic(interp, args, "find", true)
+}
+
+fn string_match(interp: &mut Interp, args: Vec<Value>) -> Native {
+    find_generic(interp, args, "match", false)
+}
+
+fn string_gmatch(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let s = check_str(interp, &args, 0, "gmatch")?;
+    let pat = check_str(interp, &args, 1, "gmatch")?;
+    let mut state = Table::default();
+    state.set_str("s", Value::Str(s));
+    state.set_str("p", Value::Str(pat));
+    state.set_str("pos", Value::Num(0.0));
+    let step: NativeFn = |interp, args| {
+        let Value::Table(state) = arg(&args, 0) else {
+            return Ok(vec![Value::Nil]);
+        };
+        let (Value::Str(s), Value::Str(pat), Value::Num(pos)) = (
+            state.borrow().get_str("s"),
+            state.borrow().get_str("p"),
+            state.borrow().get_str("pos"),
+        ) else {
+            return Ok(vec![Value::Nil]);
+        };
+        let mut pattern = Pattern::new(&s, &pat);
+        for start in pos as usize..=s.len() {
+            let Some(end) = pattern.match_at(start).map_err(|e| interp.error(e))? else {
+                continue;
+            };
+            let next = if end == start { end + 1 } else { end };
+            state.borrow_mut().set_str("pos", Value::Num(next as f64));
+            let captures = pattern
+                .captures(start, end, true)
+                .map_err(|e| interp.error(e))?;
+            return Ok(capture_values(&s, captures));
+        }
+        state
+            .borrow_mut()
+            .set_str("pos", Value::Num((s.len() + 1) as f64));
+        Ok(vec![Value::Nil])
+    };
+    Ok(vec![Value::Function(Rc::new(Function::Bound(
+        step,
+        Value::table(state),
+    )))])
+}
+
+fn string_gsub(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let s = check_str(interp, &args, 0, "gsub")?;
+    let pat = check_str(interp, &args, 1, "gsub")?;
+    let replacement = arg(&args, 2);
+    if !matches!(
+        replacement,
+        Value::Num(_) | Value::Str(_) | Value::Table(_) | Value::Function(_)
+    ) {
+        return Err(bad_argument(
+            interp,
+            &args,
+            2,
+            "gsub",
+            "string/function/table",
+        ));
+    }
+    let max = opt_num(interp, &args, 3, "gsub", f64::INFINITY)?;
+    let mut pattern = Pattern::new(&s, &pat);
+    let mut out = vec![];
+    let mut count = 0.0;
+    let mut pos = 0;
+    while count < max {
+        let end = pattern.match_at(pos).map_err(|e| interp.error(e))?;
+        if let Some(end) = end {
+            count += 1.0;
+            let captures = pattern
+                .captures(pos, end, true)
+                .map_err(|e| interp.error(e))?;
+            let captures = capture_values(&s, captures);
+            let value = match &replacement {
+                Value::Table(table) => table.borrow().get(&captures[0]),
+                Value::Function(_) => interp
+                    .call(&replacement, captures.clone())?
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default(),
+                _ => {
+                    let template = replacement.to_bytes().unwrap();
+                    let mut expanded = vec![];
+                    let mut chars = template.iter();
+                    while let Some(&c) = chars.next() {
+                        if c != b'%' {
+                            expanded.push(c);
+                            continue;
+                        }
+                        match chars.next() {
+                            Some(b'0') => expanded.extend_from_slice(&s[pos..end]),
+                            Some(d @ b'1'..=b'9') => {
+                                let Some(capture) = captures.get((d - b'1') as usize) else {
+                                    return Err(interp.error("invalid capture index"));
+                                };
+                                expanded.extend_from_slice(&capture.to_bytes().unwrap());
+                            }
+                            Some(&other) => expanded.push(other),
+                            None => {}
+                        }
+                    }
+                    Value::str(expanded)
+                }
+            };
+            match value {
+                Value::Nil | Value::Bool(false) => out.extend_from_slice(&s[pos..end]),
+                value => match value.to_bytes() {
+                    Some(bytes) => out.extend_from_slice(&bytes),
+                    None => {
+                        return Err(interp.error(format!(
+                            "invalid replacement value (a {})",
+                            value.type_name()
+                        )))
+                    }
+                },
+            }
+            if end > pos {
+                pos = end;
+            } else if pos < s.len() {
+                out.push(s[pos]);
+                pos += 1;
+            } else {
+                break;
+            }
+        } else if pos < s.len() {
+            out.push(s[pos]);
+            pos += 1;
+        } else {
+            break;
+        }
+        if pattern.anchored {
+            break;
+        }
+    }
+    out.extend_from_slice(&s[pos.min(s.len())..]);
+    Ok(vec![Value::str(out), Value::Num(count)])
+}
+
+// printf-style directives over Lua values: %d %i %u %c %o %x %X %e %E %f %g %G %q %s %%.
+fn string_format(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let format = check_str(interp, &args, 0, "format")?;
+    let mut out = vec![];
+    let mut next_arg = 1;
+    let mut i = 0;
+    while i < format.len() {
+        let c = format[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        if format.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+        let flags_start = i;
+        while i < format.len() && b"-+ #0".contains(&format[i]) {
+            i += 1;
+        }
+        let flags = &format[flags_start..i];
+        let width_start = i;
+        while i < format.len() && format[i].is_ascii_digit() {
+            i += 1;
+        }
+        let width: usize = std::str::from_utf8(&format[width_start..i])
+            .unwrap()
+            .parse()
+            .unwrap_or(0);
+        let mut precision = None;
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            let start = i;
+            while i < format.len() && format[i].is_ascii_digit() {
+                i += 1;
+            }
+            precision = Some(
+                std::str::from_utf8(&format[start..i])
+                    .unwrap()
+                    .parse()
+                    .unwrap_or(0),
+            );
+        }
+        if flags.len() > 5 {
+            return Err(interp.error("invalid format (repeated flags)"));
+        }
+        if width > 99 || precision.unwrap_or(0) > 99 {
+            return Err(interp.error("invalid format (width or precision too long)"));
+        }
+        let Some(&conversion) = format.get(i) else {
+            return Err(interp.error("invalid option '%' to 'format'"));
+        };
+        i += 1;
+        let n = next_arg;
+        next_arg += 1;
+        let has = |flag: u8| flags.contains(&flag);
+        let (body, numeric) = match conversion {
+            b'd' | b'i' | b'u' => {
+                let value = check_num(interp, &args, n, "format")?.trunc() as i64;
+                (value.to_string().into_bytes(), true)
+            }
+            b'c' => (vec![check_num(interp, &args, n, "format")? as u8], false),
+            b'o' | b'x' | b'X' => {
+                let value = check_num(interp, &args, n, "format")?.trunc() as i64 as u64;
+                let digits = match conversion {
+                    b'o' => format!("{value:o}"),
+                    b'x' => format!("{value:x}"),
+                    _ => format!("{value:X}"),
+                };
+                let prefix = match conversion {
+                    _ if !has(b'#') || value == 0 => "",
+                    b'o' => "0",
+                    b'x' => "0x",
+                    _ => "0X",
+                };
+                (format!("{prefix}{digits}").into_bytes(), true)
+            }
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let value = check_num(interp, &args, n, "format")?;
+                let precision = precision.unwrap_or(6);
+                let text = if !value.is_finite() {
+                    format_general(value, 1, false)
+                } else {
+                    match conversion {
+                        b'f' | b'F' => format!("{value:.precision$}"),
+                        b'e' | b'E' => {
+                            let scientific = format!("{value:.precision$e}");
+                            let (mantissa, exponent) = scientific.split_once('e').unwrap();
+                            let exponent: i32 = exponent.parse().unwrap();
+                            let sign = if exponent < 0 { '-' } else { '+' };
+                            format!("{mantissa}e{sign}{:02}", exponent.abs())
+                        }
+                        _ => format_general(
+                            value,
+                            if precision == 0 { 1 } else { precision },
+                            has(b'#'),
+                        ),
+                    }
+                };
+                let text = if conversion.is_ascii_uppercase() {
+                    text.to_ascii_uppercase()
+                } else {
+                    text
+                };
+                (text.into_bytes(), value.is_finite())
+            }
+            b'q' => {
+                let s = check_str(interp, &args, n, "format")?;
+                let mut quoted = vec![b'"'];
+                for &c in s.iter() {
+                    match c {
+                        b'"' | b'\\' | b'\n' => quoted.extend_from_slice(&[b'\\', c]),
+                        b'\r' => quoted.extend_from_slice(b"\\r"),
+                        0 => quoted.extend_from_slice(b"\\000"),
+                        _ => quoted.push(c),
+                    }
+                }
+                quoted.push(b'"');
+                (quoted, false)
+            }
+            b's' => {
+                let mut s = check_str(interp, &args, n, "format")?.to_vec();
+                if let Some(precision) = precision {
+                    s.truncate(precision);
+                }
+                (s, false)
+            }
+            other => {
+                return Err(interp.error(format!("invalid option '%{}' to 'format'", other as char)))
+            }
+        };
+        let mut body = body;
+        if numeric && body.first() != Some(&b'-') {
+            if has(b'+') {
+                body.insert(0, b'+');
+            } else if has(b' ') {
+                body.insert(0, b' ');
+            }
+        }
+        let padding = width.saturating_sub(body.len());
+        if has(b'-') {
+            out.extend_from_slice(&body);
+            out.resize(out.len() + padding, b' ');
+        } else if has(b'0') && numeric {
+            let sign = body.first().is_some_and(|c| b"+- ".contains(c)) as usize;
+            out.extend_from_slice(&body[..sign]);
+            out.resize(out.len() + padding, b'0');
+            out.extend_from_slice(&body[sign..]);
+        } else {
+            out.resize(out.len() + padding, b' ');
+            out.extend_from_slice(&body);
+        }
+    }
+    Ok(vec![Value::str(out)])
+}
+
+fn table_getn(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "getn")?;
+    let len = table.borrow().len();
+    Ok(vec![Value::Num(len as f64)])
+}
+
+fn table_insert(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "insert")?;
+    if table.borrow().readonly {
+        return Err(interp.error("Attempt to modify a readonly table"));
+    }
+    let mut table = table.borrow_mut();
+    let len = table.len();
+    match args.len() {
+        2 => table.push(args[1].clone()),
+        3 => {
+            let pos = check_num(interp, &args, 1, "insert")? as i64;
+            if pos >= 1 && pos as usize <= len + 1 {
+                table.insert(pos as usize, args[2].clone());
+            } else {
+                table.set(Value::Num(pos as f64), args[2].clone())?;
+            }
+        }
+        _ => return Err(interp.error("wrong number of arguments to 'insert'")),
+    }
+    Ok(vec![])
+}
+
+fn table_remove(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "remove")?;
+    if table.borrow().readonly {
+        return Err(interp.error("Attempt to modify a readonly table"));
+    }
+    let mut table = table.borrow_mut();
+    let len = table.len();
+    if len == 0 {
+        return Ok(vec![]);
+    }
+    let pos = opt_num(interp, &args, 1, "remove", len as f64)? as i64;
+    if pos < 1 || pos as usize > len {
+        return Ok(vec![]);
+    }
+    Ok(vec![table.remove(pos as usize)])
+}
+
+fn table_concat(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "concat")?;
+    let separator = match args.get(1) {
+        None | Some(Value::Nil) => Rc::from(&b""[..]),
+        Some(_) => check_str(interp, &args, 1, "concat")?,
+    };
+    let table = table.borrow();
+    let first = opt_num(interp, &args, 2, "concat", 1.0)? as i64;
+    let last = opt_num(interp, &args, 3, "concat", table.len() as f64)? as i64;
+    let mut out = vec![];
+    for i in first..=last {
+        let Some(item) = table.get(&Value::Num(i as f64)).to_bytes() else {
+            return Err(interp.error(format!(
+                "invalid value (at index {i}) in table for 'concat'"
+            )));
+        };
+        if i > first {
+            out.extend_from_slice(&separator);
+        }
+        out.extend_from_slice(&item);
+    }
+    Ok(vec![Value::str(out)])
+}
+
+fn less_than(interp: &mut Interp, compare: &Value, a: &Value, b: &Value) -> Result<bool, LuaError> {
+    if !matches!(compare, Value::Nil) {
+        let result = interp.call(compare, vec![a.clone(), b.clone()])?;
+        return Ok(result.first().is_some_and(Value::truthy));
+    }
+    interp.less_than(a, b)
+}
+
+// A merge sort, since the comparison can fail and need not be consistent.
+fn merge_sort(
+    interp: &mut Interp,
+    compare: &Value,
+    items: Vec<Value>,
+) -> Result<Vec<Value>, LuaError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(interp, compare, left)?;
+    let right = merge_sort(interp, compare, right)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if less_than(interp, compare, b, a)? {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn table_sort(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let table = check_table(interp, &args, 0, "sort")?;
+    let compare = arg(&args, 1);
+    if !matches!(compare, Value::Nil | Value::Function(_)) {
+        return Err(bad_argument(interp, &args, 1, "sort", "function"));
+    }
+    let items = table.borrow().array().to_vec();
+    let sorted = merge_sort(interp, &compare, items)?;
+    let mut table = table.borrow_mut();
+    for (i, item) in sorted.into_iter().enumerate() {
+        table.set(Value::Num((i + 1) as f64), item)?;
+    }
+    Ok(vec![])
+}
+
+fn math1(interp: &mut Interp, args: Vec<Value>, name: &str, f: fn(f64) -> f64) -> Native {
+    Ok(vec![Value::Num(f(check_num(interp, &args, 0, name)?))])
+}
+
+fn math_fmod(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let a = check_num(interp, &args, 0, "fmod")?;
+    let b = check_num(interp, &args, 1, "fmod")?;
+    Ok(vec![Value::Num(a % b)])
+}
+
+fn math_pow(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let a = check_num(interp, &args, 0, "pow")?;
+    let b = check_num(interp, &args, 1, "pow")?;
+    Ok(vec![Value::Num(a.powf(b))])
+}
+
+fn math_max(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let mut max = check_num(interp, &args, 0, "max")?;
+    for i in 1..args.len() {
+        max = max.max(check_num(interp, &args, i, "max")?);
+    }
+    Ok(vec![Value::Num(max)])
+}
+
+fn math_min(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let mut min = check_num(interp, &args, 0, "min")?;
+    for i in 1..args.len() {
+        min = min.min(check_num(interp, &args, i, "min")?);
+    }
+    Ok(vec![Value::Num(min)])
+}
+
+// lrand48, which Redis swaps in for the C library's rand so scripts replay alike everywhere.
+const RAND48_MULTIPLIER: u64 = 0x5DEECE66D;
+const RAND48_MASK: u64 = (1 << 48) - 1;
+const RAND48_MAX: u64 = i32::MAX as u64;
+
+fn seed(seed: i32) -> u64 {
+    ((seed as u32 as u64) << 16) | 0x330E
+}
+
+fn rand48(interp: &mut Interp) -> u64 {
+    interp.random = (interp.random.wrapping_mul(RAND48_MULTIPLIER) + 0xB) & RAND48_MASK;
+    interp.random >> 17
+}
+
+fn math_random(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let r = (rand48(interp) % RAND48_MAX) as f64 / RAND48_MAX as f64;
+    let (low, high) = match args.len() {
+        0 => return Ok(vec![Value::Num(r)]),
+        1 => (1.0, check_num(interp, &args, 0, "random")?.floor()),
+        2 => (
+            check_num(interp, &args, 0, "random")?.floor(),
+            check_num(interp, &args, 1, "random")?.floor(),
+        ),
+        _ => return Err(interp.error("wrong number of arguments")),
+    };
+    if low > high {
+        let i = args.len();
+        return Err(interp.error(format!("bad argument #{i} to 'random' (interval is empty)")));
+    }
+    Ok(vec![Value::Num((r * (high - low + 1.0)).floor() + low)])
+}
+
+fn math_randomseed(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let n = check_num(interp, &args, 0, "randomseed")?;
+    interp.random = seed(n as i64 as i32);
+    Ok(vec![])
+}
+
+// A number as the bit library sees it: rounded, then wrapped into 32 bits.
+fn check_bit(interp: &Interp, args: &[Value], i: usize, name: &str) -> Result<i32, LuaError> {
+    let n = check_num(interp, args, i, name)?;
+    Ok(n.round_ties_even().rem_euclid(4294967296.0) as u32 as i32)
+}
+
+fn bit_value(n: i32) -> Value {
+    Value::Num(n as f64)
+}
+
+fn bit_fold(interp: &mut Interp, args: Vec<Value>, name: &str, f: fn(i32, i32) -> i32) -> Native {
+    let mut acc = check_bit(interp, &args, 0, name)?;
+    for i in 1..args.len() {
+        acc = f(acc, check_bit(interp, &args, i, name)?);
+    }
+    Ok(vec![bit_value(acc)])
+}
+
+fn bit_shift(interp: &mut Interp, args: Vec<Value>, name: &str, f: fn(i32, u32) -> i32) -> Native {
+    let x = check_bit(interp, &args, 0, name)?;
+    let n = check_bit(interp, &args, 1, name)? as u32 & 31;
+    Ok(vec![bit_value(f(x, n))])
+}
+
+// The low `n` hex digits of x, 8 by default; a negative `n` asks for upper case.
+fn bit_tohex(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let x = check_bit(interp, &args, 0, "tohex")? as u32;
+    let n = match args.get(1) {
+        None | Some(Value::Nil) => 8,
+        Some(_) => check_bit(interp, &args, 1, "tohex")?,
+    };
+    let digits = n.unsigned_abs().min(8) as usize;
+    let hex = match n < 0 {
+        true => format!("{x:08X}"),
+        false => format!("{x:08x}"),
+    };
+    Ok(vec![Value::str(&hex[8 - digits..])])
+}
+
+fn redis_call(interp: &mut Interp, args: Vec<Value>, raise: bool) -> Native {
+    if args.is_empty() {
+        return Err(interp.error("Please specify at least one argument for this redis lib call"));
+    }
+    let mut command = Vec::with_capacity(args.len());
+    for value in &args {
+        match value {
+            Value::Str(s) => command.push(s.to_vec()),
+            Value::Num(n) => command.push(format_number(*n).into_bytes()),
+            _ => {
+                return Err(
+                    interp.error("Lua redis lib command arguments must be strings or integers")
+                )
+            }
+        }
+    }
+    let Some(host) = interp.host.as_deref_mut() else {
+        return Err(interp.error("redis.call is not available here"));
+    };
+    let reply = host.call(command);
+    match &reply {
+        Value::Table(table) if raise && table.borrow().get_str("err").truthy() => {
+            Err(LuaError(reply))
+        }
+        _ => Ok(vec![reply]),
+    }
+}
+
+fn single_field(interp: &mut Interp, args: Vec<Value>, name: &str, field: &str) -> Native {
+    let message = check_str(interp, &args, 0, name)?;
+    let mut table = Table::default();
+    table.set_str(field, Value::Str(message));
+    Ok(vec![Value::table(table)])
+}
+
+fn redis_error_reply(interp: &mut Interp, args: Vec<Value>) -> Native {
+    single_field(interp, args, "error_reply", "err")
+}
+
+fn redis_status_reply(interp: &mut Interp, args: Vec<Value>) -> Native {
+    single_field(interp, args, "status_reply", "ok")
+}
+
+fn redis_sha1hex(interp: &mut Interp, args: Vec<Value>) -> Native {
+    let body = check_str(interp, &args, 0, "sha1hex")?;
+    Ok(vec![Value::str(sha1::hex_digest(&body))])
+}
+
+fn redis_log(interp: &mut Interp, args: Vec<Value>) -> Native {
+    if args.len() < 2 {
+        return Err(interp.error("redis.log() requires two arguments or more."));
+    }
+    let level = check_num(interp, &args, 0, "log")?;
+    if !(0.0..=3.0).contains(&level) {
+        return Err(interp.error("Invalid debug level."));
+    }
+    let message: Vec<Vec<u8>> = args[1..]
+        .iter()
+        .filter_map(Value::to_bytes)
+        .map(|s| s.to_vec())
+        .collect();
+    println!("{}", String::from_utf8_lossy(&message.join(&b' ')));
+    Ok(vec![])
+}
+
+const FUNCTION_FLAGS: &[&str] = &[
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+// Either register_function(name, callback) or register_function{function_name=..., callback=...,
+// flags={...}, description=...}.
+fn redis_register_function(interp: &mut Interp, args: Vec<Value>) -> Native {
+    if interp.registered.is_none() {
+        return Err(
+            interp.error("redis.register_function can only be called on FUNCTION LOAD command")
+        );
+    }
+    let (name, callback, flags, description) = match args.as_slice() {
+        [Value::Table(table)] => {
+            let table = table.borrow();
+            let mut key = Value::Nil;
+            while let Some((k, _)) = table.next(&key)? {
+                match &k {
+                    Value::Str(s)
+                        if matches!(
+                            &s[..],
+                            b"function_name" | b"callback" | b"flags" | b"description"
+                        ) => {}
+                    _ => {
+                        return Err(
+                            interp.error("unknown argument given to redis.register_function")
+                        )
+                    }
+                }
+                key = k;
+            }
+            (
+                table.get_str("function_name"),
+                table.get_str("callback"),
+                table.get_str("flags"),
+                table.get_str("description"),
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => return Err(interp.error("wrong number of arguments to redis.register_function")),
+    };
+    let Value::Str(name) = name else {
+        return Err(interp
+            .error("function_name argument given to redis.register_function must be a string"));
+    };
+    let name = String::from_utf8_lossy(&name).into_owned();
+    if name.is_empty() || !name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_') {
+        return Err(interp.error(
+            "Function names can only contain letters, numbers, or underscores(_) and must be at \
+             least one character long",
+        ));
+    }
+    if !matches!(callback, Value::Function(_)) {
+        return Err(
+            interp.error("callback argument given to redis.register_function must be a function")
+        );
+    }
+    let flags = match flags {
+        Value::Nil => vec![],
+        Value::Table(table) => {
+            let mut names = vec![];
+            for flag in table.borrow().array() {
+                match flag.to_bytes() {
+                    Some(flag)
+                        if FUNCTION_FLAGS
+                            .iter()
+                            .any(|known| known.as_bytes() == &flag[..]) =>
+                    {
+                        names.push(String::from_utf8_lossy(&flag).into_owned())
+                    }
+                    _ => return Err(interp.error("unknown flag given")),
+                }
+            }
+            names
+        }
+        _ => return Err(interp.error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    };
+    let description = match description {
+        Value::Nil => None,
+        Value::Str(s) => Some(String::from_utf8_lossy(&s).into_owned()),
+        _ => {
+            return Err(interp
+                .error("description argument given to redis.register_function must be a string"))
+        }
+    };
+    let registered = interp.registered.as_mut().unwrap();
+    if registered.iter().any(|function| function.name == name) {
+        return Err(interp.error("Function already exists in the library"));
+    }
+    registered.push(Registered {
+        name,
+        callback,
+        flags,
+        description,
+    });
+    Ok(vec![])
+}
@@ -0,0 +1,102 @@
+// used_memory: the heap bytes the process holds, counted by wrapping the system allocator the way
+// Redis's zmalloc counts them. Everything is included, so cached scripts and function libraries
+// count toward maxmemory like the dataset does.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+struct Counting;
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn grew(by: usize) {
+    let now = ALLOCATED.fetch_add(by, Ordering::Relaxed) + by;
+    PEAK.fetch_max(now, Ordering::Relaxed);
+}
+
+fn shrank(by: usize) {
+    ALLOCATED.fetch_sub(by, Ordering::Relaxed);
+}
+
+// SAFETY: every call is passed straight to System; only the counters are added.
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            grew(layout.size());
+        }
+        ptr
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            grew(layout.size());
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        shrank(layout.size());
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let moved = unsafe { System.realloc(ptr, layout, new_size) };
+        if !moved.is_null() {
+            match new_size.checked_sub(layout.size()) {
+                Some(more) => grew(more),
+                None => shrank(layout.size() - new_size),
+            }
+        }
+        moved
+    }
+}
+
+pub fn used() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+pub fn peak() -> usize {
+    PEAK.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cluster_testing::Topology, resp::Reply};
+
+    #[test]
+    fn allocations_are_counted() {
+        // Other tests allocate concurrently, so only a large allocation stands out clearly.
+        const SIZE: usize = 64 * 1024 * 1024;
+        let before = used();
+        let block = vec![0u8; SIZE];
+        assert!(used() >= before + SIZE / 2);
+        assert!(peak() >= SIZE);
+        drop(block);
+        assert!(used() < before + SIZE / 2);
+    }
+
+    #[test]
+    fn growing_commands_are_refused_over_maxmemory() {
+        let topology = Topology::builder().start().unwrap();
+        let master = topology.master();
+        master.execute(&["SET", "k", "v"]);
+        master.execute(&["CONFIG", "SET", "maxmemory", "1"]);
+        let oom = Reply::Error("OOM command not allowed when used memory > 'maxmemory'.".into());
+        assert_eq!(master.execute(&["SET", "k", "w"]), oom);
+        assert_eq!(master.execute(&["RPUSH", "list", "a"]), oom);
+        let script = master.execute(&["EVAL", "return redis.pcall('SET', 'k', 'w')", "0"]);
+        assert_eq!(script, oom);
+        assert_eq!(
+            master.execute(&["GET", "k"]),
+            Reply::BulkString(b"v".to_vec())
+        );
+        assert_eq!(master.execute(&["DEL", "k"]), Reply::Integer(1));
+        master.execute(&["CONFIG", "SET", "maxmemory", "0"]);
+        assert_eq!(master.execute(&["SET", "k", "w"]), Reply::ok());
+    }
+}
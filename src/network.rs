@@ -0,0 +1,214 @@
+// Serving clients over TCP: the accept loop, a reader and a writer thread per connection, and
+// the background work every server runs beside them.
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use crate::{
+    client::{ClientInfo, Outbox},
+    command::{self, Context, Session},
+    db::{self, Databases},
+    notify, random, replication,
+    resp::{self, Reply},
+    server::Server,
+};
+
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Follows the master if there is one, runs the active expire cycle, and serves connections
+// from `listener` until accepting fails for good.
+pub fn run(listener: TcpListener, server: Arc<Server>, databases: Databases) -> io::Result<()> {
+    if server.master.is_some() {
+        let (server, databases) = (server.clone(), databases.clone());
+        std::thread::spawn(move || replication::follow_master(&server, &databases));
+    }
+
+    let expire_databases = databases.clone();
+    let expire_server = server.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(ACTIVE_EXPIRE_INTERVAL);
+        // While bulk loading nothing expires, and DEBUG LOADING-MODE OFF announces the backlog.
+        if expire_server.bulk_load.load(Ordering::Relaxed) {
+            continue;
+        }
+        db::active_expire_cycle(&expire_databases);
+        for (index, db) in expire_databases.iter().enumerate() {
+            notify::expired(&expire_server, db, index);
+        }
+    });
+
+    // Held open so there is always one descriptor to give back when the process runs out.
+    let mut spare_fd = File::open("/dev/null").ok();
+    let mut backoff = ACCEPT_BACKOFF_MIN;
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut _stream) => {
+                backoff = ACCEPT_BACKOFF_MIN;
+                let databases = databases.clone();
+                let server = server.clone();
+                std::thread::spawn(|| handle_incoming(_stream, databases, server));
+            }
+            Err(e) => {
+                println!("error: {}", e);
+                if is_fd_exhaustion(&e) {
+                    // Without a free descriptor the pending connection would sit in the backlog
+                    // and keep the listener readable; accept it with the spare and hang up.
+                    drop(spare_fd.take());
+                    if listener.accept().is_ok() {
+                        server.rejected_connections.fetch_add(1, Ordering::Relaxed);
+                    }
+                    spare_fd = File::open("/dev/null").ok();
+                }
+                let jitter = random::below(backoff.as_millis() as usize / 2 + 1);
+                std::thread::sleep(backoff + Duration::from_millis(jitter as u64));
+                backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_incoming(
+    mut stream: TcpStream,
+    databases: Databases,
+    server: Arc<Server>,
+) -> io::Result<()> {
+    println!("accepted new connection");
+    let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+    let outbox = Arc::new(Outbox::default());
+    let info = ClientInfo {
+        id,
+        addr: stream
+            .peer_addr()
+            .map_or(String::new(), |addr| addr.to_string()),
+        laddr: stream
+            .local_addr()
+            .map_or(String::new(), |addr| addr.to_string()),
+        outbox: outbox.clone(),
+        ..Default::default()
+    };
+    let mut session = Session {
+        id,
+        outbox,
+        info: Arc::new(Mutex::new(info)),
+        ..Default::default()
+    };
+    server
+        .clients
+        .lock()
+        .unwrap()
+        .insert(id, session.info.clone());
+    let writer = {
+        let mut stream = stream.try_clone()?;
+        let outbox = session.outbox.clone();
+        std::thread::spawn(move || {
+            while let Some(out) = outbox.take() {
+                if stream.write_all(&out).is_err() {
+                    break;
+                }
+            }
+            // Also ends the read loop when the client was evicted.
+            let _ = stream.shutdown(Shutdown::Both);
+        })
+    };
+    // The cleanup below runs however serve ends, a panicking handler included, so the writer
+    // thread finishes and no registry keeps the client.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        serve(&mut stream, &databases, &server, &mut session)
+    }))
+    .unwrap_or_else(|_| Err(io::Error::other("command handler panicked")));
+    // Whatever is already queued is still written before the writer shuts the socket.
+    session.outbox.close();
+    {
+        let mut registry = server.pubsub.lock().unwrap_or_else(PoisonError::into_inner);
+        for channel in &session.subscriptions {
+            registry.unsubscribe(channel, session.id);
+        }
+    }
+    server
+        .replicas
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .detach(session.id);
+    server
+        .clients
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&session.id);
+    session.unwatch_all(&databases);
+    let _ = writer.join();
+    result
+}
+
+fn serve(
+    stream: &mut TcpStream,
+    databases: &Databases,
+    server: &Server,
+    session: &mut Session,
+) -> io::Result<()> {
+    let mut buf = vec![];
+    let mut chunk = [0; 4096];
+    loop {
+        let bytes_read = stream.read(&mut chunk)?;
+        if bytes_read == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..bytes_read]);
+        let mut out = vec![];
+        let mut consumed = 0;
+        loop {
+            let (args, used) = match resp::parse_command(&buf[consumed..]) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => break,
+                Err(e) => {
+                    Reply::Error(format!("ERR Protocol error: {e}"))
+                        .encode(session.protocol, &mut out);
+                    session.outbox.push(out, None);
+                    return Err(e);
+                }
+            };
+            consumed += used;
+            if args.is_empty() {
+                continue;
+            }
+            session.query_buffer = buf.len() - consumed;
+            let mut ctx = Context {
+                db: &databases[session.db],
+                databases,
+                server,
+                session,
+            };
+            let reply = command::execute(&mut ctx, &args);
+            reply.encode(session.protocol, &mut out);
+            session.commands_processed += 1;
+            session.refresh_info(&args[0]);
+            if session.close_after_reply {
+                session.outbox.push(out, None);
+                return Ok(());
+            }
+        }
+        buf.drain(..consumed);
+        if !out.is_empty() {
+            session.outbox.push(out, None);
+        }
+    }
+    Ok(())
+}
+
+fn is_fd_exhaustion(e: &io::Error) -> bool {
+    const ENFILE: i32 = 23;
+    const EMFILE: i32 = 24;
+    matches!(e.raw_os_error(), Some(ENFILE | EMFILE))
+}
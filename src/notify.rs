@@ -0,0 +1,120 @@
+use crate::{db::ThreadSafeDataMap, pubsub, server::Server};
+
+// Event classes for notify-keyspace-events, one bit per character of the setting.
+pub const KEYSPACE: u32 = 1 << 0; // K
+pub const KEYEVENT: u32 = 1 << 1; // E
+pub const GENERIC: u32 = 1 << 2; // g
+pub const STRING: u32 = 1 << 3; // $
+pub const LIST: u32 = 1 << 4; // l
+pub const SET: u32 = 1 << 5; // s
+pub const HASH: u32 = 1 << 6; // h
+pub const ZSET: u32 = 1 << 7; // z
+pub const EXPIRED: u32 = 1 << 8; // x
+pub const EVICTED: u32 = 1 << 9; // e
+pub const STREAM: u32 = 1 << 10; // t
+                                 // Everything `A` stands for.
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+const CLASSES: &[(u8, u32)] = &[
+    (b'g', GENERIC),
+    (b'$', STRING),
+    (b'l', LIST),
+    (b's', SET),
+    (b'h', HASH),
+    (b'z', ZSET),
+    (b'x', EXPIRED),
+    (b'e', EVICTED),
+    (b't', STREAM),
+    (b'K', KEYSPACE),
+    (b'E', KEYEVENT),
+];
+
+pub fn parse_classes(value: &[u8]) -> Option<u32> {
+    value.iter().try_fold(0, |flags, c| match c {
+        b'A' => Some(flags | ALL),
+        _ => CLASSES
+            .iter()
+            .find(|(name, _)| name == c)
+            .map(|(_, class)| flags | class),
+    })
+}
+
+pub fn format_classes(flags: u32) -> String {
+    let mut out = String::new();
+    let mut rest = flags;
+    if flags & ALL == ALL {
+        out.push('A');
+        rest &= !ALL;
+    }
+    for (name, class) in CLASSES {
+        if rest & class != 0 {
+            out.push(*name as char);
+        }
+    }
+    out
+}
+
+// Publishes `event` on `key` to the keyspace and keyevent channels of database `db`, as far as
+// notify-keyspace-events asks for it.
+pub fn notify(server: &Server, class: u32, event: &str, key: &[u8], db: usize) {
+    let flags = server.config.lock().unwrap().notify_keyspace_events;
+    if flags & class == 0 {
+        return;
+    }
+    if flags & KEYSPACE != 0 {
+        let channel = [format!("__keyspace@{db}__:").as_bytes(), key].concat();
+        pubsub::publish(server, &channel, event.as_bytes());
+    }
+    if flags & KEYEVENT != 0 {
+        let channel = format!("__keyevent@{db}__:{event}");
+        pubsub::publish(server, channel.as_bytes(), key);
+    }
+}
+
+// Announces the keys of database `index` that were deleted for having expired.
+pub fn expired(server: &Server, db: &ThreadSafeDataMap, index: usize) {
+    let keys = db.read().unwrap().take_expired();
+    for key in keys {
+        notify(server, EXPIRED, "expired", &key, index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::{self, Context, Session},
+        db,
+        resp::{self, Reply},
+        server::Server,
+    };
+
+    #[test]
+    fn del_announces_each_deleted_key() {
+        let (server, databases) = (Server::new(0), db::new_databases());
+        let (mut client, mut subscriber) = (Session::default(), Session::default());
+        let run = |session: &mut Session, command: &str| {
+            let args: Vec<Vec<u8>> = command.split(' ').map(|arg| arg.into()).collect();
+            let mut ctx = Context {
+                db: &databases[session.db],
+                databases: &databases,
+                server: &server,
+                session,
+            };
+            command::execute(&mut ctx, &args)
+        };
+        run(&mut client, "CONFIG SET notify-keyspace-events Eg");
+        run(&mut subscriber, "SUBSCRIBE __keyevent@0__:del");
+        run(&mut client, "SET a 1");
+        run(&mut client, "SET b 1");
+        assert_eq!(run(&mut client, "EXISTS a b a c"), Reply::Integer(3));
+        assert_eq!(run(&mut client, "DEL a b c"), Reply::Integer(2));
+        assert_eq!(run(&mut client, "EXISTS a b"), Reply::Integer(0));
+        let mut expected = vec![];
+        for key in ["a", "b"] {
+            let message = ["message", "__keyevent@0__:del", key];
+            let message = message.map(|part| Reply::from(part.as_bytes()));
+            Reply::Array(message.to_vec()).encode(resp::Protocol::Resp2, &mut expected);
+        }
+        assert_eq!(subscriber.outbox.take(), Some(expected));
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+
+use crate::{
+    client::{ClientInfo, Delivery, Outbox, PUBSUB_OUTPUT_LIMIT},
+    resp::{Protocol, Reply},
+    server::Server,
+};
+
+#[derive(Clone)]
+pub struct Subscriber {
+    pub outbox: Arc<Outbox>,
+    // Read on every delivery, so a HELLO while subscribed changes how messages are encoded.
+    pub info: Arc<Mutex<ClientInfo>>,
+}
+
+impl Subscriber {
+    pub fn protocol(&self) -> Protocol {
+        self.info.lock().unwrap().protocol
+    }
+}
+
+// Channel name to subscribers, keyed by client id.
+#[derive(Default)]
+pub struct Registry {
+    channels: HashMap<Vec<u8>, HashMap<u64, Subscriber>>,
+}
+
+impl Registry {
+    pub fn subscribe(&mut self, channel: &[u8], client: u64, subscriber: &Subscriber) {
+        self.channels
+            .entry(channel.to_vec())
+            .or_default()
+            .insert(client, subscriber.clone());
+    }
+    pub fn unsubscribe(&mut self, channel: &[u8], client: u64) {
+        if let Some(subscribers) = self.channels.get_mut(channel) {
+            subscribers.remove(&client);
+            if subscribers.is_empty() {
+                self.channels.remove(channel);
+            }
+        }
+    }
+    // Channels with at least one subscriber.
+    pub fn channels(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.channels.keys()
+    }
+    pub fn count(&self, channel: &[u8]) -> usize {
+        self.channels.get(channel).map_or(0, HashMap::len)
+    }
+    pub fn subscribers(&self, channel: &[u8]) -> Vec<(u64, Subscriber)> {
+        self.channels.get(channel).map_or(vec![], |subscribers| {
+            subscribers
+                .iter()
+                .map(|(client, subscriber)| (*client, subscriber.clone()))
+                .collect()
+        })
+    }
+}
+
+// Queues the message for the channel's subscribers and returns how many there were.
+// Subscribers whose backlog outgrows the pub/sub output limit are disconnected and not counted.
+pub fn publish(server: &Server, channel: &[u8], message: &[u8]) -> i64 {
+    let message = Reply::Push(vec![
+        Reply::from(&b"message"[..]),
+        Reply::from(channel),
+        Reply::from(message),
+    ]);
+    // Encoded at most once per protocol: a `*3` array for RESP2 subscribers, a `>3` push for
+    // RESP3 ones.
+    let (mut resp2, mut resp3) = (None, None);
+    let mut registry = server.pubsub.lock().unwrap();
+    let mut receivers = 0;
+    for (client, subscriber) in registry.subscribers(channel) {
+        let protocol = subscriber.protocol();
+        let frame = match protocol {
+            Protocol::Resp2 => &mut resp2,
+            Protocol::Resp3 => &mut resp3,
+        };
+        let frame = frame.get_or_insert_with(|| {
+            let mut out = vec![];
+            message.encode(protocol, &mut out);
+            out
+        });
+        let outbox = &subscriber.outbox;
+        match outbox.push(frame.clone(), Some(PUBSUB_OUTPUT_LIMIT)) {
+            Delivery::Queued => {
+                receivers += 1;
+                continue;
+            }
+            Delivery::Closed => {
+                registry.unsubscribe(channel, client);
+                continue;
+            }
+            Delivery::Evicted => {}
+        }
+        server
+            .pubsub_clients_evicted
+            .fetch_add(1, Ordering::Relaxed);
+        println!(
+            "Client id={client} evicted for overcoming the pubsub output buffer limit \
+             ({} bytes pending, channel '{}')",
+            outbox.backlog(),
+            String::from_utf8_lossy(channel)
+        );
+        registry.unsubscribe(channel, client);
+    }
+    receivers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscriber(protocol: Protocol) -> Subscriber {
+        Subscriber {
+            outbox: Arc::default(),
+            info: Arc::new(Mutex::new(ClientInfo {
+                protocol,
+                ..ClientInfo::default()
+            })),
+        }
+    }
+
+    #[test]
+    fn publish_encodes_per_protocol() {
+        let server = Server::new(0);
+        let (resp2, resp3) = (subscriber(Protocol::Resp2), subscriber(Protocol::Resp3));
+        let mut registry = server.pubsub.lock().unwrap();
+        registry.subscribe(b"news", 1, &resp2);
+        registry.subscribe(b"news", 2, &resp3);
+        drop(registry);
+        assert_eq!(publish(&server, b"news", b"hi"), 2);
+        let body = b"$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n";
+        assert_eq!(resp2.outbox.take(), Some([&b"*3\r\n"[..], body].concat()));
+        assert_eq!(resp3.outbox.take(), Some([&b">3\r\n"[..], body].concat()));
+        assert_eq!(publish(&server, b"other", b"hi"), 0);
+    }
+}
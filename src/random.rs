@@ -0,0 +1,22 @@
+use std::{cell::Cell, collections::hash_map::RandomState, hash::BuildHasher, time::SystemTime};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(RandomState::new().hash_one(SystemTime::now()) | 1);
+}
+
+// xorshift64*; plenty for sampling keys and members, not for anything security related.
+pub fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    })
+}
+
+// Uniform index in 0..n, n must be non-zero.
+pub fn below(n: usize) -> usize {
+    (next_u64() % n as u64) as usize
+}
@@ -0,0 +1,863 @@
+// RDB value serialization, shared by DUMP/RESTORE. Values are written with the simplest
+// encodings every Redis version understands; on load the compact encodings a real Redis emits
+// (integer and LZF strings, intsets, listpacks) are accepted as well. Streams have no simple
+// encoding, so they are written as listpacks keyed by their master entry ID, followed by their
+// consumer groups.
+
+use crate::{
+    db::{self, ThreadSafeDataMap, Value},
+    types::{
+        hash::Hash,
+        set::Set,
+        stream::{Consumer, ConsumerGroup, PendingEntry, Stream, StreamId},
+        zset::{AddFlags, SortedSet},
+    },
+};
+
+pub const RDB_VERSION: u16 = 11;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// Precedes a function library's code.
+const OPCODE_FUNCTION2: u8 = 245;
+const OPCODE_SLOT_INFO: u8 = 244;
+const OPCODE_IDLE: u8 = 248;
+const OPCODE_FREQ: u8 = 249;
+const OPCODE_AUX: u8 = 250;
+const OPCODE_RESIZEDB: u8 = 251;
+const OPCODE_EXPIRETIME_MS: u8 = 252;
+const OPCODE_EXPIRETIME: u8 = 253;
+const OPCODE_SELECTDB: u8 = 254;
+const OPCODE_EOF: u8 = 255;
+
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+// Entries per stream listpack, stream-node-max-entries' default.
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+const ENC_INT8: u64 = 0;
+const ENC_INT16: u64 = 1;
+const ENC_INT32: u64 = 2;
+const ENC_LZF: u64 = 3;
+
+// CRC-64/Jones as used by Redis (reflected, zero init, no final xor).
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+    for byte in data {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+pub fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.push(0x40 | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+pub fn write_string(out: &mut Vec<u8>, data: &[u8]) {
+    write_length(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+// Type byte followed by the object body.
+pub fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::String(data) => {
+            out.push(TYPE_STRING);
+            write_string(out, data);
+        }
+        Value::List(list) => {
+            out.push(TYPE_LIST);
+            write_length(out, list.len() as u64);
+            for item in list.iter() {
+                write_string(out, item);
+            }
+        }
+        Value::Set(set) => {
+            out.push(TYPE_SET);
+            let members = set.members();
+            write_length(out, members.len() as u64);
+            for member in members {
+                write_string(out, &member);
+            }
+        }
+        Value::SortedSet(zset) => {
+            out.push(TYPE_ZSET_2);
+            let entries = zset.entries();
+            write_length(out, entries.len() as u64);
+            for (member, score) in entries {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Hash(hash) => {
+            out.push(TYPE_HASH);
+            // Expired fields are skipped, so they are not counted by len().
+            let fields: Vec<_> = hash.iter().collect();
+            write_length(out, fields.len() as u64);
+            for (field, value) in fields {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        Value::Stream(stream) => {
+            out.push(TYPE_STREAM_LISTPACKS);
+            write_stream(out, stream);
+        }
+    }
+}
+
+enum ListpackEntry<'a> {
+    Int(i64),
+    Str(&'a [u8]),
+}
+
+fn listpack(entries: &[ListpackEntry]) -> Vec<u8> {
+    let mut out = vec![0; 6];
+    for entry in entries {
+        let start = out.len();
+        match entry {
+            ListpackEntry::Int(value @ 0..=127) => out.push(*value as u8),
+            ListpackEntry::Int(value @ -4096..=4095) => {
+                out.push(0xC0 | ((*value >> 8) as u8 & 0x1F));
+                out.push(*value as u8);
+            }
+            ListpackEntry::Int(value) => {
+                out.push(0xF4);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            ListpackEntry::Str(data) if data.len() < 1 << 6 => {
+                out.push(0x80 | data.len() as u8);
+                out.extend_from_slice(data);
+            }
+            ListpackEntry::Str(data) if data.len() < 1 << 12 => {
+                out.push(0xE0 | (data.len() >> 8) as u8);
+                out.push(data.len() as u8);
+                out.extend_from_slice(data);
+            }
+            ListpackEntry::Str(data) => {
+                out.push(0xF0);
+                out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                out.extend_from_slice(data);
+            }
+        }
+        // The entry length again, 7 bits per byte, for walking the listpack backwards.
+        let len = out.len() - start;
+        let groups = (0..5)
+            .rev()
+            .filter(|group| *group == 0 || len >> (7 * group) > 0);
+        for (idx, group) in groups.enumerate() {
+            let bits = (len >> (7 * group)) as u8 & 0x7F;
+            out.push(if idx == 0 { bits } else { bits | 0x80 });
+        }
+    }
+    out.push(0xFF);
+    let total = out.len() as u32;
+    out[..4].copy_from_slice(&total.to_le_bytes());
+    let count = entries.len().min(u16::MAX as usize) as u16;
+    out[4..6].copy_from_slice(&count.to_le_bytes());
+    out
+}
+
+fn write_stream_id(out: &mut Vec<u8>, id: StreamId) {
+    write_length(out, id.ms);
+    write_length(out, id.seq);
+}
+
+// Pending entries lists store IDs as 128 bit big endian integers.
+fn write_raw_stream_id(out: &mut Vec<u8>, id: StreamId) {
+    out.extend_from_slice(&id.ms.to_be_bytes());
+    out.extend_from_slice(&id.seq.to_be_bytes());
+}
+
+// Each node is a listpack starting with a master entry (entry count, deleted count, the field
+// names of its first entry), followed by the entries as deltas from the master ID.
+fn write_stream(out: &mut Vec<u8>, stream: &Stream) {
+    // Deleted entries are left out rather than written with the deleted flag.
+    let entries: Vec<_> = stream.iter().collect();
+    let nodes: Vec<_> = entries.chunks(STREAM_NODE_MAX_ENTRIES).collect();
+    write_length(out, nodes.len() as u64);
+    for node in nodes {
+        let master = node[0].id;
+        let master_fields: Vec<&[u8]> = node[0]
+            .fields
+            .iter()
+            .step_by(2)
+            .map(Vec::as_slice)
+            .collect();
+        let mut items = vec![
+            ListpackEntry::Int(node.len() as i64),
+            ListpackEntry::Int(0),
+            ListpackEntry::Int(master_fields.len() as i64),
+        ];
+        items.extend(master_fields.iter().map(|field| ListpackEntry::Str(field)));
+        items.push(ListpackEntry::Int(0));
+        for entry in node.iter() {
+            let same_fields = entry.fields.len() == master_fields.len() * 2
+                && entry
+                    .fields
+                    .iter()
+                    .step_by(2)
+                    .eq(master_fields.iter().copied());
+            let flags = if same_fields {
+                STREAM_ITEM_FLAG_SAMEFIELDS
+            } else {
+                0
+            };
+            items.push(ListpackEntry::Int(flags));
+            items.push(ListpackEntry::Int((entry.id.ms - master.ms) as i64));
+            items.push(ListpackEntry::Int(
+                entry.id.seq.wrapping_sub(master.seq) as i64
+            ));
+            let pairs = entry.fields.len() / 2;
+            if same_fields {
+                let values = entry.fields.iter().skip(1).step_by(2);
+                items.extend(values.map(|value| ListpackEntry::Str(value)));
+                items.push(ListpackEntry::Int(pairs as i64 + 3));
+            } else {
+                items.push(ListpackEntry::Int(pairs as i64));
+                items.extend(entry.fields.iter().map(|item| ListpackEntry::Str(item)));
+                items.push(ListpackEntry::Int(pairs as i64 * 2 + 4));
+            }
+        }
+        let mut key = master.ms.to_be_bytes().to_vec();
+        key.extend_from_slice(&master.seq.to_be_bytes());
+        write_string(out, &key);
+        write_string(out, &listpack(&items));
+    }
+    write_length(out, stream.len() as u64);
+    write_stream_id(out, stream.last_id());
+    write_length(out, stream.groups().count() as u64);
+    for (name, group) in stream.groups() {
+        write_string(out, name);
+        write_stream_id(out, group.last_delivered);
+        write_length(out, group.pending.len() as u64);
+        for (id, entry) in &group.pending {
+            write_raw_stream_id(out, *id);
+            out.extend_from_slice(&entry.delivery_time.to_le_bytes());
+            write_length(out, entry.delivery_count);
+        }
+        write_length(out, group.consumers.len() as u64);
+        for (name, consumer) in &group.consumers {
+            write_string(out, name);
+            out.extend_from_slice(&consumer.seen_time.to_le_bytes());
+            write_length(out, consumer.pending.len() as u64);
+            for id in &consumer.pending {
+                write_raw_stream_id(out, *id);
+            }
+        }
+    }
+}
+
+// DUMP format: serialized object, 2 byte RDB version, CRC64 of everything before it.
+pub fn dump(value: &Value) -> Vec<u8> {
+    let mut out = vec![];
+    write_value(&mut out, value);
+    seal(out)
+}
+
+// FUNCTION DUMP format: the code of each library behind its opcode, then the same footer.
+pub fn dump_functions<'a>(codes: impl Iterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut out = vec![];
+    for code in codes {
+        out.push(OPCODE_FUNCTION2);
+        write_string(&mut out, code);
+    }
+    seal(out)
+}
+
+// A whole RDB file, as sent to a replica on full resync: the function libraries, then every
+// non-empty database with its keys and their deadlines.
+pub fn snapshot<'a>(
+    databases: &[ThreadSafeDataMap],
+    functions: impl Iterator<Item = &'a [u8]>,
+) -> Vec<u8> {
+    let mut out = format!("REDIS{RDB_VERSION:04}").into_bytes();
+    for code in functions {
+        out.push(OPCODE_FUNCTION2);
+        write_string(&mut out, code);
+    }
+    for (index, db) in databases.iter().enumerate() {
+        let (mut keys, mut expires) = (0, 0);
+        let mut entries = vec![];
+        db::for_each_entry(db, .., |key, value, deadline| {
+            keys += 1;
+            if let Some(deadline) = deadline {
+                expires += 1;
+                entries.push(OPCODE_EXPIRETIME_MS);
+                entries.extend_from_slice(&deadline.to_le_bytes());
+            }
+            // The key goes between the type byte and the object body.
+            let mut object = vec![];
+            write_value(&mut object, &value.data);
+            entries.push(object[0]);
+            write_string(&mut entries, key);
+            entries.extend_from_slice(&object[1..]);
+        });
+        if keys == 0 {
+            continue;
+        }
+        out.push(OPCODE_SELECTDB);
+        write_length(&mut out, index as u64);
+        out.push(OPCODE_RESIZEDB);
+        write_length(&mut out, keys);
+        write_length(&mut out, expires);
+        out.extend_from_slice(&entries);
+    }
+    out.push(OPCODE_EOF);
+    let crc = crc64(0, &out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+// What a whole RDB file holds: its function libraries, and its keys by database index with
+// their deadlines.
+#[derive(Default)]
+pub struct Snapshot {
+    pub functions: Vec<Vec<u8>>,
+    pub entries: Vec<(usize, Vec<u8>, Value, Option<i64>)>,
+}
+
+pub fn load(data: &[u8]) -> Option<Snapshot> {
+    let (body, crc) = data.split_at(data.len().checked_sub(8)?);
+    let crc = u64::from_le_bytes(crc.try_into().ok()?);
+    let version = std::str::from_utf8(body.strip_prefix(b"REDIS")?.get(..4)?).ok()?;
+    version.parse::<u16>().ok()?;
+    // A zero checksum means the producer had checksums disabled.
+    if crc != 0 && crc != crc64(0, body) {
+        return None;
+    }
+    let mut reader = Reader::new(&body[9..]);
+    let mut snapshot = Snapshot::default();
+    let (mut db, mut deadline) = (0, None);
+    loop {
+        match reader.byte()? {
+            OPCODE_EOF => return Some(snapshot),
+            OPCODE_SELECTDB => db = reader.length()? as usize,
+            OPCODE_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    reader.length()?;
+                }
+            }
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_FUNCTION2 => snapshot.functions.push(reader.string()?),
+            OPCODE_EXPIRETIME_MS => {
+                deadline = Some(i64::from_le_bytes(reader.take(8)?.try_into().ok()?));
+            }
+            OPCODE_EXPIRETIME => {
+                let seconds = u32::from_le_bytes(reader.take(4)?.try_into().ok()?);
+                deadline = Some(seconds as i64 * 1000);
+            }
+            OPCODE_IDLE => {
+                reader.length()?;
+            }
+            OPCODE_FREQ => {
+                reader.byte()?;
+            }
+            value_type => {
+                let key = reader.string()?;
+                let value = reader.value(value_type)?;
+                snapshot.entries.push((db, key, value, deadline.take()));
+            }
+        }
+    }
+}
+
+fn seal(mut out: Vec<u8>) -> Vec<u8> {
+    out.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+// The library codes in the body of a FUNCTION DUMP payload.
+pub fn read_functions(body: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(body);
+    let mut codes = vec![];
+    while reader.pos < body.len() {
+        if reader.byte()? != OPCODE_FUNCTION2 {
+            return None;
+        }
+        codes.push(reader.string()?);
+    }
+    Some(codes)
+}
+
+// Returns the object part of a DUMP payload if its version and checksum are acceptable.
+pub fn verify_dump(payload: &[u8]) -> Option<&[u8]> {
+    if payload.len() < 10 {
+        return None;
+    }
+    let (body, footer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let crc = u64::from_le_bytes(footer[2..].try_into().unwrap());
+    (version <= RDB_VERSION && crc == crc64(0, &payload[..payload.len() - 8])).then_some(body)
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+enum Length {
+    Plain(u64),
+    Encoded(u64),
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+    pub fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+    fn length_or_encoding(&mut self) -> Option<Length> {
+        let first = self.byte()?;
+        Some(match first >> 6 {
+            0 => Length::Plain((first & 0x3F) as u64),
+            1 => Length::Plain((((first & 0x3F) as u64) << 8) | self.byte()? as u64),
+            2 if first == 0x80 => {
+                Length::Plain(u32::from_be_bytes(self.take(4)?.try_into().ok()?) as u64)
+            }
+            2 if first == 0x81 => Length::Plain(u64::from_be_bytes(self.take(8)?.try_into().ok()?)),
+            2 => return None,
+            _ => Length::Encoded((first & 0x3F) as u64),
+        })
+    }
+    pub fn length(&mut self) -> Option<u64> {
+        match self.length_or_encoding()? {
+            Length::Plain(len) => Some(len),
+            Length::Encoded(_) => None,
+        }
+    }
+    pub fn string(&mut self) -> Option<Vec<u8>> {
+        match self.length_or_encoding()? {
+            Length::Plain(len) => self.take(len as usize).map(<[u8]>::to_vec),
+            Length::Encoded(ENC_INT8) => Some((self.byte()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENC_INT16) => Some(
+                i16::from_le_bytes(self.take(2)?.try_into().ok()?)
+                    .to_string()
+                    .into_bytes(),
+            ),
+            Length::Encoded(ENC_INT32) => Some(
+                i32::from_le_bytes(self.take(4)?.try_into().ok()?)
+                    .to_string()
+                    .into_bytes(),
+            ),
+            Length::Encoded(ENC_LZF) => {
+                let compressed_len = self.length()? as usize;
+                let len = self.length()? as usize;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            Length::Encoded(_) => None,
+        }
+    }
+    fn binary_double(&mut self) -> Option<f64> {
+        Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+    // Pre-ZSET_2 scores: a length byte with 253/254/255 standing for nan/+inf/-inf.
+    fn string_double(&mut self) -> Option<f64> {
+        match self.byte()? {
+            253 => Some(f64::NAN),
+            254 => Some(f64::INFINITY),
+            255 => Some(f64::NEG_INFINITY),
+            len => std::str::from_utf8(self.take(len as usize)?)
+                .ok()?
+                .parse()
+                .ok(),
+        }
+    }
+    // Empty aggregates are refused: the keyspace never holds one, as a key goes with its last
+    // element.
+    pub fn value(&mut self, value_type: u8) -> Option<Value> {
+        self.any_value(value_type).filter(|value| match value {
+            Value::List(list) => !list.is_empty(),
+            Value::Set(set) => !set.is_empty(),
+            Value::SortedSet(zset) => zset.len() > 0,
+            Value::Hash(hash) => !hash.is_empty(),
+            Value::String(_) | Value::Stream(_) => true,
+        })
+    }
+    fn any_value(&mut self, value_type: u8) -> Option<Value> {
+        match value_type {
+            TYPE_STRING => self.string().map(|data| Value::String(data.into())),
+            TYPE_LIST => {
+                let list = (0..self.length()?)
+                    .map(|_| self.string())
+                    .collect::<Option<_>>()?;
+                Some(Value::List(list))
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                let mut items = vec![];
+                for _ in 0..self.length()? {
+                    match self.length()? {
+                        QUICKLIST_NODE_PLAIN => items.push(self.string()?),
+                        QUICKLIST_NODE_PACKED => items.extend(listpack_entries(&self.string()?)?),
+                        _ => return None,
+                    }
+                }
+                Some(Value::List(items.into_iter().collect()))
+            }
+            TYPE_SET => {
+                let mut set = Set::new();
+                for _ in 0..self.length()? {
+                    set.insert(&self.string()?);
+                }
+                Some(Value::Set(set))
+            }
+            TYPE_SET_INTSET => {
+                let mut set = Set::new();
+                for member in intset_entries(&self.string()?)? {
+                    set.insert(member.to_string().as_bytes());
+                }
+                Some(Value::Set(set))
+            }
+            TYPE_SET_LISTPACK => {
+                let mut set = Set::new();
+                for member in listpack_entries(&self.string()?)? {
+                    set.insert(&member);
+                }
+                Some(Value::Set(set))
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let mut zset = SortedSet::new();
+                for _ in 0..self.length()? {
+                    let member = self.string()?;
+                    let score = if value_type == TYPE_ZSET_2 {
+                        self.binary_double()?
+                    } else {
+                        self.string_double()?
+                    };
+                    if score.is_nan() {
+                        return None;
+                    }
+                    zset.add(&member, score, AddFlags::default());
+                }
+                Some(Value::SortedSet(zset))
+            }
+            TYPE_ZSET_LISTPACK => {
+                let mut zset = SortedSet::new();
+                let entries = listpack_entries(&self.string()?)?;
+                for pair in entries.chunks_exact(2) {
+                    let score = std::str::from_utf8(&pair[1]).ok()?.parse().ok()?;
+                    zset.add(&pair[0], score, AddFlags::default());
+                }
+                Some(Value::SortedSet(zset))
+            }
+            TYPE_HASH => {
+                let mut hash = Hash::new();
+                for _ in 0..self.length()? {
+                    let field = self.string()?;
+                    hash.insert(&field, &self.string()?);
+                }
+                Some(Value::Hash(hash))
+            }
+            TYPE_HASH_LISTPACK => {
+                let mut hash = Hash::new();
+                let entries = listpack_entries(&self.string()?)?;
+                for pair in entries.chunks_exact(2) {
+                    hash.insert(&pair[0], &pair[1]);
+                }
+                Some(Value::Hash(hash))
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                self.stream(value_type).map(Value::Stream)
+            }
+            _ => None,
+        }
+    }
+    fn stream_id(&mut self) -> Option<StreamId> {
+        Some(StreamId::new(self.length()?, self.length()?))
+    }
+    fn raw_stream_id(&mut self) -> Option<StreamId> {
+        Some(StreamId::new(
+            u64::from_be_bytes(self.take(8)?.try_into().ok()?),
+            u64::from_be_bytes(self.take(8)?.try_into().ok()?),
+        ))
+    }
+    fn millis(&mut self) -> Option<i64> {
+        Some(i64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+    // The version 2 metadata and the groups' entries-read counters are not kept.
+    fn stream(&mut self, ty: u8) -> Option<Stream> {
+        let mut stream = Stream::new();
+        for _ in 0..self.length()? {
+            let key = self.string()?;
+            let master = StreamId::new(
+                u64::from_be_bytes(key.get(..8)?.try_into().ok()?),
+                u64::from_be_bytes(key.get(8..16)?.try_into().ok()?),
+            );
+            let items = listpack_entries(&self.string()?)?;
+            let mut items = items.into_iter();
+            let int = |items: &mut std::vec::IntoIter<Vec<u8>>| -> Option<i64> {
+                std::str::from_utf8(&items.next()?).ok()?.parse().ok()
+            };
+            int(&mut items)?; // live entries
+            int(&mut items)?; // deleted entries
+            let master_fields: Vec<Vec<u8>> = (0..int(&mut items)?)
+                .map(|_| items.next())
+                .collect::<Option<_>>()?;
+            int(&mut items)?; // master entry terminator
+            while let Some(flags) = int(&mut items) {
+                let id = StreamId::new(
+                    master.ms.checked_add(int(&mut items)? as u64)?,
+                    master.seq.wrapping_add(int(&mut items)? as u64),
+                );
+                let mut fields = vec![];
+                if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+                    for field in &master_fields {
+                        fields.push(field.clone());
+                        fields.push(items.next()?);
+                    }
+                } else {
+                    for _ in 0..int(&mut items)? * 2 {
+                        fields.push(items.next()?);
+                    }
+                }
+                int(&mut items)?; // lp-count
+                if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                    if stream.last_id() >= id && stream.len() > 0 {
+                        return None;
+                    }
+                    stream.append(id, fields);
+                }
+            }
+        }
+        self.length()?; // entry count
+        let last_id = self.stream_id()?;
+        if stream.len() > 0 && stream.last_id() > last_id {
+            return None;
+        }
+        stream.set_last_id(last_id);
+        if ty >= TYPE_STREAM_LISTPACKS_2 {
+            self.stream_id()?; // first ID
+            self.stream_id()?; // max deleted entry ID
+            self.length()?; // entries added
+        }
+        for _ in 0..self.length()? {
+            let name = self.string()?;
+            let mut group = ConsumerGroup::new(self.stream_id()?);
+            if ty >= TYPE_STREAM_LISTPACKS_2 {
+                self.length()?; // entries read
+            }
+            for _ in 0..self.length()? {
+                let id = self.raw_stream_id()?;
+                let entry = PendingEntry {
+                    // Filled in from the owning consumer's list below.
+                    consumer: vec![],
+                    delivery_time: self.millis()?,
+                    delivery_count: self.length()?,
+                };
+                group.pending.insert(id, entry);
+            }
+            for _ in 0..self.length()? {
+                let name = self.string()?;
+                let mut consumer = Consumer::new(self.millis()?);
+                consumer.active_time = if ty >= TYPE_STREAM_LISTPACKS_3 {
+                    self.millis()?
+                } else {
+                    consumer.seen_time
+                };
+                for _ in 0..self.length()? {
+                    let id = self.raw_stream_id()?;
+                    group.pending.get_mut(&id)?.consumer = name.clone();
+                    consumer.pending.insert(id);
+                }
+                group.consumers.insert(name, consumer);
+            }
+            if !group.is_well_formed() || !stream.create_group(&name, group) {
+                return None;
+            }
+        }
+        Some(stream)
+    }
+}
+
+// Most an LZF back reference can expand to: 264 bytes from 3.
+const LZF_MAX_RATIO: usize = 88;
+
+fn lzf_decompress(input: &[u8], expected_len: usize) -> Option<Vec<u8>> {
+    // The length comes from the payload, so it is only trusted as far as the input could reach,
+    // and the output grows as it is produced.
+    if expected_len > input.len().saturating_mul(LZF_MAX_RATIO) {
+        return None;
+    }
+    let mut out = Vec::new();
+    let mut idx = 0;
+    while idx < input.len() {
+        if out.len() > expected_len {
+            return None;
+        }
+        let ctrl = input[idx] as usize;
+        idx += 1;
+        if ctrl < 32 {
+            out.extend_from_slice(input.get(idx..idx + ctrl + 1)?);
+            idx += ctrl + 1;
+        } else {
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += *input.get(idx)? as usize;
+                idx += 1;
+            }
+            let back = ((ctrl & 0x1F) << 8) + *input.get(idx)? as usize + 1;
+            idx += 1;
+            let start = out.len().checked_sub(back)?;
+            // Byte by byte: the reference may overlap the bytes being produced.
+            for offset in 0..len + 2 {
+                out.push(out[start + offset]);
+            }
+        }
+    }
+    (out.len() == expected_len).then_some(out)
+}
+
+fn intset_entries(blob: &[u8]) -> Option<Vec<i64>> {
+    let mut reader = Reader::new(blob);
+    let width = u32::from_le_bytes(reader.take(4)?.try_into().ok()?) as usize;
+    let len = u32::from_le_bytes(reader.take(4)?.try_into().ok()?);
+    (0..len)
+        .map(|_| {
+            let bytes = reader.take(width)?;
+            match width {
+                2 => Some(i16::from_le_bytes(bytes.try_into().ok()?) as i64),
+                4 => Some(i32::from_le_bytes(bytes.try_into().ok()?) as i64),
+                8 => Some(i64::from_le_bytes(bytes.try_into().ok()?)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Sign-extends the low `bits` of `value`.
+fn signed(value: u64, bits: u32) -> i64 {
+    ((value << (64 - bits)) as i64) >> (64 - bits)
+}
+
+pub fn listpack_entries(blob: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(blob);
+    reader.take(6)?; // total bytes + element count
+    let mut entries = vec![];
+    loop {
+        let start = reader.pos;
+        let first = reader.byte()?;
+        let int = |reader: &mut Reader, width: usize| -> Option<i64> {
+            let mut buf = [0u8; 8];
+            buf[..width].copy_from_slice(reader.take(width)?);
+            Some(signed(u64::from_le_bytes(buf), width as u32 * 8))
+        };
+        let entry = match first {
+            0xFF => return Some(entries),
+            b if b & 0x80 == 0 => (b & 0x7F).to_string().into_bytes(),
+            b if b & 0xC0 == 0x80 => reader.take((b & 0x3F) as usize)?.to_vec(),
+            b if b & 0xE0 == 0xC0 => {
+                let value = (((b & 0x1F) as u64) << 8) | reader.byte()? as u64;
+                signed(value, 13).to_string().into_bytes()
+            }
+            b if b & 0xF0 == 0xE0 => {
+                let len = (((b & 0x0F) as usize) << 8) | reader.byte()? as usize;
+                reader.take(len)?.to_vec()
+            }
+            0xF0 => {
+                let len = u32::from_le_bytes(reader.take(4)?.try_into().ok()?) as usize;
+                reader.take(len)?.to_vec()
+            }
+            0xF1 => int(&mut reader, 2)?.to_string().into_bytes(),
+            0xF2 => int(&mut reader, 3)?.to_string().into_bytes(),
+            0xF3 => int(&mut reader, 4)?.to_string().into_bytes(),
+            0xF4 => int(&mut reader, 8)?.to_string().into_bytes(),
+            _ => return None,
+        };
+        let entry_len = reader.pos - start;
+        let backlen_size = match entry_len {
+            0..=127 => 1,
+            128..=16383 => 2,
+            16384..=2097151 => 3,
+            2097152..=268435455 => 4,
+            _ => 5,
+        };
+        reader.take(backlen_size)?;
+        entries.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lzf_lengths_are_checked() {
+        // A literal run of "ab", then a back reference copying them twice more.
+        let input = [1, b'a', b'b', 0x40, 1];
+        assert_eq!(lzf_decompress(&input, 6).as_deref(), Some(&b"ababab"[..]));
+        assert_eq!(lzf_decompress(&input, 5), None);
+        assert_eq!(lzf_decompress(&input, 7), None);
+        assert_eq!(lzf_decompress(&input, usize::MAX), None);
+        assert_eq!(lzf_decompress(&input, 1 << 40), None);
+    }
+
+    #[test]
+    fn dumps_need_their_checksum() {
+        let mut payload = vec![0, 1, b'x'];
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        let crc = crc64(0, &payload);
+        payload.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(verify_dump(&payload), Some(&[0, 1, b'x'][..]));
+        let len = payload.len();
+        payload[len - 8..].fill(0);
+        assert_eq!(verify_dump(&payload), None);
+    }
+
+    #[test]
+    fn empty_aggregates_are_refused() {
+        for value_type in [TYPE_LIST, TYPE_SET, TYPE_ZSET, TYPE_HASH, TYPE_ZSET_2] {
+            assert!(Reader::new(&[0]).value(value_type).is_none());
+        }
+        let one = Reader::new(&[1, 1, b'a']).value(TYPE_LIST);
+        assert!(matches!(one, Some(Value::List(list)) if list.len() == 1));
+    }
+}
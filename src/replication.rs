@@ -0,0 +1,476 @@
+// Both sides of replication: the replicas a master feeds, and a replica's link to the master
+// given with --replicaof.
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Delivery, Outbox, REPLICA_OUTPUT_LIMIT},
+    command::{self, Context, Session},
+    db::{self, Databases, MapValue},
+    random, rdb, resp,
+    scripting::{self, Libraries},
+    server::Server,
+};
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// How often a replica reports its offset to the master unasked.
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Where a replica is in the master's stream.
+pub struct MasterLink {
+    pub replid: String,
+    pub offset: u64,
+}
+
+pub struct Replica {
+    pub id: u64,
+    pub ip: String,
+    pub port: u16,
+    pub outbox: Arc<Outbox>,
+    // The offset it last acknowledged with REPLCONF ACK.
+    pub ack: u64,
+}
+
+// The replication stream: its ID, how many bytes of it have been sent, and who receives it.
+pub struct Replicas {
+    pub replid: String,
+    pub offset: u64,
+    // Database the stream has selected, None until a command needs one.
+    db: Option<usize>,
+    list: Vec<Replica>,
+}
+
+impl Default for Replicas {
+    fn default() -> Self {
+        Self {
+            replid: (0..5)
+                .map(|_| format!("{:08x}", random::next_u64() as u32))
+                .collect(),
+            offset: 0,
+            db: None,
+            list: vec![],
+        }
+    }
+}
+
+impl Replicas {
+    pub fn attach(&mut self, replica: Replica) {
+        // The new replica starts from a snapshot with no database selected.
+        self.db = None;
+        self.list.push(replica);
+    }
+    pub fn detach(&mut self, id: u64) {
+        self.list.retain(|replica| replica.id != id);
+    }
+    pub fn acknowledge(&mut self, id: u64, offset: u64) {
+        if let Some(replica) = self.list.iter_mut().find(|replica| replica.id == id) {
+            replica.ack = offset;
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Replica> {
+        self.list.iter()
+    }
+    // Sends a write to every replica, preceded by a SELECT when it is for another database.
+    // Replicas that fell too far behind are dropped.
+    pub fn feed(&mut self, db: usize, args: &[Vec<u8>]) {
+        if self.list.is_empty() {
+            return;
+        }
+        let mut out = vec![];
+        if self.db != Some(db) {
+            out = resp::encode_command(&[b"SELECT".to_vec(), db.to_string().into_bytes()]);
+            self.db = Some(db);
+        }
+        out.extend_from_slice(&resp::encode_command(args));
+        self.offset += out.len() as u64;
+        self.list.retain(|replica| {
+            replica.outbox.push(out.clone(), Some(REPLICA_OUTPUT_LIMIT)) == Delivery::Queued
+        });
+    }
+}
+
+// Keeps a link to the master for the life of the process, reconnecting whenever it drops.
+pub fn follow_master(server: &Server, databases: &Databases) {
+    let Some((host, port)) = &server.master else {
+        return;
+    };
+    loop {
+        match handshake(server, host, *port) {
+            Ok((master, resync)) => {
+                println!("replication handshake with {host}:{port} completed");
+                if let Err(e) = replicate(server, databases, master, &resync) {
+                    println!("lost connection with master {host}:{port}: {e}");
+                }
+                *server.master_link.lock().unwrap() = None;
+            }
+            Err(e) => println!("replication handshake with {host}:{port} failed: {e}"),
+        }
+        std::thread::sleep(RECONNECT_INTERVAL);
+    }
+}
+
+// Loads the snapshot that follows +FULLRESYNC, then applies the stream of writes after it
+// without replying, except to REPLCONF GETACK. The offset is also acknowledged every
+// ACK_INTERVAL, so the master's INFO shows how far each replica got.
+fn replicate(
+    server: &Server,
+    databases: &Databases,
+    mut master: BufReader<TcpStream>,
+    resync: &str,
+) -> io::Result<()> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let (replid, offset) = match resync.split(' ').collect::<Vec<_>>()[..] {
+        [_, replid, offset] => (replid.to_string(), offset.parse().ok()),
+        _ => (String::new(), None),
+    };
+    let mut offset = offset.ok_or_else(|| invalid("malformed FULLRESYNC reply"))?;
+    let mut header = String::new();
+    master.read_line(&mut header)?;
+    let len: usize = header
+        .trim_end()
+        .strip_prefix('$')
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| invalid("malformed snapshot header"))?;
+    let mut data = vec![0; len];
+    master.read_exact(&mut data)?;
+    let snapshot = rdb::load(&data).ok_or_else(|| invalid("unreadable snapshot"))?;
+    load(server, databases, snapshot);
+    *server.master_link.lock().unwrap() = Some(MasterLink { replid, offset });
+    acknowledge(master.get_mut(), offset)?;
+    let mut acknowledged = Instant::now();
+    master.get_ref().set_read_timeout(Some(ACK_INTERVAL))?;
+
+    let mut session = Session::default();
+    let mut buf = vec![];
+    let mut chunk = [0; 4096];
+    loop {
+        let bytes_read = match master.read(&mut chunk) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(bytes_read) => bytes_read,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                0
+            }
+            Err(e) => return Err(e),
+        };
+        buf.extend_from_slice(&chunk[..bytes_read]);
+        let mut consumed = 0;
+        while let Some((args, used)) = resp::parse_command(&buf[consumed..])? {
+            consumed += used;
+            let getack = args.len() == 3
+                && args[0].eq_ignore_ascii_case(b"REPLCONF")
+                && args[1].eq_ignore_ascii_case(b"GETACK");
+            if getack {
+                // The offset acknowledged is the one before this command.
+                acknowledge(master.get_mut(), offset)?;
+                acknowledged = Instant::now();
+            } else if !args.is_empty() {
+                let mut ctx = Context {
+                    db: &databases[session.db],
+                    databases,
+                    server,
+                    session: &mut session,
+                };
+                if let Err(e) = command::apply(&mut ctx, &args) {
+                    println!("command from the master failed: {e}");
+                }
+            }
+            offset += used as u64;
+        }
+        buf.drain(..consumed);
+        if let Some(link) = server.master_link.lock().unwrap().as_mut() {
+            link.offset = offset;
+        }
+        if acknowledged.elapsed() >= ACK_INTERVAL {
+            acknowledge(master.get_mut(), offset)?;
+            acknowledged = Instant::now();
+        }
+    }
+}
+
+fn acknowledge(master: &mut TcpStream, offset: u64) -> io::Result<()> {
+    let ack = [
+        b"REPLCONF".to_vec(),
+        b"ACK".to_vec(),
+        offset.to_string().into(),
+    ];
+    master.write_all(&resp::encode_command(&ack))
+}
+
+// Replaces the dataset and function libraries with those of the master.
+fn load(server: &Server, databases: &Databases, snapshot: rdb::Snapshot) {
+    let _gate = server.exclusive_gate();
+    db::flush_all(databases);
+    for (index, key, value, deadline) in snapshot.entries {
+        let Some(db) = databases.get(index) else {
+            continue;
+        };
+        let mut guard = db.write().unwrap();
+        guard.insert(&key, MapValue::new(value));
+        guard.set_expiry(&key, deadline);
+    }
+    let mut libraries = Libraries::default();
+    for code in snapshot.functions {
+        match scripting::compile_library(&code) {
+            Ok(library) => {
+                let _ = libraries.add(library, true);
+            }
+            Err(e) => println!("function library from the master not loaded: {e}"),
+        }
+    }
+    *server.functions.lock().unwrap() = libraries;
+}
+
+// PING, REPLCONF listening-port, REPLCONF capa and PSYNC, checking each reply in turn.
+fn handshake(server: &Server, host: &str, port: u16) -> io::Result<(BufReader<TcpStream>, String)> {
+    let mut master = BufReader::new(TcpStream::connect((host, port))?);
+    let (announce_ip, announce_port) = {
+        let config = server.config.lock().unwrap();
+        (
+            config.replica_announce_ip.clone(),
+            config.replica_announce_port,
+        )
+    };
+    let listening_port = match announce_port {
+        0 => server.port,
+        port => port,
+    };
+    expect(&mut master, &["PING"], "+PONG")?;
+    expect(
+        &mut master,
+        &["REPLCONF", "listening-port", &listening_port.to_string()],
+        "+OK",
+    )?;
+    if let Some(ip) = announce_ip {
+        expect(&mut master, &["REPLCONF", "ip-address", &ip], "+OK")?;
+    }
+    expect(&mut master, &["REPLCONF", "capa", "psync2"], "+OK")?;
+    let resync = expect(&mut master, &["PSYNC", "?", "-1"], "+FULLRESYNC")?;
+    Ok((master, resync))
+}
+
+// Sends a command and fails unless the reply line starts with `wanted`.
+fn expect(master: &mut BufReader<TcpStream>, args: &[&str], wanted: &str) -> io::Result<String> {
+    let args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+    master.get_mut().write_all(&resp::encode_command(&args))?;
+    let mut line = String::new();
+    if master.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let line = line.trim_end().to_string();
+    if !line.starts_with(wanted) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} got {line}", String::from_utf8_lossy(&args[0])),
+        ));
+    }
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cluster_testing::Topology, resp::Reply};
+
+    fn args(command: &str) -> Vec<Vec<u8>> {
+        command
+            .split(' ')
+            .map(|arg| arg.as_bytes().to_vec())
+            .collect()
+    }
+
+    fn run(server: &Server, databases: &Databases, session: &mut Session, commands: &[&str]) {
+        for command in commands {
+            let mut ctx = Context {
+                db: &databases[session.db],
+                databases,
+                server,
+                session,
+            };
+            command::execute(&mut ctx, &args(command));
+        }
+    }
+
+    fn keys(databases: &Databases) -> Vec<usize> {
+        databases
+            .iter()
+            .map(|db| db.read().unwrap().len())
+            .collect()
+    }
+
+    #[test]
+    fn flushall_reaches_replicas() {
+        let (master, databases) = (Server::new(0), db::new_databases());
+        let outbox = Arc::new(Outbox::default());
+        master.replicas.lock().unwrap().attach(Replica {
+            id: 1,
+            ip: "127.0.0.1".into(),
+            port: 0,
+            outbox: outbox.clone(),
+            ack: 0,
+        });
+        let commands = ["SET a 1", "SELECT 1", "SET b 2", "FLUSHALL"];
+        run(&master, &databases, &mut Session::default(), &commands);
+        assert!(keys(&databases).iter().all(|keys| *keys == 0));
+
+        // Applied to a replica that also holds keys of its own, the stream leaves it empty.
+        let (replica, replica_databases) = (Server::new(0), db::new_databases());
+        run(
+            &replica,
+            &replica_databases,
+            &mut Session::default(),
+            &["SELECT 3", "SET c 3"],
+        );
+        let stream = outbox.take().unwrap();
+        assert!(stream.ends_with(&resp::encode_command(&args("FLUSHALL"))));
+        let mut session = Session::default();
+        let mut applied = 0;
+        while let Some((args, used)) = resp::parse_command(&stream[applied..]).unwrap() {
+            let mut ctx = Context {
+                db: &replica_databases[session.db],
+                databases: &replica_databases,
+                server: &replica,
+                session: &mut session,
+            };
+            command::apply(&mut ctx, &args).unwrap();
+            applied += used;
+        }
+        assert_eq!(applied, stream.len());
+        assert!(keys(&replica_databases).iter().all(|keys| *keys == 0));
+    }
+
+    #[test]
+    fn full_resync_replaces_the_keyspace() {
+        let (master, databases) = (Server::new(0), db::new_databases());
+        run(
+            &master,
+            &databases,
+            &mut Session::default(),
+            &["SELECT 2", "SET a 1"],
+        );
+        let snapshot = rdb::load(&rdb::snapshot(&databases, std::iter::empty())).unwrap();
+
+        let (replica, replica_databases) = (Server::new(0), db::new_databases());
+        let commands = ["SET stale 1", "SELECT 2", "SET b 2", "SELECT 5", "SET c 3"];
+        run(
+            &replica,
+            &replica_databases,
+            &mut Session::default(),
+            &commands,
+        );
+        load(&replica, &replica_databases, snapshot);
+        assert_eq!(keys(&replica_databases), keys(&databases));
+        let guard = replica_databases[2].read().unwrap();
+        assert!(guard.peek(b"a").is_some() && guard.peek(b"b").is_none());
+    }
+
+    #[test]
+    fn deadlines_are_streamed_absolute() {
+        let (master, databases) = (Server::new(0), db::new_databases());
+        let outbox = Arc::new(Outbox::default());
+        master.replicas.lock().unwrap().attach(Replica {
+            id: 1,
+            ip: "127.0.0.1".into(),
+            port: 0,
+            outbox: outbox.clone(),
+            ack: 0,
+        });
+        let commands = [
+            "SET a 1 PX 100000",
+            "SET b 1",
+            "EXPIRE b 200 NX",
+            "HSET h f v",
+            "HPEXPIRE h 300000 FIELDS 1 f",
+        ];
+        run(&master, &databases, &mut Session::default(), &commands);
+        let stream = outbox.take().unwrap();
+        let mut streamed = vec![];
+        let mut consumed = 0;
+        while let Some((args, used)) = resp::parse_command(&stream[consumed..]).unwrap() {
+            streamed.push(args);
+            consumed += used;
+        }
+        let guard = databases[0].read().unwrap();
+        let deadline = |key: &[u8]| guard.expiry(key).unwrap().to_string().into_bytes();
+        assert_eq!(
+            streamed[1],
+            [&b"SET"[..], b"a", b"1", b"PXAT", &deadline(b"a")]
+        );
+        assert_eq!(
+            streamed[3],
+            [&b"PEXPIREAT"[..], b"b", &deadline(b"b"), b"NX"]
+        );
+        let Some(db::Value::Hash(hash)) = guard.peek(b"h").map(|value| &value.data) else {
+            panic!("h is not a hash");
+        };
+        let field_deadline = hash.expiry(b"f").unwrap().to_string().into_bytes();
+        assert_eq!(
+            streamed[5][..3],
+            [&b"HPEXPIREAT"[..], b"h", &field_deadline]
+        );
+    }
+
+    #[test]
+    fn only_sort_with_store_is_streamed() {
+        let (master, databases) = (Server::new(0), db::new_databases());
+        let outbox = Arc::new(Outbox::default());
+        master.replicas.lock().unwrap().attach(Replica {
+            id: 1,
+            ip: "127.0.0.1".into(),
+            port: 0,
+            outbox: outbox.clone(),
+            ack: 0,
+        });
+        let commands = ["RPUSH l 2 1", "SORT l", "SORT_RO l", "SORT l STORE d"];
+        run(&master, &databases, &mut Session::default(), &commands);
+        let expected: Vec<u8> = ["SELECT 0", "RPUSH l 2 1", "SORT l STORE d"]
+            .iter()
+            .flat_map(|command| resp::encode_command(&args(command)))
+            .collect();
+        assert_eq!(outbox.take().unwrap(), expected);
+    }
+
+    #[test]
+    fn replicas_refuse_writes_and_acknowledge_their_offset() {
+        let topology = Topology::builder().replicas(1).start().unwrap();
+        let (master, replica) = (topology.master(), topology.replica(0));
+        assert!(topology.wait_for_sync(Duration::from_secs(10)));
+        let read_only =
+            |reply: Reply| matches!(reply, Reply::Error(e) if e.starts_with("READONLY"));
+        assert!(read_only(replica.execute(&["SET", "x", "1"])));
+        assert!(read_only(replica.execute(&["FUNCTION", "FLUSH"])));
+        let script = replica.execute(&["EVAL", "return redis.call('SET', 'x', '1')", "0"]);
+        assert!(matches!(script, Reply::Error(e) if e.contains("READONLY")));
+        assert_eq!(
+            replica.execute(&["EVAL", "return 1", "0"]),
+            Reply::Integer(1)
+        );
+        assert_eq!(replica.execute(&["GET", "x"]), Reply::Nil);
+
+        master.execute(&["SET", "k", "v"]);
+        assert!(topology.wait_for_sync(Duration::from_secs(10)));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let acknowledged = || {
+            let replicas = master.server.replicas.lock().unwrap();
+            let synced = replicas
+                .iter()
+                .all(|replica| replica.ack == replicas.offset);
+            synced
+        };
+        while !acknowledged() {
+            assert!(
+                Instant::now() < deadline,
+                "no REPLCONF ACK from the replica"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
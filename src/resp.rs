@@ -0,0 +1,395 @@
+use std::io;
+
+#[derive(Debug)]
+pub enum DataType<'a> {
+    SimpleString(&'a [u8]),
+    BulkString(Option<&'a [u8]>),
+    Array(Vec<DataType<'a>>),
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn split_line(buf: &[u8]) -> Option<(&[u8], usize)> {
+    buf.windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|pos| (&buf[..pos], pos + 2))
+}
+
+fn parse_length(line: &[u8]) -> io::Result<isize> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_data(format!("Invalid length {}", String::from_utf8_lossy(line))))
+}
+
+// Limits on what a peer may announce, so a hostile frame is refused before it can exhaust the
+// stack or make the connection buffer without bound. Nothing a client sends legitimately is
+// nested; the depth only leaves room for the generic parser. The bulk limit is the default
+// proto-max-bulk-len and the multibulk one Redis' own.
+const MAX_NESTING: usize = 32;
+const MAX_MULTIBULK_LEN: isize = i32::MAX as isize;
+const MAX_BULK_LEN: isize = 512 * 1024 * 1024;
+
+impl<'a> DataType<'a> {
+    // Parses a single frame from the front of `buf`. Returns `None` when more input is needed.
+    pub fn parse(buf: &'a [u8]) -> io::Result<Option<(Self, usize)>> {
+        Self::parse_nested(buf, 0)
+    }
+    fn parse_nested(buf: &'a [u8], depth: usize) -> io::Result<Option<(Self, usize)>> {
+        use DataType::*;
+        let Some((line, mut consumed)) = split_line(buf) else {
+            return Ok(None);
+        };
+        let Some((prefix, hd)) = line.split_first() else {
+            return Err(invalid_data("Missing type prefix"));
+        };
+        match prefix {
+            b'+' => Ok(Some((SimpleString(hd), consumed))),
+            b'*' => {
+                let count = parse_length(hd)?;
+                if count > MAX_MULTIBULK_LEN {
+                    return Err(invalid_data("invalid multibulk length"));
+                }
+                if count > 0 && depth == MAX_NESTING {
+                    return Err(invalid_data("too many nested multibulks"));
+                }
+                let mut buf_elts = vec![];
+                for _ in 0..count.max(0) {
+                    match DataType::parse_nested(&buf[consumed..], depth + 1)? {
+                        Some((elt, used)) => {
+                            consumed += used;
+                            buf_elts.push(elt);
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some((Array(buf_elts), consumed)))
+            }
+            b'$' => match parse_length(hd)? {
+                -1 => Ok(Some((BulkString(None), consumed))),
+                len if !(0..=MAX_BULK_LEN).contains(&len) => {
+                    Err(invalid_data("invalid bulk length"))
+                }
+                len => {
+                    let len = len as usize;
+                    if buf.len() < consumed + len + 2 {
+                        return Ok(None);
+                    }
+                    if &buf[consumed + len..consumed + len + 2] != b"\r\n" {
+                        return Err(invalid_data("Bulk-string not terminated by CRLF"));
+                    }
+                    let content = &buf[consumed..consumed + len];
+                    Ok(Some((BulkString(Some(content)), consumed + len + 2)))
+                }
+            },
+            _ => Err(invalid_data(format!(
+                "Unknown type prefix {}",
+                *prefix as char
+            ))),
+        }
+    }
+    fn try_take(self) -> Option<&'a [u8]> {
+        match self {
+            Self::SimpleString(s) => Some(s),
+            Self::BulkString(s) => s,
+            _ => None,
+        }
+    }
+}
+
+// Splits an inline command the way redis-cli and sdssplitargs do: arguments are separated by
+// whitespace and may be "double quoted" (with \n, \xHH, ... escapes) or 'single quoted' (where
+// only \' is an escape). A closing quote must end the argument.
+fn split_inline(line: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let unbalanced = || invalid_data("unbalanced quotes in request");
+    let mut args = vec![];
+    let mut pos = 0;
+    loop {
+        while line.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if pos == line.len() {
+            return Ok(args);
+        }
+        let mut arg = vec![];
+        loop {
+            match line.get(pos) {
+                None => break,
+                Some(b) if b.is_ascii_whitespace() => break,
+                Some(b'"') => {
+                    pos += 1;
+                    loop {
+                        match line.get(pos..) {
+                            Some([b'\\', b'x', hi, lo, ..])
+                                if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() =>
+                            {
+                                let hex = [*hi, *lo];
+                                let hex = std::str::from_utf8(&hex).unwrap();
+                                arg.push(u8::from_str_radix(hex, 16).unwrap());
+                                pos += 4;
+                            }
+                            Some([b'\\', escaped, ..]) => {
+                                arg.push(match escaped {
+                                    b'n' => b'\n',
+                                    b'r' => b'\r',
+                                    b't' => b'\t',
+                                    b'b' => 0x08,
+                                    b'a' => 0x07,
+                                    other => *other,
+                                });
+                                pos += 2;
+                            }
+                            Some([b'"', ..]) => {
+                                pos += 1;
+                                break;
+                            }
+                            Some([b, ..]) => {
+                                arg.push(*b);
+                                pos += 1;
+                            }
+                            _ => return Err(unbalanced()),
+                        }
+                    }
+                    if line.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        return Err(unbalanced());
+                    }
+                }
+                Some(b'\'') => {
+                    pos += 1;
+                    loop {
+                        match line.get(pos..) {
+                            Some([b'\\', b'\'', ..]) => {
+                                arg.push(b'\'');
+                                pos += 2;
+                            }
+                            Some([b'\'', ..]) => {
+                                pos += 1;
+                                break;
+                            }
+                            Some([b, ..]) => {
+                                arg.push(*b);
+                                pos += 1;
+                            }
+                            _ => return Err(unbalanced()),
+                        }
+                    }
+                    if line.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        return Err(unbalanced());
+                    }
+                }
+                Some(b) => {
+                    arg.push(*b);
+                    pos += 1;
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+// Reads one command from the front of `buf`, either as a RESP array or an inline command.
+pub fn parse_command(buf: &[u8]) -> io::Result<Option<(Vec<Vec<u8>>, usize)>> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'*') => match DataType::parse(buf)? {
+            Some((DataType::Array(elts), consumed)) => elts
+                .into_iter()
+                .map(|elt| {
+                    elt.try_take()
+                        .map(<[u8]>::to_vec)
+                        .ok_or_else(|| invalid_data("Expected bulk-string argument"))
+                })
+                .collect::<io::Result<_>>()
+                .map(|args| Some((args, consumed))),
+            Some(_) => Err(invalid_data("Expected array")),
+            None => Ok(None),
+        },
+        Some(_) => {
+            let Some((line, consumed)) = buf
+                .iter()
+                .position(|b| *b == b'\n')
+                .map(|pos| (&buf[..pos], pos + 1))
+            else {
+                return Ok(None);
+            };
+            Ok(Some((split_inline(line)?, consumed)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Vec<u8>),
+    Nil,
+    // The null array RESP2 uses where a multi-element reply is absent.
+    NilArray,
+    Array(Vec<Reply>),
+    Map(Vec<(Reply, Reply)>),
+    Set(Vec<Reply>),
+    Double(f64),
+    // Integers outside the i64 range; RESP2 clients get them as bulk strings.
+    BigNumber(String),
+    Boolean(bool),
+    // Three letter format (e.g. "txt") and content.
+    Verbatim(&'static str, Vec<u8>),
+    // Out-of-band data; only RESP3 clients see it.
+    Attribute(Vec<(Reply, Reply)>),
+    Push(Vec<Reply>),
+    // Several frames written back to back for a single command.
+    Sequence(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::SimpleString("OK".into())
+    }
+    pub fn encode(&self, protocol: Protocol, out: &mut Vec<u8>) {
+        use Reply::*;
+        let resp3 = protocol == Protocol::Resp3;
+        match self {
+            SimpleString(s) => out.extend_from_slice(format!("+{s}\r\n").as_bytes()),
+            Error(e) => out.extend_from_slice(format!("-{e}\r\n").as_bytes()),
+            Integer(i) => out.extend_from_slice(format!(":{i}\r\n").as_bytes()),
+            BulkString(data) => {
+                out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            Nil | NilArray if resp3 => out.extend_from_slice(b"_\r\n"),
+            Nil => out.extend_from_slice(b"$-1\r\n"),
+            NilArray => out.extend_from_slice(b"*-1\r\n"),
+            Array(elts) => encode_aggregate('*', elts, protocol, out),
+            Set(elts) if resp3 => encode_aggregate('~', elts, protocol, out),
+            Push(elts) if resp3 => encode_aggregate('>', elts, protocol, out),
+            Set(elts) | Push(elts) => encode_aggregate('*', elts, protocol, out),
+            Map(pairs) => {
+                let header = if resp3 {
+                    format!("%{}\r\n", pairs.len())
+                } else {
+                    format!("*{}\r\n", pairs.len() * 2)
+                };
+                out.extend_from_slice(header.as_bytes());
+                for (k, v) in pairs {
+                    k.encode(protocol, out);
+                    v.encode(protocol, out);
+                }
+            }
+            Attribute(pairs) if resp3 => {
+                out.extend_from_slice(format!("|{}\r\n", pairs.len()).as_bytes());
+                for (k, v) in pairs {
+                    k.encode(protocol, out);
+                    v.encode(protocol, out);
+                }
+            }
+            Attribute(_) => {}
+            Sequence(frames) => {
+                for frame in frames {
+                    frame.encode(protocol, out);
+                }
+            }
+            Double(d) if resp3 => {
+                let formatted = if d.is_nan() {
+                    "nan".into()
+                } else {
+                    format_double(*d)
+                };
+                out.extend_from_slice(format!(",{formatted}\r\n").as_bytes());
+            }
+            Double(d) => BulkString(format_double(*d).into_bytes()).encode(protocol, out),
+            BigNumber(n) if resp3 => out.extend_from_slice(format!("({n}\r\n").as_bytes()),
+            BigNumber(n) => BulkString(n.clone().into_bytes()).encode(protocol, out),
+            Boolean(b) if resp3 => out.extend_from_slice(if *b { b"#t\r\n" } else { b"#f\r\n" }),
+            Boolean(b) => Integer(*b as i64).encode(protocol, out),
+            Verbatim(format, data) if resp3 => {
+                out.extend_from_slice(format!("={}\r\n{format}:", data.len() + 4).as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            Verbatim(_, data) => BulkString(data.clone()).encode(protocol, out),
+        }
+    }
+}
+
+fn encode_aggregate(prefix: char, elts: &[Reply], protocol: Protocol, out: &mut Vec<u8>) {
+    out.extend_from_slice(format!("{prefix}{}\r\n", elts.len()).as_bytes());
+    for elt in elts {
+        elt.encode(protocol, out);
+    }
+}
+
+impl From<Vec<u8>> for Reply {
+    fn from(value: Vec<u8>) -> Self {
+        Reply::BulkString(value)
+    }
+}
+
+impl From<&[u8]> for Reply {
+    fn from(value: &[u8]) -> Self {
+        Reply::BulkString(value.to_vec())
+    }
+}
+
+impl From<Option<Vec<u8>>> for Reply {
+    fn from(value: Option<Vec<u8>>) -> Self {
+        value.map_or(Reply::Nil, Reply::BulkString)
+    }
+}
+
+impl From<i64> for Reply {
+    fn from(value: i64) -> Self {
+        Reply::Integer(value)
+    }
+}
+
+// A command as clients send it: an array of bulk strings.
+pub fn encode_command(args: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = vec![];
+    Reply::Array(args.iter().map(|arg| Reply::from(arg.as_slice())).collect())
+        .encode(Protocol::Resp2, &mut out);
+    out
+}
+
+// Shortest round-trip representation, switching to exponent notation where C's %.17g would.
+pub fn format_double(value: f64) -> String {
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.into();
+    }
+    let scientific = format!("{value:e}");
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    if (-4..17).contains(&exponent) {
+        format!("{value}")
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{mantissa}e{sign}{:02}", exponent.abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostile_frames_are_refused() {
+        let nested = b"*1\r\n".repeat(200_000);
+        assert!(parse_command(&nested).is_err());
+        assert!(parse_command(b"*1\r\n$536870913\r\n").is_err());
+        assert!(parse_command(b"*1\r\n$-2\r\n").is_err());
+        assert!(parse_command(b"*4294967296\r\n").is_err());
+        let args = parse_command(b"*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\n").unwrap();
+        assert_eq!(args, Some((vec![b"ECHO".to_vec(), b"hi".to_vec()], 22)));
+        assert!(parse_command(b"*2\r\n$4\r\nECHO\r\n").unwrap().is_none());
+    }
+}
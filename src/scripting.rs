@@ -0,0 +1,182 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    lua::{self, Interp},
+    sha1,
+};
+
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Script bodies loaded with SCRIPT LOAD or EVAL, by lowercase SHA-1 hex digest.
+#[derive(Default)]
+pub struct ScriptCache {
+    scripts: HashMap<String, Vec<u8>>,
+    // Bytes held by the cache, reported as used_memory_scripts_eval.
+    memory: usize,
+}
+
+impl ScriptCache {
+    // Returns the digest the script is known by.
+    pub fn load(&mut self, body: &[u8]) -> String {
+        let sha = sha1::hex_digest(body);
+        if !self.scripts.contains_key(&sha) {
+            self.memory += sha.len() + body.len();
+            self.scripts.insert(sha.clone(), body.to_vec());
+        }
+        sha
+    }
+    pub fn get(&self, sha: &[u8]) -> Option<Vec<u8>> {
+        let sha = std::str::from_utf8(sha).ok()?;
+        self.scripts.get(&sha.to_ascii_lowercase()).cloned()
+    }
+    pub fn contains(&self, sha: &[u8]) -> bool {
+        std::str::from_utf8(sha)
+            .is_ok_and(|sha| self.scripts.contains_key(&sha.to_ascii_lowercase()))
+    }
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+}
+
+// A library loaded with FUNCTION LOAD. Its code is run again for every FCALL, as interpreters
+// do not outlive a command.
+#[derive(Clone)]
+pub struct Library {
+    pub name: String,
+    pub code: Vec<u8>,
+    pub functions: Vec<FunctionInfo>,
+}
+
+#[derive(Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub flags: Vec<String>,
+    pub description: Option<String>,
+}
+
+// Function libraries by name.
+#[derive(Default, Clone)]
+pub struct Libraries {
+    libraries: BTreeMap<String, Library>,
+}
+
+impl Libraries {
+    pub fn find(&self, function: &str) -> Option<(&Library, &FunctionInfo)> {
+        self.libraries.values().find_map(|library| {
+            library
+                .functions
+                .iter()
+                .find(|info| info.name == function)
+                .map(|info| (library, info))
+        })
+    }
+    // Adds a library, replacing one of the same name only with `replace`.
+    pub fn add(&mut self, library: Library, replace: bool) -> Result<(), String> {
+        if !replace && self.libraries.contains_key(&library.name) {
+            return Err(format!("Library '{}' already exists", library.name));
+        }
+        for info in &library.functions {
+            if let Some((other, _)) = self.find(&info.name) {
+                if other.name != library.name {
+                    return Err(format!("Function {} already exists", info.name));
+                }
+            }
+        }
+        self.libraries.insert(library.name.clone(), library);
+        Ok(())
+    }
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.libraries.remove(name).is_some()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Library> {
+        self.libraries.values()
+    }
+    pub fn len(&self) -> usize {
+        self.libraries.len()
+    }
+    pub fn function_count(&self) -> usize {
+        self.iter().map(|library| library.functions.len()).sum()
+    }
+    // Bytes held by the libraries' names, code and function metadata, reported as
+    // used_memory_functions.
+    pub fn memory(&self) -> usize {
+        self.iter()
+            .map(|library| {
+                let functions = library.functions.iter().map(|info| {
+                    let flags: usize = info.flags.iter().map(String::len).sum();
+                    info.name.len() + flags + info.description.as_ref().map_or(0, String::len)
+                });
+                library.name.len() + library.code.len() + functions.sum::<usize>()
+            })
+            .sum()
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+// Reads the `#!lua name=<library>` line and runs the code to find out what it registers.
+pub fn compile_library(code: &[u8]) -> Result<Library, String> {
+    let first_line = code.split(|c| *c == b'\n').next().unwrap_or_default();
+    let Some(shebang) = first_line.strip_prefix(b"#!") else {
+        return Err("Missing library metadata".into());
+    };
+    let shebang = String::from_utf8_lossy(shebang);
+    let mut parts = shebang.split_ascii_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("Engine '{engine}' not found"));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(format!("Invalid metadata value given: {part}")),
+        }
+    }
+    let Some(name) = name else {
+        return Err("Library name was not given".into());
+    };
+    if !valid_name(&name) {
+        return Err(
+            "Library names can only contain letters, numbers, or underscores(_) and must \
+                    be at least one character long"
+                .into(),
+        );
+    }
+    let functions = lua::on_script_stack(|| {
+        let mut interp = Interp::new(None);
+        // Loading has to be quick, as nothing else runs meanwhile.
+        let started = Instant::now();
+        interp.watchdog = Some(Box::new(move || {
+            (started.elapsed() >= LOAD_TIMEOUT).then(|| "FUNCTION LOAD timeout".to_string())
+        }));
+        let registered = interp.register_functions(code)?;
+        Ok::<_, String>(
+            registered
+                .into_iter()
+                .map(|function| FunctionInfo {
+                    name: function.name,
+                    flags: function.flags,
+                    description: function.description,
+                })
+                .collect::<Vec<_>>(),
+        )
+    })
+    .map_err(|e| format!("failed to start the script: {e}"))??;
+    if functions.is_empty() {
+        return Err("No functions registered".into());
+    }
+    Ok(Library {
+        name,
+        code: code.to_vec(),
+        functions,
+    })
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::ClientInfo,
+    config::Config,
+    journal::Journal,
+    latency::Monitor,
+    pubsub::Registry,
+    replication::{MasterLink, Replicas},
+    scripting::{Libraries, ScriptCache},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusyReason {
+    DebugSleep,
+    // An EVAL or FCALL past busy-reply-threshold.
+    Script,
+    Function,
+}
+
+impl BusyReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            BusyReason::DebugSleep => "debug-sleep",
+            BusyReason::Script => "script",
+            BusyReason::Function => "function",
+        }
+    }
+    pub fn message(self) -> &'static str {
+        match self {
+            BusyReason::DebugSleep => {
+                "Redis is busy running DEBUG SLEEP. You can only call SHUTDOWN NOSAVE."
+            }
+            BusyReason::Script => {
+                "Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE."
+            }
+            BusyReason::Function => {
+                "Redis is busy running a script. You can only call FUNCTION KILL or SHUTDOWN \
+                 NOSAVE."
+            }
+        }
+    }
+}
+
+struct Busy {
+    reason: BusyReason,
+    since: Instant,
+}
+
+// Process-wide state shared by every connection.
+pub struct Server {
+    pub port: u16,
+    // Connections dropped because the process was out of file descriptors.
+    pub rejected_connections: AtomicU64,
+    pub journal: Option<Mutex<Journal>>,
+    // Set while a mass import runs: background expiry is held back and done in one pass when
+    // the import ends.
+    pub bulk_load: AtomicBool,
+    pub config: Mutex<Config>,
+    pub pubsub: Mutex<Registry>,
+    pub scripts: Mutex<ScriptCache>,
+    pub functions: Mutex<Libraries>,
+    pub latency: Mutex<Monitor>,
+    // Connected clients by id.
+    pub clients: Mutex<BTreeMap<u64, Arc<Mutex<ClientInfo>>>>,
+    // Subscribers disconnected for falling too far behind on published messages.
+    pub pubsub_clients_evicted: AtomicU64,
+    // Held shared by every command and exclusively by EXEC, so no other client's command runs
+    // in the middle of a transaction.
+    pub transactions: RwLock<()>,
+    // Held by a write from its changes until it is propagated, so replicas and the journal get
+    // writes in the order they were made.
+    pub write_order: Mutex<()>,
+    // Set by SCRIPT KILL and FUNCTION KILL for the running script to stop at its next check,
+    // which they refuse to do once it has written anything.
+    pub script_kill: AtomicBool,
+    pub script_wrote: AtomicBool,
+    // Replicas fed with every propagated command, when this server is a master.
+    pub replicas: Mutex<Replicas>,
+    // The master given with --replicaof; the server is a replica when set.
+    pub master: Option<(String, u16)>,
+    // The replication stream from the master, while the link to it is up.
+    pub master_link: Mutex<Option<MasterLink>>,
+    started: Instant,
+    busy: Mutex<Option<Busy>>,
+}
+
+// Clears the busy state when the long-running operation finishes, however it finishes.
+pub struct BusyGuard<'a>(&'a Server);
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.busy.lock().unwrap() = None;
+    }
+}
+
+impl Server {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            rejected_connections: AtomicU64::new(0),
+            journal: None,
+            bulk_load: AtomicBool::new(false),
+            config: Mutex::default(),
+            pubsub: Mutex::default(),
+            scripts: Mutex::default(),
+            functions: Mutex::default(),
+            latency: Mutex::default(),
+            clients: Mutex::default(),
+            pubsub_clients_evicted: AtomicU64::new(0),
+            transactions: RwLock::default(),
+            write_order: Mutex::default(),
+            script_kill: AtomicBool::new(false),
+            script_wrote: AtomicBool::new(false),
+            replicas: Mutex::default(),
+            master: None,
+            master_link: Mutex::default(),
+            started: Instant::now(),
+            busy: Mutex::new(None),
+        }
+    }
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+    // The gate and the write order guard no data, so a handler that panicked while holding one
+    // left nothing half-done behind it; they are taken regardless of poisoning.
+    pub fn shared_gate(&self) -> RwLockReadGuard<'_, ()> {
+        self.transactions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    pub fn exclusive_gate(&self) -> RwLockWriteGuard<'_, ()> {
+        self.transactions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    pub fn order_writes(&self) -> MutexGuard<'_, ()> {
+        self.write_order
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    // Fails if another operation already holds the server busy.
+    pub fn begin_busy(&self, reason: BusyReason) -> Option<BusyGuard<'_>> {
+        let mut busy = self.busy.lock().unwrap();
+        if busy.is_some() {
+            return None;
+        }
+        *busy = Some(Busy {
+            reason,
+            since: Instant::now(),
+        });
+        Some(BusyGuard(self))
+    }
+    pub fn busy(&self) -> Option<(BusyReason, Duration)> {
+        self.busy
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|busy| (busy.reason, busy.since.elapsed()))
+    }
+}
@@ -0,0 +1,225 @@
+// Bit numbering follows Redis: bit 0 is the most significant bit of the first byte.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Byte,
+    Bit,
+}
+
+impl Unit {
+    fn bits(self) -> i64 {
+        match self {
+            Unit::Byte => 8,
+            Unit::Bit => 1,
+        }
+    }
+}
+
+// Resolves a possibly negative [start, end] range (in `unit`s) into an inclusive bit range,
+// or `None` when it selects nothing.
+pub fn bit_range(len: usize, start: i64, end: i64, unit: Unit) -> Option<(usize, usize)> {
+    let total = len as i64 * 8 / unit.bits();
+    let normalize = |idx: i64| if idx < 0 { (total + idx).max(0) } else { idx };
+    let (start, end) = (normalize(start), normalize(end).min(total - 1));
+    if total == 0 || start > end {
+        return None;
+    }
+    let first_bit = start * unit.bits();
+    let last_bit = (end + 1) * unit.bits() - 1;
+    Some((first_bit as usize, last_bit as usize))
+}
+
+// Mask of the bits of a byte that lie within [first, last] (bit offsets inside that byte).
+fn byte_mask(first: usize, last: usize) -> u8 {
+    (0xFFu8 >> first) & (0xFFu8 << (7 - last))
+}
+
+pub fn count(bytes: &[u8], (first_bit, last_bit): (usize, usize)) -> usize {
+    let (first_byte, last_byte) = (first_bit / 8, last_bit / 8);
+    (first_byte..=last_byte)
+        .map(|idx| {
+            let lo = if idx == first_byte { first_bit % 8 } else { 0 };
+            let hi = if idx == last_byte { last_bit % 8 } else { 7 };
+            (bytes[idx] & byte_mask(lo, hi)).count_ones() as usize
+        })
+        .sum()
+}
+
+pub fn position(bytes: &[u8], bit: bool, (first_bit, last_bit): (usize, usize)) -> Option<usize> {
+    let (first_byte, last_byte) = (first_bit / 8, last_bit / 8);
+    (first_byte..=last_byte).find_map(|idx| {
+        let lo = if idx == first_byte { first_bit % 8 } else { 0 };
+        let hi = if idx == last_byte { last_bit % 8 } else { 7 };
+        let mask = byte_mask(lo, hi);
+        // Normalize to searching for set bits within the masked part of the byte.
+        let candidates = if bit { bytes[idx] } else { !bytes[idx] } & mask;
+        (candidates != 0).then(|| idx * 8 + candidates.leading_zeros() as usize)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+// Shorter (or missing) sources behave as if padded with zero bytes up to the longest one.
+pub fn bitop(op: BitOp, sources: &[&[u8]]) -> Vec<u8> {
+    let len = sources.iter().map(|src| src.len()).max().unwrap_or(0);
+    let byte_at = |src: &[u8], idx: usize| src.get(idx).copied().unwrap_or(0);
+    (0..len)
+        .map(|idx| {
+            let mut bytes = sources.iter().map(|src| byte_at(src, idx));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOp::And => bytes.fold(first, |acc, b| acc & b),
+                BitOp::Or => bytes.fold(first, |acc, b| acc | b),
+                BitOp::Xor => bytes.fold(first, |acc, b| acc ^ b),
+                BitOp::Not => !first,
+            }
+        })
+        .collect()
+}
+
+pub fn get_bit(bytes: &[u8], offset: usize) -> bool {
+    bytes
+        .get(offset / 8)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+// Zero-extends `bytes` as needed; returns the bit's previous value.
+pub fn set_bit(bytes: &mut Vec<u8>, offset: usize, bit: bool) -> bool {
+    let idx = offset / 8;
+    if bytes.len() <= idx {
+        bytes.resize(idx + 1, 0);
+    }
+    let mask = 0x80 >> (offset % 8);
+    let old = bytes[idx] & mask != 0;
+    if bit {
+        bytes[idx] |= mask;
+    } else {
+        bytes[idx] &= !mask;
+    }
+    old
+}
+
+// A BITFIELD integer: `bits` wide, two's complement if signed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+impl FieldType {
+    fn range(self) -> (i128, i128) {
+        if self.signed {
+            (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+    // Bits past the end of the string read as zero.
+    pub fn get(self, bytes: &[u8], offset: usize) -> i64 {
+        let mut value = 0u64;
+        for i in 0..self.bits as usize {
+            value = (value << 1) | get_bit(bytes, offset + i) as u64;
+        }
+        if self.signed && self.bits < 64 && (value >> (self.bits - 1)) & 1 == 1 {
+            value |= u64::MAX << self.bits;
+        }
+        value as i64
+    }
+    // Writes the low `bits` bits of `value`, zero-extending `bytes` as needed.
+    pub fn set(self, bytes: &mut Vec<u8>, offset: usize, value: i64) {
+        for i in 0..self.bits as usize {
+            let bit = (value as u64 >> (self.bits as usize - 1 - i)) & 1 == 1;
+            set_bit(bytes, offset + i, bit);
+        }
+    }
+    // Brings `value` into the field's range as the overflow policy says; None if it is out of
+    // range and the policy is FAIL.
+    pub fn fit(self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let low = value & ((1 << self.bits) - 1);
+                Some(if low > max {
+                    low - (1 << self.bits)
+                } else {
+                    low
+                } as i64)
+            }
+            Overflow::Sat => Some(if value < min { min } else { max } as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const U2: FieldType = FieldType {
+        signed: false,
+        bits: 2,
+    };
+    const I8: FieldType = FieldType {
+        signed: true,
+        bits: 8,
+    };
+    const I64: FieldType = FieldType {
+        signed: true,
+        bits: 64,
+    };
+
+    #[test]
+    fn overflow_policies() {
+        assert_eq!(U2.fit(4, Overflow::Wrap), Some(0));
+        assert_eq!(U2.fit(-1, Overflow::Wrap), Some(3));
+        assert_eq!(U2.fit(4, Overflow::Sat), Some(3));
+        assert_eq!(U2.fit(-1, Overflow::Sat), Some(0));
+        assert_eq!(U2.fit(4, Overflow::Fail), None);
+        assert_eq!(U2.fit(3, Overflow::Fail), Some(3));
+
+        assert_eq!(I8.fit(128, Overflow::Wrap), Some(-128));
+        assert_eq!(I8.fit(-129, Overflow::Wrap), Some(127));
+        assert_eq!(I8.fit(300, Overflow::Sat), Some(127));
+        assert_eq!(I8.fit(-300, Overflow::Sat), Some(-128));
+        assert_eq!(I8.fit(-129, Overflow::Fail), None);
+
+        let max = i64::MAX as i128;
+        assert_eq!(I64.fit(max + 1, Overflow::Wrap), Some(i64::MIN));
+        assert_eq!(I64.fit(max + 1, Overflow::Sat), Some(i64::MAX));
+        assert_eq!(I64.fit(i64::MIN as i128 - 1, Overflow::Sat), Some(i64::MIN));
+        assert_eq!(I64.fit(max + 1, Overflow::Fail), None);
+    }
+
+    #[test]
+    fn fields_span_bytes_and_sign_extend() {
+        let mut bytes = vec![];
+        I8.set(&mut bytes, 4, -2);
+        assert_eq!(bytes, [0x0F, 0xE0]);
+        assert_eq!(I8.get(&bytes, 4), -2);
+        let unsigned = FieldType {
+            signed: false,
+            bits: 8,
+        };
+        assert_eq!(unsigned.get(&bytes, 4), 254);
+        // Past the end reads as zero.
+        assert_eq!(I64.get(&bytes, 100), 0);
+        I64.set(&mut bytes, 3, i64::MIN);
+        assert_eq!(I64.get(&bytes, 3), i64::MIN);
+    }
+}
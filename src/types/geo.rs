@@ -0,0 +1,89 @@
+// Geohash helpers matching Redis' geohash.c: 26 bits per axis interleaved into a 52 bit
+// integer (latitude on even bits), which is stored as the member's sorted set score.
+
+const STEP: u32 = 26;
+pub const LON_MIN: f64 = -180.0;
+pub const LON_MAX: f64 = 180.0;
+pub const LAT_MIN: f64 = -85.051_128_78;
+pub const LAT_MAX: f64 = 85.051_128_78;
+const EARTH_RADIUS_IN_METERS: f64 = 6_372_797.560_856;
+
+pub fn is_valid(lon: f64, lat: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+fn spread(value: u32) -> u64 {
+    let mut x = value as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+fn squash(value: u64) -> u32 {
+    let mut x = value & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x >> 4)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x >> 8)) & 0x0000_FFFF_0000_FFFF;
+    ((x | (x >> 16)) & 0x0000_0000_FFFF_FFFF) as u32
+}
+
+pub fn encode(lon: f64, lat: f64) -> u64 {
+    let scale = (1u64 << STEP) as f64;
+    let lat_offset = (lat - LAT_MIN) / (LAT_MAX - LAT_MIN) * scale;
+    let lon_offset = (lon - LON_MIN) / (LON_MAX - LON_MIN) * scale;
+    spread(lat_offset as u32) | (spread(lon_offset as u32) << 1)
+}
+
+// Center of the geohash cell, as (longitude, latitude).
+pub fn decode(hash: u64) -> (f64, f64) {
+    let scale = (1u64 << STEP) as f64;
+    let (ilat, ilon) = (squash(hash) as f64, squash(hash >> 1) as f64);
+    let lat_min = LAT_MIN + ilat / scale * (LAT_MAX - LAT_MIN);
+    let lat_max = LAT_MIN + (ilat + 1.0) / scale * (LAT_MAX - LAT_MIN);
+    let lon_min = LON_MIN + ilon / scale * (LON_MAX - LON_MIN);
+    let lon_max = LON_MIN + (ilon + 1.0) / scale * (LON_MAX - LON_MIN);
+    (
+        ((lon_min + lon_max) / 2.0).clamp(LON_MIN, LON_MAX),
+        ((lat_min + lat_max) / 2.0).clamp(LAT_MIN, LAT_MAX),
+    )
+}
+
+pub fn distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lon1, lat2, lon2) = (
+        lat1.to_radians(),
+        lon1.to_radians(),
+        lat2.to_radians(),
+        lon2.to_radians(),
+    );
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1) / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+// Distance from `center` to `point` in meters if the point lies within the shape.
+pub fn within(shape: Shape, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+    match shape {
+        Shape::Radius(radius) => Some(distance(center, point)).filter(|d| *d <= radius),
+        Shape::Box { width, height } => {
+            let lat_distance =
+                EARTH_RADIUS_IN_METERS * (point.1.to_radians() - center.1.to_radians()).abs();
+            if lat_distance > height / 2.0 {
+                return None;
+            }
+            let lon_distance = distance((point.0, point.1), (center.0, point.1));
+            if lon_distance > width / 2.0 {
+                return None;
+            }
+            Some(distance(center, point))
+        }
+    }
+}
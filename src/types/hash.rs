@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::db::now_millis;
+
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: HashMap<Vec<u8>, Vec<u8>>,
+    // Deadlines set with HEXPIRE and friends, in Unix milliseconds. Expired fields read as
+    // missing until the active expire cycle deletes them.
+    expires: HashMap<Vec<u8>, i64>,
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Like HLEN, this counts fields that expired but were not deleted yet.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+    fn is_expired(&self, field: &[u8]) -> bool {
+        self.expires
+            .get(field)
+            .is_some_and(|deadline| *deadline <= now_millis())
+    }
+    pub fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        self.fields.get(field).filter(|_| !self.is_expired(field))
+    }
+    // Returns true if the field is new. Like HSET, this discards the field's deadline.
+    pub fn insert(&mut self, field: &[u8], value: &[u8]) -> bool {
+        let expired = self.is_expired(field);
+        self.expires.remove(field);
+        self.fields.insert(field.to_vec(), value.to_vec()).is_none() || expired
+    }
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        let expired = self.is_expired(field);
+        self.expires.remove(field);
+        self.fields.remove(field).is_some() && !expired
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        self.fields
+            .iter()
+            .filter(|(field, _)| !self.is_expired(field))
+    }
+    pub fn expiry(&self, field: &[u8]) -> Option<i64> {
+        self.get(field)?;
+        self.expires.get(field).copied()
+    }
+    // Replaces the deadline of a live field; returns false if there is no such field.
+    pub fn set_expiry(&mut self, field: &[u8], deadline: Option<i64>) -> bool {
+        if self.get(field).is_none() {
+            return false;
+        }
+        match deadline {
+            Some(deadline) => self.expires.insert(field.to_vec(), deadline),
+            None => self.expires.remove(field),
+        };
+        true
+    }
+    pub fn has_volatile_fields(&self) -> bool {
+        !self.expires.is_empty()
+    }
+    // Deletes the fields whose deadline has passed; returns how many there were.
+    pub fn remove_expired(&mut self) -> usize {
+        let now = now_millis();
+        let due: Vec<Vec<u8>> = self
+            .expires
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in &due {
+            self.expires.remove(field);
+            self.fields.remove(field);
+        }
+        due.len()
+    }
+}
@@ -0,0 +1,385 @@
+// HyperLogLog stored in the same string layout as Redis, so values can be moved between this
+// server and a real one: a 16 byte header ("HYLL", encoding, 3 unused bytes, cached cardinality)
+// followed by either sparse run-length opcodes or 16384 packed 6-bit registers.
+
+const P: u32 = 14;
+const Q: u32 = 64 - P;
+pub const REGISTERS: usize = 1 << P;
+const BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << BITS) - 1;
+const HEADER_SIZE: usize = 16;
+const DENSE_SIZE: usize = HEADER_SIZE + (REGISTERS * BITS).div_ceil(8);
+const MAGIC: &[u8] = b"HYLL";
+const ENCODING_DENSE: u8 = 0;
+const ENCODING_SPARSE: u8 = 1;
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+pub const SPARSE_MAX_BYTES: usize = 3000;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Dense,
+    Sparse,
+}
+
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    pub encoding: Encoding,
+    registers: Vec<u8>,
+    cached_cardinality: Option<u64>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Sparse,
+            registers: vec![0; REGISTERS],
+            cached_cardinality: Some(0),
+        }
+    }
+}
+
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate().rev() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// Register index and run length of the "000..1" pattern for an element.
+fn pattern(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, 0xadc8_3b19);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    let hash = (hash >> P) | (1 << Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let z_prime = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z_prime == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let z_prime = z;
+        z += x * y;
+        y += y;
+        if z_prime == z {
+            return z;
+        }
+    }
+}
+
+fn dense_get(dense: &[u8], regnum: usize) -> u8 {
+    let byte = regnum * BITS / 8;
+    let fb = regnum * BITS % 8;
+    let b0 = dense[byte] as u16;
+    let b1 = dense.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((b0 >> fb) | (b1 << (8 - fb))) & REGISTER_MAX as u16) as u8
+}
+
+fn dense_set(dense: &mut [u8], regnum: usize, value: u8) {
+    let byte = regnum * BITS / 8;
+    let fb = regnum * BITS % 8;
+    let value = value as u16;
+    dense[byte] &= !((REGISTER_MAX as u16) << fb) as u8;
+    dense[byte] |= (value << fb) as u8;
+    if let Some(next) = dense.get_mut(byte + 1) {
+        *next &= !((REGISTER_MAX as u16) >> (8 - fb)) as u8;
+        *next |= (value >> (8 - fb)) as u8;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SparseOp {
+    Zero(usize),
+    XZero(usize),
+    Val(u8, usize),
+}
+
+fn decode_sparse(mut data: &[u8]) -> Option<Vec<SparseOp>> {
+    let mut ops = vec![];
+    while let Some((&op, rest)) = data.split_first() {
+        data = rest;
+        ops.push(match op & 0xC0 {
+            0x00 => SparseOp::Zero((op & 0x3F) as usize + 1),
+            0x40 => {
+                let (&next, rest) = data.split_first()?;
+                data = rest;
+                SparseOp::XZero(((((op & 0x3F) as usize) << 8) | next as usize) + 1)
+            }
+            _ => SparseOp::Val(((op >> 2) & 0x1F) + 1, (op & 0x03) as usize + 1),
+        });
+    }
+    Some(ops)
+}
+
+fn encode_sparse(registers: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut idx = 0;
+    while idx < registers.len() {
+        let value = registers[idx];
+        let run = registers[idx..]
+            .iter()
+            .take_while(|register| **register == value)
+            .count();
+        let mut remaining = run;
+        while remaining > 0 {
+            if value == 0 && remaining > SPARSE_ZERO_MAX_LEN {
+                let len = remaining.min(SPARSE_XZERO_MAX_LEN);
+                out.push(0x40 | ((len - 1) >> 8) as u8);
+                out.push(((len - 1) & 0xFF) as u8);
+                remaining -= len;
+            } else if value == 0 {
+                out.push((remaining - 1) as u8);
+                remaining = 0;
+            } else {
+                let len = remaining.min(SPARSE_VAL_MAX_LEN);
+                out.push(0x80 | ((value - 1) << 2) | (len - 1) as u8);
+                remaining -= len;
+            }
+        }
+        idx += run;
+    }
+    out
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Validates a string value the way Redis' isHLLObjectOrReply does.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return None;
+        }
+        let card = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        let cached_cardinality = (card & (1 << 63) == 0).then_some(card);
+        let body = &bytes[HEADER_SIZE..];
+        let (encoding, registers) = match bytes[4] {
+            ENCODING_DENSE if bytes.len() == DENSE_SIZE => (
+                Encoding::Dense,
+                (0..REGISTERS).map(|idx| dense_get(body, idx)).collect(),
+            ),
+            ENCODING_SPARSE => {
+                let mut registers = Vec::with_capacity(REGISTERS);
+                for op in decode_sparse(body)? {
+                    let (value, len) = match op {
+                        SparseOp::Zero(len) | SparseOp::XZero(len) => (0, len),
+                        SparseOp::Val(value, len) => (value, len),
+                    };
+                    registers.extend(std::iter::repeat_n(value, len));
+                }
+                if registers.len() != REGISTERS {
+                    return None;
+                }
+                (Encoding::Sparse, registers)
+            }
+            _ => return None,
+        };
+        Some(Self {
+            encoding,
+            registers,
+            cached_cardinality,
+        })
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(DENSE_SIZE);
+        out.extend_from_slice(MAGIC);
+        out.push(match self.encoding {
+            Encoding::Dense => ENCODING_DENSE,
+            Encoding::Sparse => ENCODING_SPARSE,
+        });
+        out.extend_from_slice(&[0; 3]);
+        let card = self.cached_cardinality.unwrap_or(1 << 63);
+        out.extend_from_slice(&card.to_le_bytes());
+        match self.encoding {
+            Encoding::Dense => {
+                let mut dense = vec![0; DENSE_SIZE - HEADER_SIZE];
+                for (idx, value) in self.registers.iter().enumerate() {
+                    dense_set(&mut dense, idx, *value);
+                }
+                out.extend(dense);
+            }
+            Encoding::Sparse => out.extend(encode_sparse(&self.registers)),
+        }
+        out
+    }
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+    pub fn sparse_ops(&self) -> Option<Vec<SparseOp>> {
+        match self.encoding {
+            Encoding::Sparse => decode_sparse(&encode_sparse(&self.registers)),
+            Encoding::Dense => None,
+        }
+    }
+    pub fn promote_to_dense(&mut self) -> bool {
+        let converted = self.encoding == Encoding::Sparse;
+        self.encoding = Encoding::Dense;
+        converted
+    }
+    // Returns whether any register changed. A sparse HLL promotes itself to dense once a
+    // register no longer fits a VAL opcode or the encoding outgrows `sparse_max_bytes`.
+    pub fn add(&mut self, element: &[u8], sparse_max_bytes: usize) -> bool {
+        let (index, count) = pattern(element);
+        if self.registers[index] >= count {
+            return false;
+        }
+        self.registers[index] = count;
+        self.cached_cardinality = None;
+        if self.encoding == Encoding::Sparse
+            && (count > SPARSE_VAL_MAX_VALUE
+                || encode_sparse(&self.registers).len() > sparse_max_bytes)
+        {
+            self.encoding = Encoding::Dense;
+        }
+        true
+    }
+    // Takes the register-wise maximum, which is the HLL of the union of both sets.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*theirs);
+        }
+        self.cached_cardinality = None;
+    }
+    pub fn has_cached_cardinality(&self) -> bool {
+        self.cached_cardinality.is_some()
+    }
+    pub fn count(&mut self) -> u64 {
+        if let Some(card) = self.cached_cardinality {
+            return card;
+        }
+        let card = estimate(&self.registers);
+        self.cached_cardinality = Some(card);
+        card
+    }
+}
+
+pub fn estimate(registers: &[u8]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for j in (1..=Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(from: usize, to: usize, sparse_max_bytes: usize) -> HyperLogLog {
+        let mut hll = HyperLogLog::new();
+        for i in from..to {
+            hll.add(format!("element:{i}").as_bytes(), sparse_max_bytes);
+        }
+        hll
+    }
+
+    #[test]
+    fn counts_stay_within_the_error_bound() {
+        // The standard error is 0.81%; Redis's own tests allow 5%.
+        for n in [1, 10, 100, 1_000, 10_000, 100_000] {
+            let mut hll = filled(0, n, SPARSE_MAX_BYTES);
+            let error = (hll.count() as f64 - n as f64).abs() / n as f64;
+            assert!(error <= 0.05, "{n} counted as {}", hll.count());
+        }
+    }
+
+    #[test]
+    fn sparse_promotes_to_dense_past_the_byte_limit() {
+        let mut hll = HyperLogLog::new();
+        let mut added = 0;
+        while hll.encoding == Encoding::Sparse {
+            let sparse_len = hll.to_bytes().len();
+            assert!(sparse_len <= HEADER_SIZE + SPARSE_MAX_BYTES, "{sparse_len}");
+            hll.add(format!("element:{added}").as_bytes(), SPARSE_MAX_BYTES);
+            added += 1;
+        }
+        assert!(added > 100, "promoted after {added}");
+        assert_eq!(hll.to_bytes().len(), DENSE_SIZE);
+        // Promotion changes the layout, not the registers or the count.
+        let mut sparse = filled(0, added, usize::MAX);
+        assert_eq!(sparse.encoding, Encoding::Sparse);
+        assert_eq!(sparse.registers(), hll.registers());
+        assert_eq!(sparse.count(), hll.count());
+        // A lower limit promotes sooner.
+        assert_eq!(filled(0, 10, 10).encoding, Encoding::Dense);
+    }
+
+    #[test]
+    fn both_encodings_round_trip() {
+        for sparse_max_bytes in [usize::MAX, 0] {
+            let mut hll = filled(0, 500, sparse_max_bytes);
+            let count = hll.count();
+            let bytes = hll.to_bytes();
+            let mut back = HyperLogLog::from_bytes(&bytes).unwrap();
+            assert_eq!(back.encoding, hll.encoding);
+            assert_eq!(back.registers(), hll.registers());
+            assert!(back.has_cached_cardinality());
+            assert_eq!(back.count(), count);
+        }
+        let dense = filled(0, 10, 0).to_bytes();
+        assert!(HyperLogLog::from_bytes(&dense[..dense.len() - 1]).is_none());
+        assert!(HyperLogLog::from_bytes(b"HYLX\x01\0\0\0\0\0\0\0\0\0\0\0").is_none());
+        // Sparse opcodes that cover too few registers.
+        assert!(HyperLogLog::from_bytes(b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x00").is_none());
+    }
+
+    #[test]
+    fn merges_count_the_union() {
+        let mut a = filled(0, 6_000, SPARSE_MAX_BYTES);
+        let b = filled(4_000, 10_000, SPARSE_MAX_BYTES);
+        a.merge(&b);
+        assert!(!a.has_cached_cardinality());
+        let error = (a.count() as f64 - 10_000.0).abs() / 10_000.0;
+        assert!(error <= 0.05, "{}", a.count());
+    }
+}
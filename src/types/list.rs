@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+// Lists drained below a quarter of their allocation give memory back, down to this much.
+const MIN_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Default)]
+pub struct List {
+    items: VecDeque<Vec<u8>>,
+}
+
+impl List {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn push_front(&mut self, item: &[u8]) {
+        self.items.push_front(item.to_vec());
+    }
+    pub fn push_back(&mut self, item: &[u8]) {
+        self.items.push_back(item.to_vec());
+    }
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        let item = self.items.pop_front();
+        self.shrink();
+        item
+    }
+    pub fn pop_back(&mut self) -> Option<Vec<u8>> {
+        let item = self.items.pop_back();
+        self.shrink();
+        item
+    }
+    fn shrink(&mut self) {
+        let capacity = self.items.capacity();
+        if capacity > MIN_CAPACITY && self.items.len() < capacity / 4 {
+            self.items
+                .shrink_to((self.items.len() * 2).max(MIN_CAPACITY));
+        }
+    }
+    // Resolves a Redis-style index, where negative ones count from the tail.
+    fn resolve_index(&self, index: i64) -> Option<usize> {
+        let len = self.len() as i64;
+        let index = if index < 0 { index + len } else { index };
+        (0..len).contains(&index).then_some(index as usize)
+    }
+    pub fn get(&self, index: i64) -> Option<&Vec<u8>> {
+        self.items.get(self.resolve_index(index)?)
+    }
+    // Returns false when the index is out of range.
+    pub fn set(&mut self, index: i64, item: &[u8]) -> bool {
+        let Some(index) = self.resolve_index(index) else {
+            return false;
+        };
+        self.items[index] = item.to_vec();
+        true
+    }
+    // Inserts next to the first occurrence of `pivot`; returns false if there is none.
+    pub fn insert(&mut self, pivot: &[u8], item: &[u8], after: bool) -> bool {
+        let Some(position) = self.items.iter().position(|i| i == pivot) else {
+            return false;
+        };
+        self.items.insert(position + after as usize, item.to_vec());
+        true
+    }
+    // Removes up to `count` occurrences of `item` (all if 0), scanning from the tail when
+    // `count` is negative; returns how many were removed.
+    pub fn remove(&mut self, item: &[u8], count: i64) -> usize {
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let mut removed = 0;
+        let keep = |candidate: &Vec<u8>| {
+            if removed < limit && candidate == item {
+                removed += 1;
+                return false;
+            }
+            true
+        };
+        if count < 0 {
+            let kept: Vec<_> = self.items.drain(..).rev().filter(keep).collect();
+            self.items = kept.into_iter().rev().collect();
+        } else {
+            self.items.retain(keep);
+        }
+        self.shrink();
+        removed
+    }
+    // Keeps only the inclusive range; may leave the list empty.
+    pub fn trim(&mut self, start: i64, stop: i64) {
+        match self.resolve_range(start, stop) {
+            Some((from, to)) => {
+                self.items.truncate(to);
+                self.items.drain(..from);
+            }
+            None => self.items.clear(),
+        }
+        self.shrink();
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.items.iter()
+    }
+    // Resolves Redis-style inclusive indexes, where negative ones count from the tail, to a
+    // half-open range; None when the range is empty.
+    pub fn resolve_range(&self, start: i64, stop: i64) -> Option<(usize, usize)> {
+        let len = self.len() as i64;
+        let start = if start < 0 {
+            (start + len).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            stop + len
+        } else {
+            stop.min(len - 1)
+        };
+        (start <= stop && start < len).then(|| (start as usize, stop as usize + 1))
+    }
+    pub fn range(&self, start: i64, stop: i64) -> impl Iterator<Item = &Vec<u8>> {
+        let (from, to) = self.resolve_range(start, stop).unwrap_or((0, 0));
+        self.items.range(from..to)
+    }
+}
+
+impl FromIterator<Vec<u8>> for List {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(iter: I) -> Self {
+        Self {
+            items: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_past_either_end() {
+        let list: List = [b"a", b"b", b"c"]
+            .map(|item| item.to_vec())
+            .into_iter()
+            .collect();
+        assert_eq!(list.resolve_range(0, -1), Some((0, 3)));
+        assert_eq!(list.resolve_range(-100, 100), Some((0, 3)));
+        assert_eq!(list.resolve_range(1, -2), Some((1, 2)));
+        assert_eq!(list.resolve_range(0, -4), None);
+        assert_eq!(list.resolve_range(-5, -4), None);
+        assert_eq!(list.resolve_range(3, 5), None);
+        assert_eq!(list.resolve_range(i64::MIN, i64::MIN), None);
+        assert_eq!(list.range(0, -4).count(), 0);
+    }
+}
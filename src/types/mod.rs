@@ -0,0 +1,10 @@
+pub mod bitmap;
+pub mod geo;
+pub mod hash;
+pub mod hll;
+pub mod list;
+pub mod set;
+pub mod skiplist;
+pub mod stream;
+pub mod string;
+pub mod zset;
@@ -0,0 +1,218 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::random;
+
+const MAX_INTSET_ENTRIES: usize = 512;
+
+// Members kept densely for O(1) random picks, with a map from each member to its position;
+// removals swap the last member into the hole, like DataMap's sampling list.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    positions: HashMap<Arc<[u8]>, usize>,
+    members: Vec<Arc<[u8]>>,
+}
+
+impl Table {
+    fn insert(&mut self, member: &[u8]) -> bool {
+        if self.positions.contains_key(member) {
+            return false;
+        }
+        let member = Arc::<[u8]>::from(member);
+        self.positions.insert(member.clone(), self.members.len());
+        self.members.push(member);
+        true
+    }
+    fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(position) = self.positions.remove(member) else {
+            return false;
+        };
+        self.members.swap_remove(position);
+        if let Some(moved) = self.members.get(position) {
+            *self.positions.get_mut(moved).unwrap() = position;
+        }
+        true
+    }
+}
+
+// Small integer-only sets are kept as a sorted Vec<i64> (Redis' intset) and upgraded to a
+// hash table once a non-integer member is added or the size threshold is crossed.
+#[derive(Debug, Clone)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    HashTable(Table),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::IntSet(vec![])
+    }
+}
+
+// Only canonical decimal representations qualify, so members round-trip byte for byte.
+fn as_intset_member(member: &[u8]) -> Option<i64> {
+    std::str::from_utf8(member)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .filter(|i| i.to_string().as_bytes() == member)
+}
+
+impl Set {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.len(),
+            Set::HashTable(table) => table.members.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Set::IntSet(_) => "intset",
+            Set::HashTable(_) => "hashtable",
+        }
+    }
+    // An intset stays sorted, duplicate-free and within the size threshold.
+    pub fn is_well_formed(&self) -> bool {
+        match self {
+            Set::IntSet(ints) => {
+                ints.len() <= MAX_INTSET_ENTRIES && ints.windows(2).all(|pair| pair[0] < pair[1])
+            }
+            Set::HashTable(_) => true,
+        }
+    }
+    fn upgrade(&mut self) {
+        if let Set::IntSet(ints) = self {
+            let mut table = Table::default();
+            for i in ints.iter() {
+                table.insert(i.to_string().as_bytes());
+            }
+            *self = Set::HashTable(table);
+        }
+    }
+    pub fn insert(&mut self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(ints) => match as_intset_member(member) {
+                Some(i) => match ints.binary_search(&i) {
+                    Ok(_) => false,
+                    Err(_) if ints.len() >= MAX_INTSET_ENTRIES => {
+                        self.upgrade();
+                        self.insert(member)
+                    }
+                    Err(pos) => {
+                        ints.insert(pos, i);
+                        true
+                    }
+                },
+                None => {
+                    self.upgrade();
+                    self.insert(member)
+                }
+            },
+            Set::HashTable(table) => table.insert(member),
+        }
+    }
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(ints) => {
+                let Some(pos) = as_intset_member(member).and_then(|i| ints.binary_search(&i).ok())
+                else {
+                    return false;
+                };
+                ints.remove(pos);
+                true
+            }
+            Set::HashTable(table) => table.remove(member),
+        }
+    }
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(ints) => {
+                as_intset_member(member).is_some_and(|i| ints.binary_search(&i).is_ok())
+            }
+            Set::HashTable(table) => table.positions.contains_key(member),
+        }
+    }
+    pub fn members(&self) -> Vec<Vec<u8>> {
+        match self {
+            Set::IntSet(ints) => ints.iter().map(|i| i.to_string().into_bytes()).collect(),
+            Set::HashTable(table) => table.members.iter().map(|member| member.to_vec()).collect(),
+        }
+    }
+    pub fn random_member(&self) -> Option<Vec<u8>> {
+        if self.is_empty() {
+            return None;
+        }
+        self.sample(1, false).pop()
+    }
+    // Picks `count` members, possibly repeating them when `allow_duplicates` is set; otherwise
+    // at most the whole set is returned.
+    pub fn sample(&self, count: usize, allow_duplicates: bool) -> Vec<Vec<u8>> {
+        match self {
+            Set::IntSet(ints) => sample_indices(ints.len(), count, allow_duplicates)
+                .into_iter()
+                .map(|idx| ints[idx].to_string().into_bytes())
+                .collect(),
+            Set::HashTable(table) => sample_indices(table.members.len(), count, allow_duplicates)
+                .into_iter()
+                .map(|idx| table.members[idx].to_vec())
+                .collect(),
+        }
+    }
+    // Removes and returns up to `count` distinct random members.
+    pub fn pop(&mut self, count: usize) -> Vec<Vec<u8>> {
+        if count >= self.len() {
+            return std::mem::take(self).members();
+        }
+        let popped = self.sample(count, false);
+        for member in &popped {
+            self.remove(member);
+        }
+        popped
+    }
+}
+
+// Most picks a sample with repeats may ask for: the reply is built whole before it is sent.
+pub const MAX_REPEATED_SAMPLE: usize = 1 << 24;
+
+pub fn sample_indices(len: usize, count: usize, allow_duplicates: bool) -> Vec<usize> {
+    if len == 0 {
+        return vec![];
+    }
+    if allow_duplicates {
+        // Grown as it is filled rather than sized up front from the count the client gave.
+        let mut indices = vec![];
+        for _ in 0..count.min(MAX_REPEATED_SAMPLE) {
+            indices.push(random::below(len));
+        }
+        return indices;
+    }
+    if count >= len {
+        return (0..len).collect();
+    }
+    if count * 3 > len {
+        // Close to the full set: a partial Fisher-Yates shuffle beats rejection sampling.
+        let mut indices: Vec<usize> = (0..len).collect();
+        for i in 0..count {
+            let j = i + random::below(len - i);
+            indices.swap(i, j);
+        }
+        indices.truncate(count);
+        return indices;
+    }
+    let mut picked = HashSet::with_capacity(count);
+    let mut indices = Vec::with_capacity(count);
+    while indices.len() < count {
+        let idx = random::below(len);
+        if picked.insert(idx) {
+            indices.push(idx);
+        }
+    }
+    indices
+}
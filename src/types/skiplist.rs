@@ -0,0 +1,433 @@
+use std::sync::Arc;
+
+use crate::random;
+
+const MAX_LEVEL: usize = 32;
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    forward: Option<usize>,
+    // How many nodes the forward link skips over, counting the one it lands on.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Arc<[u8]>,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+impl Node {
+    fn precedes(&self, score: f64, member: &[u8]) -> bool {
+        self.score < score || (self.score == score && *self.member < *member)
+    }
+}
+
+// Redis' zskiplist, with the nodes kept in an arena and linked by index. Entries are ordered by
+// score, then by member bytes; spans make rank lookups O(log n) as well.
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    // nodes[HEAD] is the header; freed slots are reused through `free`.
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Arc::from(&[][..]),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+        Self {
+            nodes: vec![head],
+            free: vec![],
+            tail: None,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < MAX_LEVEL && random::next_u64().is_multiple_of(4) {
+        level += 1;
+    }
+    level
+}
+
+impl SkipList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    fn forward(&self, idx: usize, level: usize) -> Option<usize> {
+        self.nodes[idx].levels[level].forward
+    }
+    // The last node at each level that sorts before (score, member), and its rank.
+    fn predecessors(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i) {
+                if !self.nodes[next].precedes(score, member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+        (update, rank)
+    }
+    // The member must not be in the list yet.
+    pub fn insert(&mut self, member: Arc<[u8]>, score: f64) {
+        let (mut update, mut rank) = self.predecessors(score, &member);
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+        let node = Node {
+            member,
+            score,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                level
+            ],
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = node;
+                idx
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let prev = self.nodes[update[i]].levels[i];
+            self.nodes[idx].levels[i] = Level {
+                forward: prev.forward,
+                span: prev.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Level {
+                forward: Some(idx),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+        match self.forward(idx, 0) {
+            Some(next) => self.nodes[next].backward = Some(idx),
+            None => self.tail = Some(idx),
+        }
+        self.len += 1;
+    }
+    pub fn remove(&mut self, member: &[u8], score: f64) -> bool {
+        let (update, _) = self.predecessors(score, member);
+        let Some(idx) = self.forward(update[0], 0) else {
+            return false;
+        };
+        if self.nodes[idx].score != score || *self.nodes[idx].member != *member {
+            return false;
+        }
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            let removed = self.nodes[idx].levels.get(i).copied();
+            let link = &mut self.nodes[*prev].levels[i];
+            match removed {
+                Some(removed) if link.forward == Some(idx) => {
+                    link.span += removed.span;
+                    link.span -= 1;
+                    link.forward = removed.forward;
+                }
+                _ => link.span -= 1,
+            }
+        }
+        let backward = self.nodes[idx].backward;
+        match self.forward(idx, 0) {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+        // Drop the member now rather than when the slot is reused.
+        self.nodes[idx].member = Arc::from(&[][..]);
+        self.nodes[idx].levels = vec![];
+        self.free.push(idx);
+        self.len -= 1;
+        true
+    }
+    // 0-based rank of the entry, if it is in the list.
+    pub fn rank(&self, member: &[u8], score: f64) -> Option<usize> {
+        let mut x = HEAD;
+        let mut rank = 0;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = &self.nodes[next];
+                if !(node.precedes(score, member)
+                    || (node.score == score && *node.member == *member))
+                {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        (x != HEAD && *self.nodes[x].member == *member).then(|| rank - 1)
+    }
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None;
+        }
+        let target = rank + 1;
+        let mut x = HEAD;
+        let mut traversed = 0;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.nodes[x].levels[i].span > target {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_from_rank(0, false)
+    }
+    // Walks from the entry at `rank` towards the tail, or towards the head if `rev`.
+    pub fn iter_from_rank(&self, rank: usize, rev: bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            next: self.by_rank(rank),
+            rev,
+        }
+    }
+    // Walks towards the tail from the first entry for which `below` is false.
+    pub fn iter_from(&self, below: impl Fn(&[u8], f64) -> bool) -> Iter<'_> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !below(&self.nodes[next].member, self.nodes[next].score) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        Iter {
+            list: self,
+            next: self.forward(x, 0),
+            rev: false,
+        }
+    }
+    // Walks towards the head from the last entry for which `above` is false.
+    pub fn iter_back_from(&self, above: impl Fn(&[u8], f64) -> bool) -> Iter<'_> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if above(&self.nodes[next].member, self.nodes[next].score) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        Iter {
+            list: self,
+            next: (x != HEAD).then_some(x),
+            rev: true,
+        }
+    }
+    // Checks ordering, back links and spans against a plain walk of the bottom level.
+    pub fn is_well_formed(&self) -> bool {
+        let mut ranks = vec![None; self.nodes.len()];
+        let mut prev: Option<usize> = None;
+        let mut x = self.forward(HEAD, 0);
+        let mut count = 0;
+        while let Some(idx) = x {
+            let node = &self.nodes[idx];
+            if node.backward != prev {
+                return false;
+            }
+            if prev.is_some_and(|prev| !self.nodes[prev].precedes(node.score, &node.member)) {
+                return false;
+            }
+            count += 1;
+            ranks[idx] = Some(count);
+            prev = Some(idx);
+            x = node.levels[0].forward;
+        }
+        if count != self.len || self.tail != prev {
+            return false;
+        }
+        ranks[HEAD] = Some(0);
+        (0..self.level).all(|i| {
+            let mut x = HEAD;
+            while let Some(next) = self.forward(x, i) {
+                match (ranks[x], ranks[next]) {
+                    (Some(from), Some(to)) if to - from == self.nodes[x].levels[i].span => x = next,
+                    _ => return false,
+                }
+            }
+            true
+        })
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    next: Option<usize>,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.list.nodes[self.next?];
+        self.next = if self.rev {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+        Some((&node.member, node.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(entries: &[(&str, f64)]) -> SkipList {
+        let mut list = SkipList::default();
+        for (member, score) in entries {
+            list.insert(Arc::from(member.as_bytes()), *score);
+        }
+        list
+    }
+
+    fn members<'a>(iter: impl Iterator<Item = (&'a [u8], f64)>) -> Vec<String> {
+        iter.map(|(member, _)| String::from_utf8_lossy(member).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn ties_are_ordered_by_member() {
+        let list = list(&[("c", 1.0), ("b", 2.0), ("a", 1.0), ("d", 1.0), ("e", -1.0)]);
+        assert!(list.is_well_formed());
+        assert_eq!(members(list.iter()), ["e", "a", "c", "d", "b"]);
+        assert_eq!(list.rank(b"a", 1.0), Some(1));
+        assert_eq!(list.rank(b"d", 1.0), Some(3));
+        assert_eq!(list.rank(b"b", 2.0), Some(4));
+        // A member is only found under its own score.
+        assert_eq!(list.rank(b"a", 2.0), None);
+        assert_eq!(list.rank(b"z", 1.0), None);
+    }
+
+    #[test]
+    fn inserts_and_removes_keep_ranks_and_spans() {
+        let mut list = SkipList::default();
+        let mut model: Vec<(f64, String)> = vec![];
+        for i in 0..2000u64 {
+            let member = format!("m{}", random::next_u64() % 500);
+            let score = (random::next_u64() % 20) as f64;
+            let existing = model.iter().position(|(_, m)| *m == member);
+            match existing {
+                Some(at) if i % 3 != 0 => {
+                    let (score, member) = model.remove(at);
+                    assert!(list.remove(member.as_bytes(), score));
+                }
+                Some(_) => {}
+                None => {
+                    list.insert(Arc::from(member.as_bytes()), score);
+                    model.push((score, member));
+                }
+            }
+            if i % 100 == 0 {
+                assert!(list.is_well_formed());
+            }
+        }
+        model.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        assert!(list.is_well_formed());
+        assert_eq!(list.len(), model.len());
+        let expected: Vec<String> = model.iter().map(|(_, m)| m.clone()).collect();
+        assert_eq!(members(list.iter()), expected);
+        for (rank, (score, member)) in model.iter().enumerate() {
+            assert_eq!(list.rank(member.as_bytes(), *score), Some(rank));
+            let at = list.iter_from_rank(rank, false).next().unwrap();
+            assert_eq!(at.0, member.as_bytes());
+        }
+        assert!(!list.remove(b"absent", 0.0));
+        let (score, member) = &model[0];
+        assert!(!list.remove(member.as_bytes(), score + 0.5));
+    }
+
+    #[test]
+    fn ranges_by_score() {
+        let list = list(&[("a", 1.0), ("b", 2.0), ("c", 2.0), ("d", 3.0), ("e", 5.0)]);
+        // [2, 3]
+        let forward = list
+            .iter_from(|_, score| score < 2.0)
+            .take_while(|(_, score)| *score <= 3.0);
+        assert_eq!(members(forward), ["b", "c", "d"]);
+        // (1, 4] walked from the top, as ZREVRANGEBYSCORE does.
+        let backward = list
+            .iter_back_from(|_, score| score > 4.0)
+            .take_while(|(_, score)| *score > 1.0);
+        assert_eq!(members(backward), ["d", "c", "b"]);
+        assert_eq!(
+            members(list.iter_from(|_, score| score < 6.0)),
+            [] as [&str; 0]
+        );
+        assert_eq!(members(list.iter_from_rank(3, true)), ["d", "c", "b", "a"]);
+        assert!(list.iter_from_rank(5, false).next().is_none());
+    }
+
+    #[test]
+    fn ranges_by_lex() {
+        let list = list(&[
+            ("delta", 0.0),
+            ("alpha", 0.0),
+            ("charlie", 0.0),
+            ("bravo", 0.0),
+        ]);
+        // [b, d)
+        let forward = list
+            .iter_from(|member, _| member < &b"b"[..])
+            .take_while(|(member, _)| *member < &b"d"[..]);
+        assert_eq!(members(forward), ["bravo", "charlie"]);
+        // (-inf, charlie] from the top.
+        let backward = list.iter_back_from(|member, _| member > &b"charlie"[..]);
+        assert_eq!(members(backward), ["charlie", "bravo", "alpha"]);
+    }
+}
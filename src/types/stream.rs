@@ -0,0 +1,481 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+    // The smallest ID greater than this one, if there is any.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+    // The largest ID smaller than this one, if there is any.
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Trim {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub id: StreamId,
+    // Field names and values, interleaved as XADD takes them.
+    pub fields: Vec<Vec<u8>>,
+    deleted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    // Unix milliseconds of the latest delivery.
+    pub delivery_time: i64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Consumer {
+    // Unix milliseconds of the consumer's latest attempt to read, and of its latest successful
+    // one (-1 if there was none).
+    pub seen_time: i64,
+    pub active_time: i64,
+    // IDs this consumer owns in its group's pending entries list.
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    pub fn new(now: i64) -> Self {
+        Self {
+            seen_time: now,
+            active_time: -1,
+            pending: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    // Entries delivered but not acknowledged yet, for all consumers of the group.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered: StreamId) -> Self {
+        Self {
+            last_delivered,
+            ..Self::default()
+        }
+    }
+    // Returns false if the consumer already exists.
+    pub fn create_consumer(&mut self, name: &[u8], now: i64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers.insert(name.to_vec(), Consumer::new(now));
+        true
+    }
+    // Records a delivery of `id` to `consumer`, which must exist. An entry pending for another
+    // consumer changes hands and starts counting deliveries over.
+    pub fn deliver(&mut self, id: StreamId, consumer: &[u8], now: i64) {
+        self.claim(id, consumer, now, 1);
+    }
+    // Makes `consumer`, which must exist, the owner of the pending entry `id`, creating the
+    // entry if needed.
+    pub fn claim(
+        &mut self,
+        id: StreamId,
+        consumer: &[u8],
+        delivery_time: i64,
+        delivery_count: u64,
+    ) {
+        let previous = self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_vec(),
+                delivery_time,
+                delivery_count,
+            },
+        );
+        if let Some(previous) = previous {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        if let Some(owner) = self.consumers.get_mut(consumer) {
+            owner.pending.insert(id);
+        }
+    }
+    // Removes an entry from the pending entries list; returns false if it was not pending.
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        let Some(entry) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+            owner.pending.remove(&id);
+        }
+        true
+    }
+    // Every pending entry is owned by exactly one existing consumer, which lists it.
+    pub fn is_well_formed(&self) -> bool {
+        self.pending.iter().all(|(id, entry)| {
+            self.consumers
+                .get(&entry.consumer)
+                .is_some_and(|consumer| consumer.pending.contains(id))
+        }) && self
+            .consumers
+            .values()
+            .map(|consumer| consumer.pending.len())
+            .sum::<usize>()
+            == self.pending.len()
+    }
+}
+
+// Entries per node, stream-node-max-entries' default.
+const NODE_MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    entries: Vec<Entry>,
+    // XDEL only marks entries as deleted; they are dropped once they make up half the node.
+    deleted: usize,
+}
+
+impl Node {
+    fn live(&self) -> usize {
+        self.entries.len() - self.deleted
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    // Like Redis' radix tree of listpacks: nodes of up to NODE_MAX_ENTRIES entries, keyed by an
+    // ID no greater than their first entry's. Only the last node takes appends, and trimming
+    // drops whole nodes where it can.
+    nodes: BTreeMap<StreamId, Node>,
+    len: usize,
+    // The ID of the newest entry ever added, which may have been deleted since.
+    last_id: StreamId,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = id;
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.nodes
+            .values()
+            .flat_map(|node| &node.entries)
+            .filter(|entry| !entry.deleted)
+    }
+    // The node that would hold `id`.
+    fn node_key(&self, id: StreamId) -> Option<StreamId> {
+        self.nodes.range(..=id).next_back().map(|(key, _)| *key)
+    }
+    // The live entry with this ID, if there is one.
+    pub fn get(&self, id: StreamId) -> Option<&Entry> {
+        let node = &self.nodes[&self.node_key(id)?];
+        let idx = node
+            .entries
+            .binary_search_by_key(&id, |entry| entry.id)
+            .ok()?;
+        Some(&node.entries[idx]).filter(|entry| !entry.deleted)
+    }
+    // Entries with IDs in start..=end, from either end.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        rev: bool,
+    ) -> Box<dyn Iterator<Item = &Entry> + '_> {
+        if start > end {
+            return Box::new(std::iter::empty());
+        }
+        let first = self.node_key(start).unwrap_or(start);
+        let entries = self
+            .nodes
+            .range(first..=end)
+            .flat_map(move |(_, node)| {
+                let from = node.entries.partition_point(|entry| entry.id < start);
+                let to = node.entries.partition_point(|entry| entry.id <= end);
+                &node.entries[from..to.max(from)]
+            })
+            .filter(|entry| !entry.deleted);
+        if rev {
+            Box::new(entries.rev())
+        } else {
+            Box::new(entries)
+        }
+    }
+    // The ID must be greater than last_id().
+    pub fn append(&mut self, id: StreamId, fields: Vec<Vec<u8>>) {
+        let entry = Entry {
+            id,
+            fields,
+            deleted: false,
+        };
+        match self.nodes.last_entry() {
+            Some(mut node) if node.get().entries.len() < NODE_MAX_ENTRIES => {
+                node.get_mut().entries.push(entry)
+            }
+            _ => {
+                let node = Node {
+                    entries: vec![entry],
+                    deleted: 0,
+                };
+                self.nodes.insert(id, node);
+            }
+        }
+        self.len += 1;
+        self.last_id = id;
+    }
+    // Returns false if there is no such entry. last_id() is unaffected.
+    pub fn delete(&mut self, id: StreamId) -> bool {
+        let Some(key) = self.node_key(id) else {
+            return false;
+        };
+        let node = self.nodes.get_mut(&key).unwrap();
+        let Ok(idx) = node.entries.binary_search_by_key(&id, |entry| entry.id) else {
+            return false;
+        };
+        let entry = &mut node.entries[idx];
+        if entry.deleted {
+            return false;
+        }
+        entry.deleted = true;
+        entry.fields = vec![];
+        node.deleted += 1;
+        self.len -= 1;
+        if node.deleted * 2 >= node.entries.len() {
+            node.entries.retain(|entry| !entry.deleted);
+            node.deleted = 0;
+        }
+        if node.entries.is_empty() {
+            self.nodes.remove(&key);
+        }
+        true
+    }
+    // Evicts the oldest entries until `trim` is satisfied, or until `limit` entries are gone.
+    // Returns how many were evicted.
+    pub fn trim(&mut self, trim: Trim, limit: usize) -> usize {
+        let mut evicted = 0;
+        while let Some(mut first) = self.nodes.first_entry() {
+            let node = first.get_mut();
+            let live = node.live();
+            let whole = match trim {
+                Trim::MaxLen(max_len) => self.len - live >= max_len,
+                Trim::MinId(min_id) => node.entries.last().is_some_and(|entry| entry.id < min_id),
+            };
+            if whole && live <= limit - evicted {
+                first.remove();
+                self.len -= live;
+                evicted += live;
+                continue;
+            }
+            // The trim ends inside this node.
+            let mut tombstones = 0;
+            let mut cut = 0;
+            for entry in &node.entries {
+                let keep = match trim {
+                    Trim::MaxLen(max_len) => self.len <= max_len,
+                    Trim::MinId(min_id) => entry.id >= min_id,
+                };
+                if entry.deleted {
+                    tombstones += 1;
+                } else if keep || evicted == limit {
+                    break;
+                } else {
+                    evicted += 1;
+                    self.len -= 1;
+                }
+                cut += 1;
+            }
+            node.entries.drain(..cut);
+            node.deleted -= tombstones;
+            if node.entries.is_empty() {
+                first.remove();
+            }
+            break;
+        }
+        evicted
+    }
+    pub fn groups(&self) -> impl Iterator<Item = (&Vec<u8>, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+    // Returns false if a group with this name already exists.
+    pub fn create_group(&mut self, name: &[u8], group: ConsumerGroup) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        self.groups.insert(name.to_vec(), group);
+        true
+    }
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+    // Strictly increasing IDs, none past last_id, whole field/value pairs, nodes that are
+    // non-empty, bounded and correctly keyed, counts that match, and consistent pending
+    // entries lists.
+    pub fn is_well_formed(&self) -> bool {
+        let nodes_ok = self.nodes.iter().all(|(key, node)| {
+            node.entries.first().is_some_and(|entry| *key <= entry.id)
+                && node.entries.len() <= NODE_MAX_ENTRIES
+                && node.entries.iter().filter(|entry| entry.deleted).count() == node.deleted
+        });
+        let ids: Vec<StreamId> = self
+            .nodes
+            .values()
+            .flat_map(|node| node.entries.iter().map(|entry| entry.id))
+            .collect();
+        // Each node's entries also sort before the next node's key.
+        let keyed = self
+            .nodes
+            .keys()
+            .skip(1)
+            .zip(self.nodes.values())
+            .all(|(next, node)| node.entries.last().is_some_and(|entry| entry.id < *next));
+        nodes_ok
+            && keyed
+            && ids.windows(2).all(|pair| pair[0] < pair[1])
+            && ids.last().is_none_or(|id| *id <= self.last_id)
+            && self
+                .iter()
+                .all(|entry| !entry.fields.is_empty() && entry.fields.len().is_multiple_of(2))
+            && self.nodes.values().map(Node::live).sum::<usize>() == self.len
+            && self.groups.values().all(ConsumerGroup::is_well_formed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Entries 1-0 through n-0, each with one field named after its ID.
+    fn stream(n: u64) -> Stream {
+        let mut stream = Stream::new();
+        for ms in 1..=n {
+            stream.append(
+                StreamId::new(ms, 0),
+                vec![b"f".to_vec(), ms.to_string().into()],
+            );
+        }
+        stream
+    }
+
+    fn ids<'a>(entries: impl Iterator<Item = &'a Entry>) -> Vec<u64> {
+        entries.map(|entry| entry.id.ms).collect()
+    }
+
+    #[test]
+    fn ranges_cross_node_boundaries() {
+        let stream = stream(350);
+        assert_eq!(stream.nodes.len(), 4);
+        assert!(stream.is_well_formed());
+        let (start, end) = (StreamId::new(95, 0), StreamId::new(205, 0));
+        assert_eq!(
+            ids(stream.range(start, end, false)),
+            (95..=205).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids(stream.range(start, end, true)),
+            (95..=205).rev().collect::<Vec<_>>()
+        );
+        // Bounds that fall between entries, and ranges past either end.
+        let between = stream.range(StreamId::new(100, 1), StreamId::new(101, 0), false);
+        assert_eq!(ids(between), [101]);
+        assert_eq!(
+            ids(stream.range(StreamId::new(351, 0), StreamId::MAX, false)),
+            []
+        );
+        assert_eq!(
+            ids(stream.range(StreamId::MIN, StreamId::new(1, 0), true)),
+            [1]
+        );
+        assert_eq!(ids(stream.range(end, start, false)), []);
+        assert_eq!(stream.get(StreamId::new(201, 0)).unwrap().fields[1], b"201");
+        assert!(stream.get(StreamId::new(201, 1)).is_none());
+    }
+
+    #[test]
+    fn deletes_compact_and_drop_nodes() {
+        let mut stream = stream(300);
+        for ms in 101..=200 {
+            assert!(stream.delete(StreamId::new(ms, 0)));
+            assert!(stream.is_well_formed());
+        }
+        assert!(!stream.delete(StreamId::new(150, 0)));
+        assert_eq!(stream.nodes.len(), 2);
+        assert_eq!(stream.len(), 200);
+        let around = stream.range(StreamId::new(99, 0), StreamId::new(202, 0), false);
+        assert_eq!(ids(around), [99, 100, 201, 202]);
+        // Deleting the newest entry leaves last_id where it was.
+        assert!(stream.delete(StreamId::new(300, 0)));
+        assert_eq!(stream.last_id(), StreamId::new(300, 0));
+        stream.append(StreamId::new(301, 0), vec![b"f".to_vec(), b"v".to_vec()]);
+        assert!(stream.is_well_formed());
+    }
+
+    #[test]
+    fn trims_whole_nodes_then_entries() {
+        let mut stream = stream(350);
+        assert_eq!(stream.trim(Trim::MaxLen(120), usize::MAX), 230);
+        assert_eq!(stream.len(), 120);
+        assert_eq!(stream.iter().next().unwrap().id, StreamId::new(231, 0));
+        assert!(stream.is_well_formed());
+        // A limit stops the trim partway.
+        assert_eq!(stream.trim(Trim::MinId(StreamId::new(300, 0)), 10), 10);
+        assert_eq!(stream.iter().next().unwrap().id, StreamId::new(241, 0));
+        assert_eq!(
+            stream.trim(Trim::MinId(StreamId::new(300, 0)), usize::MAX),
+            59
+        );
+        assert_eq!(stream.iter().next().unwrap().id, StreamId::new(300, 0));
+        assert!(stream.is_well_formed());
+    }
+}
@@ -0,0 +1,85 @@
+use std::ops::Deref;
+
+// Strings up to this length are kept inline in the value, the way Redis allocates embstr
+// objects together with their header, instead of in a second heap allocation.
+pub const EMBSTR_SIZE_LIMIT: usize = 44;
+
+#[derive(Debug, Clone)]
+pub enum Str {
+    Embedded {
+        len: u8,
+        bytes: [u8; EMBSTR_SIZE_LIMIT],
+    },
+    Raw(Vec<u8>),
+}
+
+impl Str {
+    // Name of the representation as OBJECT ENCODING reports it.
+    pub fn encoding(&self) -> &'static str {
+        let canonical_int = std::str::from_utf8(self)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .is_some_and(|i| i.to_string().as_bytes() == &self[..]);
+        match self {
+            _ if canonical_int => "int",
+            Str::Embedded { .. } => "embstr",
+            Str::Raw(_) => "raw",
+        }
+    }
+    // Edits the bytes in place, re-embedding or spilling them if their length crosses
+    // EMBSTR_SIZE_LIMIT.
+    pub fn update<R>(&mut self, f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+        let Str::Raw(data) = self else {
+            let mut data = self.to_vec();
+            let result = f(&mut data);
+            *self = Str::from(data);
+            return result;
+        };
+        let result = f(data);
+        if data.len() <= EMBSTR_SIZE_LIMIT {
+            *self = Str::from(data.as_slice());
+        }
+        result
+    }
+    // Short strings are always embedded and long ones never are.
+    pub fn is_well_formed(&self) -> bool {
+        match self {
+            Str::Embedded { len, .. } => *len as usize <= EMBSTR_SIZE_LIMIT,
+            Str::Raw(data) => data.len() > EMBSTR_SIZE_LIMIT,
+        }
+    }
+}
+
+impl Deref for Str {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Str::Embedded { len, bytes } => &bytes[..*len as usize],
+            Str::Raw(data) => data,
+        }
+    }
+}
+
+impl From<&[u8]> for Str {
+    fn from(data: &[u8]) -> Self {
+        if data.len() > EMBSTR_SIZE_LIMIT {
+            return Str::Raw(data.to_vec());
+        }
+        let mut bytes = [0; EMBSTR_SIZE_LIMIT];
+        bytes[..data.len()].copy_from_slice(data);
+        Str::Embedded {
+            len: data.len() as u8,
+            bytes,
+        }
+    }
+}
+
+impl From<Vec<u8>> for Str {
+    fn from(data: Vec<u8>) -> Self {
+        if data.len() > EMBSTR_SIZE_LIMIT {
+            Str::Raw(data)
+        } else {
+            Str::from(data.as_slice())
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::{collections::HashMap, sync::Arc};
+
+use super::{
+    set::sample_indices,
+    skiplist::{Iter, SkipList},
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AddFlags {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub incr: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddOutcome {
+    Added(f64),
+    Updated(f64),
+    // The member already had the resulting score; INCR still reports it.
+    Unchanged(f64),
+    // NX/XX/GT/LT prevented the operation.
+    Skipped,
+    Nan,
+}
+
+// One end of a BYSCORE range; `(` in front of the score makes it exclusive.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+// A BYSCORE or BYLEX range. `below` and `above` tell on which side of the range an entry falls,
+// which is what walking the skiplist needs.
+pub trait EntryRange {
+    fn below(&self, member: &[u8], score: f64) -> bool;
+    fn above(&self, member: &[u8], score: f64) -> bool;
+    fn contains(&self, member: &[u8], score: f64) -> bool {
+        !self.below(member, score) && !self.above(member, score)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreRange {
+    pub min: ScoreBound,
+    pub max: ScoreBound,
+}
+
+impl EntryRange for ScoreRange {
+    fn below(&self, _: &[u8], score: f64) -> bool {
+        if self.min.exclusive {
+            score <= self.min.score
+        } else {
+            score < self.min.score
+        }
+    }
+    fn above(&self, _: &[u8], score: f64) -> bool {
+        if self.max.exclusive {
+            score >= self.max.score
+        } else {
+            score > self.max.score
+        }
+    }
+}
+
+// One end of a BYLEX range: `-`, `+`, `[member` or `(member`.
+#[derive(Debug, Clone)]
+pub enum LexBound {
+    NegativeInfinity,
+    PositiveInfinity,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+// Like in Redis, only meaningful when all members share the same score.
+#[derive(Debug, Clone)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl EntryRange for LexRange {
+    fn below(&self, member: &[u8], _: f64) -> bool {
+        match &self.min {
+            LexBound::NegativeInfinity => false,
+            LexBound::PositiveInfinity => true,
+            LexBound::Inclusive(min) => member < min.as_slice(),
+            LexBound::Exclusive(min) => member <= min.as_slice(),
+        }
+    }
+    fn above(&self, member: &[u8], _: f64) -> bool {
+        match &self.max {
+            LexBound::NegativeInfinity => true,
+            LexBound::PositiveInfinity => false,
+            LexBound::Inclusive(max) => member > max.as_slice(),
+            LexBound::Exclusive(max) => member >= max.as_slice(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<Arc<[u8]>, f64>,
+    // The same entries in rank order: by score, ties broken by member bytes.
+    list: SkipList,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+    // All entries, in rank order.
+    pub fn entries(&self) -> Vec<(&[u8], f64)> {
+        self.list.iter().collect()
+    }
+    // Walks from the entry at `rank`; with `rev`, ranks count from the highest score down.
+    pub fn iter_from_rank(&self, rank: usize, rev: bool) -> Iter<'_> {
+        let rank = if rev {
+            self.len().checked_sub(rank + 1).unwrap_or(usize::MAX)
+        } else {
+            rank
+        };
+        self.list.iter_from_rank(rank, rev)
+    }
+    // The entries inside `range`, from the low end or from the high end if `rev`.
+    pub fn range<'a, R: EntryRange>(
+        &'a self,
+        range: &'a R,
+        rev: bool,
+    ) -> impl Iterator<Item = (&'a [u8], f64)> + 'a {
+        let start = if rev {
+            self.list
+                .iter_back_from(|member, score| range.above(member, score))
+        } else {
+            self.list
+                .iter_from(|member, score| range.below(member, score))
+        };
+        start.take_while(|(member, score)| range.contains(member, *score))
+    }
+    pub fn count<R: EntryRange>(&self, range: &R) -> usize {
+        let first = self.range(range, false).next();
+        let last = self.range(range, true).next();
+        match (first, last) {
+            (Some((first, low)), Some((last, high))) => {
+                let first = self.list.rank(first, low).unwrap();
+                let last = self.list.rank(last, high).unwrap();
+                (last + 1).saturating_sub(first)
+            }
+            _ => 0,
+        }
+    }
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let rank = self.list.rank(member, self.score(member)?)?;
+        Some(if rev { self.len() - 1 - rank } else { rank })
+    }
+    // Same sampling rules as Set::sample.
+    pub fn sample(&self, count: usize, allow_duplicates: bool) -> Vec<(&[u8], f64)> {
+        sample_indices(self.len(), count, allow_duplicates)
+            .into_iter()
+            .filter_map(|idx| self.list.iter_from_rank(idx, false).next())
+            .collect()
+    }
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.list.remove(member, score),
+            None => false,
+        }
+    }
+    // Removes up to `count` entries from the low end (or the high end if `max`), in pop order.
+    pub fn pop(&mut self, max: bool, count: usize) -> Vec<(Vec<u8>, f64)> {
+        let popped: Vec<(Vec<u8>, f64)> = self
+            .iter_from_rank(0, max)
+            .take(count)
+            .map(|(member, score)| (member.to_vec(), score))
+            .collect();
+        for (member, _) in &popped {
+            self.remove(member);
+        }
+        popped
+    }
+    // Same decision table as Redis' zsetAdd.
+    pub fn add(&mut self, member: &[u8], score: f64, flags: AddFlags) -> AddOutcome {
+        match self.scores.get_key_value(member) {
+            Some(_) if flags.nx => AddOutcome::Skipped,
+            Some((key, current)) => {
+                let (key, current) = (key.clone(), *current);
+                let new_score = if flags.incr { current + score } else { score };
+                if new_score.is_nan() {
+                    return AddOutcome::Nan;
+                }
+                if (flags.gt && new_score <= current) || (flags.lt && new_score >= current) {
+                    return AddOutcome::Skipped;
+                }
+                if new_score == current {
+                    return AddOutcome::Unchanged(new_score);
+                }
+                self.list.remove(member, current);
+                self.list.insert(key.clone(), new_score);
+                self.scores.insert(key, new_score);
+                AddOutcome::Updated(new_score)
+            }
+            None if flags.xx => AddOutcome::Skipped,
+            None => {
+                let key: Arc<[u8]> = Arc::from(member);
+                self.list.insert(key.clone(), score);
+                self.scores.insert(key, score);
+                AddOutcome::Added(score)
+            }
+        }
+    }
+    // The hash map and the skiplist hold the same entries, and the skiplist is consistent.
+    pub fn is_well_formed(&self) -> bool {
+        self.list.len() == self.scores.len()
+            && self.list.is_well_formed()
+            && self
+                .list
+                .iter()
+                .all(|(member, score)| self.score(member) == Some(score))
+    }
+}